@@ -2,6 +2,10 @@ struct Globals {
     clip_view: mat4x4<f32>,
     view_world: mat4x4<f32>,
     elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
 }
 
 @group(0) @binding(0)
@@ -64,6 +68,9 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
 struct PointLight {
     position: vec3<f32>,
     radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: u32,
 }
 
 struct PointLightData {
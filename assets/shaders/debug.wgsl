@@ -0,0 +1,99 @@
+struct Globals {
+    clip_view: mat4x4<f32>,
+    view_world: mat4x4<f32>,
+    elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) view_depth: f32,
+}
+
+struct InstanceInput {
+    @location(3) model_matrix_0: vec4<f32>,
+    @location(4) model_matrix_1: vec4<f32>,
+    @location(5) model_matrix_2: vec4<f32>,
+    @location(6) model_matrix_3: vec4<f32>,
+    @location(7) normal_matrix_0: vec3<f32>,
+    @location(8) normal_matrix_1: vec3<f32>,
+    @location(9) normal_matrix_2: vec3<f32>,
+}
+
+// Instances of the batches drawn from a storage buffer.
+struct InstanceData {
+    world_local: mat4x4<f32>,
+    normal: mat3x3<f32>,
+    tint: vec3<f32>,
+    seed: u32,
+    custom: vec4<f32>,
+}
+
+@group(3) @binding(0)
+var<storage, read> instances: array<InstanceData>;
+
+fn vertex(
+    model: VertexInput,
+    world_local: mat4x4<f32>,
+    normal_rotation: mat3x3<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.world_normal = normal_rotation * model.normal;
+
+    let world_position = world_local * vec4<f32>(model.position, 1.0);
+    let view_position = globals.view_world * world_position;
+    out.view_depth = -view_position.z;
+    out.clip_position = globals.clip_view * view_position;
+
+    return out;
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let world_local = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_rotation = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    return vertex(model, world_local, normal_rotation);
+}
+
+@vertex
+fn vs_storage(
+    model: VertexInput,
+    @builtin(instance_index) index: u32,
+) -> VertexOutput {
+    let instance = instances[index];
+    return vertex(model, instance.world_local, instance.normal);
+}
+
+@fragment
+fn fs_normals(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(normalize(in.world_normal) * 0.5 + 0.5, 1.0);
+}
+
+@fragment
+fn fs_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = exp(-in.view_depth * 0.05);
+    return vec4<f32>(vec3<f32>(depth), 1.0);
+}
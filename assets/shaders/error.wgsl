@@ -0,0 +1,47 @@
+// Drawn in place of the shaders that failed to load.
+
+struct Globals {
+    clip_view: mat4x4<f32>,
+    view_world: mat4x4<f32>,
+    elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(3) model_matrix_0: vec4<f32>,
+    @location(4) model_matrix_1: vec4<f32>,
+    @location(5) model_matrix_2: vec4<f32>,
+    @location(6) model_matrix_3: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let world_local = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = world_local * vec4<f32>(model.position, 1.0);
+    return globals.clip_view * globals.view_world * world_position;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+}
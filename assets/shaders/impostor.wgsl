@@ -0,0 +1,281 @@
+struct Globals {
+    clip_view: mat4x4<f32>,
+    view_world: mat4x4<f32>,
+    elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+    // Color in xyz, intensity in w.
+    ambient: vec4<f32>,
+    // Color in xyz, mode in w: 0 without fog, 1 linear, 2 exponential.
+    fog_color: vec4<f32>,
+    // Start, end and density.
+    fog: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+// Blend toward the fog color with the distance to the camera.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let mode = globals.fog_color.w;
+    if mode == 0.0 {
+        return color;
+    }
+    let view_position = globals.view_world * vec4<f32>(world_position, 1.0);
+    let distance = length(view_position.xyz);
+    var amount = 0.0;
+    if mode == 1.0 {
+        amount = clamp(
+            (distance - globals.fog.x) / (globals.fog.y - globals.fog.x),
+            0.0,
+            1.0,
+        );
+    } else {
+        amount = 1.0 - exp(-globals.fog.z * distance);
+    }
+    return mix(color, globals.fog_color.xyz, amount);
+}
+
+// Quad in the billboard plane, x along its right and y up, the normal
+// holds the pivot of the baked mesh in its local space.
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(3) model_matrix_0: vec4<f32>,
+    @location(4) model_matrix_1: vec4<f32>,
+    @location(5) model_matrix_2: vec4<f32>,
+    @location(6) model_matrix_3: vec4<f32>,
+    @location(7) normal_matrix_0: vec3<f32>,
+    @location(8) normal_matrix_1: vec3<f32>,
+    @location(9) normal_matrix_2: vec3<f32>,
+    @location(12) seed: u32,
+    @location(13) tint: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) tint: vec3<f32>,
+    // Turn around the instance the camera looks from, picks the view.
+    @location(3) @interpolate(flat) view: f32,
+    @location(4) @interpolate(flat) seed: u32,
+}
+
+const TAU: f32 = 6.28318530718;
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let world_local = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let rotation = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let pivot = (world_local * vec4<f32>(model.normal, 1.0)).xyz;
+
+    let view_rotation = mat3x3<f32>(
+        globals.view_world[0].xyz,
+        globals.view_world[1].xyz,
+        globals.view_world[2].xyz,
+    );
+    let camera = -(transpose(view_rotation) * globals.view_world[3].xyz);
+    let to_camera = vec3<f32>(camera.x - pivot.x, 0.0, camera.z - pivot.z);
+    var forward = vec3<f32>(0.0, 0.0, 1.0);
+    if length(to_camera) > 1e-4 {
+        forward = normalize(to_camera);
+    }
+    // Cylindrical billboard, it turns around the world up axis only.
+    let right = vec3<f32>(forward.z, 0.0, -forward.x);
+    let scale = vec2<f32>(
+        length(world_local[0].xyz),
+        length(world_local[1].xyz),
+    );
+    let world_position = pivot + right * model.position.x * scale.x
+        + vec3<f32>(0.0, model.position.y * scale.y, 0.0);
+
+    let local = transpose(rotation) * (camera - pivot);
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coord;
+    out.world_position = world_position;
+    out.tint = instance.tint;
+    out.seed = instance.seed;
+    out.view = fract(atan2(local.x, local.z) / TAU);
+    out.clip_position = globals.clip_view * globals.view_world
+        * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: u32,
+}
+
+struct PointLightData {
+    len: u32,
+    data: array<PointLight>,
+}
+
+@group(1) @binding(0)
+var<storage, read> point_lights: PointLightData;
+
+struct Isolation {
+    light: i32,
+    dim: f32,
+}
+
+@group(1) @binding(1)
+var<uniform> isolation: Isolation;
+
+// Screen tiles and depth slices of the camera, with one in the w of the
+// size when the lights are clustered.
+struct ClusterGrid {
+    size: vec4<u32>,
+    screen: vec2<f32>,
+    near: f32,
+    slice_scale: f32,
+}
+
+@group(1) @binding(2)
+var<uniform> cluster_grid: ClusterGrid;
+// Offset and count into the cluster lights, per cell.
+@group(1) @binding(3)
+var<storage, read> clusters: array<vec2<u32>>;
+@group(1) @binding(4)
+var<storage, read> cluster_lights: array<u32>;
+
+// Range of the lights reaching the fragment, all of them without clusters.
+fn light_range(frag_position: vec2<f32>, world_position: vec3<f32>) -> vec2<u32> {
+    let size = cluster_grid.size;
+    if size.w == 0u {
+        return vec2<u32>(0u, point_lights.len);
+    }
+    let tile = min(
+        vec2<u32>(frag_position / cluster_grid.screen * vec2<f32>(size.xy)),
+        size.xy - 1u,
+    );
+    let depth = -(globals.view_world * vec4<f32>(world_position, 1.0)).z;
+    let slice = min(
+        u32(max(log(depth / cluster_grid.near) * cluster_grid.slice_scale, 0.0)),
+        size.z - 1u,
+    );
+    return clusters[tile.x + tile.y * size.x + slice * size.x * size.y];
+}
+
+fn light_index(range_index: u32) -> u32 {
+    if cluster_grid.size.w == 0u {
+        return range_index;
+    }
+    return cluster_lights[range_index];
+}
+
+struct SimpleMaterial {
+    color: vec3<f32>,
+    hue_variation: f32,
+    brightness_variation: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> uniform: SimpleMaterial;
+// One square cell per view, side by side.
+@group(2) @binding(1)
+var t_diffuse: texture_2d<f32>;
+@group(2) @binding(2)
+var s_diffuse: sampler;
+@group(2) @binding(3)
+var<uniform> uv_rect: vec4<f32>;
+
+// Same per instance variations as the model shader.
+fn instance_random(seed: u32, salt: u32) -> f32 {
+    let state = (seed ^ (salt * 0x9e3779b9u)) * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return f32((word >> 22u) ^ word) / 4294967295.0;
+}
+
+fn hue_shift(color: vec3<f32>, turns: f32) -> vec3<f32> {
+    let k = vec3<f32>(0.57735);
+    let angle = turns * TAU;
+    let cos_angle = cos(angle);
+    return color * cos_angle + cross(k, color) * sin(angle)
+        + k * dot(k, color) * (1.0 - cos_angle);
+}
+
+const ATTENUATION_INVERSE_SQUARE: u32 = 1;
+const ATTENUATION_LINEAR: u32 = 2;
+const ATTENUATION_EXPONENTIAL: u32 = 3;
+
+// Same falloffs as the model shader.
+fn attenuate(distance: f32, radius: f32, model: u32) -> f32 {
+    let s = saturate(distance / radius);
+    switch model {
+        case ATTENUATION_INVERSE_SQUARE: {
+            let window = saturate(1.0 - s * s * s * s);
+            return window * window / (distance * distance + 1.0);
+        }
+        case ATTENUATION_LINEAR: {
+            return 1.0 - s;
+        }
+        case ATTENUATION_EXPONENTIAL: {
+            let tail = exp(-4.0);
+            return (exp(-4.0 * s) - tail) / (1.0 - tail);
+        }
+        default: {
+            let inv_s2 = 1.0 - s * s;
+            return inv_s2 * inv_s2 / (1.0 + s);
+        }
+    }
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(t_diffuse);
+    let views = max(size.x / max(size.y, 1u), 1u);
+    let view = u32(round(in.view * f32(views))) % views;
+    let tex_coords = vec2<f32>(
+        (f32(view) + in.tex_coords.x) / f32(views),
+        in.tex_coords.y,
+    );
+    let diffuse = textureSample(t_diffuse, s_diffuse, tex_coords);
+    if diffuse.a < 0.5 {
+        discard;
+    }
+    let hue = (instance_random(in.seed, 0u) - 0.5) * uniform.hue_variation;
+    let brightness = 1.0
+        - instance_random(in.seed, 1u) * uniform.brightness_variation;
+    let albedo = hue_shift(diffuse.rgb * in.tint * uniform.color, hue)
+        * brightness;
+
+    var color = vec3<f32>(0.0);
+    if isolation.light < 0 {
+        color = albedo * globals.ambient.xyz * globals.ambient.w;
+    }
+    let range = light_range(in.clip_position.xy, in.world_position);
+    for (var j: u32 = range.x; j < range.x + range.y; j++) {
+        let i = light_index(j);
+        if isolation.light >= 0 && i32(i) != isolation.light {
+            continue;
+        }
+        let point_light = point_lights.data[i];
+        let distance = length(point_light.position - in.world_position);
+        color += albedo * point_light.color * point_light.intensity
+            * attenuate(distance, point_light.radius, point_light.attenuation);
+    }
+
+    color = apply_fog(color, in.world_position);
+    return vec4<f32>(color * isolation.dim, 1.0);
+}
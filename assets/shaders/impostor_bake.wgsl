@@ -0,0 +1,42 @@
+// Draws a mesh once per view, each view in its own cell of the atlas.
+struct Bake {
+    view_clips: array<mat4x4<f32>, 16>,
+    // Offset in xy and scale in zw of the material texture region.
+    uv_rect: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> bake: Bake;
+@group(0) @binding(1)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(2)
+var s_diffuse: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+// The instance index is the view.
+@vertex
+fn vs_main(
+    model: VertexInput,
+    @builtin(instance_index) view: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = bake.view_clips[view] * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coord * bake.uv_rect.zw + bake.uv_rect.xy;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let diffuse = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(diffuse.rgb, 1.0);
+}
@@ -2,11 +2,57 @@ struct Globals {
     clip_view: mat4x4<f32>,
     view_world: mat4x4<f32>,
     elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+    // Color in xyz, intensity in w.
+    ambient: vec4<f32>,
+    // Color in xyz, mode in w: 0 without fog, 1 linear, 2 exponential.
+    fog_color: vec4<f32>,
+    // Start, end and density.
+    fog: vec4<f32>,
 }
 
 @group(0) @binding(0)
 var<uniform> globals: Globals;
 
+// Blend toward the fog color with the distance to the camera.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let mode = globals.fog_color.w;
+    if mode == 0.0 {
+        return color;
+    }
+    let view_position = globals.view_world * vec4<f32>(world_position, 1.0);
+    let distance = length(view_position.xyz);
+    var amount = 0.0;
+    if mode == 1.0 {
+        amount = clamp(
+            (distance - globals.fog.x) / (globals.fog.y - globals.fog.x),
+            0.0,
+            1.0,
+        );
+    } else {
+        amount = 1.0 - exp(-globals.fog.z * distance);
+    }
+    return mix(color, globals.fog_color.xyz, amount);
+}
+
+// Air velocity at a world position, same gusts as the ropes and the
+// scripts, for the shaders swaying with the wind.
+fn wind_at(position: vec3<f32>) -> vec3<f32> {
+    let velocity = globals.wind.xyz;
+    let strength = length(velocity);
+    if strength == 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let direction = velocity / strength;
+    let phase = (dot(position, direction) * globals.gusts.y
+        - globals.gusts.z * globals.gusts.x) * 6.28318530718;
+    let noise = (sin(phase) + sin(phase * 2.3 + 1.7) * 0.5) / 1.5;
+    return velocity * (1.0 + globals.wind.w * noise);
+}
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coord: vec2<f32>,
@@ -18,6 +64,9 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) tex_coords2: vec2<f32>,
+    @location(5) @interpolate(flat) seed: u32,
 }
 
 struct InstanceInput {
@@ -28,15 +77,76 @@ struct InstanceInput {
     @location(7) normal_matrix_0: vec3<f32>,
     @location(8) normal_matrix_1: vec3<f32>,
     @location(9) normal_matrix_2: vec3<f32>,
+    // Stable per instance, hashed from the entity id or the position.
+    @location(12) seed: u32,
+    // Multiplies the vertex color, white unless the script tints it.
+    @location(13) tint: vec3<f32>,
 }
 
+// Only bound for meshes with vertex colors or a second uv channel.
+struct VertexExtraInput {
+    @location(10) color: vec4<f32>,
+    @location(11) tex_coord2: vec2<f32>,
+}
+
+// Instances of the batches drawn from a storage buffer, for the meshes
+// without extra vertex data.
+struct InstanceData {
+    world_local: mat4x4<f32>,
+    normal: mat3x3<f32>,
+    tint: vec3<f32>,
+    seed: u32,
+    custom: vec4<f32>,
+}
+
+@group(3) @binding(0)
+var<storage, read> instances: array<InstanceData>;
+
 
 fn map(val: f32, min1: f32, max1: f32, min2: f32, max2: f32) -> f32 {
     return min2 + (val - min1) * (max2 - min2) / (max1 - min1);
 }
 
-@vertex
-fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+// Random value from 0 to 1, a different salt gives an independent value
+// for the same instance.
+fn instance_random(seed: u32, salt: u32) -> f32 {
+    let state = (seed ^ (salt * 0x9e3779b9u)) * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return f32((word >> 22u) ^ word) / 4294967295.0;
+}
+
+// Rotate the hue around the grey axis, the angle is in turns.
+fn hue_shift(color: vec3<f32>, turns: f32) -> vec3<f32> {
+    let k = vec3<f32>(0.57735);
+    let angle = turns * 6.28318530718;
+    let cos_angle = cos(angle);
+    return color * cos_angle + cross(k, color) * sin(angle)
+        + k * dot(k, color) * (1.0 - cos_angle);
+}
+
+fn vertex(
+    model: VertexInput,
+    world_local: mat4x4<f32>,
+    normal_rotation: mat3x3<f32>,
+    tint: vec3<f32>,
+    seed: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.tex_coords = model.tex_coord;
+    out.tex_coords2 = model.tex_coord;
+    out.color = vec4<f32>(tint, 1.0);
+    out.seed = seed;
+    out.world_normal = normal_rotation * model.normal;
+
+    let world_position = world_local * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = globals.clip_view * globals.view_world * world_position;
+
+    return out;
+}
+
+fn instance_vertex(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     let world_local = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
@@ -48,22 +158,53 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
         instance.normal_matrix_1,
         instance.normal_matrix_2,
     );
+    return vertex(
+        model,
+        world_local,
+        normal_rotation,
+        instance.tint,
+        instance.seed,
+    );
+}
 
-    var out: VertexOutput;
-
-    out.tex_coords = model.tex_coord;
-    out.world_normal = normal_rotation * model.normal;
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    return instance_vertex(model, instance);
+}
 
-    let world_position = world_local * vec4<f32>(model.position, 1.0);
-    out.world_position = world_position.xyz;
-    out.clip_position = globals.clip_view * globals.view_world * world_position;
+@vertex
+fn vs_storage(
+    model: VertexInput,
+    @builtin(instance_index) index: u32,
+) -> VertexOutput {
+    let instance = instances[index];
+    return vertex(
+        model,
+        instance.world_local,
+        instance.normal,
+        instance.tint,
+        instance.seed,
+    );
+}
 
+@vertex
+fn vs_extra(
+    model: VertexInput,
+    instance: InstanceInput,
+    extra: VertexExtraInput,
+) -> VertexOutput {
+    var out = instance_vertex(model, instance);
+    out.color = extra.color * vec4<f32>(instance.tint, 1.0);
+    out.tex_coords2 = extra.tex_coord2;
     return out;
 }
 
 struct PointLight {
     position: vec3<f32>,
     radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: u32,
 }
 
 struct PointLightData {
@@ -74,8 +215,232 @@ struct PointLightData {
 @group(1) @binding(0)
 var<storage, read> point_lights: PointLightData;
 
+// Debug isolation, a negative light keeps all of them.
+struct Isolation {
+    light: i32,
+    dim: f32,
+}
+
+@group(1) @binding(1)
+var<uniform> isolation: Isolation;
+
+// Screen tiles and depth slices of the camera, with one in the w of the
+// size when the lights are clustered.
+struct ClusterGrid {
+    size: vec4<u32>,
+    screen: vec2<f32>,
+    near: f32,
+    slice_scale: f32,
+}
+
+@group(1) @binding(2)
+var<uniform> cluster_grid: ClusterGrid;
+// Offset and count into the cluster lights, per cell.
+@group(1) @binding(3)
+var<storage, read> clusters: array<vec2<u32>>;
+@group(1) @binding(4)
+var<storage, read> cluster_lights: array<u32>;
+
+// Range of the lights reaching the fragment, all of them without clusters.
+fn light_range(frag_position: vec2<f32>, world_position: vec3<f32>) -> vec2<u32> {
+    let size = cluster_grid.size;
+    if size.w == 0u {
+        return vec2<u32>(0u, point_lights.len);
+    }
+    let tile = min(
+        vec2<u32>(frag_position / cluster_grid.screen * vec2<f32>(size.xy)),
+        size.xy - 1u,
+    );
+    let depth = -(globals.view_world * vec4<f32>(world_position, 1.0)).z;
+    let slice = min(
+        u32(max(log(depth / cluster_grid.near) * cluster_grid.slice_scale, 0.0)),
+        size.z - 1u,
+    );
+    return clusters[tile.x + tile.y * size.x + slice * size.x * size.y];
+}
+
+fn light_index(range_index: u32) -> u32 {
+    if cluster_grid.size.w == 0u {
+        return range_index;
+    }
+    return cluster_lights[range_index];
+}
+
+// Reflection probe nearest to the camera, without mip levels when none.
+struct ReflectionProbe {
+    position: vec3<f32>,
+    levels: f32,
+}
+
+@group(1) @binding(5)
+var t_probe: texture_cube<f32>;
+@group(1) @binding(6)
+var s_probe: sampler;
+@group(1) @binding(7)
+var<uniform> probe: ReflectionProbe;
+
+// Specular image based lighting from the probe, blurrier down the mips with
+// the roughness, Schlick fresnel toward the albedo of the metals.
+fn probe_specular(
+    world_position: vec3<f32>,
+    normal: vec3<f32>,
+    albedo: vec3<f32>,
+) -> vec3<f32> {
+    if probe.levels == 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let view = globals.view_world;
+    let rotation = mat3x3<f32>(view[0].xyz, view[1].xyz, view[2].xyz);
+    let camera = -(transpose(rotation) * view[3].xyz);
+    let to_fragment = normalize(world_position - camera);
+    let lod = ROUGHNESS * (probe.levels - 1.0);
+    let reflected = textureSampleLevel(
+        t_probe,
+        s_probe,
+        reflect(to_fragment, normal),
+        lod,
+    ).rgb;
+    let f0 = mix(vec3<f32>(0.04), albedo, METALLIC);
+    let facing = saturate(dot(normal, -to_fragment));
+    let fresnel = f0
+        + (max(vec3<f32>(1.0 - ROUGHNESS), f0) - f0) * pow(1.0 - facing, 5.0);
+    return reflected * fresnel;
+}
+
+// Bvh of the entities traced while accumulating, same layout as the path
+// tracer. Internal nodes have their children at first and first + 1.
+struct BvhNode {
+    min: vec3<f32>,
+    first: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+struct BvhTriangle {
+    v0: vec4<f32>,
+    v1: vec4<f32>,
+    v2: vec4<f32>,
+}
+
+// One shadow ray per light and one occlusion ray per frame, averaged by
+// the accumulation into soft shadows and ambient occlusion.
+struct Visibility {
+    frame: u32,
+    traced: u32,
+    light_size: f32,
+    occlusion_distance: f32,
+}
+
+@group(1) @binding(8)
+var<storage, read> bvh_nodes: array<BvhNode>;
+@group(1) @binding(9)
+var<storage, read> bvh_triangles: array<BvhTriangle>;
+@group(1) @binding(10)
+var<uniform> visibility: Visibility;
+
+var<private> rng_state: u32;
+
+fn random() -> f32 {
+    rng_state = rng_state * 747796405u + 2891336453u;
+    let word = ((rng_state >> ((rng_state >> 28u) + 4u)) ^ rng_state)
+        * 277803737u;
+    return f32((word >> 22u) ^ word) / 4294967295.0;
+}
+
+fn hits_aabb(origin: vec3<f32>, inv_dir: vec3<f32>, node: BvhNode, t_max: f32) -> bool {
+    let t0 = (node.min - origin) * inv_dir;
+    let t1 = (node.max - origin) * inv_dir;
+    let near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+    return near <= far && far > 0.0 && near < t_max;
+}
+
+// Moller-Trumbore, whether the triangle is hit before t_max.
+fn hits_triangle(origin: vec3<f32>, dir: vec3<f32>, triangle: BvhTriangle, t_max: f32) -> bool {
+    let edge1 = triangle.v1.xyz - triangle.v0.xyz;
+    let edge2 = triangle.v2.xyz - triangle.v0.xyz;
+    let p = cross(dir, edge2);
+    let det = dot(edge1, p);
+    if abs(det) < 1e-8 {
+        return false;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - triangle.v0.xyz;
+    let u = dot(s, p) * inv_det;
+    let q = cross(s, edge1);
+    let v = dot(dir, q) * inv_det;
+    let t = dot(edge2, q) * inv_det;
+    return u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > 1e-4 && t < t_max;
+}
+
+// Whether any triangle is hit before t_max.
+fn occluded(origin: vec3<f32>, dir: vec3<f32>, t_max: f32) -> bool {
+    let inv_dir = 1.0 / dir;
+    var stack: array<u32, 32>;
+    var top = 1u;
+    stack[0] = 0u;
+    while top > 0u {
+        top -= 1u;
+        let node = bvh_nodes[stack[top]];
+        if !hits_aabb(origin, inv_dir, node, t_max) {
+            continue;
+        }
+        if node.count == 0u {
+            if top < 31u {
+                stack[top] = node.first;
+                stack[top + 1u] = node.first + 1u;
+                top += 2u;
+            }
+            continue;
+        }
+        for (var i = node.first; i < node.first + node.count; i++) {
+            if hits_triangle(origin, dir, bvh_triangles[i], t_max) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// Lit fraction toward a random point of the light, 1 when not accumulating.
+fn light_visibility(position: vec3<f32>, normal: vec3<f32>, light: vec3<f32>) -> f32 {
+    if visibility.traced == 0u {
+        return 1.0;
+    }
+    let offset = vec3<f32>(random(), random(), random()) * 2.0 - 1.0;
+    let to_light = light + offset * visibility.light_size - position;
+    let distance = length(to_light);
+    let dir = to_light / distance;
+    let origin = position + normal * sign(dot(normal, dir)) * 1e-3;
+    return select(1.0, 0.0, occluded(origin, dir, distance));
+}
+
+// Unoccluded fraction of a cosine weighted direction around the normal, 1
+// when not accumulating.
+fn ambient_visibility(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if visibility.traced == 0u {
+        return 1.0;
+    }
+    let angle = 6.28318530718 * random();
+    let r = sqrt(random());
+    let tangent = normalize(select(
+        cross(normal, vec3<f32>(1.0, 0.0, 0.0)),
+        cross(normal, vec3<f32>(0.0, 1.0, 0.0)),
+        abs(normal.x) > 0.9,
+    ));
+    let bitangent = cross(normal, tangent);
+    let dir = normalize(
+        tangent * r * cos(angle) + bitangent * r * sin(angle)
+            + normal * sqrt(max(1.0 - r * r, 0.0))
+    );
+    let origin = position + normal * 1e-3;
+    return select(1.0, 0.0, occluded(origin, dir, visibility.occlusion_distance));
+}
+
 struct SimpleMaterial {
     color: vec3<f32>,
+    hue_variation: f32,
+    brightness_variation: f32,
 }
 
 @group(2) @binding(0)
@@ -84,28 +449,120 @@ var<uniform> uniform: SimpleMaterial;
 var t_diffuse: texture_2d<f32>;
 @group(2) @binding(2)
 var s_diffuse: sampler;
+// Offset in xy and scale in zw of the atlas region.
+@group(2) @binding(3)
+var<uniform> uv_rect: vec4<f32>;
+
+// Bands of the diffuse lighting for a toon look, smooth when zero. Set per
+// material through its constants.
+override TOON_STEPS: f32 = 0.0;
+// Surface of the probe reflections, a rough dielectric by default.
+override METALLIC: f32 = 0.0;
+override ROUGHNESS: f32 = 1.0;
+
+const ATTENUATION_RADIUS: u32 = 0;
+const ATTENUATION_INVERSE_SQUARE: u32 = 1;
+const ATTENUATION_LINEAR: u32 = 2;
+const ATTENUATION_EXPONENTIAL: u32 = 3;
 
 // Radius based attenuation
 // https://lisyarus.github.io/blog/posts/point-light-attenuation.html
-fn attenuate(distance: f32, radius: f32) -> f32 {
+fn attenuate_radius(distance: f32, radius: f32) -> f32 {
     let s = saturate(distance / radius);
     let s2 = s * s;
     let inv_s2 = 1.0 - s2;
     return inv_s2 * inv_s2 / (1.0 + s);
 }
 
+// Physically based inverse square, windowed to reach zero at the radius
+// https://cdn2.unrealengine.com/Resources/files/2013SiggraphPresentationsNotes-26915738.pdf
+fn attenuate_inverse_square(distance: f32, radius: f32) -> f32 {
+    let s = distance / radius;
+    let window = saturate(1.0 - s * s * s * s);
+    return window * window / (distance * distance + 1.0);
+}
+
+fn attenuate_linear(distance: f32, radius: f32) -> f32 {
+    return saturate(1.0 - distance / radius);
+}
+
+// Exponential falloff rescaled to reach zero at the radius
+fn attenuate_exponential(distance: f32, radius: f32) -> f32 {
+    let s = saturate(distance / radius);
+    let tail = exp(-4.0);
+    return (exp(-4.0 * s) - tail) / (1.0 - tail);
+}
+
+fn attenuate(distance: f32, radius: f32, model: u32) -> f32 {
+    switch model {
+        case ATTENUATION_INVERSE_SQUARE: {
+            return attenuate_inverse_square(distance, radius);
+        }
+        case ATTENUATION_LINEAR: {
+            return attenuate_linear(distance, radius);
+        }
+        case ATTENUATION_EXPONENTIAL: {
+            return attenuate_exponential(distance, radius);
+        }
+        default: {
+            return attenuate_radius(distance, radius);
+        }
+    }
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let diffuse_sample = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let ambient = diffuse_sample.xyz * vec3<f32>(0.03);
-    var color = ambient;
+    let tex_coords = in.tex_coords * uv_rect.zw + uv_rect.xy;
+    var diffuse_sample = textureSample(t_diffuse, s_diffuse, tex_coords)
+        * in.color * vec4<f32>(uniform.color, 1.0);
+    let hue = (instance_random(in.seed, 0u) - 0.5) * uniform.hue_variation;
+    let brightness = 1.0
+        - instance_random(in.seed, 1u) * uniform.brightness_variation;
+    diffuse_sample = vec4<f32>(
+        hue_shift(diffuse_sample.rgb, hue) * brightness,
+        diffuse_sample.a,
+    );
+    let normal = normalize(in.world_normal);
+    let pixel = vec2<u32>(in.clip_position.xy);
+    rng_state = (pixel.x * 1973u + pixel.y * 9277u) ^ (visibility.frame * 26699u);
+    var color = vec3<f32>(0.0);
+    if isolation.light < 0 {
+        color = diffuse_sample.xyz * globals.ambient.xyz * globals.ambient.w
+            * ambient_visibility(in.world_position, normal);
+    }
 
-    for (var i: u32 = 0; i < point_lights.len; i++) {
+    let range = light_range(in.clip_position.xy, in.world_position);
+    for (var j: u32 = range.x; j < range.x + range.y; j++) {
+        let i = light_index(j);
+        if isolation.light >= 0 && i32(i) != isolation.light {
+            continue;
+        }
         let point_light = point_lights.data[i];
         let distance = length(point_light.position - in.world_position);
-        let attenuation = attenuate(distance, point_light.radius);
-        color += diffuse_sample.xyz * attenuation;
+        var attenuation = attenuate(
+            distance,
+            point_light.radius,
+            point_light.attenuation,
+        );
+        if TOON_STEPS > 0.0 {
+            attenuation = ceil(attenuation * TOON_STEPS) / TOON_STEPS;
+        }
+        if attenuation > 0.0 {
+            attenuation *= light_visibility(
+                in.world_position,
+                normal,
+                point_light.position,
+            );
+        }
+        color += diffuse_sample.xyz * point_light.color
+            * point_light.intensity * attenuation;
+    }
+    // Metals only reflect, they keep their diffuse without a probe.
+    if isolation.light < 0 && probe.levels > 0.0 {
+        color *= 1.0 - METALLIC;
+        color += probe_specular(in.world_position, normal, diffuse_sample.xyz);
     }
 
-    return vec4<f32>(color, 1.0);
+    color = apply_fog(color, in.world_position);
+    return vec4<f32>(color * isolation.dim, 1.0);
 }
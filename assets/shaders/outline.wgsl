@@ -0,0 +1,93 @@
+struct Globals {
+    clip_view: mat4x4<f32>,
+    view_world: mat4x4<f32>,
+    elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+struct Outline {
+    // Target size in pixels.
+    viewport: vec2<f32>,
+    // Thickness in pixels.
+    width: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> outline: Outline;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(3) model_matrix_0: vec4<f32>,
+    @location(4) model_matrix_1: vec4<f32>,
+    @location(5) model_matrix_2: vec4<f32>,
+    @location(6) model_matrix_3: vec4<f32>,
+    @location(7) normal_matrix_0: vec3<f32>,
+    @location(8) normal_matrix_1: vec3<f32>,
+    @location(9) normal_matrix_2: vec3<f32>,
+    // Outline color.
+    @location(13) tint: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+fn world_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+@vertex
+fn vs_mask(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = globals.clip_view * globals.view_world
+        * world_position(model, instance);
+    out.color = instance.tint;
+    return out;
+}
+
+// Pushed along the normal in screen space, the same thickness at any
+// distance.
+@vertex
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_normal = normal_matrix * model.normal;
+    let clip_world = globals.clip_view * globals.view_world;
+    var clip_position = clip_world * world_position(model, instance);
+    let clip_normal = (clip_world * vec4<f32>(world_normal, 0.0)).xy;
+    if length(clip_normal) > 0.0 {
+        let offset = normalize(clip_normal) * outline.width * 2.0
+            / outline.viewport;
+        clip_position += vec4<f32>(offset * clip_position.w, 0.0, 0.0);
+    }
+
+    var out: VertexOutput;
+    out.clip_position = clip_position;
+    out.color = instance.tint;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -0,0 +1,191 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+struct Exposure {
+    // Multiplier from the exposure value, 2^ev.
+    multiplier: f32,
+    // Blend to black of the scene transitions.
+    fade: f32,
+}
+
+@group(0) @binding(2)
+var<uniform> exposure: Exposure;
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+// ACES filmic curve fitted by Krzysztof Narkowicz
+// https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return saturate((color * (a * color + b)) / (color * (c * color + d) + e));
+}
+
+fn tonemap_color(color: vec3<f32>) -> vec3<f32> {
+    let mapped = aces(max(color, vec3<f32>(0.0)) * exposure.multiplier);
+    return mapped * (1.0 - exposure.fade);
+}
+
+fn load_scene(coords: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(t_scene));
+    let clamped = clamp(coords, vec2<i32>(0), size - 1);
+    return max(textureLoad(t_scene, clamped, 0).rgb, vec3<f32>(0.0));
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return color.b * 0.5 + color.r * 0.5 + color.g;
+}
+
+// Edge direction and length around one of the 4 center texels of the
+// neighbourhood, weighted by its bilinear weight.
+fn easu_edge(
+    lumas: ptr<function, array<f32, 16>>,
+    index: i32,
+    weight: f32,
+) -> vec3<f32> {
+    let center = (*lumas)[index];
+    let left = (*lumas)[index - 1];
+    let right = (*lumas)[index + 1];
+    let up = (*lumas)[index - 4];
+    let down = (*lumas)[index + 4];
+
+    let dir_x = right - left;
+    var len_x = abs(dir_x) / max(max(abs(right - center), abs(center - left)), 1e-5);
+    len_x = saturate(len_x);
+    let dir_y = down - up;
+    var len_y = abs(dir_y) / max(max(abs(down - center), abs(center - up)), 1e-5);
+    len_y = saturate(len_y);
+    return vec3<f32>(dir_x, dir_y, len_x * len_x + len_y * len_y) * weight;
+}
+
+// Edge adaptive upscale of FSR1 (EASU): a lanczos like kernel over 12
+// texels, stretched along the local edge and clamped to the center texels
+// to avoid ringing.
+fn easu(tex_coords: vec2<f32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_scene));
+    let position = tex_coords * size - 0.5;
+    let base = floor(position);
+    let pp = position - base;
+
+    // 4x4 texels around the position, the corners are not used
+    var colors: array<vec3<f32>, 16>;
+    var lumas: array<f32, 16>;
+    for (var i = 0; i < 16; i++) {
+        let offset = vec2<i32>(i % 4 - 1, i / 4 - 1);
+        colors[i] = load_scene(vec2<i32>(base) + offset);
+        lumas[i] = luma(colors[i]);
+    }
+
+    var edge = easu_edge(&lumas, 5, (1.0 - pp.x) * (1.0 - pp.y));
+    edge += easu_edge(&lumas, 6, pp.x * (1.0 - pp.y));
+    edge += easu_edge(&lumas, 9, (1.0 - pp.x) * pp.y);
+    edge += easu_edge(&lumas, 10, pp.x * pp.y);
+
+    var dir = edge.xy;
+    let dir2 = dot(dir, dir);
+    if dir2 < 1.0 / 32768.0 {
+        dir = vec2<f32>(1.0, 0.0);
+    } else {
+        dir *= inverseSqrt(dir2);
+    }
+    var len = edge.z * 0.5;
+    len *= len;
+
+    // Stretch the kernel along the edge, shrink it across
+    let stretch = 1.0 / max(abs(dir.x), abs(dir.y));
+    let len2 = vec2<f32>(1.0 + (stretch - 1.0) * len, 1.0 - 0.5 * len);
+    let lobe = 0.5 + (1.0 / 4.0 - 0.04 - 0.5) * len;
+    let clip = 1.0 / lobe;
+
+    var color = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var i = 0; i < 16; i++) {
+        if i == 0 || i == 3 || i == 12 || i == 15 {
+            continue;
+        }
+        let offset = vec2<f32>(f32(i % 4 - 1), f32(i / 4 - 1)) - pp;
+        let rotated = vec2<f32>(
+            dot(offset, dir),
+            dot(offset, vec2<f32>(-dir.y, dir.x)),
+        ) * len2;
+        let d2 = min(dot(rotated, rotated), clip);
+        let wb = 2.0 / 5.0 * d2 - 1.0;
+        let wa = lobe * d2 - 1.0;
+        let weight = (25.0 / 16.0 * wb * wb - (25.0 / 16.0 - 1.0)) * wa * wa;
+        color += colors[i] * weight;
+        total += weight;
+    }
+
+    let low = min(min(colors[5], colors[6]), min(colors[9], colors[10]));
+    let high = max(max(colors[5], colors[6]), max(colors[9], colors[10]));
+    return clamp(color / total, low, high);
+}
+
+// Contrast adaptive sharpening of FSR1 (RCAS) on the tonemapped colors,
+// done in the same pass with the nearest scene texels as neighbours.
+fn rcas(tex_coords: vec2<f32>, center: vec3<f32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_scene));
+    let coords = vec2<i32>(tex_coords * size);
+    let up = tonemap_color(load_scene(coords + vec2<i32>(0, -1)));
+    let left = tonemap_color(load_scene(coords + vec2<i32>(-1, 0)));
+    let right = tonemap_color(load_scene(coords + vec2<i32>(1, 0)));
+    let down = tonemap_color(load_scene(coords + vec2<i32>(0, 1)));
+
+    let low = min(min(up, left), min(right, down));
+    let high = max(max(up, left), max(right, down));
+    let hit_low = min(low, center) / (4.0 * high + 1e-5);
+    let hit_high = (1.0 - max(high, center)) / (4.0 * low - 4.0 - 1e-5);
+    let lobes = max(-hit_low, hit_high);
+    // Limit and sharpness of the reference, sharpness is 2^-0.2
+    let lobe = max(-0.1875, min(max(lobes.r, max(lobes.g, lobes.b)), 0.0))
+        * 0.87;
+    let sum = up + left + right + down;
+    return saturate((lobe * sum + center) / (4.0 * lobe + 1.0));
+}
+
+fn tonemap(tex_coords: vec2<f32>) -> vec4<f32> {
+    let color = textureSample(t_scene, s_scene, tex_coords);
+    // The scene target is smaller than the pixels it covers when the
+    // dynamic resolution scaled it down, upscale it with FSR1.
+    let texels = dpdx(tex_coords.x) * f32(textureDimensions(t_scene).x);
+    if texels > 0.999 {
+        return vec4<f32>(tonemap_color(color.rgb), color.a);
+    }
+    let upscaled = tonemap_color(easu(tex_coords));
+    return vec4<f32>(rcas(tex_coords, upscaled), color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = tonemap(in.tex_coords);
+    return vec4<f32>(linear_to_srgb(color.rgb), color.a);
+}
+
+// The surface does the sRGB encoding
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    return tonemap(in.tex_coords);
+}
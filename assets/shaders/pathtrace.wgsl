@@ -0,0 +1,266 @@
+struct Uniforms {
+    world_clip: mat4x4<f32>,
+    camera_pos: vec3<f32>,
+    // Frames accumulated before this one, 0 restarts the average.
+    frame: u32,
+    size: vec2<u32>,
+    light_count: u32,
+    bounces: u32,
+}
+
+// Internal nodes have their children at first and first + 1, leaves have
+// count triangles from first.
+struct Node {
+    min: vec3<f32>,
+    first: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+// Corners in world space, the albedo in their w.
+struct Triangle {
+    v0: vec4<f32>,
+    v1: vec4<f32>,
+    v2: vec4<f32>,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    attenuation: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+@group(0) @binding(1)
+var<storage, read> nodes: array<Node>;
+@group(0) @binding(2)
+var<storage, read> triangles: array<Triangle>;
+@group(0) @binding(3)
+var<storage, read> lights: array<PointLight>;
+// Sum of the samples, their count in w.
+@group(0) @binding(4)
+var<storage, read_write> accumulation: array<vec4<f32>>;
+@group(0) @binding(5)
+var output: texture_storage_2d<rgba16float, write>;
+
+const PI: f32 = 3.14159265359;
+const FAR: f32 = 1e30;
+const EPSILON: f32 = 1e-4;
+// Light coming from every direction, the raster ambient term.
+const SKY: vec3<f32> = vec3<f32>(0.03);
+
+const ATTENUATION_INVERSE_SQUARE: u32 = 1;
+const ATTENUATION_LINEAR: u32 = 2;
+const ATTENUATION_EXPONENTIAL: u32 = 3;
+
+// Same falloffs as the model shader.
+fn attenuate(distance: f32, radius: f32, model: u32) -> f32 {
+    let s = saturate(distance / radius);
+    switch model {
+        case ATTENUATION_INVERSE_SQUARE: {
+            let window = saturate(1.0 - s * s * s * s);
+            return window * window / (distance * distance + 1.0);
+        }
+        case ATTENUATION_LINEAR: {
+            return 1.0 - s;
+        }
+        case ATTENUATION_EXPONENTIAL: {
+            let tail = exp(-4.0);
+            return (exp(-4.0 * s) - tail) / (1.0 - tail);
+        }
+        default: {
+            let inv_s2 = 1.0 - s * s;
+            return inv_s2 * inv_s2 / (1.0 + s);
+        }
+    }
+}
+
+var<private> rng_state: u32;
+
+// PCG hash, https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+fn random() -> f32 {
+    rng_state = rng_state * 747796405u + 2891336453u;
+    let word = ((rng_state >> ((rng_state >> 28u) + 4u)) ^ rng_state)
+        * 277803737u;
+    return f32((word >> 22u) ^ word) / 4294967295.0;
+}
+
+struct Hit {
+    distance: f32,
+    normal: vec3<f32>,
+    albedo: vec3<f32>,
+}
+
+fn intersect_aabb(
+    origin: vec3<f32>,
+    inv_dir: vec3<f32>,
+    min_corner: vec3<f32>,
+    max_corner: vec3<f32>,
+    t_max: f32,
+) -> f32 {
+    let t0 = (min_corner - origin) * inv_dir;
+    let t1 = (max_corner - origin) * inv_dir;
+    let near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+    if near <= far && far > 0.0 && near < t_max {
+        return max(near, 0.0);
+    }
+    return FAR;
+}
+
+// Moller-Trumbore, FAR when missed.
+fn intersect_triangle(
+    origin: vec3<f32>,
+    dir: vec3<f32>,
+    triangle: Triangle,
+) -> f32 {
+    let edge1 = triangle.v1.xyz - triangle.v0.xyz;
+    let edge2 = triangle.v2.xyz - triangle.v0.xyz;
+    let p = cross(dir, edge2);
+    let det = dot(edge1, p);
+    if abs(det) < 1e-8 {
+        return FAR;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - triangle.v0.xyz;
+    let u = dot(s, p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return FAR;
+    }
+    let q = cross(s, edge1);
+    let v = dot(dir, q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return FAR;
+    }
+    let t = dot(edge2, q) * inv_det;
+    return select(FAR, t, t > EPSILON);
+}
+
+// Closest triangle before t_max, any one when shadow is set.
+fn trace(origin: vec3<f32>, dir: vec3<f32>, t_max: f32, shadow: bool) -> Hit {
+    var hit = Hit(t_max, vec3<f32>(0.0), vec3<f32>(0.0));
+    var closest = 0u;
+    var found = false;
+    let inv_dir = 1.0 / dir;
+    var stack: array<u32, 32>;
+    var top = 1u;
+    stack[0] = 0u;
+    while top > 0u {
+        top -= 1u;
+        let node = nodes[stack[top]];
+        if intersect_aabb(origin, inv_dir, node.min, node.max, hit.distance) >= FAR {
+            continue;
+        }
+        if node.count == 0u {
+            if top < 31u {
+                stack[top] = node.first;
+                stack[top + 1u] = node.first + 1u;
+                top += 2u;
+            }
+            continue;
+        }
+        for (var i = node.first; i < node.first + node.count; i++) {
+            let t = intersect_triangle(origin, dir, triangles[i]);
+            if t < hit.distance {
+                hit.distance = t;
+                closest = i;
+                found = true;
+                if shadow {
+                    return hit;
+                }
+            }
+        }
+    }
+    if found {
+        let triangle = triangles[closest];
+        let normal = normalize(cross(
+            triangle.v1.xyz - triangle.v0.xyz,
+            triangle.v2.xyz - triangle.v0.xyz,
+        ));
+        hit.normal = select(normal, -normal, dot(normal, dir) > 0.0);
+        hit.albedo = vec3<f32>(triangle.v0.w, triangle.v1.w, triangle.v2.w);
+    } else {
+        hit.distance = FAR;
+    }
+    return hit;
+}
+
+// Cosine weighted direction around the normal.
+fn sample_hemisphere(normal: vec3<f32>) -> vec3<f32> {
+    let angle = 2.0 * PI * random();
+    let r = sqrt(random());
+    let tangent = normalize(select(
+        cross(normal, vec3<f32>(1.0, 0.0, 0.0)),
+        cross(normal, vec3<f32>(0.0, 1.0, 0.0)),
+        abs(normal.x) > 0.9,
+    ));
+    let bitangent = cross(normal, tangent);
+    return normalize(
+        tangent * r * cos(angle) + bitangent * r * sin(angle)
+            + normal * sqrt(max(1.0 - r * r, 0.0))
+    );
+}
+
+fn direct_light(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var light = vec3<f32>(0.0);
+    for (var i = 0u; i < uniforms.light_count; i++) {
+        let to_light = lights[i].position - position;
+        let distance = length(to_light);
+        if distance >= lights[i].radius {
+            continue;
+        }
+        let dir = to_light / distance;
+        let facing = dot(normal, dir);
+        if facing <= 0.0 {
+            continue;
+        }
+        if trace(position, dir, distance, true).distance < FAR {
+            continue;
+        }
+        light += lights[i].color * lights[i].intensity * facing
+            * attenuate(distance, lights[i].radius, lights[i].attenuation);
+    }
+    return light;
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= uniforms.size.x || id.y >= uniforms.size.y {
+        return;
+    }
+    let index = id.y * uniforms.size.x + id.x;
+    rng_state = index * 1973u + uniforms.frame * 9277u + 26699u;
+
+    // Jittered inside the pixel, the average is antialiased.
+    let pixel = vec2<f32>(id.xy) + vec2<f32>(random(), random());
+    let ndc = pixel / vec2<f32>(uniforms.size) * vec2<f32>(2.0, -2.0)
+        + vec2<f32>(-1.0, 1.0);
+    let far = uniforms.world_clip * vec4<f32>(ndc, 1.0, 1.0);
+    var origin = uniforms.camera_pos;
+    var dir = normalize(far.xyz / far.w - origin);
+
+    var radiance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var bounce = 0u; bounce <= uniforms.bounces; bounce++) {
+        let hit = trace(origin, dir, FAR, false);
+        if hit.distance >= FAR {
+            radiance += throughput * SKY;
+            break;
+        }
+        let position = origin + dir * hit.distance + hit.normal * EPSILON;
+        throughput *= hit.albedo;
+        radiance += throughput * direct_light(position, hit.normal);
+        origin = position;
+        dir = sample_hemisphere(hit.normal);
+    }
+
+    var sum = vec4<f32>(radiance, 1.0);
+    if uniforms.frame > 0u {
+        sum += accumulation[index];
+    }
+    accumulation[index] = sum;
+    textureStore(output, vec2<i32>(id.xy), vec4<f32>(sum.rgb / sum.w, 1.0));
+}
@@ -0,0 +1,115 @@
+struct Globals {
+    clip_view: mat4x4<f32>,
+    view_world: mat4x4<f32>,
+    elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+struct Draw {
+    color: vec4<f32>,
+    viewport: vec2<f32>,
+    size: f32,
+    world: u32,
+}
+
+@group(1) @binding(0)
+var<uniform> draw: Draw;
+
+struct Point {
+    position: vec3<f32>,
+    // Packed rgba8.
+    color: u32,
+}
+
+@group(1) @binding(1)
+var<storage, read> points: array<Point>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position in the quad, from -1 to 1.
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+// Two triangles covering a quad.
+fn quad_corner(index: u32) -> vec2<f32> {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    return corners[index];
+}
+
+@vertex
+fn vs_points(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let corner = quad_corner(vertex_index % 6u);
+    let point = points[vertex_index / 6u];
+    var view_position = globals.view_world * vec4<f32>(point.position, 1.0);
+
+    var out: VertexOutput;
+    out.corner = corner;
+    out.color = draw.color * unpack4x8unorm(point.color);
+    if draw.world == 1u {
+        view_position += vec4<f32>(corner * draw.size * 0.5, 0.0, 0.0);
+        out.clip_position = globals.clip_view * view_position;
+    } else {
+        let clip = globals.clip_view * view_position;
+        let offset = corner * draw.size / draw.viewport * clip.w;
+        out.clip_position = clip + vec4<f32>(offset, 0.0, 0.0);
+    }
+    return out;
+}
+
+// Each segment joins two consecutive positions, expanded in screen space.
+@vertex
+fn vs_lines(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let corner = quad_corner(vertex_index % 6u);
+    let segment = vertex_index / 6u;
+    let start = globals.clip_view * globals.view_world
+        * vec4<f32>(points[segment].position, 1.0);
+    let end = globals.clip_view * globals.view_world
+        * vec4<f32>(points[segment + 1u].position, 1.0);
+
+    let screen_start = start.xy / start.w * draw.viewport;
+    let screen_end = end.xy / end.w * draw.viewport;
+    let dir = normalize(screen_end - screen_start + vec2<f32>(1e-6, 0.0));
+    let normal = vec2<f32>(-dir.y, dir.x);
+
+    let clip = select(start, end, corner.x > 0.0);
+    let offset = normal * corner.y * draw.size / draw.viewport * clip.w;
+
+    var out: VertexOutput;
+    out.corner = corner;
+    out.color = draw.color * unpack4x8unorm(points[segment].color);
+    out.clip_position = clip + vec4<f32>(offset, 0.0, 0.0);
+    return out;
+}
+
+@fragment
+fn fs_points(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.corner);
+    let edge = fwidth(distance);
+    let alpha = 1.0 - smoothstep(1.0 - edge, 1.0, distance);
+    if alpha <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * alpha);
+}
+
+@fragment
+fn fs_lines(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = abs(in.corner.y);
+    let edge = fwidth(distance);
+    let alpha = 1.0 - smoothstep(1.0 - edge, 1.0, distance);
+    return vec4<f32>(in.color.rgb, in.color.a * alpha);
+}
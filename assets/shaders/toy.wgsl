@@ -0,0 +1,36 @@
+// Fullscreen shader, see `graphics:set_fullscreen_shader`. Positions are in
+// pixels from the top left, like the fragment position.
+struct Toy {
+    resolution: vec2<f32>,
+    // Seconds since the start and since the last frame.
+    time: f32,
+    time_delta: f32,
+    // Cursor while the left button is held in xy, last click in zw,
+    // negative once released.
+    mouse: vec4<f32>,
+    frame: u32,
+    // Whether the surface expects the color encoded to sRGB.
+    encode_srgb: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> toy: Toy;
+
+fn output(color: vec3<f32>) -> vec4<f32> {
+    if toy.encode_srgb == 0u {
+        return vec4<f32>(color, 1.0);
+    }
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return vec4<f32>(select(high, low, color <= vec3<f32>(0.0031308)), 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = position.xy / toy.resolution;
+    var color = 0.5 + 0.5 * cos(toy.time + uv.xyx + vec3<f32>(0.0, 2.0, 4.0));
+    let mouse = toy.mouse.xy / toy.resolution;
+    let glow = 0.02 / max(distance(uv, mouse), 0.02);
+    color = color * color + select(0.0, glow, toy.mouse.z > 0.0);
+    return output(color);
+}
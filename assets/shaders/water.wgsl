@@ -0,0 +1,60 @@
+// Effect plane, see `scene:batch_effect_plane`. Params are the color in
+// xyz and the opacity in w, then the wave scale and speed.
+struct Globals {
+    clip_view: mat4x4<f32>,
+    view_world: mat4x4<f32>,
+    elapsed: f32,
+    // Velocity in xyz, gustiness in w.
+    wind: vec4<f32>,
+    // Gust frequency, scale and time.
+    gusts: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+
+struct Draw {
+    world: mat4x4<f32>,
+    params: array<vec4<f32>, 4>,
+}
+
+@group(1) @binding(0)
+var<uniform> draw: Draw;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // From 0 to 1 across the plane.
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let uv = corners[vertex_index];
+    let local = vec4<f32>(uv.x - 0.5, 0.0, uv.y - 0.5, 1.0);
+
+    var out: VertexOutput;
+    out.uv = uv;
+    out.clip_position =
+        globals.clip_view * globals.view_world * draw.world * local;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = draw.params[0];
+    let scale = max(draw.params[1].x, 1.0);
+    let time = globals.elapsed * draw.params[1].y;
+    let p = in.uv * scale;
+    let waves = sin(p.x * 6.0 + time) * sin(p.y * 5.0 - time * 1.3)
+        + 0.5 * sin((p.x + p.y) * 11.0 + time * 2.1);
+    let highlight = smoothstep(0.6, 1.2, waves);
+    return vec4<f32>(color.xyz + highlight * 0.4, color.w);
+}
@@ -6,6 +6,7 @@ declare class Vec3
   function __sub(self, other: Vec3 | number): Vec3
   function __mul(self, other: Vec3 | number): Vec3
   function __div(self, other: Vec3 | number): Vec3
+  function copy(self): Vec3
 end
 
 declare Vec3: {
@@ -14,6 +15,8 @@ declare Vec3: {
   Z: Vec3,
   new: (x: number, y: number, z: number) -> Vec3,
   splat: (val: number) -> Vec3,
+  -- Reused from the next frame, copy it to keep it.
+  temp: (x: number, y: number, z: number) -> Vec3,
 }
 
 declare class Quat
@@ -23,6 +26,25 @@ declare Quat: {
   default: () -> Quat
 }
 
+declare class Mat4
+  function inverse(self): Mat4
+  function transpose(self): Mat4
+  function transform_point(self, point: Vec3): Vec3
+  function transform_vector(self, vector: Vec3): Vec3
+  function __mul(self, other: Mat4): Mat4
+  function __mul(self, point: Vec3): Vec3
+end
+
+declare Mat4: {
+  IDENTITY: Mat4,
+  from_transform: (transform: Transform) -> Mat4,
+  translation: (translation: Vec3) -> Mat4,
+  scale: (scale: Vec3) -> Mat4,
+  rotation: (axis: Vec3, angle: number) -> Mat4,
+  look_at: (eye: Vec3, target: Vec3, up: Vec3) -> Mat4,
+  perspective: (fovy: number, aspect_ratio: number, znear: number, zfar: number) -> Mat4,
+}
+
 declare class Transform
   pos: Vec3
   rot: Quat
@@ -31,21 +53,184 @@ declare class Transform
   function right(self): Vec3
   function rotate(self, axis: Vec3, angle: number): ()
   function rotate_local(self, axis: Vec3, angle: number): ()
+  -- Bounds of a mesh drawn with this transform, the box stays axis aligned.
+  function transformed_bounds(self, bounds: Bounds): Bounds
 end
 
 declare Transform: {
   new: (pos: Vec3) -> Transform,
 }
 
+declare class Bounds
+  min: Vec3
+  max: Vec3
+  -- Bounding sphere, around the box center.
+  center: Vec3
+  radius: number
+  function contains(self, point: Vec3): boolean
+  function intersects(self, other: Bounds): boolean
+end
+
+type CameraController = "orbit" | "fly" | "follow"
+
 declare class Camera
 	transform: Transform
 	fovy: number
+	controller: CameraController?
+	function attach_controller(self, kind: CameraController, options: {
+		target: (Vec3 | Entity)?,
+		distance: number?,
+		offset: Vec3?,
+		speed: number?,
+		sensitivity: number?,
+		damping: number?,
+	}?): ()
+	function detach_controller(self): ()
+	path: ("recording" | "playing")?
+	-- Keyframes saved to assets/camera_paths/<name>.json when stopped.
+	function record_path(self, name: string): ()
+	-- Returns the number of keyframes saved.
+	function stop_record(self): number
+	-- Replaces the controller while playing.
+	function play_path(self, name: string, options: {
+		speed: number?,
+		loop: boolean?,
+	}?): ()
+	function stop_path(self): ()
+end
+
+type Attenuation = "radius" | "inverse_square" | "linear" | "exponential"
+
+type PointLight = {
+  pos: Vec3,
+  radius: number,
+  color: Vec3?,
+  intensity: number?,
+  attenuation: Attenuation?,
+}
+
+type InstanceSort = "back_to_front" | "front_to_back" | "axis"
+
+-- A number alone is the seed.
+type BatchOptions = {
+  seed: number?,
+  -- Multiplies the vertex color.
+  tint: Vec3?,
+  -- Four numbers free for the material shader.
+  custom: { number }?,
+  -- "default" unless named, a scene has at most 32 layers.
+  layer: string?,
+  -- Color of an outline drawn around the model, over the scene.
+  outline: Vec3?,
+}
+
+type PickHit = {
+  mesh: string,
+  material: string,
+  instance: number,
+  distance: number,
+  point: Vec3,
+  normal: Vec3,
+}
+
+declare class PointBuffer
+  len: number
+end
+
+declare PointBuffer: {
+  new: (points: { Vec3 }) -> PointBuffer,
+}
+
+declare class Entity
+  id: number
+  data: { [string]: any }
+  function constrain_look_at(self, target: Entity | Vec3): ()
+  function constrain_follow_path(self, path: { Vec3 }, speed: number): ()
+  function copy_position(self, source: Entity, offset: Vec3?): ()
+  function clear_constraints(self): ()
+  -- Move the transform field to the target over the duration, from its
+  -- value once the frame's update is done.
+  function tween(self, field: "pos" | "scale", to: Vec3, duration: number, easing: Easing?): TaskHandle
+  -- The entity is rendered between its targets, a delay behind the elapsed
+  -- time given to update, and extrapolated shortly past the last one.
+  function push_target_transform(self, transform: Transform, timestamp: number): ()
+  -- Seconds behind the elapsed time, 0.1 by default.
+  function set_interpolation_delay(self, delay: number): ()
+  function clear_target_transforms(self): ()
+  -- Call update(state, ctx, entity, dt) of the required module every
+  -- frame, an error disables it until the module reloads.
+  function attach_script(self, module: string, state: { [string]: any }?): ()
+  function detach_script(self, module: string): ()
 end
 
 declare class Scene
   camera: Camera
-  function batch_model(self, mesh: string, material: string?, transform: Transform): ()
-  function point_light(self, pos: Vec3, radius: number): ()
+  -- Another camera, drawn once graphics:set_viewport gives it a rect.
+  function add_camera(self, name: string): Camera
+  function remove_camera(self, name: string): ()
+  -- The seed defaults to one hashed from the position, entities use their id.
+  -- Without a material an obj with .mtl materials is drawn with its own, as
+  -- the "mesh/material" meshes and materials.
+  function batch_model(self, mesh: string, material: string?, transform: Transform | Mat4, options: (number | BatchOptions)?): ()
+  -- Uploaded once and drawn every frame until freed, replacing the batch of
+  -- the same id. Not culled, nor swapped for lods, impostors or obj parts.
+  function create_static_batch(self, id: string, mesh: string, material: string?, transforms: { Transform | Mat4 }): ()
+  -- Whether there was a static batch of the id.
+  function free_static_batch(self, id: string): boolean
+  -- Unit quad on the xz plane blended over the scene this frame, the shader
+  -- has a vs_main and fs_main, the params are its 16 draw.params values.
+  function batch_effect_plane(self, shader: string, transform: Transform | Mat4, params: { number }?): ()
+  -- Draw order within the batch, kept across frames, nil to clear.
+  function set_instance_sort(self, mesh: string, material: string?, sort: InstanceSort?, axis: Vec3?): ()
+  function spawn(self, mesh: string, material: string?, transform: Transform?): Entity
+  function despawn(self, entity: Entity): ()
+  function transform(self, entity: Entity): Transform?
+  function set_transform(self, entity: Entity, transform: Transform): ()
+  -- The transform becomes relative to the parent, nil to detach.
+  function set_parent(self, entity: Entity, parent: Entity?): ()
+  function parent(self, entity: Entity): Entity?
+  -- Weight of a morph target of the entity mesh, zero removes it.
+  function set_morph_weight(self, entity: Entity, name: string, weight: number): ()
+  function morph_weight(self, entity: Entity, name: string): number?
+  function local_to_world(self, entity: Entity): Mat4?
+  -- Replaces the camera and entities, returns the saved data and the lights to submit again.
+  function load(self, path: string): (any, { PointLight })
+  -- A position and radius, or a table with a color and intensity, white at 1 by default.
+  function point_light(self, light: Vec3 | PointLight, radius: number?, attenuation: Attenuation?): ()
+  -- Light of the models away from the point lights, 0.03 grey by default.
+  function set_ambient(self, color: Vec3, intensity: number?): ()
+  -- Linear between the start and end distances, or exponential with a
+  -- density, nil to clear.
+  function set_fog(self, fog: { color: Vec3, start: number, ["end"]: number } | { color: Vec3, density: number } | nil): ()
+  -- Cubemap of the scene around the position, 128 pixels a face by default, rendered once then only when refreshed.
+  function add_reflection_probe(self, pos: Vec3, resolution: number?): number
+  -- Seconds between two renders of the probe, nil to only render it when refreshed.
+  function set_probe_interval(self, probe: number, interval: number?): ()
+  function refresh_probe(self, probe: number): ()
+  function set_probe_pos(self, probe: number, pos: Vec3): ()
+  function remove_reflection_probe(self, probe: number): ()
+  function points(self, buffer: PointBuffer, size: number, color: Vec3?, options: {
+    world: boolean?,
+  }?): ()
+  function lines(self, buffer: PointBuffer, width: number, color: Vec3?): ()
+  function pick(self, x: number, y: number): PickHit?
+  function rope(self, a: Vec3 | Transform, b: Vec3 | Transform, options: {
+    segments: number?,
+    radius: number?,
+    length: number?,
+    material: string?,
+    key: string?,
+  }?): ()
+  -- Writes the camera, the lights submitted so far this frame and the entities as json.
+  function save(self, path: string, data: any?): ()
+  -- Loads assets/scenes/<name>.json and its meshes in the background, then swaps it in like load.
+  function switch_to(self, name: string, options: {
+    transition: ("cut" | "fade")?,
+    duration: number?,
+    on_switch: ((ctx: Context, data: any, lights: { PointLight }) -> ())?,
+  }?): ()
+  -- Name of the scene being switched to, until its transition ends.
+  function switching(self): string?
 end
 
 type Action = "forward" | "backward" | "right" | "left" | "up" | "down" | "focus"| "interact"
@@ -53,29 +238,440 @@ type Action = "forward" | "backward" | "right" | "left" | "up" | "down" | "focus
 declare class Inputs
   function cursor_in_window(self): boolean
   function focused(self): boolean
-  function pressed(self, action: Action): boolean
-  function just_pressed(self, action: Action): boolean
+  function pressed(self, action: Action | string): boolean
+  function just_pressed(self, action: Action | string): boolean
+  -- Keys by name, like "W", "1", "Space" or "ShiftLeft", listed in the F1 help.
+  function register_action(self, action: string, keys: { string }): ()
   function mouse_pressed(self, button: "left" | "right"): boolean
   function mouse_just_pressed(self, button: "left" | "right"): boolean
+  function cursor_position(self): { x: number, y: number }
   function mouse_delta(self): { x: number, y: number }
+  function scroll_delta(self): number
 end
 
 declare class Window 
   function grab_cursor(self): ()
   function release_cursor(self): ()
+  function set_icon(self, texture: string): ()
+  function request_attention(self, kind: ("informational" | "critical")?): ()
+  function set_title(self, title: string): ()
+  -- Logical size, the platform can refuse it.
+  function set_size(self, width: number, height: number): ()
+  -- Borderless on the current monitor.
+  function set_fullscreen(self, enabled: boolean): ()
+  function fullscreen(self): boolean
+  function set_decorations(self, enabled: boolean): ()
+  function set_on_top(self, on_top: boolean): ()
+  -- From 0 to 1, nil to clear, shown in the title.
+  function set_progress(self, progress: number?): ()
+end
+
+-- Shared by the shaders (`wind_at` in model.wgsl), the ropes and the
+-- scripts, gusts travel along the direction.
+declare class Wind
+  function set(self, direction: Vec3, strength: number): ()
+  -- Relative strength change, and gusts per second passing a point.
+  function set_gusts(self, gustiness: number, frequency: number): ()
+  function direction(self): Vec3
+  function strength(self): number
+  -- Air velocity at a world position, for the scripted effects.
+  function at(self, position: Vec3): Vec3
+end
+
+declare class BufferData
+  len: number
+  function f32(self, index: number): number?
+  function u32(self, index: number): number?
+  function i32(self, index: number): number?
+  function to_f32(self): { number }
+  function to_u32(self): { number }
+  function to_i32(self): { number }
+  -- Raw little endian content, for string.unpack.
+  function bytes(self): string
 end
 
+type MaterialParam = number | Vec3 | { number }
+
+declare class Material
+  key: string
+  -- Applied before the frame is rendered, unknown fields are logged.
+  function set(self, name: string, value: MaterialParam): ()
+end
+
+type ScreenshotDiff = {
+  created: boolean,
+  different_pixels: number,
+  max_difference: number,
+  mean_difference: number,
+  heatmap: string,
+}
+
 declare class Graphics
+  -- "cube", "sphere", "plane", "cylinder", "cone" and "torus" are built in.
   function load_mesh(self, mesh: string): ()
-  function add_material(self, material_type: "simple", data: { key: string } & any): ()
+  -- Kind is "mesh" by default, "texture" or "shader".
+  function is_loaded(self, id: string, kind: string?): boolean
+  -- Called once the asset loads or fails to, on the next frame when it
+  -- already did.
+  function on_loaded(self, id: string, callback: (ctx: Context, payload: { kind: string, id: string, failed: boolean }) -> (), kind: string?): ()
+  -- Queue the assets together for loading_progress.
+  function preload(self, assets: { meshes: { string }?, textures: { string }?, shaders: { string }? }): ()
+  -- Fraction of the preloaded assets done loading, failed ones included.
+  function loading_progress(self): number
+  -- Meshes, textures and shaders loaded for the first time last frame.
+  function loaded(self): { { kind: string, id: string } }
+  -- Local space bounds, nil until the mesh is loaded or when it has no vertices.
+  function mesh_bounds(self, mesh: string): Bounds?
+  -- Names of the morph targets, nil until the mesh is loaded.
+  function morph_targets(self, mesh: string): { string }?
+  function load_lods(self, model: string, meshes: { string }, distances: { number }): ()
+  -- Bake views around the mesh drawn with the material, instances farther
+  -- than the distance are drawn as a billboard of the closest view.
+  function bake_impostor(self, mesh: string, material: string, options: {
+    distance: number?,
+    size: number?,
+    views: number?,
+  }?): ()
+  function remove_impostor(self, mesh: string): ()
+  function load_point_cloud(self, point_cloud: string): ()
+  function point_cloud(self, point_cloud: string): { PointBuffer }?
+  function load_heightfield(self, tile: string): ()
+  function build_terrain(self, mesh: string, options: {
+    tiles: { { string } },
+    overlap: boolean?,
+    spacing: number?,
+    vertical_scale: number?,
+  }): boolean
+  function terrain_height(self, mesh: string, x: number, z: number): number?
+  -- Constants set the shader's `override` declarations, e.g. { TOON_STEPS = 4 }.
+  -- Custom materials declare the uniform struct of their shader as "name: type"
+  -- fields, with f32, vec2, vec3 or vec4 types.
+  function add_material(self, material_type: "simple" | "custom", data: {
+    key: string,
+    shader: string?,
+    texture: string?,
+    constants: { [string]: number }?,
+    block: { string }?,
+  }): Material
+  function material(self, material_key: string): Material?
   function material_data(self, material_key: string, data: any): ()
+  function set_material_param(self, material_key: string, name: string, value: MaterialParam): ()
+  -- Replaced when the texture's .ron sampler file next to the image changes.
+  function set_texture_sampler(self, texture: string, options: {
+    filter: ("linear" | "nearest")?,
+    mipmap_filter: ("linear" | "nearest")?,
+    wrap: ("repeat" | "clamp" | "mirror")?,
+    anisotropy: number?,
+  }): ()
+  -- Rect as { x, y, width, height } fractions of the window, clipped to it. The scene camera is drawn first,
+  -- the added cameras over it in name order. Nil resets the scene camera and hides the others.
+  function set_viewport(self, camera: Camera, rect: { number }?): ()
+  function set_render_mode(self, mode: "lit" | "wireframe" | "normals" | "depth"): ()
+  -- Scales the scene with the gpu frame time, upscaled with FSR1. Needs timestamp queries.
+  function set_dynamic_resolution(self, enabled: boolean, options: {
+    min: number?,
+    max: number?,
+    target_fps: number?,
+  }?): ()
+  -- Draw the lit meshes depth first, shading each visible fragment once.
+  function set_depth_prepass(self, enabled: boolean): ()
+  -- Draw counts are read from a buffer written once per frame.
+  -- Batches sharing their mesh and material become one multi draw when the adapter supports it.
+  function set_indirect_draws(self, enabled: boolean): ()
+  -- The path tracer traces the entities with the color of their simple
+  -- material, one sample per pixel and frame while the camera is still.
+  function set_renderer(self, renderer: "raster" | "pathtrace", options: {
+    bounces: number?,
+  }?): ()
+  function renderer(self): "raster" | "pathtrace"
+  -- Samples per pixel averaged by the path tracer so far.
+  function traced_samples(self): number
+  -- Jittered frames averaged while the camera, instances and lights are
+  -- still, restarts when one changes. Each frame traces a shadow ray toward
+  -- a random point within light_size of each light (0.2 by default) and an
+  -- ambient occlusion ray up to occlusion_distance (1 by default) against
+  -- the entities, the average softens the shadows.
+  function set_accumulation(self, enabled: boolean, options: {
+    max_frames: number?,
+    light_size: number?,
+    occlusion_distance: number?,
+  }?): ()
+  -- Frames averaged so far, nil when disabled.
+  function accumulation(self): number?
+  -- Restart the average, for changes done in the shaders.
+  function reset_accumulation(self): ()
+  -- Adapter picked with --backend, --adapter and --low-power.
+  function adapter_info(self): {
+    name: string,
+    backend: "vulkan" | "metal" | "dx12" | "gl" | "webgpu" | "empty",
+    device_type: string,
+    driver: string,
+    limits: {
+      max_texture_dimension_2d: number,
+      max_buffer_size: number,
+      max_storage_buffer_binding_size: number,
+      max_bind_groups: number,
+      max_vertex_attributes: number,
+    },
+  }
+  -- Fifo waits for vsync, immediate and mailbox do not.
+  function set_present_mode(self, mode: "immediate" | "mailbox" | "fifo"): ()
+  function present_mode(self): "immediate" | "mailbox" | "fifo"
+  -- Hidden layers are neither drawn nor picked.
+  function set_layer_visible(self, layer: string, visible: boolean): ()
+  function layer_visible(self, layer: string): boolean
+  -- Frames per second drawn at most, nil for no cap.
+  function set_frame_cap(self, fps: number?): ()
+  -- Low skips the depth prepass, dynamic resolution stays opt-in.
+  function set_quality(self, preset: "low" | "medium" | "high"): ()
+  function quality(self): ("low" | "medium" | "high")?
+  function compare_screenshot(self, golden: string, callback: (ctx: Context, diff: ScreenshotDiff) -> (), threshold: number?): ()
+  -- Write the next seconds of frames, without the ui, as an animated png.
+  function record_animation(self, path: string, seconds: number, options: {
+    fps: number?,
+    width: number?,
+    caption: string?,
+  }?): ()
+  function recording(self): boolean
+  function set_memory_budget(self, megabytes: number?): ()
+  function isolate_light(self, index: number?): ()
+  function isolate_material(self, material: string?): ()
+  -- Fragment shader drawn over the whole window in place of the scene, nil
+  -- to go back to the scene.
+  function set_fullscreen_shader(self, shader: string?): ()
+  function fullscreen_shader(self): string?
+  -- Exposure value in stops applied before the ACES tonemapping.
+  function set_exposure(self, ev: number): ()
+  function exposure(self): number
+  function create_buffer(self, name: string, size: number): ()
+  function write_buffer(self, name: string, values: { number }, offset: number?): ()
+  function read_buffer(self, name: string, callback: (ctx: Context, data: BufferData) -> ()): ()
+  -- Texture id of the top-down view, "@minimap", size in pixels from 1 to the
+  -- largest texture size.
+  function minimap(self, options: {
+    size: number?,
+    extent: number?,
+    height: number?,
+    include: { string }?,
+  }?): string?
+end
+
+declare class App
+  fixed_rate: number
+  -- Only detected on Linux, always false elsewhere.
+  on_battery: boolean
+  paused: boolean
+  time_scale: number
+  function set_fixed_rate(self, rate: number): ()
+  -- Scales the dt and elapsed time of the scripts and shaders, F5 pauses and
+  -- F6 steps a frame. The camera controller keeps the real time. Capped at 100.
+  function set_time_scale(self, scale: number): ()
+  function pause(self): ()
+  function resume(self): ()
+  -- Pauses then runs the next frame with a dt of the fixed rate.
+  function step_frame(self): ()
+  -- Writes every frame of the next seconds into the folder as numbered pngs,
+  -- the scripts see a dt of 1 / fps meanwhile. Encoded into video.mp4 when
+  -- ffmpeg is installed.
+  function export_frames(self, dir: string, options: {
+    fps: number?,
+    duration: number?,
+  }?): ()
+  -- Frame rates, 0 removes one. Unfocused windows only draw on events until unfocused_fps
+  -- is set, battery_quality is the preset switched to on battery.
+  function set_power_mode(self, options: {
+    max_fps: number?,
+    battery_fps: number?,
+    battery_quality: ("low" | "medium" | "high")?,
+    unfocused_fps: number?,
+    hidden_fps: number?,
+  }): ()
+  function create_window(self, options: {
+    title: string?,
+    size: { number }?,
+    render: ((ctx: Context, camera: Camera) -> ())?,
+  }): Camera
+end
+
+declare class Ui
+  -- Same as F3, frame time graph and per pipeline instances.
+  function show_stats(self, visible: boolean): ()
+  function window(self, title: string, f: (panel: UiPanel) -> ()): ()
+end
+
+-- Only valid during the window callback. Widgets sharing a label keep their own value.
+declare class UiPanel
+  function label(self, text: string): ()
+  function button(self, label: string): boolean
+  function checkbox(self, label: string, default: boolean?): boolean
+  function slider(self, label: string, min: number, max: number, default: number?): number
+end
+
+declare class Audio
+  function play(self, sound: string): ()
+  function play_at(self, sound: string, pos: Vec3): ()
+end
+
+declare class Mic
+  function level(self): number
+  function spectrum(self, bands: number?): { number }
+end
+
+declare class RigidBody end
+
+type RaycastHit = {
+  distance: number,
+  normal: Vec3,
+  point: Vec3,
+  body: RigidBody?,
+}
+
+declare class Physics
+  function add_rigid_body(self, transform: Transform, options: {
+    shape: ("box" | "sphere" | "capsule")?,
+    size: Vec3?,
+    mass: number?,
+    fixed: boolean?,
+  }?): RigidBody
+  function remove_rigid_body(self, body: RigidBody): ()
+  function clear(self): ()
+  function raycast(self, origin: Vec3, dir: Vec3, max_dist: number): RaycastHit?
+end
+
+declare class Stats
+  culled: number
+  -- Model draw calls, in every pass and window.
+  draw_calls: number
+  entities: number
+  -- Seconds between the last two frames.
+  frame_time: number
+  -- Last frame times, the oldest first.
+  frame_times: { number }
+  -- Model instances batched, culled or not.
+  instances: number
+  lights: number
+  surface_format: string
+  colorspace: "srgb" | "linear"
+  srgb_conversion: boolean
+  gpu_time: number?
+  render_scale: number
+  mesh_memory: number
+  texture_memory: number
+  -- Bytes of the model instance, material and indirect buffers.
+  buffer_memory: number
+  -- Model instances by shader.
+  pipelines: { [string]: number }
+  -- Bytes of the meshes and textures, render targets are not counted.
+  vram_estimate: number
+  -- Summary shown by the F3 overlay.
+  function hud_line(self): string
+end
+
+export type Collider =
+  { type: "sphere", pos: Vec3, r: number }
+  | { type: "aabb", min: Vec3, max: Vec3 }
+  | { type: "capsule", a: Vec3, b: Vec3, r: number }
+
+export type SweepHit = {
+  id: string,
+  distance: number,
+  -- Away from the collider hit.
+  normal: Vec3,
+}
+
+declare class Collision
+  -- Replaces the collider of the same id, move a collider by adding it again.
+  function add(self, id: string, collider: Collider): ()
+  function remove(self, id: string): ()
+  function clear(self): ()
+  -- Ids of the other colliders touching this one.
+  function overlaps(self, id: string): { string }
+  -- First collider met moving this one along the direction, up to the
+  -- distance. The collider itself does not move.
+  function sweep(self, id: string, dir: Vec3, distance: number): SweepHit?
 end
 
 export type Context = {
+  app: App,
   scene: Scene,
   inputs: Inputs,
   window: Window,
+  wind: Wind,
+  collision: Collision,
   graphics: Graphics,
+  audio: Audio,
+  mic: Mic,
+  ui: Ui,
+  physics: Physics,
+  stats: Stats,
 }
 
 declare function cached_table(id: string): any
+
+-- A function maps the time in [0, 1] to the progress, linear when it does
+-- not return a number.
+type Easing = "linear" | "ease_in" | "ease_out" | "ease_in_out" | (t: number) -> number
+
+declare class TaskHandle
+  function running(self): boolean
+  function cancel(self): ()
+end
+
+-- Run the function as a coroutine once the update and components of the
+-- frame are done, or from the next frame when scheduled by a task or an
+-- event handler. It is stopped when the script defining it reloads.
+declare function schedule(task: (ctx: Context) -> ()): TaskHandle
+-- Inside a scheduled task, resume on the first frame the seconds have
+-- elapsed by, or on the next frame without, with the context of that frame.
+declare function wait(seconds: number?): Context
+-- Move an owned vector to the target over the duration. A transform field
+-- only lives for its frame, tween the entity with entity:tween instead.
+declare function tween(target: Vec3, to: Vec3, duration: number, easing: Easing?): TaskHandle
+
+-- Kept across reloads, values are copied in and out so a value changed in
+-- place must be assigned again.
+declare persist: { [string]: any }
+
+declare shared: {
+  get: (key: string) -> any,
+  set: (key: string, value: any) -> (),
+  update: (key: string, update: (value: any) -> any) -> any,
+  watch: (key: string, watcher: (value: any, old: any) -> ()) -> (),
+  unwatch: (key: string) -> (),
+}
+
+declare class EventHandle end
+
+-- Handlers run after the update with the context and payload, in the order
+-- they were added. Calling on again from the same line replaces the handler,
+-- a reloaded script drops the handlers it registered. off removes the
+-- handler of the handle, or every handler of the calling script for the
+-- event. The engine emits
+-- "window_resized" { width, height }, "file_dropped" { path },
+-- "asset_imported" { kind, id }, "asset_loaded" { kind, id, failed },
+-- "asset_failed" { kind, id, failed } and "asset_reloaded" { kind, id }, kind
+-- being mesh, texture, shader or script.
+declare events: {
+  emit: (self: any, name: string, payload: any?) -> (),
+  on: (self: any, name: string, handler: (ctx: Context, payload: any) -> ()) -> EventHandle,
+  off: (self: any, name: string, handle: EventHandle?) -> (),
+}
+
+declare engine: {
+  -- Command line arguments, the mesh id for the viewer.
+  args: { string },
+  dump_api: (path: string) -> (),
+  -- Assets under root/<kind> become loadable as "namespace:id".
+  mount: (namespace: string, root: string) -> (),
+  -- Vec3 operators return temporaries reused from the next frame.
+  set_math_arena: (enabled: boolean) -> (),
+}
+
+-- Plays assets/timelines/<name>.json after update, one timeline at a time.
+declare timeline: {
+  play: (self: any, name: string, options: { loop: boolean? }?) -> (),
+  stop: (self: any) -> (),
+  -- Name of the timeline playing.
+  playing: (self: any) -> string?,
+  time: (self: any) -> number?,
+}
@@ -1,20 +1,40 @@
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Once};
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use log::info;
+use glam::Vec2;
+use log::{error, info};
 use threadpool::ThreadPool;
 use winit::application::ApplicationHandler;
-use winit::dpi::{LogicalPosition, LogicalSize};
+use winit::dpi::LogicalSize;
 use winit::event::WindowEvent;
-use winit::event_loop::{ActiveEventLoop, EventLoopProxy};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoopProxy};
 use winit::keyboard::KeyCode;
-use winit::window::{Window, WindowId, WindowLevel};
+use winit::window::{Window, WindowId};
 
+use crate::assets;
+#[cfg(feature = "audio")]
+use crate::audio::Audio;
+use crate::collision::Collisions;
+use crate::events::EngineEvent;
 use crate::input::{Inputs, UserEvent};
-use crate::lua::LuaState;
-use crate::render::state::RenderState;
+use crate::lua::{limits::Limits, Context, LuaState};
+#[cfg(feature = "mic")]
+use crate::mic::Mic;
+#[cfg(feature = "physics")]
+use crate::physics::Physics;
+use crate::power::{Pacing, Power};
+use crate::render::{
+    adapter::AdapterOptions, bundle::globals::Environment,
+    export::ExportSettings, state::RenderState,
+};
 use crate::scene::Scene;
+#[cfg(feature = "ui")]
+use crate::ui::Ui;
+use crate::wind::Wind;
+use crate::window::WindowOptions;
 
 pub const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
 
@@ -30,30 +50,161 @@ pub fn get_pool() -> Arc<ThreadPool> {
     }
 }
 
+/// Secondary window asked by the scripts, created from the event loop.
+#[derive(Debug, Clone)]
+pub struct WindowRequest {
+    pub size: [u32; 2],
+    pub title: String,
+}
+
+/// App wide settings the scripts can change.
+#[derive(Debug)]
+pub struct AppState {
+    accumulator: f32,
+    /// Engine events for the scripts, handled after their update.
+    pub events: Vec<EngineEvent>,
+    /// Frame time while exporting frames, instead of the measured one.
+    pub export_dt: Option<f32>,
+    pub export_requests: Vec<ExportSettings>,
+    /// Frames are exported or still being written.
+    pub exporting: bool,
+    pub fixed_rate: f32,
+    /// Scripts and shaders see no time passing, the camera still moves.
+    pub paused: bool,
+    pub power: Power,
+    /// A frame of the fixed rate runs while paused.
+    step: bool,
+    /// Multiplies the frame time seen by the scripts and shaders.
+    pub time_scale: f32,
+    pub window_requests: Vec<WindowRequest>,
+}
+
+impl AppState {
+    /// Steps allowed in a frame, the simulation slows down past it.
+    const MAX_FIXED_STEPS: u32 = 5;
+    /// Past it the fixed steps cap and the frame time lose their meaning.
+    pub const MAX_TIME_SCALE: f32 = 100.0;
+
+    pub fn fixed_dt(&self) -> f32 {
+        1.0 / self.fixed_rate
+    }
+
+    /// Accumulate the frame time, returns how many fixed steps to run.
+    pub fn fixed_steps(&mut self, dt: f32) -> u32 {
+        let fixed_dt = self.fixed_dt();
+        self.accumulator = (self.accumulator + dt)
+            .min(fixed_dt * Self::MAX_FIXED_STEPS as f32);
+        let steps = (self.accumulator / fixed_dt) as u32;
+        self.accumulator -= steps as f32 * fixed_dt;
+        steps
+    }
+
+    /// Frame time once scaled, zero while paused unless stepping a frame.
+    pub fn scaled_dt(&mut self, dt: f32) -> f32 {
+        let dt = self.export_dt.unwrap_or(dt);
+        if self.paused {
+            return match std::mem::take(&mut self.step) {
+                true => self.fixed_dt(),
+                false => 0.0,
+            };
+        }
+        dt * self.time_scale
+    }
+
+    /// Pause and run the next frame only.
+    pub fn step_frame(&mut self) {
+        self.paused = true;
+        self.step = true;
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            accumulator: 0.0,
+            events: Vec::new(),
+            export_dt: None,
+            export_requests: Vec::new(),
+            exporting: false,
+            fixed_rate: 60.0,
+            paused: false,
+            power: Power::new(),
+            step: false,
+            time_scale: 1.0,
+            window_requests: Vec::new(),
+        }
+    }
+}
+
 pub struct App {
+    adapter_options: AdapterOptions,
+    app_state: AppState,
+    #[cfg(feature = "audio")]
+    audio: Audio,
+    collision: Collisions,
     current: Instant,
     elapsed: Duration,
+    /// Write the luau definitions there after the init, then exit.
+    emit_defs: Option<PathBuf>,
+    focused: bool,
     inputs: Inputs,
+    /// Minimized or occluded, nothing is drawn.
+    hidden: bool,
     lua: LuaState,
-    not_on_top: bool,
+    #[cfg(feature = "mic")]
+    mic: Mic,
+    #[cfg(feature = "physics")]
+    physics: Physics,
     proxy: EventLoopProxy<UserEvent>,
+    /// A window event arrived while only drawing on events.
+    redraw_on_event: bool,
     render_state: Option<RenderState>,
     scene: Scene,
+    #[cfg(feature = "ui")]
+    ui: Option<Ui>,
+    wind: Wind,
     window: Option<Arc<Window>>,
+    window_options: WindowOptions,
 }
 
 impl App {
-    pub fn new(proxy: EventLoopProxy<UserEvent>, not_on_top: bool) -> Self {
+    /// The arguments left after the adapter flags are handed to the entry
+    /// point script.
+    pub fn new(
+        proxy: EventLoopProxy<UserEvent>,
+        adapter_options: AdapterOptions,
+        window_options: WindowOptions,
+        entry_point: &str,
+        args: Vec<String>,
+        emit_defs: Option<PathBuf>,
+        limits: &Limits,
+    ) -> Self {
         Self {
-            not_on_top,
+            adapter_options,
+            app_state: AppState::default(),
+            #[cfg(feature = "audio")]
+            audio: Audio::new(),
+            collision: Collisions::default(),
             current: Instant::now(),
             elapsed: Duration::default(),
+            emit_defs,
+            focused: true,
+            hidden: false,
             inputs: Inputs::default(),
-            lua: LuaState::new("main"),
+            lua: LuaState::new(entry_point, args, limits),
+            #[cfg(feature = "mic")]
+            mic: Mic::new(),
+            #[cfg(feature = "physics")]
+            physics: Physics::new(),
             proxy,
+            redraw_on_event: false,
             render_state: None,
             scene: Scene::new(),
+            #[cfg(feature = "ui")]
+            ui: None,
+            wind: Wind::default(),
             window: None,
+            window_options,
         }
     }
 
@@ -70,71 +221,267 @@ impl App {
 
         self.render_state = Some(pollster::block_on(RenderState::new(
             self.window.clone().unwrap(),
-        )));
-        self.lua.init(
-            &mut self.scene,
-            &self.inputs,
-            self.window.clone().unwrap(),
-            self.render_state.as_mut().unwrap(),
-        )?;
+            &self.adapter_options,
+        ))?);
+        #[cfg(feature = "ui")]
+        {
+            self.ui = Some(Ui::new(self.window.as_ref().unwrap()));
+        }
+        self.lua.init(&mut Context {
+            app: &mut self.app_state,
+            #[cfg(feature = "audio")]
+            audio: &mut self.audio,
+            collision: &mut self.collision,
+            inputs: &mut self.inputs,
+            #[cfg(feature = "mic")]
+            mic: &mut self.mic,
+            #[cfg(feature = "physics")]
+            physics: &mut self.physics,
+            render_state: self.render_state.as_mut().unwrap(),
+            scene: &mut self.scene,
+            #[cfg(feature = "ui")]
+            ui: self.ui.as_mut().unwrap(),
+            wind: &mut self.wind,
+            window: self.window.clone().unwrap(),
+        })?;
+        if let Some(path) = self.emit_defs.take() {
+            self.lua.emit_defs(&path)?;
+            self.proxy.send_event(UserEvent::ExitApp)?;
+        }
 
         Ok(())
     }
 
-    pub fn update(&mut self) -> Result<()> {
-        let delta = self.current.elapsed();
-        self.elapsed += delta;
+    pub fn update(&mut self, draw: bool) -> Result<()> {
+        let real_delta_sec = self.current.elapsed().as_secs_f32();
         self.current = Instant::now();
-        let delta_sec = delta.as_secs_f32();
-        let elapsed_sec = self.elapsed.as_secs_f32();
 
         self.inputs.update();
-        let render_state = self.render_state.as_mut().unwrap();
+        if self.inputs.key_just_pressed(KeyCode::F5) {
+            self.app_state.paused = !self.app_state.paused;
+        }
+        if self.inputs.key_just_pressed(KeyCode::F6) {
+            self.app_state.step_frame();
+        }
+        let exporter = &self.render_state.as_ref().unwrap().exporter;
+        // A frame that is not drawn is not exported, the exported clock
+        // does not move for it.
+        self.app_state.export_dt =
+            exporter.frame_dt().map(|dt| if draw { dt } else { 0.0 });
+        self.app_state.exporting = exporter.is_exporting();
+        let delta_sec = self.app_state.scaled_dt(real_delta_sec);
+        self.elapsed += Duration::from_secs_f32(delta_sec);
+        let elapsed_sec = self.elapsed.as_secs_f32();
+        #[cfg(feature = "mic")]
+        self.mic.update();
         self.scene.begin_frame();
+        self.wind.time = elapsed_sec;
+        let window = self.window.clone().unwrap();
+        #[cfg(feature = "ui")]
+        self.ui.as_mut().unwrap().begin_frame(&window);
+        let size = window.inner_size();
+        self.scene.viewport =
+            Vec2::new(size.width as f32, size.height as f32).max(Vec2::ONE);
 
         if self.inputs.key_just_pressed(KeyCode::Escape) {
             self.proxy.send_event(UserEvent::ExitApp)?;
         }
-        if self.inputs.key_just_pressed(KeyCode::KeyR) {
-            self.lua.init(
-                &mut self.scene,
-                &self.inputs,
-                self.window.clone().unwrap(),
-                render_state,
-            )?;
+        #[cfg(feature = "ui")]
+        if self.inputs.key_just_pressed(KeyCode::F1) {
+            let ui = self.ui.as_mut().unwrap();
+            ui.show_help = !ui.show_help;
         }
+        #[cfg(feature = "ui")]
+        if self.inputs.key_just_pressed(KeyCode::F3) {
+            let ui = self.ui.as_mut().unwrap();
+            ui.show_stats = !ui.show_stats;
+        }
+        self.scene.update_camera(&self.inputs, real_delta_sec);
+        let mut context = Context {
+            app: &mut self.app_state,
+            #[cfg(feature = "audio")]
+            audio: &mut self.audio,
+            collision: &mut self.collision,
+            inputs: &mut self.inputs,
+            #[cfg(feature = "mic")]
+            mic: &mut self.mic,
+            #[cfg(feature = "physics")]
+            physics: &mut self.physics,
+            render_state: self.render_state.as_mut().unwrap(),
+            scene: &mut self.scene,
+            #[cfg(feature = "ui")]
+            ui: self.ui.as_mut().unwrap(),
+            wind: &mut self.wind,
+            window: self.window.clone().unwrap(),
+        };
+        if context.inputs.key_just_pressed(KeyCode::KeyR) {
+            self.lua.init(&mut context)?;
+        }
+        if context.app.power.update() {
+            let on_battery = context.app.power.on_battery;
+            info!("Running on battery: {}", on_battery);
+            let quality = context.render_state.quality();
+            if let Some(quality) = context.app.power.switch_quality(quality) {
+                context.render_state.set_quality(quality);
+            }
+            self.lua.power_changed(&mut context, on_battery);
+        }
+        self.lua.deliver_readbacks(&mut context);
+        self.lua.deliver_screenshots(&mut context);
 
-        self.lua.update(
-            &mut self.scene,
-            &self.inputs,
-            self.window.clone().unwrap(),
-            render_state,
-            delta_sec,
-            elapsed_sec,
-        )?;
+        let steps = context.app.fixed_steps(delta_sec);
+        let fixed_dt = context.app.fixed_dt();
+        for _ in 0..steps {
+            self.lua.fixed_update(&mut context, fixed_dt)?;
+            #[cfg(feature = "physics")]
+            context.physics.step(fixed_dt);
+        }
+        #[cfg(feature = "physics")]
+        if steps > 0 {
+            self.lua.sync_physics(context.physics);
+        }
+
+        self.lua.update(&mut context, delta_sec, elapsed_sec)?;
+        self.lua.run_components(&mut context, delta_sec);
+        self.lua.run_scheduler(&mut context, delta_sec);
+        self.lua.dispatch_events(&mut context);
+        self.lua.play_timeline(&mut context, delta_sec);
+        self.lua.switch_scene(&mut context, delta_sec);
+        self.lua.render_windows(&mut context);
+        self.lua.sync_viewports(context.render_state);
+        self.lua.apply_material_params(context.render_state);
+        for settings in context.app.export_requests.drain(..) {
+            if let Err(err) = context.render_state.exporter.start(settings) {
+                error!("frame export\n{:?}", err);
+            }
+        }
+        // Sizes requested by the scripts can apply without a resize event.
+        let size = window.inner_size();
+        if size.width > 0
+            && size.height > 0
+            && size != context.render_state.surface_size()
+        {
+            context.render_state.resize(size);
+        }
+        #[cfg(feature = "ui")]
+        {
+            context.ui.help(context.inputs);
+            context.ui.stats(&context.render_state.stats);
+            context.ui.script_errors(&self.lua.script_errors());
+            context.ui.end_frame(&window);
+        }
+        self.scene.ropes.simulate(delta_sec, &self.wind);
+        self.lua
+            .apply_interpolation(&mut self.scene.entities, elapsed_sec);
+        self.lua
+            .apply_constraints(&mut self.scene.entities, delta_sec);
+        let render_state = self.render_state.as_mut().unwrap();
+        render_state
+            .bundles
+            .fullscreen
+            .track_mouse(self.inputs.cursor_pos, self.inputs.mouse_pressed(0));
+        render_state.prepare_morphs(&mut self.scene);
+        self.scene.batch_entities();
+        #[cfg(feature = "audio")]
+        self.audio.update(&self.scene.camera.transform);
 
         render_state.hot_reload();
-        render_state.render(elapsed_sec, &mut self.scene);
+        self.app_state.events.extend(render_state.take_loaded());
+        self.app_state.events.extend(render_state.take_reloaded());
+        if !draw {
+            return Ok(());
+        }
+        let environment = Environment {
+            ambient: self.scene.ambient,
+            elapsed: elapsed_sec,
+            fog: self.scene.fog,
+            wind: self.wind,
+        };
+        render_state.render(
+            &environment,
+            &mut self.scene,
+            #[cfg(feature = "ui")]
+            self.ui.as_mut().unwrap(),
+        );
 
         Ok(())
     }
+
+    fn create_windows(&mut self, event_loop: &ActiveEventLoop) {
+        let render_state = self.render_state.as_mut().unwrap();
+        for request in self.app_state.window_requests.drain(..) {
+            if render_state.has_view(&request.title) {
+                continue;
+            }
+            let [width, height] = request.size;
+            let result = event_loop
+                .create_window(
+                    Window::default_attributes()
+                        .with_title(&request.title)
+                        .with_inner_size(LogicalSize::new(width, height)),
+                )
+                .map_err(anyhow::Error::from)
+                .and_then(|window| {
+                    render_state.add_view(&request.title, Arc::new(window))
+                });
+            if let Err(err) = result {
+                error!("Could not create window {}: {}", request.title, err);
+            }
+        }
+    }
+
+    /// Copy a dropped asset file and start loading it, the scripts get its
+    /// id to use it right away.
+    fn import_dropped(&mut self, path: &Path) {
+        let (kind, id) = match assets::import_dropped(path) {
+            Ok(imported) => imported,
+            Err(err) => {
+                error!("import {}\n{:?}", path.display(), err);
+                return;
+            }
+        };
+        info!("Imported {} {}", kind, id);
+        let render_state = self.render_state.as_mut().unwrap();
+        match kind {
+            "mesh" => render_state.meshes.load(&id),
+            "texture" => render_state.textures.load(&id),
+            "shader" => render_state.shaders.load(&id),
+            // Required by the scripts.
+            _ => (),
+        }
+        self.app_state
+            .events
+            .push(EngineEvent::AssetImported { kind, id });
+    }
+
+    /// Time spent paused does not count in the next frame.
+    fn set_hidden(&mut self, hidden: bool) {
+        if self.hidden && !hidden {
+            self.current = Instant::now();
+        }
+        self.hidden = hidden;
+    }
+
+    /// Events of the secondary windows, inputs only come from the main one.
+    fn view_window_event(&mut self, id: WindowId, event: WindowEvent) {
+        let render_state = self.render_state.as_mut().unwrap();
+        match event {
+            WindowEvent::CloseRequested => {
+                if let Some(name) = render_state.remove_view(id) {
+                    self.lua.close_window(&name);
+                }
+            }
+            WindowEvent::Resized(size) => render_state.resize_view(id, size),
+            _ => (),
+        }
+    }
 }
 
 impl ApplicationHandler<UserEvent> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_level = match self.not_on_top {
-            true => WindowLevel::Normal,
-            false => WindowLevel::AlwaysOnTop,
-        };
         self.window = Some(Arc::new(
             event_loop
-                .create_window(
-                    Window::default_attributes()
-                        .with_title("bloup")
-                        .with_inner_size(LogicalSize::new(720, 550))
-                        .with_position(LogicalPosition::new(880, 0))
-                        .with_window_level(window_level),
-                )
+                .create_window(self.window_options.attributes())
                 .expect("Could not create window"),
         ));
         self.init().unwrap();
@@ -161,31 +508,102 @@ impl ApplicationHandler<UserEvent> for App {
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        _id: WindowId,
+        id: WindowId,
         event: WindowEvent,
     ) {
+        if self.window.as_ref().is_some_and(|window| window.id() != id) {
+            self.view_window_event(id, event);
+            return;
+        }
+        #[cfg(feature = "ui")]
+        let consumed = match (&mut self.ui, &self.window) {
+            (Some(ui), Some(window)) => ui.on_window_event(window, &event),
+            _ => false,
+        };
+        #[cfg(not(feature = "ui"))]
+        let consumed = false;
         match event {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             }
             WindowEvent::Resized(size) => {
-                self.render_state.as_mut().unwrap().resize(size);
+                let minimized = size.width == 0 || size.height == 0;
+                self.set_hidden(minimized);
+                if !minimized {
+                    self.render_state.as_mut().unwrap().resize(size);
+                    self.app_state.events.push(EngineEvent::WindowResized {
+                        width: size.width,
+                        height: size.height,
+                    });
+                }
+            }
+            WindowEvent::Occluded(occluded) => self.set_hidden(occluded),
+            WindowEvent::DroppedFile(ref path) => {
+                info!("File dropped: {}", path.display());
+                self.import_dropped(path);
+                self.app_state
+                    .events
+                    .push(EngineEvent::FileDropped(path.clone()));
             }
             WindowEvent::RedrawRequested => {
-                self.update().unwrap();
-                self.window.clone().unwrap().request_redraw();
+                self.update(!self.hidden).unwrap();
+                self.create_windows(event_loop);
             }
             WindowEvent::Focused(is_focused) => {
+                self.focused = is_focused;
                 if !is_focused {
                     self.inputs.focus_out();
                 }
             }
             _ => (),
         }
-        self.inputs.on_window_event(event);
+        if event != WindowEvent::RedrawRequested {
+            self.redraw_on_event = true;
+        }
+        if !consumed {
+            self.inputs.on_window_event(event);
+        }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        self.window.clone().unwrap().request_redraw();
+    /// Draw continuously, at a capped rate or only after window events,
+    /// following the power settings. Hidden windows are updated without
+    /// drawing, or paused.
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let window = self.window.clone().unwrap();
+        let frame_cap = self.render_state.as_ref().unwrap().frame_cap;
+        match self
+            .app_state
+            .power
+            .pacing(self.focused, self.hidden)
+            .with_cap(frame_cap)
+        {
+            Pacing::Continuous => {
+                event_loop.set_control_flow(ControlFlow::Poll);
+                window.request_redraw();
+            }
+            Pacing::Capped(interval) => {
+                let next = self.current + interval;
+                if Instant::now() >= next {
+                    window.request_redraw();
+                }
+                event_loop.set_control_flow(ControlFlow::WaitUntil(next));
+            }
+            Pacing::OnEvents => {
+                event_loop.set_control_flow(ControlFlow::Wait);
+                if mem::take(&mut self.redraw_on_event) {
+                    window.request_redraw();
+                }
+            }
+            Pacing::Hidden(interval) => {
+                if Instant::now() >= self.current + interval {
+                    self.update(false).unwrap();
+                    self.create_windows(event_loop);
+                }
+                event_loop.set_control_flow(ControlFlow::WaitUntil(
+                    self.current + interval,
+                ));
+            }
+            Pacing::Paused => event_loop.set_control_flow(ControlFlow::Wait),
+        }
     }
 }
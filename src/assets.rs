@@ -0,0 +1,372 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::{bail, Context, Result};
+use assets_manager::AssetCache;
+use log::warn;
+
+/// Directories under `assets`, one per kind of asset.
+const KINDS: [&str; 6] = [
+    "heightfields",
+    "meshes",
+    "point_clouds",
+    "shaders",
+    "sounds",
+    "textures",
+];
+
+/// Files that can be dropped on the window by extension, with the directory
+/// they are copied into and the kind of asset they become.
+const DROPPED: [(&[&str], &str, &str); 4] = [
+    (&["obj", "glb", "gltf"], "meshes", "mesh"),
+    (&["png", "jpeg"], "textures", "texture"),
+    (&["wgsl"], "shaders", "shader"),
+    (&["luau"], "scripts", "script"),
+];
+
+/// Statements of an mtl naming a texture file, as their last argument.
+const MTL_TEXTURES: [&str; 5] = ["bump", "decal", "disp", "norm", "refl"];
+
+/// Project directory and the directory under it holding one directory per
+/// kind, set by the project.
+static ROOT: OnceLock<(PathBuf, PathBuf)> = OnceLock::new();
+
+/// `assets` unless a project set another root.
+pub fn root() -> &'static Path {
+    ROOT.get().map_or(Path::new("assets"), |(_, root)| root)
+}
+
+/// Scripts `require` modules by their path from it, the working directory
+/// unless a project set another one.
+pub fn project_dir() -> &'static Path {
+    ROOT.get().map_or(Path::new(""), |(dir, _)| dir)
+}
+
+/// Must be set before any cache is created.
+pub fn set_root(project_dir: PathBuf, root: PathBuf) {
+    if ROOT.set((project_dir, root)).is_err() {
+        warn!("Asset root already set");
+    }
+}
+
+/// Meshes are parsed from their bytes, a gltf must embed its buffers and
+/// images.
+fn check_embedded(path: &Path) -> Result<()> {
+    let gltf = gltf::Gltf::open(path)?;
+    let external = |uri: &str| !uri.starts_with("data:");
+    let buffers = gltf.buffers().filter_map(|buffer| match buffer.source() {
+        gltf::buffer::Source::Uri(uri) => Some(uri),
+        gltf::buffer::Source::Bin => None,
+    });
+    let images = gltf.images().filter_map(|image| match image.source() {
+        gltf::image::Source::Uri { uri, .. } => Some(uri),
+        gltf::image::Source::View { .. } => None,
+    });
+    if let Some(uri) = buffers.chain(images).find(|uri| external(uri)) {
+        bail!("external file {} unsupported, use a glb", uri);
+    }
+    Ok(())
+}
+
+/// Arguments of the statements of an obj or mtl file matching the keyword.
+fn statements(
+    path: &Path,
+    keyword: impl Fn(&str) -> bool,
+) -> Result<Vec<Vec<String>>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("reading {}", path.display()))?;
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let statement = words.next()?;
+            keyword(statement).then(|| words.map(str::to_string).collect())
+        })
+        .collect())
+}
+
+/// Path inside the directory it is relative to, none when it leaves it.
+fn inner_path(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path.replace('\\', "/"));
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(path)
+}
+
+/// Materials referenced by an obj with `mtllib`, copied to the same path
+/// next to it, and their textures copied into the textures directory where
+/// the mtl lookup by file name finds them. Conflicting materials fail the
+/// import, what can not be copied is left behind with a warning.
+fn obj_companions(obj: &Path, dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let obj_dir = obj.parent().unwrap_or(Path::new(""));
+    let textures_dir = root().join("textures");
+    let mut companions = Vec::new();
+    let mtls = statements(obj, |statement| statement == "mtllib")?;
+    for mtl in mtls.into_iter().flatten() {
+        let from = obj_dir.join(&mtl);
+        let Some(relative) = inner_path(&mtl).filter(|_| from.is_file()) else {
+            warn!("Material {} of {} left behind", mtl, obj.display());
+            continue;
+        };
+        let to = dir.join(relative);
+        if to.exists() && !same_contents(&from, &to)? {
+            bail!("{} already exists", to.display());
+        }
+        let textures = statements(&from, |statement| {
+            statement.starts_with("map_") || MTL_TEXTURES.contains(&statement)
+        })?;
+        for texture in textures.iter().filter_map(|arguments| arguments.last())
+        {
+            // Relative to the obj, as the mtl is read.
+            let texture_from = obj_dir.join(texture.replace('\\', "/"));
+            let supported = texture_from
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    ["png", "jpeg"].contains(&ext.to_lowercase().as_str())
+                });
+            let Some(name) = texture_from
+                .file_name()
+                .filter(|_| supported && texture_from.is_file())
+            else {
+                warn!("Texture {} of {} left behind", texture, mtl);
+                continue;
+            };
+            let texture_to = textures_dir.join(name);
+            if !texture_to.exists() {
+                companions.push((texture_from, texture_to));
+            } else if !same_contents(&texture_from, &texture_to)? {
+                warn!(
+                    "Texture {} of {} not copied, {} already exists",
+                    texture,
+                    mtl,
+                    texture_to.display()
+                );
+            }
+        }
+        if !to.exists() {
+            companions.push((from, to));
+        }
+    }
+    Ok(companions)
+}
+
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    Ok(fs::read(a)? == fs::read(b)?)
+}
+
+/// First id not taken by another file, suffixed by a number. The id of a
+/// file with the same contents is reused, returned with false as there is
+/// nothing to copy.
+fn free_id(
+    dir: &Path,
+    id: &str,
+    extension: &str,
+    from: &Path,
+) -> Result<(String, bool)> {
+    for n in 1.. {
+        let candidate = match n {
+            1 => id.to_string(),
+            n => format!("{}_{}", id, n),
+        };
+        let to = dir.join(&candidate).with_extension(extension);
+        if !to.exists() {
+            return Ok((candidate, true));
+        }
+        if same_contents(from, &to)? {
+            return Ok((candidate, false));
+        }
+    }
+    unreachable!()
+}
+
+/// Copy a file dropped on the window into the directory of its kind, with
+/// the materials and textures it references. Returns the kind of asset and its id, a file
+/// already in that directory is used in place. Existing assets are never
+/// overwritten, the dropped file gets a new id instead.
+pub fn import_dropped(path: &Path) -> Result<(&'static str, String)> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let Some((_, dir, kind)) = DROPPED
+        .iter()
+        .find(|(extensions, _, _)| extensions.contains(&extension.as_str()))
+    else {
+        bail!("unsupported file {}", path.display());
+    };
+    let stem = path
+        .file_stem()
+        .context("file without a name")?
+        .to_string_lossy();
+    // Dots separate the directories of the ids.
+    let id = stem.replace('.', "_");
+    let dir = root().join(dir);
+    let in_place = path
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .zip(dir.canonicalize().ok())
+        .is_some_and(|(parent, dir)| parent == dir && id == stem);
+    if in_place {
+        return Ok((kind, id));
+    }
+    if extension == "gltf" {
+        check_embedded(path)?;
+    }
+
+    let companions = match extension.as_str() {
+        "obj" => obj_companions(path, &dir)?,
+        _ => Vec::new(),
+    };
+
+    fs::create_dir_all(&dir)?;
+    let copy = |from: &Path, to: PathBuf| -> Result<()> {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, &to).with_context(|| {
+            format!("copying {} to {}", from.display(), to.display())
+        })?;
+        Ok(())
+    };
+    let (id, new) = free_id(&dir, &id, &extension, path)?;
+    if new {
+        copy(path, dir.join(&id).with_extension(&extension))?;
+    }
+    for (from, to) in companions {
+        copy(&from, to)?;
+    }
+    Ok((kind, id))
+}
+
+/// Asset directories by namespace and kind, a namespace mounted from a root
+/// has one entry per kind under it.
+static MOUNTS: Mutex<BTreeMap<(String, String), PathBuf>> =
+    Mutex::new(BTreeMap::new());
+
+/// Make the assets under `root` available as `namespace:id`.
+pub fn mount(namespace: &str, root: &Path) -> Result<()> {
+    if !root.is_dir() {
+        bail!("{} is not a directory", root.display());
+    }
+    for kind in KINDS {
+        mount_dir(namespace, kind, &root.join(kind))?;
+    }
+    Ok(())
+}
+
+/// Make the assets of one kind in `dir` available as `namespace:id`.
+pub fn mount_dir(namespace: &str, kind: &str, dir: &Path) -> Result<()> {
+    if namespace.is_empty() || namespace.contains([':', '/']) {
+        bail!("invalid namespace {:?}", namespace);
+    }
+    let mut mounts = MOUNTS.lock().unwrap();
+    let key = (namespace.to_string(), kind.to_string());
+    match mounts.get(&key) {
+        // Caches already created keep their directory.
+        Some(mounted) if mounted != dir => {
+            bail!("{} {} is already mounted", namespace, kind)
+        }
+        Some(_) => {}
+        None => {
+            mounts.insert(key, dir.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Namespace and id inside it, when the id starts with a mounted namespace.
+pub fn split_namespace(id: &str) -> Option<(&str, &str)> {
+    let (namespace, local_id) = id.split_once(':')?;
+    let mounts = MOUNTS.lock().unwrap();
+    mounts
+        .keys()
+        .any(|(mounted, _)| mounted == namespace)
+        .then_some((namespace, local_id))
+}
+
+/// Directory of a kind under the asset root, created when a project lacks
+/// it so that its assets are simply missing.
+pub fn kind_dir(kind: &str) -> PathBuf {
+    let dir = root().join(kind);
+    if !dir.is_dir() {
+        warn!("Creating the missing {} directory", dir.display());
+        if let Err(err) = fs::create_dir_all(&dir) {
+            warn!("No {}: {}", kind, err);
+        }
+    }
+    dir
+}
+
+/// Caches of a kind of asset, `assets/<kind>` for the plain ids and the
+/// directory mounted for the namespace of the others.
+#[derive(Clone)]
+pub struct AssetCaches {
+    default: Arc<AssetCache>,
+    kind: &'static str,
+    /// None when the namespace has no directory for this kind.
+    mounted: Arc<Mutex<HashMap<String, Option<Arc<AssetCache>>>>>,
+}
+
+impl AssetCaches {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            default: Arc::new(AssetCache::new(kind_dir(kind)).unwrap()),
+            kind,
+            mounted: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Cache holding the asset and its id in that cache.
+    pub fn resolve<'a>(&self, id: &'a str) -> (Arc<AssetCache>, &'a str) {
+        let Some((namespace, local_id)) = split_namespace(id) else {
+            return (self.default.clone(), id);
+        };
+        let mut mounted = self.mounted.lock().unwrap();
+        let cache = mounted.entry(namespace.to_string()).or_insert_with(|| {
+            let key = (namespace.to_string(), self.kind.to_string());
+            let dir = MOUNTS.lock().unwrap().get(&key).cloned()?;
+            AssetCache::new(&dir)
+                .inspect_err(|err| {
+                    warn!("No {} in {}: {}", self.kind, namespace, err)
+                })
+                .ok()
+                .map(Arc::new)
+        });
+        match cache {
+            Some(cache) => (cache.clone(), local_id),
+            // Fails to load from the default cache, with the full id.
+            None => (self.default.clone(), id),
+        }
+    }
+
+    /// Directory of the asset file, for the files it references.
+    pub fn dir(&self, id: &str) -> PathBuf {
+        let (base, local_id) = match split_namespace(id) {
+            Some((namespace, local_id)) => {
+                let key = (namespace.to_string(), self.kind.to_string());
+                let mounted = MOUNTS.lock().unwrap().get(&key).cloned();
+                (mounted.unwrap_or_else(|| root().join(self.kind)), local_id)
+            }
+            None => (root().join(self.kind), id),
+        };
+        // Ids separate the directories with dots.
+        let relative = PathBuf::from(local_id.replace('.', "/"));
+        match relative.parent() {
+            Some(parent) => base.join(parent),
+            None => base,
+        }
+    }
+
+    pub fn hot_reload(&self) {
+        self.default.hot_reload();
+        for cache in self.mounted.lock().unwrap().values().flatten() {
+            cache.hot_reload();
+        }
+    }
+}
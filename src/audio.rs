@@ -0,0 +1,96 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use assets_manager::{loader, Asset};
+use glam::Vec3;
+use log::warn;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, SpatialSink};
+
+use crate::{assets::AssetCaches, transform::Transform};
+
+/// Half the distance between the listener ears.
+const EAR_OFFSET: f32 = 0.1;
+
+pub struct SoundSource(Vec<u8>);
+
+impl From<Vec<u8>> for SoundSource {
+    fn from(value: Vec<u8>) -> Self {
+        SoundSource(value)
+    }
+}
+
+impl Asset for SoundSource {
+    const EXTENSIONS: &'static [&'static str] = &["ogg", "wav"];
+    type Loader = loader::LoadFrom<Vec<u8>, loader::BytesLoader>;
+}
+
+pub struct Audio {
+    caches: AssetCaches,
+    /// Positional sounds still playing, their ears follow the listener.
+    emitters: Vec<SpatialSink>,
+    ears: [Vec3; 2],
+    /// The stream must be kept alive for the handle to play sounds.
+    output: Option<(OutputStream, OutputStreamHandle)>,
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        let output = OutputStream::try_default()
+            .inspect_err(|err| warn!("No audio output: {}", err))
+            .ok();
+        Self {
+            caches: AssetCaches::new("sounds"),
+            emitters: Vec::new(),
+            ears: [Vec3::NEG_X * EAR_OFFSET, Vec3::X * EAR_OFFSET],
+            output,
+        }
+    }
+
+    fn decode(&self, sound_id: &str) -> Result<Decoder<Cursor<Vec<u8>>>> {
+        let (cache, local_id) = self.caches.resolve(sound_id);
+        let handle = cache.load::<SoundSource>(local_id)?;
+        let data = handle.read().0.clone();
+        Ok(Decoder::new(Cursor::new(data))?)
+    }
+
+    pub fn play(&self, sound_id: &str) -> Result<()> {
+        let Some((_, handle)) = &self.output else {
+            return Ok(());
+        };
+        let sink = Sink::try_new(handle)?;
+        sink.append(self.decode(sound_id)?);
+        sink.detach();
+        Ok(())
+    }
+
+    /// Play a sound from a world position, attenuated with the distance to
+    /// the listener and panned between its ears.
+    pub fn play_at(&mut self, sound_id: &str, pos: Vec3) -> Result<()> {
+        let Some((_, handle)) = &self.output else {
+            return Ok(());
+        };
+        let [left, right] = self.ears;
+        let sink = SpatialSink::try_new(
+            handle,
+            pos.to_array(),
+            left.to_array(),
+            right.to_array(),
+        )?;
+        sink.append(self.decode(sound_id)?);
+        self.emitters.push(sink);
+        Ok(())
+    }
+
+    /// Move the listener ears to the given transform, usually the camera.
+    pub fn update(&mut self, listener: &Transform) {
+        self.caches.hot_reload();
+
+        let offset = listener.right() * EAR_OFFSET;
+        self.ears = [listener.pos - offset, listener.pos + offset];
+        self.emitters.retain(|sink| !sink.empty());
+        for sink in &self.emitters {
+            sink.set_left_ear_position(self.ears[0].to_array());
+            sink.set_right_ear_position(self.ears[1].to_array());
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use glam::{Mat3, Mat4, Vec3, Vec4};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for point in points {
+            min = min.min(point);
+            max = max.max(point);
+        }
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Conservative bounds of this box once transformed by the matrix.
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let extents = Mat3::from_mat4(*matrix).abs() * self.extents();
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    /// Slab test, returns the distance along the ray in units of `dir` and
+    /// the normal of the face hit.
+    pub fn ray_intersection(
+        &self,
+        origin: Vec3,
+        dir: Vec3,
+    ) -> Option<(f32, Vec3)> {
+        let inv_dir = dir.recip();
+        let t0 = (self.min - origin) * inv_dir;
+        let t1 = (self.max - origin) * inv_dir;
+        let (near, far) = (t0.min(t1), t0.max(t1));
+        let (t_near, t_far) = (near.max_element(), far.min_element());
+        if t_near > t_far || t_far < 0.0 {
+            return None;
+        }
+
+        let axis = match near.to_array() {
+            [x, ..] if x == t_near => 0,
+            [_, y, _] if y == t_near => 1,
+            _ => 2,
+        };
+        let mut normal = Vec3::ZERO;
+        normal[axis] = -dir[axis].signum();
+        Some((t_near.max(0.0), normal))
+    }
+}
+
+/// Sphere around the aabb center, looser than the smallest one.
+#[derive(Debug, Clone, Copy)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let center = Aabb::from_points(points.iter().copied()).center();
+        let radius = points
+            .iter()
+            .map(|point| point.distance_squared(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    /// The radius grows with the largest scale of the matrix.
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        let scale = Mat3::from_mat4(*matrix)
+            .to_cols_array_2d()
+            .map(|col| Vec3::from(col).length());
+        Self {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale.into_iter().fold(0.0, f32::max),
+        }
+    }
+}
+
+/// Box and sphere of a mesh, for the scripts.
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+}
+
+impl Bounds {
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        Self {
+            aabb: self.aabb.transformed(matrix),
+            sphere: self.sphere.transformed(matrix),
+        }
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.aabb.min).all() && point.cmple(self.aabb.max).all()
+    }
+
+    /// Boxes overlap, the spheres are checked first.
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        let radii = self.sphere.radius + other.sphere.radius;
+        if self.sphere.center.distance_squared(other.sphere.center)
+            > radii * radii
+        {
+            return false;
+        }
+        self.aabb.min.cmple(other.aabb.max).all()
+            && other.aabb.min.cmple(self.aabb.max).all()
+    }
+}
+
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of a clip-space matrix, with a 0..1 depth range.
+    pub fn from_matrix(clip_world: &Mat4) -> Self {
+        let (r0, r1, r2, r3) = (
+            clip_world.row(0),
+            clip_world.row(1),
+            clip_world.row(2),
+            clip_world.row(3),
+        );
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let positive =
+                Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(positive) + plane.w >= 0.0
+        })
+    }
+}
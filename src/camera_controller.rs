@@ -0,0 +1,160 @@
+use std::f32::consts::FRAC_PI_2;
+
+use glam::Vec3;
+use winit::keyboard::KeyCode;
+
+use crate::{
+    constraint::Target, entity::Entities, input::Inputs, transform::Transform,
+};
+
+/// Keeps the orbit away from the poles, where look_at is undefined.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+const LEFT_BUTTON: usize = 0;
+const RIGHT_BUTTON: usize = 1;
+
+#[derive(Debug, Clone)]
+pub enum ControllerKind {
+    /// Rotate around the target with the left button, zoom with the wheel.
+    Orbit {
+        distance: f32,
+        pitch: f32,
+        target: Target,
+        yaw: f32,
+    },
+    /// Move with WASD, Space and Shift, look around with the right button.
+    Fly,
+    /// Trail the target at a world offset while looking at it.
+    Follow { offset: Vec3, target: Target },
+}
+
+/// Camera movement driven by the inputs, before the scripts update.
+#[derive(Debug, Clone)]
+pub struct CameraController {
+    /// How fast the movement catches up with the inputs, per second.
+    pub damping: f32,
+    pub kind: ControllerKind,
+    /// Radians per pixel of mouse movement.
+    pub sensitivity: f32,
+    /// Units per second when flying.
+    pub speed: f32,
+    /// Fly: linear velocity. Orbit: yaw, pitch and zoom rates.
+    velocity: Vec3,
+    /// Transform written by the last update, a different one means the
+    /// scripts moved the camera.
+    written: Option<Transform>,
+}
+
+impl CameraController {
+    pub fn new(kind: ControllerKind) -> Self {
+        Self {
+            damping: 10.0,
+            kind,
+            sensitivity: 0.005,
+            speed: 10.0,
+            velocity: Vec3::ZERO,
+            written: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            ControllerKind::Orbit { .. } => "orbit",
+            ControllerKind::Fly => "fly",
+            ControllerKind::Follow { .. } => "follow",
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        transform: &mut Transform,
+        inputs: &Inputs,
+        entities: &Entities,
+        dt: f32,
+    ) {
+        let moved = self.written.is_some_and(|written| written != *transform);
+        let blend = 1.0 - (-self.damping * dt).exp();
+        let look = inputs.mouse_delta * self.sensitivity / dt.max(1e-4);
+
+        match &mut self.kind {
+            ControllerKind::Orbit {
+                distance,
+                pitch,
+                target,
+                yaw,
+            } => {
+                let Some(target) = target_pos(*target, entities) else {
+                    return;
+                };
+                let offset = transform.pos - target;
+                if (self.written.is_none() || moved) && offset.length() > 1e-4 {
+                    *yaw = offset.x.atan2(offset.z);
+                    *pitch = (offset.y / offset.length()).asin();
+                    if moved || *distance <= 0.0 {
+                        *distance = offset.length();
+                    }
+                }
+
+                // The scroll of the frame as a rate, like the look, so a
+                // notch zooms as much at any frame rate.
+                let zoom = -inputs.scroll_delta / dt.max(1e-4);
+                let mut input = Vec3::new(0.0, 0.0, zoom);
+                if inputs.mouse_pressed(LEFT_BUTTON) {
+                    input.x = -look.x;
+                    input.y = look.y;
+                }
+                self.velocity = self.velocity.lerp(input, blend);
+                *yaw += self.velocity.x * dt;
+                *pitch = (*pitch + self.velocity.y * dt)
+                    .clamp(-MAX_PITCH, MAX_PITCH);
+                *distance =
+                    (*distance * (1.0 + self.velocity.z * 0.1 * dt)).max(0.01);
+
+                let direction = Vec3::new(
+                    yaw.sin() * pitch.cos(),
+                    pitch.sin(),
+                    yaw.cos() * pitch.cos(),
+                );
+                transform.pos = target + direction * *distance;
+                transform.look_at(target);
+            }
+            ControllerKind::Fly => {
+                let key = |code| inputs.key_pressed(code) as i32 as f32;
+                let local = Vec3::new(
+                    key(KeyCode::KeyD) - key(KeyCode::KeyA),
+                    key(KeyCode::Space) - key(KeyCode::ShiftLeft),
+                    key(KeyCode::KeyW) - key(KeyCode::KeyS),
+                );
+                let direction = transform.right() * local.x
+                    + Vec3::Y * local.y
+                    + transform.forward() * local.z;
+                self.velocity = self
+                    .velocity
+                    .lerp(direction.normalize_or_zero() * self.speed, blend);
+                transform.pos += self.velocity * dt;
+                if inputs.mouse_pressed(RIGHT_BUTTON) {
+                    transform.rotate(Vec3::Y, -look.x * dt);
+                    transform.rotate_local(Vec3::X, -look.y * dt);
+                }
+            }
+            ControllerKind::Follow { offset, target } => {
+                let Some(target) = target_pos(*target, entities) else {
+                    return;
+                };
+                transform.pos = transform.pos.lerp(target + *offset, blend);
+                if transform.pos.distance_squared(target) > f32::EPSILON {
+                    transform.look_at(target);
+                }
+            }
+        }
+        self.written = Some(*transform);
+    }
+}
+
+fn target_pos(target: Target, entities: &Entities) -> Option<Vec3> {
+    match target {
+        Target::Entity(id) => {
+            entities.local_to_world(id).map(|m| m.w_axis.truncate())
+        }
+        Target::Point(point) => Some(point),
+    }
+}
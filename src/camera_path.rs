@@ -0,0 +1,178 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{assets, transform::Transform};
+
+const CAMERA_PATHS_DIR: &str = "camera_paths";
+/// Seconds between the recorded keyframes, the spline fills the gaps.
+const RECORD_INTERVAL: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub fovy: f32,
+    pub pos: Vec3,
+    pub rot: Quat,
+    /// Seconds from the start of the path.
+    pub time: f32,
+}
+
+/// Keyframes of the camera, stored as `assets/camera_paths/<name>.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    fn file(name: &str) -> PathBuf {
+        assets::root()
+            .join(CAMERA_PATHS_DIR)
+            .join(format!("{}.json", name))
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let path: Self =
+            serde_json::from_str(&fs::read_to_string(Self::file(name))?)?;
+        if path.keyframes.is_empty() {
+            bail!("camera path {} has no keyframes", name);
+        }
+        Ok(path)
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        fs::create_dir_all(assets::root().join(CAMERA_PATHS_DIR))?;
+        fs::write(Self::file(name), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Position on a catmull-rom spline through the keyframes, rotation and
+    /// field of view interpolated between the surrounding two.
+    pub fn sample(&self, time: f32) -> (Vec3, Quat, f32) {
+        let keys = &self.keyframes;
+        if keys.len() == 1 {
+            return (keys[0].pos, keys[0].rot, keys[0].fovy);
+        }
+        let time = time.clamp(0.0, self.duration());
+        let next = keys
+            .iter()
+            .position(|keyframe| keyframe.time > time)
+            .unwrap_or(keys.len() - 1)
+            .max(1)
+            .min(keys.len() - 1);
+        let i = next - 1;
+        let (a, b) = (&keys[i], &keys[next]);
+        let span = b.time - a.time;
+        let t = match span > 0.0 {
+            true => ((time - a.time) / span).clamp(0.0, 1.0),
+            false => 1.0,
+        };
+        let p0 = keys[i.saturating_sub(1)].pos;
+        let p3 = keys[(next + 1).min(keys.len() - 1)].pos;
+        let pos = catmull_rom(p0, a.pos, b.pos, p3, t);
+        (pos, a.rot.slerp(b.rot, t), a.fovy + (b.fovy - a.fovy) * t)
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let (t2, t3) = (t * t, t * t * t);
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// Recording or playback of a path, driving the camera each frame.
+#[derive(Debug, Clone)]
+pub enum PathState {
+    Recording {
+        elapsed: f32,
+        name: String,
+        path: CameraPath,
+    },
+    Playing {
+        looping: bool,
+        path: CameraPath,
+        speed: f32,
+        time: f32,
+    },
+}
+
+impl PathState {
+    /// Returns false once a path that does not loop is done playing.
+    pub fn update(
+        &mut self,
+        transform: &mut Transform,
+        fovy: &mut f32,
+        dt: f32,
+    ) -> bool {
+        match self {
+            PathState::Recording { elapsed, path, .. } => {
+                let recent = path
+                    .keyframes
+                    .last()
+                    .is_some_and(|last| *elapsed - last.time < RECORD_INTERVAL);
+                if !recent {
+                    path.keyframes.push(Keyframe {
+                        fovy: *fovy,
+                        pos: transform.pos,
+                        rot: transform.rot,
+                        time: *elapsed,
+                    });
+                }
+                *elapsed += dt;
+                true
+            }
+            PathState::Playing {
+                looping,
+                path,
+                speed,
+                time,
+            } => {
+                *time += dt * *speed;
+                let duration = path.duration();
+                if *looping && duration > 0.0 {
+                    *time = time.rem_euclid(duration);
+                }
+                (transform.pos, transform.rot, *fovy) = path.sample(*time);
+                *looping || (0.0..duration).contains(time)
+            }
+        }
+    }
+
+    /// Save a recording with its last keyframe at the current transform,
+    /// returns the number of keyframes.
+    pub fn finish_recording(
+        self,
+        transform: &Transform,
+        fovy: f32,
+    ) -> Result<usize> {
+        let PathState::Recording {
+            elapsed,
+            name,
+            mut path,
+        } = self
+        else {
+            bail!("camera is not recording");
+        };
+        if path
+            .keyframes
+            .last()
+            .is_some_and(|last| last.time < elapsed)
+        {
+            path.keyframes.push(Keyframe {
+                fovy,
+                pos: transform.pos,
+                rot: transform.rot,
+                time: elapsed,
+            });
+        }
+        path.save(&name)?;
+        Ok(path.keyframes.len())
+    }
+}
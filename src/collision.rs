@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+
+use glam::Vec3;
+
+/// Distance under which shapes touch.
+const CONTACT: f32 = 1e-3;
+/// Steps of a sweep before giving up, each one moves by the distance left
+/// between the shapes.
+const SWEEP_STEPS: u32 = 64;
+/// Iterations of the search along a segment for the closest box point.
+const SEGMENT_STEPS: u32 = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Aabb {
+        min: Vec3,
+        max: Vec3,
+    },
+    /// Segment between two points inflated by the radius.
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+}
+
+impl Shape {
+    pub fn translated(&self, offset: Vec3) -> Self {
+        match *self {
+            Self::Sphere { center, radius } => Self::Sphere {
+                center: center + offset,
+                radius,
+            },
+            Self::Aabb { min, max } => Self::Aabb {
+                min: min + offset,
+                max: max + offset,
+            },
+            Self::Capsule { a, b, radius } => Self::Capsule {
+                a: a + offset,
+                b: b + offset,
+                radius,
+            },
+        }
+    }
+
+    /// Segment or box the shape inflates, and by how much.
+    fn core(&self) -> (Core, f32) {
+        match *self {
+            Self::Sphere { center, radius } => {
+                (Core::Segment(center, center), radius)
+            }
+            Self::Aabb { min, max } => (Core::Box(min, max), 0.0),
+            Self::Capsule { a, b, radius } => (Core::Segment(a, b), radius),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Core {
+    Segment(Vec3, Vec3),
+    Box(Vec3, Vec3),
+}
+
+/// Gap between two shapes, negative when they overlap, with the closest
+/// points of their cores.
+fn separation(a: &Shape, b: &Shape) -> (f32, Vec3, Vec3) {
+    let ((core_a, radius_a), (core_b, radius_b)) = (a.core(), b.core());
+    let (p, q) = match (core_a, core_b) {
+        (Core::Segment(a0, a1), Core::Segment(b0, b1)) => {
+            closest_segments(a0, a1, b0, b1)
+        }
+        (Core::Segment(a0, a1), Core::Box(min, max)) => {
+            closest_segment_box(a0, a1, min, max)
+        }
+        (Core::Box(min, max), Core::Segment(b0, b1)) => {
+            let (q, p) = closest_segment_box(b0, b1, min, max);
+            (p, q)
+        }
+        (Core::Box(a_min, a_max), Core::Box(b_min, b_max)) => {
+            closest_boxes(a_min, a_max, b_min, b_max)
+        }
+    };
+    (p.distance(q) - radius_a - radius_b, p, q)
+}
+
+/// Closest points of two segments, clamped to their ends.
+fn closest_segments(a0: Vec3, a1: Vec3, b0: Vec3, b1: Vec3) -> (Vec3, Vec3) {
+    let (da, db, r) = (a1 - a0, b1 - b0, a0 - b0);
+    let (len_a, len_b) = (da.length_squared(), db.length_squared());
+    let f = db.dot(r);
+    let (s, t) = if len_a <= f32::EPSILON && len_b <= f32::EPSILON {
+        (0.0, 0.0)
+    } else if len_a <= f32::EPSILON {
+        (0.0, (f / len_b).clamp(0.0, 1.0))
+    } else {
+        let c = da.dot(r);
+        if len_b <= f32::EPSILON {
+            ((-c / len_a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = da.dot(db);
+            let denom = len_a * len_b - b * b;
+            let s = match denom > f32::EPSILON {
+                true => ((b * f - c * len_b) / denom).clamp(0.0, 1.0),
+                // Parallel, any point works.
+                false => 0.0,
+            };
+            let t = (b * s + f) / len_b;
+            match t {
+                t if t < 0.0 => ((-c / len_a).clamp(0.0, 1.0), 0.0),
+                t if t > 1.0 => (((b - c) / len_a).clamp(0.0, 1.0), 1.0),
+                t => (s, t),
+            }
+        }
+    };
+    (a0 + da * s, b0 + db * t)
+}
+
+/// The distance to a box is convex along the segment, searched by
+/// narrowing the interval.
+fn closest_segment_box(a: Vec3, b: Vec3, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    let distance = |t: f32| {
+        let point = a.lerp(b, t);
+        point.distance_squared(point.clamp(min, max))
+    };
+    let (mut low, mut high) = (0.0_f32, 1.0_f32);
+    for _ in 0..SEGMENT_STEPS {
+        let third = (high - low) / 3.0;
+        if distance(low + third) < distance(high - third) {
+            high -= third;
+        } else {
+            low += third;
+        }
+    }
+    let point = a.lerp(b, (low + high) * 0.5);
+    (point, point.clamp(min, max))
+}
+
+/// Per axis, the middle of the overlap or the facing sides.
+fn closest_boxes(
+    a_min: Vec3,
+    a_max: Vec3,
+    b_min: Vec3,
+    b_max: Vec3,
+) -> (Vec3, Vec3) {
+    let (mut p, mut q) = (Vec3::ZERO, Vec3::ZERO);
+    for axis in 0..3 {
+        (p[axis], q[axis]) = if a_max[axis] < b_min[axis] {
+            (a_max[axis], b_min[axis])
+        } else if b_max[axis] < a_min[axis] {
+            (a_min[axis], b_max[axis])
+        } else {
+            let middle = (a_min[axis].max(b_min[axis])
+                + a_max[axis].min(b_max[axis]))
+                * 0.5;
+            (middle, middle)
+        };
+    }
+    (p, q)
+}
+
+/// First collider met by a sweep.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub distance: f32,
+    pub id: String,
+    /// Away from the collider hit, against the sweep when they already
+    /// overlap.
+    pub normal: Vec3,
+}
+
+/// Colliders of the scripts by id, tested against each other on demand
+/// without a physics simulation.
+#[derive(Debug, Default)]
+pub struct Collisions {
+    colliders: BTreeMap<String, Shape>,
+}
+
+impl Collisions {
+    /// Replaces the collider of the same id.
+    pub fn insert(&mut self, id: &str, shape: Shape) {
+        self.colliders.insert(id.to_string(), shape);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.colliders.remove(id);
+    }
+
+    pub fn clear(&mut self) {
+        self.colliders.clear();
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Shape> {
+        self.colliders.get(id)
+    }
+
+    /// Other colliders touching the shape.
+    pub fn overlapping(&self, shape: &Shape, except: &str) -> Vec<String> {
+        self.others(except)
+            .filter(|(_, other)| separation(shape, other).0 <= CONTACT)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Move the shape along the direction up to the distance, by steps as
+    /// long as the gap left to any other collider.
+    pub fn sweep(
+        &self,
+        shape: &Shape,
+        except: &str,
+        dir: Vec3,
+        max_distance: f32,
+    ) -> Option<Hit> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return None;
+        }
+        let mut travelled = 0.0;
+        for _ in 0..SWEEP_STEPS {
+            let moved = shape.translated(dir * travelled);
+            let closest = self
+                .others(except)
+                .map(|(id, other)| (id, separation(&moved, other)))
+                .min_by(|(_, a), (_, b)| a.0.total_cmp(&b.0))?;
+            let (id, (gap, p, q)) = closest;
+            if gap <= CONTACT {
+                return Some(Hit {
+                    distance: travelled,
+                    id: id.clone(),
+                    normal: (p - q).try_normalize().unwrap_or(-dir),
+                });
+            }
+            travelled += gap;
+            if travelled > max_distance {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn others<'a>(
+        &'a self,
+        except: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a Shape)> {
+        self.colliders.iter().filter(move |(id, _)| *id != except)
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use glam::Vec3;
+
+use crate::entity::{Entities, EntityId};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    Entity(EntityId),
+    Point(Vec3),
+}
+
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    CopyPosition {
+        offset: Vec3,
+        source: EntityId,
+    },
+    /// Move along a looping polyline at a constant speed.
+    FollowPath {
+        distance: f32,
+        path: Vec<Vec3>,
+        speed: f32,
+    },
+    LookAt(Target),
+}
+
+/// Transform constraints of the entities, evaluated in entity id order and
+/// then in the order they were added.
+#[derive(Debug, Default)]
+pub struct Constraints {
+    constraints: BTreeMap<EntityId, Vec<Constraint>>,
+}
+
+impl Constraints {
+    pub fn add(&mut self, id: EntityId, constraint: Constraint) {
+        self.constraints.entry(id).or_default().push(constraint);
+    }
+
+    pub fn clear(&mut self, id: EntityId) {
+        self.constraints.remove(&id);
+    }
+
+    pub fn apply(&mut self, entities: &mut Entities, dt: f32) {
+        self.constraints.retain(|id, _| entities.get(*id).is_some());
+        for (id, constraints) in &mut self.constraints {
+            for constraint in constraints {
+                apply(*id, constraint, entities, dt);
+            }
+        }
+    }
+}
+
+fn apply(
+    id: EntityId,
+    constraint: &mut Constraint,
+    entities: &mut Entities,
+    dt: f32,
+) {
+    match constraint {
+        Constraint::CopyPosition { offset, source } => {
+            let Some(source) = entities.get(*source) else {
+                return;
+            };
+            let pos = source.transform.pos + *offset;
+            if let Some(entity) = entities.get_mut(id) {
+                entity.transform.pos = pos;
+            }
+        }
+        Constraint::FollowPath {
+            distance,
+            path,
+            speed,
+        } => {
+            *distance += *speed * dt;
+            let Some(pos) = path_point(path, *distance) else {
+                return;
+            };
+            if let Some(entity) = entities.get_mut(id) {
+                entity.transform.pos = pos;
+            }
+        }
+        Constraint::LookAt(target) => {
+            let target = match *target {
+                Target::Entity(target) => match entities.get(target) {
+                    Some(target) => target.transform.pos,
+                    None => return,
+                },
+                Target::Point(point) => point,
+            };
+            if let Some(entity) = entities.get_mut(id) {
+                if entity.transform.pos.distance_squared(target) > f32::EPSILON
+                {
+                    entity.transform.look_at(target);
+                }
+            }
+        }
+    }
+}
+
+/// Point at a distance along the looping path.
+fn path_point(path: &[Vec3], distance: f32) -> Option<Vec3> {
+    let segments = || {
+        path.iter()
+            .zip(path.iter().cycle().skip(1))
+            .map(|(a, b)| (*a, *b, a.distance(*b)))
+    };
+    let length = segments().map(|(_, _, length)| length).sum::<f32>();
+    if length <= f32::EPSILON {
+        return path.first().copied();
+    }
+    let mut remaining = distance.rem_euclid(length);
+    for (a, b, segment) in segments() {
+        if remaining <= segment {
+            return Some(a.lerp(b, remaining / segment.max(f32::EPSILON)));
+        }
+        remaining -= segment;
+    }
+    path.last().copied()
+}
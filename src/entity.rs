@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{bail, Result};
+use glam::{Mat4, Quat};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    render::{bundle::model, morph},
+    transform::Transform,
+};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+pub struct EntityId(pub u32);
+
+/// Model kept in the scene across frames, batched every frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entity {
+    pub material_id: String,
+    pub mesh_id: String,
+    /// Weights of the morph targets of the mesh by name.
+    #[serde(default)]
+    pub morph_weights: BTreeMap<String, f32>,
+    /// The transform is relative to the parent.
+    pub parent: Option<EntityId>,
+    pub transform: Transform,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Entities {
+    entities: BTreeMap<EntityId, Entity>,
+    next_id: u32,
+}
+
+impl Entities {
+    pub fn spawn(&mut self, entity: Entity) -> EntityId {
+        let id = EntityId(self.next_id);
+        self.next_id += 1;
+        self.entities.insert(id, entity);
+        id
+    }
+
+    /// Children are detached, keeping their place in the world.
+    pub fn despawn(&mut self, id: EntityId) -> Option<Entity> {
+        let children = self
+            .entities
+            .iter()
+            .filter(|(_, entity)| entity.parent == Some(id))
+            .map(|(child, _)| *child)
+            .collect::<Vec<_>>();
+        for child in children {
+            let world = self.local_to_world(child).unwrap();
+            let entity = self.entities.get_mut(&child).unwrap();
+            entity.parent = None;
+            entity.transform = Transform::from_matrix(world);
+        }
+        self.entities.remove(&id)
+    }
+
+    pub fn count(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.entities.iter().map(|(id, entity)| (*id, entity))
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        self.entities.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut Entity> {
+        self.entities.get_mut(&id)
+    }
+
+    /// Fails when the parent is the entity itself or one of its children.
+    pub fn set_parent(
+        &mut self,
+        id: EntityId,
+        parent: Option<EntityId>,
+    ) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                bail!("entity can not be its own ancestor");
+            }
+            let Some(entity) = self.entities.get(&ancestor_id) else {
+                bail!("parent was despawned");
+            };
+            ancestor = entity.parent;
+        }
+        let Some(entity) = self.entities.get_mut(&id) else {
+            bail!("entity was despawned");
+        };
+        entity.parent = parent;
+        Ok(())
+    }
+
+    /// Fails when a parent is missing or in a cycle, for the entities read
+    /// from a file. The next id is moved past the existing ones.
+    pub fn validate(&mut self) -> Result<()> {
+        for (id, entity) in &self.entities {
+            let mut ancestor = entity.parent;
+            let mut depth = 0;
+            while let Some(ancestor_id) = ancestor {
+                depth += 1;
+                if ancestor_id == *id || depth > self.entities.len() {
+                    bail!("entity {} is in a parent cycle", id.0);
+                }
+                let Some(parent) = self.entities.get(&ancestor_id) else {
+                    bail!("parent of entity {} is missing", id.0);
+                };
+                ancestor = parent.parent;
+            }
+        }
+        if let Some(last) = self.entities.keys().next_back() {
+            self.next_id = self.next_id.max(last.0 + 1);
+        }
+        Ok(())
+    }
+
+    /// Transform composed with the ones of the parents.
+    pub fn local_to_world(&self, id: EntityId) -> Option<Mat4> {
+        self.world_matrix_rotation(id).map(|(matrix, _)| matrix)
+    }
+
+    fn world_matrix_rotation(&self, id: EntityId) -> Option<(Mat4, Quat)> {
+        let entity = self.entities.get(&id)?;
+        let (mut matrix, mut rot) =
+            (entity.transform.build_matrix(), entity.transform.rot);
+        let mut parent = entity.parent;
+        while let Some(parent_id) = parent {
+            let entity = self.entities.get(&parent_id)?;
+            matrix = entity.transform.build_matrix() * matrix;
+            rot = entity.transform.rot * rot;
+            parent = entity.parent;
+        }
+        Some((matrix, rot))
+    }
+
+    /// The morphed entities use their blended mesh.
+    pub fn batch(
+        &self,
+        batches: &mut model::Batches,
+        morphed: &HashSet<EntityId>,
+    ) {
+        for (id, entity) in &self.entities {
+            let (matrix, rot) = self.world_matrix_rotation(*id).unwrap();
+            let mesh_id = match morphed.contains(id) {
+                true => morph::mesh_id(*id),
+                false => entity.mesh_id.clone(),
+            };
+            batches.add_model(
+                mesh_id,
+                entity.material_id.clone(),
+                model::Instance::new(matrix, rot).with_seed(id.0),
+            );
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+/// Happening of the engine handed to the scripts as an event, queued on the
+/// app state until the scripts run.
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    /// A mesh, texture or shader finished loading for the first time.
+    AssetLoaded {
+        kind: &'static str,
+        id: String,
+    },
+    /// A mesh, texture or shader failed to load before ever loading.
+    AssetFailed {
+        kind: &'static str,
+        id: String,
+    },
+    /// A mesh, texture, shader or script loaded again after its file
+    /// changed, the kind is one of those.
+    AssetReloaded {
+        kind: &'static str,
+        id: String,
+    },
+    /// A file dropped on the window got copied into the assets, loading.
+    AssetImported {
+        kind: &'static str,
+        id: String,
+    },
+    FileDropped(PathBuf),
+    WindowResized {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl EngineEvent {
+    /// Name the scripts listen to.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AssetLoaded { .. } => "asset_loaded",
+            Self::AssetFailed { .. } => "asset_failed",
+            Self::AssetReloaded { .. } => "asset_reloaded",
+            Self::AssetImported { .. } => "asset_imported",
+            Self::FileDropped(_) => "file_dropped",
+            Self::WindowResized { .. } => "window_resized",
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+
+use crate::{
+    assets::{self, split_namespace},
+    render::{adapter::AdapterOptions, ktx, mesh},
+};
+
+/// Preprocessed assets under the asset root, `<kind>/<id>.<ext>` like the
+/// sources.
+const CACHE_DIR: &str = ".import";
+
+const MESH_EXTENSIONS: [&str; 3] = ["obj", "glb", "gltf"];
+const IMAGE_EXTENSIONS: [&str; 2] = ["jpeg", "png"];
+
+/// Imported file of an asset, when it is newer than every source file of
+/// that id. Assets of mounted namespaces are never imported.
+pub fn cached(kind: &str, id: &str, extension: &str) -> Option<PathBuf> {
+    if split_namespace(id).is_some() {
+        return None;
+    }
+    let relative = PathBuf::from(id.replace('.', "/"));
+    let path = assets::root()
+        .join(CACHE_DIR)
+        .join(kind)
+        .join(&relative)
+        .with_extension(extension);
+    let imported = modified(&path)?;
+    let sources = assets::root().join(kind).join(&relative);
+    let parent = sources.parent()?;
+    let stem = sources.file_name()?;
+    let stale = fs::read_dir(parent).ok()?.flatten().any(|entry| {
+        let source = entry.path();
+        source.file_stem() == Some(stem)
+            && modified(&source).is_some_and(|time| time > imported)
+    });
+    (!stale).then_some(path)
+}
+
+/// Optimize the meshes, generate the texture mipmaps and validate the
+/// shaders under `assets`. Materials are created by the scripts, there is
+/// nothing to bake for them.
+pub fn run(adapter_options: &AdapterOptions) -> Result<()> {
+    let mut failed = 0;
+    let mut imported = 0;
+
+    for (id, path) in sources("meshes", &MESH_EXTENSIONS)? {
+        let extension = path.extension().unwrap().to_str().unwrap();
+        let result = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| {
+                mesh::preprocess(&data, extension, path.parent().unwrap())
+            })
+            .and_then(|bytes| {
+                write("meshes", &id, mesh::IMPORTED_EXTENSION, &bytes)
+            });
+        report(&path, result, &mut imported, &mut failed);
+    }
+
+    for (id, path) in sources("textures", &IMAGE_EXTENSIONS)? {
+        // Authored ktx2 files are used as they are.
+        if path.with_extension("ktx2").exists() {
+            continue;
+        }
+        let result =
+            image::open(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|image| {
+                    let bytes = ktx::encode_mipmapped(&image);
+                    write("textures", &id, "ktx2", &bytes)
+                });
+        report(&path, result, &mut imported, &mut failed);
+    }
+
+    match create_device(adapter_options) {
+        Ok(device) => {
+            for (id, path) in sources("shaders", &["wgsl"])? {
+                let result = validate_shader(&device, &id, &path);
+                report(&path, result, &mut imported, &mut failed);
+            }
+        }
+        Err(err) => warn!("Shaders not validated, {:?}", err),
+    }
+
+    let cache_dir = assets::root().join(CACHE_DIR);
+    info!("Imported {} assets into {}", imported, cache_dir.display());
+    anyhow::ensure!(failed == 0, "{} assets failed to import", failed);
+    Ok(())
+}
+
+fn report(
+    path: &Path,
+    result: Result<()>,
+    imported: &mut u32,
+    failed: &mut u32,
+) {
+    match result {
+        Ok(()) => {
+            info!("{} ok", path.display());
+            *imported += 1;
+        }
+        Err(err) => {
+            error!("import {}\n{:?}", path.display(), err);
+            *failed += 1;
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Files of a kind with one of the extensions, with their asset id.
+pub fn sources(
+    kind: &str,
+    extensions: &[&str],
+) -> Result<Vec<(String, PathBuf)>> {
+    let root = assets::root().join(kind);
+    let mut files = Vec::new();
+    let mut dirs = vec![root.clone()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .with_context(|| format!("reading {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let extension = path.extension().and_then(|ext| ext.to_str());
+            if !extension.is_some_and(|ext| extensions.contains(&ext)) {
+                continue;
+            }
+            let relative = path.strip_prefix(&root)?.with_extension("");
+            let id = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(".");
+            files.push((id, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn write(kind: &str, id: &str, extension: &str, bytes: &[u8]) -> Result<()> {
+    let path = assets::root()
+        .join(CACHE_DIR)
+        .join(kind)
+        .join(id.replace('.', "/"))
+        .with_extension(extension);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, bytes)
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+fn create_device(adapter_options: &AdapterOptions) -> Result<wgpu::Device> {
+    let instance = adapter_options.create_instance();
+    let adapter =
+        pollster::block_on(adapter_options.request_adapter(&instance))?;
+    let (device, _) = pollster::block_on(
+        adapter.request_device(&wgpu::DeviceDescriptor::default(), None),
+    )?;
+    Ok(device)
+}
+
+fn validate_shader(device: &wgpu::Device, id: &str, path: &Path) -> Result<()> {
+    let source = fs::read_to_string(path)?;
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(id),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(anyhow::anyhow!("{}", err)),
+        None => Ok(()),
+    }
+}
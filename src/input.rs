@@ -2,10 +2,81 @@ use std::collections::HashMap;
 
 use glam::Vec2;
 use winit::{
-    event::{DeviceEvent, ElementState, KeyEvent, WindowEvent},
+    event::{
+        DeviceEvent, ElementState, KeyEvent, MouseScrollDelta, WindowEvent,
+    },
     keyboard::{KeyCode, PhysicalKey},
 };
 
+/// Keys the scripts can bind actions to, by their name without the `Key`
+/// or `Digit` prefix.
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::Space,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+];
+
+pub fn key_name(code: KeyCode) -> String {
+    let name = format!("{:?}", code);
+    let name = name.strip_prefix("Key").unwrap_or(&name);
+    name.strip_prefix("Digit").unwrap_or(name).to_string()
+}
+
+/// Case insensitive, none for keys that can not be bound.
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|code| key_name(*code).eq_ignore_ascii_case(name))
+}
+
 #[derive(Debug)]
 pub enum UserEvent {
     ExitApp,
@@ -15,6 +86,8 @@ pub enum UserEvent {
 pub struct Inputs {
     actions: HashMap<String, Vec<KeyCode>>,
     pub cursor_in_window: bool,
+    /// Cursor position in physical pixels from the top left of the window.
+    pub cursor_pos: Vec2,
     pub focused: bool,
     frame_device_events: Vec<DeviceEvent>,
     frame_window_events: Vec<WindowEvent>,
@@ -23,6 +96,8 @@ pub struct Inputs {
     last_mouse_state: [bool; 32],
     pub mouse_delta: Vec2,
     mouse_state: [bool; 32],
+    /// Wheel movement this frame in lines, positive away from the user.
+    pub scroll_delta: f32,
 }
 
 impl Default for Inputs {
@@ -30,6 +105,7 @@ impl Default for Inputs {
         Self {
             actions: HashMap::new(),
             cursor_in_window: false,
+            cursor_pos: Vec2::default(),
             focused: false,
             frame_device_events: vec![],
             frame_window_events: vec![],
@@ -38,6 +114,7 @@ impl Default for Inputs {
             last_mouse_state: [false; 32],
             mouse_delta: Vec2::default(),
             mouse_state: [false; 32],
+            scroll_delta: 0.0,
         }
     }
 }
@@ -51,6 +128,16 @@ impl Inputs {
             WindowEvent::Focused(focused) => self.focused = focused,
             WindowEvent::CursorEntered { .. } => self.cursor_in_window = true,
             WindowEvent::CursorLeft { .. } => self.cursor_in_window = false,
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_pos =
+                    Vec2::new(position.x as f32, position.y as f32);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 40.0,
+                };
+            }
             _ => (),
         };
     }
@@ -110,6 +197,18 @@ impl Inputs {
         self.actions.insert(name.to_string(), codes);
     }
 
+    /// Registered actions and their keys, sorted by name.
+    #[cfg(feature = "ui")]
+    pub fn actions(&self) -> Vec<(&str, &[KeyCode])> {
+        let mut actions = self
+            .actions
+            .iter()
+            .map(|(name, codes)| (name.as_str(), codes.as_slice()))
+            .collect::<Vec<_>>();
+        actions.sort_by_key(|(name, _)| *name);
+        actions
+    }
+
     pub fn action_pressed(&self, action: &str) -> bool {
         self.actions
             .get(action)
@@ -138,6 +237,7 @@ impl Inputs {
         self.last_keys_state.copy_from_slice(&self.keys_state);
         self.last_mouse_state.copy_from_slice(&self.mouse_state);
         self.mouse_delta = Vec2::default();
+        self.scroll_delta = 0.0;
         while let Some(event) = self.frame_window_events.pop() {
             self.handle_window_event(event);
         }
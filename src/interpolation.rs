@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use glam::Quat;
+
+use crate::{
+    entity::{Entities, EntityId},
+    transform::Transform,
+};
+
+/// Seconds the rendered transform lags behind the latest target.
+const DEFAULT_DELAY: f32 = 0.1;
+/// Seconds past the last target the motion keeps going before it stops.
+const MAX_EXTRAPOLATION: f32 = 0.25;
+/// Targets kept per entity, the oldest are dropped first.
+const MAX_TARGETS: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+struct Target {
+    time: f32,
+    transform: Transform,
+}
+
+#[derive(Debug)]
+struct Buffer {
+    delay: f32,
+    targets: VecDeque<Target>,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self {
+            delay: DEFAULT_DELAY,
+            targets: VecDeque::new(),
+        }
+    }
+}
+
+impl Buffer {
+    fn sample(&mut self, time: f32) -> Option<Transform> {
+        // Keep one target before the sampled time to interpolate from.
+        while self.targets.len() > 2 && self.targets[1].time <= time {
+            self.targets.pop_front();
+        }
+        let first = *self.targets.front()?;
+        let Some(second) = self.targets.get(1).copied() else {
+            return Some(first.transform);
+        };
+        let span = second.time - first.time;
+        if span <= 0.0 {
+            return Some(second.transform);
+        }
+        let max = 1.0 + MAX_EXTRAPOLATION / span;
+        let t = ((time - first.time) / span).clamp(0.0, max);
+        Some(lerp(&first.transform, &second.transform, t))
+    }
+}
+
+/// Transforms pushed by the scripts at discrete times, from physics ticks
+/// or network snapshots, rendered smoothly a delay behind the latest one.
+/// Past the last target the motion is extrapolated for a short while.
+#[derive(Debug, Default)]
+pub struct Interpolation {
+    buffers: BTreeMap<EntityId, Buffer>,
+}
+
+impl Interpolation {
+    /// Targets older than the latest one are inserted in time order.
+    pub fn push(&mut self, id: EntityId, transform: Transform, time: f32) {
+        let targets = &mut self.buffers.entry(id).or_default().targets;
+        let index = targets
+            .iter()
+            .rposition(|target| target.time <= time)
+            .map_or(0, |index| index + 1);
+        targets.insert(index, Target { time, transform });
+        if targets.len() > MAX_TARGETS {
+            targets.pop_front();
+        }
+    }
+
+    pub fn set_delay(&mut self, id: EntityId, delay: f32) {
+        self.buffers.entry(id).or_default().delay = delay.max(0.0);
+    }
+
+    /// The entity keeps its last transform.
+    pub fn clear(&mut self, id: EntityId) {
+        self.buffers.remove(&id);
+    }
+
+    /// Move the entities to their targets sampled at the time minus their
+    /// delay, in the clock of the pushed timestamps.
+    pub fn apply(&mut self, entities: &mut Entities, time: f32) {
+        self.buffers.retain(|id, _| entities.get(*id).is_some());
+        for (id, buffer) in &mut self.buffers {
+            if let Some(transform) = buffer.sample(time - buffer.delay) {
+                entities.get_mut(*id).unwrap().transform = transform;
+            }
+        }
+    }
+}
+
+/// Extrapolates past the second transform for t above one.
+fn lerp(a: &Transform, b: &Transform, t: f32) -> Transform {
+    let rot = match t <= 1.0 {
+        true => a.rot.slerp(b.rot, t),
+        false => {
+            let mut delta = b.rot * a.rot.inverse();
+            // Shortest arc, a quaternion and its negation are the same.
+            if delta.w < 0.0 {
+                delta = -delta;
+            }
+            let (axis, angle) = delta.to_axis_angle();
+            Quat::from_axis_angle(axis, angle * (t - 1.0)) * b.rot
+        }
+    };
+    Transform {
+        pos: a.pos.lerp(b.pos, t),
+        rot: rot.normalize(),
+        scale: a.scale.lerp(b.scale, t),
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs,
+    path::Path,
+};
+
+use mlua::{
+    AnyUserData, FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, Lua, MetaMethod,
+    Result, Table, UserDataFields, UserDataMethods, UserDataRegistry, Value,
+};
+use serde::Serialize;
+
+/// Description of a registered userdata type.
+#[derive(Debug, Default, Serialize)]
+pub struct TypeApi {
+    pub fields: Vec<String>,
+    pub methods: Vec<String>,
+    pub meta_methods: Vec<String>,
+}
+
+/// Everything registered to Lua, written as json for tooling.
+#[derive(Debug, Default, Serialize)]
+pub struct Api {
+    pub context: BTreeMap<String, String>,
+    /// Globals that are functions.
+    pub functions: BTreeSet<String>,
+    pub globals: BTreeMap<String, Vec<String>>,
+    pub types: BTreeMap<String, TypeApi>,
+}
+
+impl Api {
+    pub fn write(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Luau definition file for luau-lsp. The bindings do not keep their
+    /// signatures, so only the names are known and everything is any.
+    pub fn definitions(&self) -> String {
+        let mut defs = String::from("-- Generated by --emit-defs.\n");
+        for (name, api) in &self.types {
+            let _ = writeln!(defs, "\ndeclare class {}", name);
+            for field in api.fields.iter().collect::<BTreeSet<_>>() {
+                let _ = writeln!(defs, "  {}: any", field);
+            }
+            let methods = api
+                .methods
+                .iter()
+                .chain(&api.meta_methods)
+                .filter(|method| *method != "__type")
+                .collect::<BTreeSet<_>>();
+            for method in methods {
+                let _ = writeln!(
+                    defs,
+                    "  function {}(self, ...: any): any",
+                    method
+                );
+            }
+            defs.push_str("end\n");
+        }
+
+        defs.push_str("\nexport type Context = {\n");
+        for (key, type_name) in &self.context {
+            let _ = writeln!(defs, "  {}: {},", key, type_name);
+        }
+        defs.push_str("}\n");
+
+        for (name, keys) in &self.globals {
+            if self.functions.contains(name) {
+                let _ = writeln!(
+                    defs,
+                    "\ndeclare function {}(...: any): any",
+                    name
+                );
+            } else if keys.is_empty() {
+                let _ =
+                    writeln!(defs, "\ndeclare {}: {{ [string]: any }}", name);
+            } else {
+                let _ = writeln!(defs, "\ndeclare {}: {{", name);
+                for key in keys {
+                    let _ = writeln!(defs, "  {}: any,", key);
+                }
+                defs.push_str("}\n");
+            }
+        }
+        defs
+    }
+}
+
+/// Record globals added since the builtins were captured, tables are
+/// described by their keys.
+pub fn record_globals(lua: &Lua, builtins: &[String]) -> Result<()> {
+    let mut globals = BTreeMap::new();
+    let mut functions = BTreeSet::new();
+    for pair in lua.globals().pairs::<String, Value>() {
+        let (name, value) = pair?;
+        if builtins.contains(&name) {
+            continue;
+        }
+        if value.is_function() {
+            functions.insert(name.clone());
+        }
+        let mut keys = match value {
+            Value::Table(table) => table
+                .pairs::<String, Value>()
+                .map(|pair| pair.map(|(key, _)| key))
+                .collect::<Result<Vec<_>>>()?,
+            _ => Vec::new(),
+        };
+        keys.sort();
+        globals.insert(name, keys);
+    }
+    let mut api = lua.app_data_mut::<Api>().unwrap();
+    api.functions = functions;
+    api.globals = globals;
+    Ok(())
+}
+
+/// Record the context entries with their type names, once.
+pub fn record_context(lua: &Lua, ctx: &Table) -> Result<()> {
+    let mut api = lua.app_data_mut::<Api>().unwrap();
+    if !api.context.is_empty() {
+        return Ok(());
+    }
+    for pair in ctx.clone().pairs::<String, AnyUserData>() {
+        let (key, value) = pair?;
+        let type_name = value.get_metatable()?.get(MetaMethod::Type)?;
+        api.context.insert(key, type_name);
+    }
+    Ok(())
+}
+
+pub fn global_names(lua: &Lua) -> Result<Vec<String>> {
+    lua.globals()
+        .pairs::<String, Value>()
+        .map(|p| Ok(p?.0))
+        .collect()
+}
+
+/// Register a userdata type under a Lua facing name, recording the names
+/// of what gets added to it.
+pub fn register_type<T: 'static>(
+    lua: &Lua,
+    name: &str,
+    f: impl FnOnce(&mut ApiRegistry<T>),
+) -> Result<()> {
+    let mut type_api = TypeApi::default();
+    lua.register_userdata_type::<T>(|reg| {
+        reg.add_meta_field(MetaMethod::Type, name.to_string());
+        f(&mut ApiRegistry {
+            reg,
+            api: &mut type_api,
+        })
+    })?;
+    lua.app_data_mut::<Api>()
+        .unwrap()
+        .types
+        .entry(name.to_string())
+        .or_insert(type_api);
+    Ok(())
+}
+
+/// Forwards to the userdata registry while keeping the added names.
+pub struct ApiRegistry<'a, 'lua, T: 'static> {
+    reg: &'a mut UserDataRegistry<'lua, T>,
+    api: &'a mut TypeApi,
+}
+
+impl<'a, 'lua, T: 'static> UserDataFields<'lua, T>
+    for ApiRegistry<'a, 'lua, T>
+{
+    fn add_field<V>(&mut self, name: impl AsRef<str>, value: V)
+    where
+        V: IntoLua<'lua> + Clone + 'static,
+    {
+        self.api.fields.push(name.as_ref().to_string());
+        self.reg.add_field(name, value);
+    }
+
+    fn add_field_method_get<M, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(&'lua Lua, &T) -> Result<R> + 'static,
+        R: IntoLua<'lua>,
+    {
+        self.api.fields.push(name.as_ref().to_string());
+        self.reg.add_field_method_get(name, method);
+    }
+
+    fn add_field_method_set<M, A>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: FnMut(&'lua Lua, &mut T, A) -> Result<()> + 'static,
+        A: FromLua<'lua>,
+    {
+        self.reg.add_field_method_set(name, method);
+    }
+
+    fn add_field_function_get<F, R>(
+        &mut self,
+        name: impl AsRef<str>,
+        function: F,
+    ) where
+        F: Fn(&'lua Lua, AnyUserData<'lua>) -> Result<R> + 'static,
+        R: IntoLua<'lua>,
+    {
+        self.api.fields.push(name.as_ref().to_string());
+        self.reg.add_field_function_get(name, function);
+    }
+
+    fn add_field_function_set<F, A>(
+        &mut self,
+        name: impl AsRef<str>,
+        function: F,
+    ) where
+        F: FnMut(&'lua Lua, AnyUserData<'lua>, A) -> Result<()> + 'static,
+        A: FromLua<'lua>,
+    {
+        self.reg.add_field_function_set(name, function);
+    }
+
+    fn add_meta_field<V>(&mut self, name: impl AsRef<str>, value: V)
+    where
+        V: IntoLua<'lua> + Clone + 'static,
+    {
+        self.api.meta_methods.push(name.as_ref().to_string());
+        self.reg.add_meta_field(name, value);
+    }
+
+    fn add_meta_field_with<F, R>(&mut self, name: impl AsRef<str>, f: F)
+    where
+        F: Fn(&'lua Lua) -> Result<R> + 'static,
+        R: IntoLua<'lua>,
+    {
+        self.api.meta_methods.push(name.as_ref().to_string());
+        self.reg.add_meta_field_with(name, f);
+    }
+}
+
+impl<'a, 'lua, T: 'static> UserDataMethods<'lua, T>
+    for ApiRegistry<'a, 'lua, T>
+{
+    fn add_method<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(&'lua Lua, &T, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.methods.push(name.as_ref().to_string());
+        self.reg.add_method(name, method);
+    }
+
+    fn add_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: FnMut(&'lua Lua, &mut T, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.methods.push(name.as_ref().to_string());
+        self.reg.add_method_mut(name, method);
+    }
+
+    fn add_function<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(&'lua Lua, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.methods.push(name.as_ref().to_string());
+        self.reg.add_function(name, function);
+    }
+
+    fn add_function_mut<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: FnMut(&'lua Lua, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.methods.push(name.as_ref().to_string());
+        self.reg.add_function_mut(name, function);
+    }
+
+    fn add_meta_method<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(&'lua Lua, &T, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.meta_methods.push(name.as_ref().to_string());
+        self.reg.add_meta_method(name, method);
+    }
+
+    fn add_meta_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: FnMut(&'lua Lua, &mut T, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.meta_methods.push(name.as_ref().to_string());
+        self.reg.add_meta_method_mut(name, method);
+    }
+
+    fn add_meta_function<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(&'lua Lua, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.meta_methods.push(name.as_ref().to_string());
+        self.reg.add_meta_function(name, function);
+    }
+
+    fn add_meta_function_mut<F, A, R>(
+        &mut self,
+        name: impl AsRef<str>,
+        function: F,
+    ) where
+        F: FnMut(&'lua Lua, A) -> Result<R> + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.api.meta_methods.push(name.as_ref().to_string());
+        self.reg.add_meta_function_mut(name, function);
+    }
+}
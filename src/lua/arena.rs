@@ -0,0 +1,54 @@
+use glam::Vec3;
+use mlua::{AnyUserData, Lua, Result, Table};
+
+/// Vectors reused every frame, spares the garbage collector the temporaries
+/// of math heavy loops.
+struct MathArena {
+    /// Arithmetic results come from the arena, the scripts copy the ones
+    /// kept past the frame.
+    enabled: bool,
+    used: usize,
+}
+
+pub fn register_arena(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("math_arena", lua.create_table()?)?;
+    lua.set_app_data(MathArena {
+        enabled: false,
+        used: 0,
+    });
+    Ok(())
+}
+
+pub fn set_enabled(lua: &Lua, enabled: bool) {
+    lua.app_data_mut::<MathArena>().unwrap().enabled = enabled;
+}
+
+/// The vectors handed out last frame are overwritten from now on.
+pub fn reset(lua: &Lua) {
+    lua.app_data_mut::<MathArena>().unwrap().used = 0;
+}
+
+/// Next vector of the arena, overwritten with the value.
+pub fn temp_vec3(lua: &Lua, value: Vec3) -> Result<AnyUserData<'_>> {
+    let pool = lua.named_registry_value::<Table>("math_arena")?;
+    let index = {
+        let mut arena = lua.app_data_mut::<MathArena>().unwrap();
+        arena.used += 1;
+        arena.used
+    };
+    if let Some(vec) = pool.raw_get::<_, Option<AnyUserData>>(index)? {
+        *vec.borrow_mut::<Vec3>()? = value;
+        return Ok(vec);
+    }
+    let vec = lua.create_any_userdata(value)?;
+    pool.raw_set(index, vec.clone())?;
+    Ok(vec)
+}
+
+/// Result of an operation, from the arena when enabled.
+pub fn vec3(lua: &Lua, value: Vec3) -> Result<AnyUserData<'_>> {
+    if lua.app_data_ref::<MathArena>().unwrap().enabled {
+        return temp_vec3(lua, value);
+    }
+    lua.create_any_userdata(value)
+}
@@ -0,0 +1,91 @@
+use glam::Vec3;
+use mlua::{AnyUserData, Error, Lua, Result, Table, UserDataMethods};
+
+use crate::collision::{Collisions, Shape};
+
+use super::{api::register_type, register::vec3_arg};
+
+fn vec3_field(values: &Table, key: &str) -> Result<Vec3> {
+    let value = values
+        .raw_get::<_, Option<AnyUserData>>(key)?
+        .ok_or_else(|| Error::runtime(format!("collider needs {}", key)))?;
+    vec3_arg(&value)
+}
+
+/// `{ type = "sphere", pos, r }`, `{ type = "aabb", min, max }` or
+/// `{ type = "capsule", a, b, r }`.
+fn shape_arg(values: &Table) -> Result<Shape> {
+    let kind = values.raw_get::<_, String>("type")?;
+    let radius = || -> Result<f32> {
+        let radius = values.raw_get::<_, f32>("r")?;
+        if radius < 0.0 {
+            return Err(Error::runtime("collider radius must not be negative"));
+        }
+        Ok(radius)
+    };
+    Ok(match kind.as_str() {
+        "sphere" => Shape::Sphere {
+            center: vec3_field(values, "pos")?,
+            radius: radius()?,
+        },
+        "aabb" => {
+            let (a, b) =
+                (vec3_field(values, "min")?, vec3_field(values, "max")?);
+            Shape::Aabb {
+                min: a.min(b),
+                max: a.max(b),
+            }
+        }
+        "capsule" => Shape::Capsule {
+            a: vec3_field(values, "a")?,
+            b: vec3_field(values, "b")?,
+            radius: radius()?,
+        },
+        _ => {
+            return Err(Error::runtime(format!(
+                "unknown collider type {}",
+                kind
+            )))
+        }
+    })
+}
+
+fn collider<'a>(this: &'a Collisions, id: &str) -> Result<&'a Shape> {
+    this.get(id)
+        .ok_or_else(|| Error::runtime(format!("no collider {}", id)))
+}
+
+pub fn register_collision(lua: &Lua) -> Result<()> {
+    register_type::<Collisions>(lua, "Collision", |reg| {
+        reg.add_method_mut("add", |_, this, (id, values): (String, Table)| {
+            this.insert(&id, shape_arg(&values)?);
+            Ok(())
+        });
+        reg.add_method_mut("remove", |_, this, id: String| {
+            this.remove(&id);
+            Ok(())
+        });
+        reg.add_method_mut("clear", |_, this, ()| {
+            this.clear();
+            Ok(())
+        });
+        reg.add_method("overlaps", |_, this, id: String| {
+            Ok(this.overlapping(collider(this, &id)?, &id))
+        });
+        reg.add_method(
+            "sweep",
+            |lua, this, (id, dir, distance): (String, AnyUserData, f32)| {
+                let dir = vec3_arg(&dir)?;
+                let shape = collider(this, &id)?;
+                let Some(hit) = this.sweep(shape, &id, dir, distance) else {
+                    return Ok(None);
+                };
+                let table = lua.create_table()?;
+                table.raw_set("id", hit.id)?;
+                table.raw_set("distance", hit.distance)?;
+                table.raw_set("normal", AnyUserData::wrap(hit.normal))?;
+                Ok(Some(table))
+            },
+        );
+    })
+}
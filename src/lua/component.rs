@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+
+use log::error;
+use mlua::{AnyUserData, Function, Lua, Nil, Result, Table};
+
+use crate::entity::{Entities, EntityId};
+
+use super::limits;
+
+struct Component {
+    /// Module required to get the update function.
+    module: String,
+    /// Error that disabled the component, until its script reloads.
+    error: Option<String>,
+}
+
+/// Scripts attached to the entities, each one runs and fails on its own.
+/// Their state tables are in the `script_components` registry table, by
+/// entity id then module.
+#[derive(Default)]
+struct Components {
+    components: BTreeMap<EntityId, Vec<Component>>,
+}
+
+pub fn register_components(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("script_components", lua.create_table()?)?;
+    lua.set_app_data(Components::default());
+    Ok(())
+}
+
+fn states(lua: &Lua, id: EntityId) -> Result<Table<'_>> {
+    let components = lua.named_registry_value::<Table>("script_components")?;
+    if let Some(states) = components.raw_get::<_, Option<Table>>(id.0)? {
+        return Ok(states);
+    }
+    let states = lua.create_table()?;
+    components.raw_set(id.0, states.clone())?;
+    Ok(states)
+}
+
+/// Attach the module to the entity with its state, replacing the previous
+/// one of the same module.
+pub fn attach<'lua>(
+    lua: &'lua Lua,
+    id: EntityId,
+    module: String,
+    state: Table<'lua>,
+) -> Result<()> {
+    states(lua, id)?.raw_set(module.as_str(), state)?;
+    let mut components = lua.app_data_mut::<Components>().unwrap();
+    let entity = components.components.entry(id).or_default();
+    entity.retain(|component| component.module != module);
+    entity.push(Component {
+        module,
+        error: None,
+    });
+    Ok(())
+}
+
+/// Drop every component, for the entities replaced by a loaded scene.
+pub fn clear(lua: &Lua) -> Result<()> {
+    lua.app_data_mut::<Components>().unwrap().components.clear();
+    lua.set_named_registry_value("script_components", lua.create_table()?)
+}
+
+pub fn detach(lua: &Lua, id: EntityId, module: &str) -> Result<()> {
+    states(lua, id)?.raw_set(module, Nil)?;
+    let mut components = lua.app_data_mut::<Components>().unwrap();
+    if let Some(entity) = components.components.get_mut(&id) {
+        entity.retain(|component| component.module != module);
+    }
+    Ok(())
+}
+
+/// Let the failed components of a reloaded module run again.
+pub fn retry(lua: &Lua, module: &str) {
+    let mut components = lua.app_data_mut::<Components>().unwrap();
+    components
+        .components
+        .values_mut()
+        .flatten()
+        .filter(|component| component.module == module)
+        .for_each(|component| component.error = None);
+}
+
+/// Components disabled by an error, as `entity module: error` lines.
+#[cfg(feature = "ui")]
+pub fn quarantined(lua: &Lua) -> Vec<String> {
+    let components = lua.app_data_ref::<Components>().unwrap();
+    components
+        .components
+        .iter()
+        .flat_map(|(id, entity)| entity.iter().map(move |c| (id, c)))
+        .filter_map(|(id, component)| {
+            let error = component.error.as_ref()?.lines().next()?;
+            Some(format!("{} {}: {}", id.0, component.module, error))
+        })
+        .collect()
+}
+
+/// Drop the components of the removed entities.
+pub fn prune(lua: &Lua, entities: &Entities) -> Result<()> {
+    let removed = {
+        let mut components = lua.app_data_mut::<Components>().unwrap();
+        let removed = components
+            .components
+            .keys()
+            .filter(|id| entities.get(**id).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        for id in &removed {
+            components.components.remove(id);
+        }
+        removed
+    };
+    let states = lua.named_registry_value::<Table>("script_components")?;
+    for id in removed {
+        states.raw_set(id.0, Nil)?;
+    }
+    Ok(())
+}
+
+/// Call `update(state, ctx, entity, dt)` of every healthy component in
+/// entity id order, a failing one is quarantined without stopping the
+/// others.
+pub fn run<'lua>(lua: &'lua Lua, ctx: &Table<'lua>, dt: f32) -> Result<()> {
+    let healthy = lua
+        .app_data_ref::<Components>()
+        .unwrap()
+        .components
+        .iter()
+        .flat_map(|(id, entity)| {
+            entity
+                .iter()
+                .filter(|component| component.error.is_none())
+                .map(|component| (*id, component.module.clone()))
+        })
+        .collect::<Vec<_>>();
+    let require = lua.globals().get::<_, Function>("require")?;
+    for (id, module) in healthy {
+        limits::reset_budget(lua);
+        let result = (|| {
+            let Some(state) = states(lua, id)?
+                .raw_get::<_, Option<Table>>(module.as_str())?
+            else {
+                return Ok(());
+            };
+            let table = require.call::<_, Table>(module.as_str())?;
+            let Some(update) =
+                table.raw_get::<_, Option<Function>>("update")?
+            else {
+                return Ok(());
+            };
+            let entity = AnyUserData::wrap(id);
+            update.call::<_, ()>((state, ctx.clone(), entity, dt))
+        })();
+        if let Err(err) = result {
+            error!("script {} of entity {}\n{}", module, id.0, err);
+            let mut components = lua.app_data_mut::<Components>().unwrap();
+            if let Some(component) =
+                components.components.get_mut(&id).and_then(|entity| {
+                    entity.iter_mut().find(|c| c.module == module)
+                })
+            {
+                component.error = Some(err.to_string());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use log::error;
+use mlua::{
+    Function, Lua, MetaMethod, Nil, Result, Table, UserDataMethods,
+    UserDataRef, Value,
+};
+
+use crate::{events::EngineEvent, register_to_string};
+
+use super::{api::register_type, limits, shared::caller};
+
+/// Handler registered by `events:on`, to remove it with `events:off`.
+#[derive(Clone, Copy, Debug)]
+pub struct EventHandle(u32);
+
+/// Last id given to a handler.
+#[derive(Default)]
+struct NextHandle(u32);
+
+/// Handlers of an event in registration order, as `{ id, source, line,
+/// handler }` entries.
+fn handlers<'lua>(lua: &'lua Lua, name: &str) -> Result<Table<'lua>> {
+    let handlers = lua.named_registry_value::<Table>("event_handlers")?;
+    if let Some(name_handlers) = handlers.raw_get::<_, Option<Table>>(name)? {
+        return Ok(name_handlers);
+    }
+    let name_handlers = lua.create_table()?;
+    handlers.raw_set(name, name_handlers.clone())?;
+    Ok(name_handlers)
+}
+
+/// Drop the handlers of the event matching the filter, keeping the order
+/// of the others.
+fn remove_handlers(
+    lua: &Lua,
+    name: &str,
+    filter: impl Fn(&Table) -> Result<bool>,
+) -> Result<()> {
+    let kept = lua.create_table()?;
+    for entry in handlers(lua, name)?.sequence_values::<Table>() {
+        let entry = entry?;
+        if !filter(&entry)? {
+            kept.raw_push(entry)?;
+        }
+    }
+    lua.named_registry_value::<Table>("event_handlers")?
+        .raw_set(name, kept)
+}
+
+/// Add the handler after the others. The call site of the script replaces
+/// the handler it registered before, when the script runs again.
+fn on(lua: &Lua, name: &str, handler: Function) -> Result<EventHandle> {
+    let source = caller(lua);
+    let line = lua.inspect_stack(1).map_or(0, |debug| debug.curr_line());
+    remove_handlers(lua, name, |entry| {
+        Ok(entry.raw_get::<_, String>("source")? == source
+            && entry.raw_get::<_, i32>("line")? == line)
+    })?;
+    let id = {
+        let mut next = lua.app_data_mut::<NextHandle>().unwrap();
+        next.0 += 1;
+        next.0
+    };
+    let entry = lua.create_table()?;
+    entry.raw_set("id", id)?;
+    entry.raw_set("source", source)?;
+    entry.raw_set("line", line)?;
+    entry.raw_set("handler", handler)?;
+    handlers(lua, name)?.raw_push(entry)?;
+    Ok(EventHandle(id))
+}
+
+/// Drop the handlers registered by the chunk, when it reloads.
+pub fn remove_source(lua: &Lua, source: &str) -> Result<()> {
+    let names = lua
+        .named_registry_value::<Table>("event_handlers")?
+        .pairs::<String, Value>()
+        .map(|pair| pair.map(|(name, _)| name))
+        .collect::<Result<Vec<_>>>()?;
+    for name in names {
+        remove_handlers(lua, &name, |entry| {
+            Ok(entry.raw_get::<_, String>("source")? == source)
+        })?;
+    }
+    Ok(())
+}
+
+fn push<'lua>(lua: &'lua Lua, name: &str, payload: Value<'lua>) -> Result<()> {
+    let queue = lua.named_registry_value::<Table>("event_queue")?;
+    queue.raw_push(lua.create_sequence_from([
+        Value::String(lua.create_string(name)?),
+        payload,
+    ])?)
+}
+
+fn payload<'lua>(lua: &'lua Lua, event: &EngineEvent) -> Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    match event {
+        EngineEvent::AssetLoaded { kind, id }
+        | EngineEvent::AssetFailed { kind, id } => {
+            table.raw_set("kind", *kind)?;
+            table.raw_set("id", id.as_str())?;
+            let failed = matches!(event, EngineEvent::AssetFailed { .. });
+            table.raw_set("failed", failed)?;
+        }
+        EngineEvent::AssetReloaded { kind, id }
+        | EngineEvent::AssetImported { kind, id } => {
+            table.raw_set("kind", *kind)?;
+            table.raw_set("id", id.as_str())?;
+        }
+        EngineEvent::FileDropped(path) => {
+            table.raw_set("path", path.to_string_lossy())?;
+        }
+        EngineEvent::WindowResized { width, height } => {
+            table.raw_set("width", *width)?;
+            table.raw_set("height", *height)?;
+        }
+    }
+    Ok(table)
+}
+
+fn loaded_key(kind: &str, id: &str) -> String {
+    format!("{}:{}", kind, id)
+}
+
+/// Call the callback with the events once the asset loads or fails to, on
+/// the next dispatch when it already did.
+pub fn on_loaded(
+    lua: &Lua,
+    kind: &str,
+    id: &str,
+    callback: Function,
+    loaded: bool,
+    failed: bool,
+) -> Result<()> {
+    if loaded || failed {
+        let payload = lua.create_table()?;
+        payload.raw_set("kind", kind)?;
+        payload.raw_set("id", id)?;
+        payload.raw_set("failed", failed)?;
+        let ready = lua.named_registry_value::<Table>("loaded_ready")?;
+        return ready.raw_push(lua.create_sequence_from([
+            Value::Table(payload),
+            Value::Function(callback),
+        ])?);
+    }
+    let callbacks = lua.named_registry_value::<Table>("loaded_callbacks")?;
+    let key = loaded_key(kind, id);
+    let waiting = match callbacks.raw_get::<_, Option<Table>>(key.as_str())? {
+        Some(waiting) => waiting,
+        None => {
+            let waiting = lua.create_table()?;
+            callbacks.raw_set(key, waiting.clone())?;
+            waiting
+        }
+    };
+    waiting.raw_push(callback)
+}
+
+/// Events emitted by the scripts and the engine, handled once the scripts
+/// updated. A reloaded script drops the handlers it registered before.
+pub fn register_events(lua: &Lua) -> Result<()> {
+    lua.set_app_data(NextHandle::default());
+    register_type::<EventHandle>(lua, "EventHandle", |reg| {
+        register_to_string!(reg);
+    })?;
+    lua.set_named_registry_value("event_handlers", lua.create_table()?)?;
+    lua.set_named_registry_value("event_queue", lua.create_table()?)?;
+    lua.set_named_registry_value("loaded_callbacks", lua.create_table()?)?;
+    lua.set_named_registry_value("loaded_ready", lua.create_table()?)?;
+
+    let table = lua.create_table()?;
+    table.set(
+        "emit",
+        lua.create_function(
+            |lua, (_, name, payload): (Value, String, Value)| {
+                push(lua, &name, payload)
+            },
+        )?,
+    )?;
+    table.set(
+        "on",
+        lua.create_function(
+            |lua, (_, name, handler): (Value, String, Function)| {
+                lua.create_any_userdata(on(lua, &name, handler)?)
+            },
+        )?,
+    )?;
+    // Without a handle, every handler of the calling script for the event.
+    table.set(
+        "off",
+        lua.create_function(
+            |lua,
+             (_, name, handle): (
+                Value,
+                String,
+                Option<UserDataRef<EventHandle>>,
+            )| {
+                let source = caller(lua);
+                remove_handlers(lua, &name, |entry| match &handle {
+                    Some(handle) => {
+                        Ok(entry.raw_get::<_, u32>("id")? == handle.0)
+                    }
+                    None => Ok(entry.raw_get::<_, String>("source")? == source),
+                })
+            },
+        )?,
+    )?;
+    lua.globals().set("events", table)
+}
+
+/// Queue the engine events after the ones emitted by the scripts and the
+/// callbacks waiting on the assets loaded, returns whether any is waiting.
+pub fn queue_engine(lua: &Lua, events: Vec<EngineEvent>) -> Result<bool> {
+    let callbacks = lua.named_registry_value::<Table>("loaded_callbacks")?;
+    let ready = lua.named_registry_value::<Table>("loaded_ready")?;
+    for event in &events {
+        let payload = payload(lua, event)?;
+        if let EngineEvent::AssetLoaded { kind, id }
+        | EngineEvent::AssetFailed { kind, id } = event
+        {
+            let key = loaded_key(kind, id);
+            if let Some(waiting) =
+                callbacks.raw_get::<_, Option<Table>>(key.as_str())?
+            {
+                callbacks.raw_set(key, Nil)?;
+                for callback in waiting.sequence_values::<Function>() {
+                    ready.raw_push(lua.create_sequence_from([
+                        Value::Table(payload.clone()),
+                        Value::Function(callback?),
+                    ])?)?;
+                }
+            }
+        }
+        push(lua, event.name(), Value::Table(payload))?;
+    }
+    let queue = lua.named_registry_value::<Table>("event_queue")?;
+    Ok(queue.raw_len() > 0 || ready.raw_len() > 0)
+}
+
+/// Call the handlers of the queued events with the context and payload, the
+/// events emitted meanwhile wait for the next frame. A failing handler is
+/// logged without stopping the others.
+pub fn dispatch(lua: &Lua, ctx: &Table) -> Result<()> {
+    let queue = lua.named_registry_value::<Table>("event_queue")?;
+    lua.set_named_registry_value("event_queue", lua.create_table()?)?;
+    for event in queue.sequence_values::<Table>() {
+        let event = event?;
+        let name = event.raw_get::<_, String>(1)?;
+        let payload = event.raw_get::<_, Value>(2)?;
+        // Copied, the handlers may register or remove others.
+        let name_handlers = handlers(lua, &name)?
+            .sequence_values::<Table>()
+            .map(|entry| {
+                let entry = entry?;
+                Ok((
+                    entry.raw_get::<_, String>("source")?,
+                    entry.raw_get::<_, Function>("handler")?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (source, handler) in name_handlers {
+            limits::reset_budget(lua);
+            if let Err(err) =
+                handler.call::<_, ()>((ctx.clone(), payload.clone()))
+            {
+                error!("event {} in {}\n{}", name, source, err);
+            }
+        }
+    }
+    let ready = lua.named_registry_value::<Table>("loaded_ready")?;
+    lua.set_named_registry_value("loaded_ready", lua.create_table()?)?;
+    for entry in ready.sequence_values::<Table>() {
+        let entry = entry?;
+        let payload = entry.raw_get::<_, Table>(1)?;
+        let callback = entry.raw_get::<_, Function>(2)?;
+        limits::reset_budget(lua);
+        if let Err(err) = callback.call::<_, ()>((ctx.clone(), payload.clone()))
+        {
+            let id = payload.raw_get::<_, String>("id")?;
+            error!("on_loaded {}\n{}", id, err);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::{cell::Cell, rc::Rc};
+
+use mlua::{Error, Lua, Result, VmState};
+use serde::Deserialize;
+
+/// Protection of the app against the scripts, the `[lua]` table of the
+/// `project.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Libraries and engine globals are read only, the globals of the
+    /// scripts live in a table of their own.
+    pub sandbox: bool,
+    /// Interrupts allowed per entry into the scripts: the update, a fixed
+    /// update, a component, a task or an event handler. The VM checks in at
+    /// function calls and loop iterations, past it the running script is
+    /// aborted, 0 for no limit.
+    pub instruction_budget: u64,
+    /// Megabytes the VM can allocate, 0 for no limit.
+    pub memory_limit_mb: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            sandbox: true,
+            instruction_budget: 10_000_000,
+            memory_limit_mb: 0,
+        }
+    }
+}
+
+impl Limits {
+    /// Set up the VM once the engine globals are registered, before any
+    /// script runs.
+    pub fn install(&self, lua: &Lua) -> Result<()> {
+        if self.sandbox {
+            lua.sandbox(true)?;
+        }
+        if self.memory_limit_mb > 0 {
+            lua.set_memory_limit(self.memory_limit_mb * 1024 * 1024)?;
+        }
+        let interrupts = Rc::new(Cell::new(0));
+        lua.set_app_data(Interrupts(interrupts.clone()));
+        let budget = self.instruction_budget;
+        if budget > 0 {
+            let interrupts = interrupts.clone();
+            lua.set_interrupt(move |_| {
+                interrupts.set(interrupts.get() + 1);
+                if interrupts.get() > budget {
+                    return Err(Error::runtime(format!(
+                        "instruction budget of {} exceeded",
+                        budget
+                    )));
+                }
+                Ok(VmState::Continue)
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Interrupts counted since the running entry into the scripts started.
+struct Interrupts(Rc<Cell<u64>>);
+
+/// Give the entry about to run a full budget, so a script is not aborted
+/// for what the ones before it spent.
+pub fn reset_budget(lua: &Lua) {
+    if let Some(interrupts) = lua.app_data_ref::<Interrupts>() {
+        interrupts.0.set(0);
+    }
+}
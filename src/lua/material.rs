@@ -0,0 +1,64 @@
+use glam::{Vec2, Vec3, Vec4};
+use log::error;
+use mlua::{Error, Lua, Result, UserDataFields, UserDataMethods, Value};
+
+use crate::render::{material::Param, state::RenderState};
+
+use super::api::register_type;
+
+/// Material of the graphics by key, the params set through it apply before
+/// the frame is rendered.
+#[derive(Debug, Clone)]
+pub struct MaterialHandle(pub String);
+
+/// Params set through the handles since the last frame, by material key.
+#[derive(Default)]
+struct PendingParams(Vec<(String, String, Param)>);
+
+pub fn register_material(lua: &Lua) -> Result<()> {
+    lua.set_app_data(PendingParams::default());
+    register_type::<MaterialHandle>(lua, "Material", |reg| {
+        reg.add_field_method_get("key", |_, this| Ok(this.0.clone()));
+        reg.add_method("set", |lua, this, (name, value): (String, Value)| {
+            let param = param_arg(value)?;
+            lua.app_data_mut::<PendingParams>().unwrap().0.push((
+                this.0.clone(),
+                name,
+                param,
+            ));
+            Ok(())
+        });
+    })
+}
+
+/// A number, a `Vec3` or an array of two to four numbers.
+pub fn param_arg(value: Value) -> Result<Param> {
+    Ok(match value {
+        Value::Number(value) => Param::Float(value as f32),
+        Value::Integer(value) => Param::Float(value as f32),
+        Value::UserData(value) => Param::Vec3(*value.borrow::<Vec3>()?),
+        Value::Table(values) => {
+            let values = values
+                .sequence_values::<f32>()
+                .collect::<Result<Vec<_>>>()?;
+            match values.len() {
+                2 => Param::Vec2(Vec2::from_slice(&values)),
+                3 => Param::Vec3(Vec3::from_slice(&values)),
+                4 => Param::Vec4(Vec4::from_slice(&values)),
+                _ => return Err(Error::runtime("invalid param array length")),
+            }
+        }
+        _ => return Err(Error::runtime("invalid param value")),
+    })
+}
+
+/// The params of a removed material or unknown to it are logged.
+pub fn apply_params(lua: &Lua, render_state: &mut RenderState) {
+    let pending =
+        std::mem::take(&mut lua.app_data_mut::<PendingParams>().unwrap().0);
+    for (key, name, value) in pending {
+        if let Err(err) = render_state.materials.set_param(&key, &name, value) {
+            error!("material param\n{}", err);
+        }
+    }
+}
@@ -1,22 +1,62 @@
-use std::{ops::Deref, sync::Arc, time::Instant};
+use std::{
+    collections::BTreeSet, fs, ops::Deref, path::Path, sync::Arc, time::Instant,
+};
 
 use anyhow::Result;
+use api::Api;
 use assets_manager::{loader, Asset, AssetCache};
-use log::error;
-use mlua::{Compiler, Function, Lua};
-use register::{create_scoped_context, register_types_globals};
+use limits::Limits;
+use log::{error, info};
+use mlua::{AnyUserData, Compiler, Function, Lua, Nil, Scope, Table};
+#[cfg(feature = "physics")]
+use register::sync_rigid_bodies;
+use register::{
+    create_scoped_context, register_types_globals, set_engine_args,
+    sync_viewports, sync_window_cameras,
+};
 use winit::window::Window;
 
+#[cfg(feature = "audio")]
+use crate::audio::Audio;
+#[cfg(feature = "mic")]
+use crate::mic::Mic;
+#[cfg(feature = "physics")]
+use crate::physics::Physics;
+#[cfg(feature = "ui")]
+use crate::ui::Ui;
 use crate::{
-    app::RELOAD_DEBOUNCE, input::Inputs, render::state::RenderState,
+    app::{AppState, RELOAD_DEBOUNCE},
+    assets,
+    collision::Collisions,
+    constraint::Constraints,
+    entity::Entities,
+    events::EngineEvent,
+    import,
+    input::Inputs,
+    interpolation::Interpolation,
+    render::{
+        buffers::BufferData,
+        state::{RenderState, SCREENSHOT_DIFF_TEXTURE},
+    },
     scene::Scene,
+    wind::Wind,
 };
 
+mod api;
+mod arena;
+mod collision;
+mod component;
+mod events;
+pub mod limits;
+mod material;
+mod persist;
 mod register;
+mod scene_switch;
+mod scheduler;
+mod shared;
+mod timeline;
 mod utils;
 
-const SCRIPTS_DIR: &str = "assets/scripts";
-
 struct LuauScript(String);
 
 impl From<String> for LuauScript {
@@ -30,24 +70,50 @@ impl Asset for LuauScript {
     type Loader = loader::LoadFrom<String, loader::StringLoader>;
 }
 
+/// Engine state exposed to the scripts.
+pub struct Context<'a> {
+    pub app: &'a mut AppState,
+    #[cfg(feature = "audio")]
+    pub audio: &'a mut Audio,
+    pub collision: &'a mut Collisions,
+    pub inputs: &'a mut Inputs,
+    #[cfg(feature = "mic")]
+    pub mic: &'a mut Mic,
+    #[cfg(feature = "physics")]
+    pub physics: &'a mut Physics,
+    pub render_state: &'a mut RenderState,
+    pub scene: &'a mut Scene,
+    #[cfg(feature = "ui")]
+    pub ui: &'a mut Ui,
+    pub wind: &'a mut Wind,
+    pub window: Arc<Window>,
+}
+
 pub struct LuaState {
     cache: AssetCache,
     entry_point: String,
     last_reload: Instant,
     lua: Lua,
-    update_got_error: bool,
+    /// Ids of the scripts found in the directory, watched for changes.
+    scripts: BTreeSet<String>,
+    /// Error that stopped the global update, until a script reloads.
+    update_error: Option<String>,
 }
 
 impl LuaState {
     /// Load lua script entrypoint, will get hot-reloaded.
     /// This should contains a global update and init function.
-    pub fn new(entry_point: &str) -> Self {
+    /// The arguments are exposed as `engine.args`.
+    pub fn new(entry_point: &str, args: Vec<String>, limits: &Limits) -> Self {
         let lua = Lua::new();
         lua.set_compiler(Compiler::new().set_type_info_level(1));
 
         register_types_globals(&lua).unwrap();
+        set_engine_args(&lua, args).unwrap();
+        set_require_root(&lua).unwrap();
+        limits.install(&lua).unwrap();
 
-        let cache = AssetCache::new(SCRIPTS_DIR).unwrap();
+        let cache = AssetCache::new(assets::kind_dir("scripts")).unwrap();
         {
             let handle = cache.load_expect::<LuauScript>(entry_point);
             Self::load_entry_point(&lua, handle.read().0.deref());
@@ -58,27 +124,17 @@ impl LuaState {
             entry_point: entry_point.to_string(),
             last_reload: Instant::now(),
             lua,
-            update_got_error: false,
+            scripts: scan_scripts(),
+            update_error: None,
         }
     }
 
-    pub fn init(
-        &mut self,
-        scene: &mut Scene,
-        inputs: &Inputs,
-        window: Arc<Window>,
-        render_state: &mut RenderState,
-    ) -> Result<()> {
-        let result = self.lua.scope(|scope| {
+    pub fn init(&mut self, context: &mut Context) -> Result<()> {
+        self.lua
+            .set_app_data(context.render_state.textures.image_reader());
+        let result = self.scope(|scope| {
             let init_fn = self.lua.globals().get::<_, Function>("init")?;
-            let ctx = create_scoped_context(
-                &self.lua,
-                scope,
-                scene,
-                inputs,
-                window,
-                render_state,
-            )?;
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
             init_fn.call::<_, ()>(ctx)?;
             Ok(())
         });
@@ -88,72 +144,463 @@ impl LuaState {
         Ok(())
     }
 
+    /// Write the luau definitions of the bindings, once the context of the
+    /// init got recorded.
+    pub fn emit_defs(&self, path: &Path) -> Result<()> {
+        let api = self.lua.app_data_ref::<Api>().unwrap();
+        fs::write(path, api.definitions())?;
+        info!("Lua definitions written to {}", path.display());
+        Ok(())
+    }
+
     fn load_entry_point(lua: &Lua, data: &str) {
+        limits::reset_budget(lua);
         if let Err(err) = lua.load(data).set_name("entry_point").exec() {
             error!("entry_point\n{}", err.to_string());
         }
     }
 
-    fn any_script_reloaded(&self) -> Result<Option<String>> {
-        let scripts_dir = self.cache.load_dir::<LuauScript>("")?.read();
-        for script_id in scripts_dir.ids() {
-            let reloaded = self
-                .cache
-                .load_expect::<LuauScript>(script_id)
-                .reloaded_global();
-            if reloaded {
-                return Ok(Some(script_id.to_string()));
-            }
+    fn any_script_reloaded(&self) -> Option<String> {
+        self.scripts
+            .iter()
+            .find(|script_id| {
+                self.cache
+                    .load::<LuauScript>(script_id)
+                    .is_ok_and(|handle| handle.reloaded_global())
+            })
+            .cloned()
+    }
+
+    /// Scripts created since the last scan, deleted ones are forgotten.
+    fn new_scripts(&mut self) -> Vec<String> {
+        let scripts = scan_scripts();
+        let added = scripts.difference(&self.scripts).cloned().collect();
+        self.scripts = scripts;
+        added
+    }
+
+    /// Call the optional global on_new_script with the id of a created
+    /// script, it can be required right away.
+    fn new_script(&self, context: &mut Context, script_id: &str) {
+        info!("Script added: {}", script_id);
+        let result = self.scope(|scope| {
+            let globals = self.lua.globals();
+            let Some(on_new_script_fn) =
+                globals.get::<_, Option<Function>>("on_new_script")?
+            else {
+                return Ok(());
+            };
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            on_new_script_fn.call::<_, ()>((ctx, script_id))?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            error!("on_new_script\n{}", err);
         }
-        Ok(None)
     }
 
     pub fn update(
         &mut self,
-        scene: &mut Scene,
-        inputs: &Inputs,
-        window: Arc<Window>,
-        render_state: &mut RenderState,
+        context: &mut Context,
         delta_sec: f32,
         elapsed_sec: f32,
     ) -> Result<()> {
         self.cache.hot_reload();
-        let handle = self.cache.load_expect::<LuauScript>(&self.entry_point);
 
         if self.last_reload.elapsed() >= RELOAD_DEBOUNCE {
             self.last_reload = Instant::now();
-            if let Some(script_id) = self.any_script_reloaded()? {
-                let mod_name = format!("{}/{}", SCRIPTS_DIR, script_id);
+            for script_id in self.new_scripts() {
+                self.new_script(context, &script_id);
+            }
+            if let Some(script_id) = self.any_script_reloaded() {
+                // Modules are required by their path from the project
+                // directory, their chunk is named after the file.
+                let scripts_dir = assets::root().join("scripts");
+                let required_dir = scripts_dir
+                    .strip_prefix(assets::project_dir())
+                    .unwrap_or(&scripts_dir);
+                let mod_name =
+                    format!("{}/{}", required_dir.display(), script_id);
                 self.lua.unload(&mod_name)?;
-                self.update_got_error = false;
+                let source = match script_id == self.entry_point {
+                    true => "entry_point".to_string(),
+                    false => {
+                        format!("={}/{}.luau", scripts_dir.display(), script_id)
+                    }
+                };
+                scheduler::cancel_source(&self.lua, &source)?;
+                events::remove_source(&self.lua, &source)?;
+                component::retry(&self.lua, &mod_name);
+                context.app.events.push(EngineEvent::AssetReloaded {
+                    kind: "script",
+                    id: script_id,
+                });
+                self.update_error = None;
+                let handle =
+                    self.cache.load_expect::<LuauScript>(&self.entry_point);
                 Self::load_entry_point(&self.lua, handle.read().0.deref());
             }
         }
 
-        if self.update_got_error {
+        if self.update_error.is_some() {
             return Ok(());
         }
 
-        let result = self.lua.scope(|scope| {
+        arena::reset(&self.lua);
+        let result = self.scope(|scope| {
             let update_fn = self.lua.globals().get::<_, Function>("update")?;
-            let ctx = create_scoped_context(
-                &self.lua,
-                scope,
-                scene,
-                inputs,
-                window,
-                render_state,
-            )?;
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
             update_fn.call::<_, ()>((ctx, delta_sec, elapsed_sec))?;
             Ok(())
         });
         if let Err(err) = result {
-            if !self.update_got_error {
-                self.update_got_error = true;
+            if self.update_error.is_none() {
                 error!("update\n{}", err);
+                self.update_error = Some(err.to_string());
             }
         }
 
         Ok(())
     }
+
+    /// Update the scripts attached to the entities, they keep running when
+    /// the global update failed.
+    pub fn run_components(&mut self, context: &mut Context, dt: f32) {
+        let result = component::prune(&self.lua, &context.scene.entities)
+            .and_then(|_| {
+                self.scope(|scope| {
+                    let ctx = create_scoped_context(&self.lua, scope, context)?;
+                    component::run(&self.lua, &ctx, dt)
+                })
+            });
+        if let Err(err) = result {
+            error!("components\n{}", err);
+        }
+    }
+
+    /// Error of the global update and of the script components disabled
+    /// by one, for the overlay.
+    #[cfg(feature = "ui")]
+    pub fn script_errors(&self) -> Vec<String> {
+        let update = self
+            .update_error
+            .as_ref()
+            .and_then(|err| Some(format!("update: {}", err.lines().next()?)));
+        update
+            .into_iter()
+            .chain(component::quarantined(&self.lua))
+            .collect()
+    }
+
+    /// Run scripts in a scope with a fresh instruction budget.
+    fn scope<'lua, 'scope, R>(
+        &'lua self,
+        f: impl FnOnce(&Scope<'lua, 'scope>) -> mlua::Result<R>,
+    ) -> mlua::Result<R>
+    where
+        'lua: 'scope,
+    {
+        limits::reset_budget(&self.lua);
+        self.lua.scope(f)
+    }
+
+    /// Advance the tweens and resume the scheduled tasks due this frame.
+    pub fn run_scheduler(&mut self, context: &mut Context, dt: f32) {
+        if self.update_error.is_some() {
+            return;
+        }
+
+        let result = self.scope(|scope| {
+            scheduler::run_tweens(&self.lua, &mut context.scene.entities, dt)?;
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            scheduler::run(&self.lua, &ctx, dt)
+        });
+        if let Err(err) = result {
+            error!("scheduler\n{}", err);
+        }
+    }
+
+    /// Call the handlers of the events emitted by the scripts and of the
+    /// engine events queued since the last frame.
+    pub fn dispatch_events(&mut self, context: &mut Context) {
+        let engine_events = std::mem::take(&mut context.app.events);
+        let result = self.scope(|scope| {
+            if !events::queue_engine(&self.lua, engine_events)? {
+                return Ok(());
+            }
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            events::dispatch(&self.lua, &ctx)
+        });
+        if let Err(err) = result {
+            error!("events\n{}", err);
+        }
+    }
+
+    /// Apply the timeline played by the scripts, after they updated.
+    pub fn play_timeline(&mut self, context: &mut Context, dt: f32) {
+        timeline::update(&self.lua, context.scene, context.render_state, dt);
+    }
+
+    /// Swap in the scene the scripts switch to once it is loaded, calling
+    /// their callback.
+    pub fn switch_scene(&mut self, context: &mut Context, dt: f32) {
+        let result = self.scope(|scope| {
+            let Some(switched) = scene_switch::update(
+                &self.lua,
+                context.scene,
+                context.render_state,
+                dt,
+            )?
+            else {
+                return Ok(());
+            };
+            let Some(on_switch) = switched.on_switch else {
+                return Ok(());
+            };
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            on_switch.call::<_, ()>((ctx, switched.data, switched.lights))
+        });
+        if let Err(err) = result {
+            error!("switch_to\n{}", err);
+        }
+    }
+
+    /// Call the optional global power_changed, when the machine starts or
+    /// stops running on battery.
+    pub fn power_changed(&mut self, context: &mut Context, on_battery: bool) {
+        let result = self.scope(|scope| {
+            let globals = self.lua.globals();
+            let Some(power_changed_fn) =
+                globals.get::<_, Option<Function>>("power_changed")?
+            else {
+                return Ok(());
+            };
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            power_changed_fn.call::<_, ()>((ctx, on_battery))?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            error!("power_changed\n{}", err);
+        }
+    }
+
+    /// Call the callbacks of the buffers read back since the last frame.
+    pub fn deliver_readbacks(&mut self, context: &mut Context) {
+        let readbacks = context.render_state.take_readbacks();
+        if readbacks.is_empty() {
+            return;
+        }
+        let result = self.scope(|scope| {
+            let callbacks =
+                self.lua.named_registry_value::<Table>("readbacks")?;
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            for (id, data) in readbacks {
+                let Some(callback) =
+                    callbacks.raw_get::<_, Option<Function>>(id)?
+                else {
+                    continue;
+                };
+                callbacks.raw_set(id, Nil)?;
+                match data {
+                    Ok(data) => {
+                        let data =
+                            self.lua.create_any_userdata(BufferData(data))?;
+                        // A failing callback does not lose the others.
+                        if let Err(err) =
+                            callback.call::<_, ()>((ctx.clone(), data))
+                        {
+                            error!("read_buffer\n{}", err);
+                        }
+                    }
+                    Err(err) => error!("read_buffer\n{:?}", err),
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            error!("read_buffer\n{}", err);
+        }
+    }
+
+    /// Call the callbacks of the screenshot comparisons done since the last
+    /// frame.
+    pub fn deliver_screenshots(&mut self, context: &mut Context) {
+        let diffs = context.render_state.take_screenshot_diffs();
+        if diffs.is_empty() {
+            return;
+        }
+        let result = self.scope(|scope| {
+            let callbacks =
+                self.lua.named_registry_value::<Table>("screenshots")?;
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            for (id, diff) in diffs {
+                let Some(callback) =
+                    callbacks.raw_get::<_, Option<Function>>(id)?
+                else {
+                    continue;
+                };
+                callbacks.raw_set(id, Nil)?;
+                match diff {
+                    Ok(diff) => {
+                        let table = self.lua.create_table()?;
+                        table.raw_set("created", diff.created)?;
+                        table.raw_set(
+                            "different_pixels",
+                            diff.different_pixels,
+                        )?;
+                        table.raw_set("max_difference", diff.max_difference)?;
+                        table
+                            .raw_set("mean_difference", diff.mean_difference)?;
+                        table.raw_set("heatmap", SCREENSHOT_DIFF_TEXTURE)?;
+                        callback.call::<_, ()>((ctx.clone(), table))?;
+                    }
+                    Err(err) => error!("compare_screenshot\n{:?}", err),
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            error!("compare_screenshot\n{}", err);
+        }
+    }
+
+    /// Call the optional global fixed_update, at the app fixed rate.
+    pub fn fixed_update(
+        &mut self,
+        context: &mut Context,
+        fixed_dt: f32,
+    ) -> Result<()> {
+        if self.update_error.is_some() {
+            return Ok(());
+        }
+
+        let result = self.scope(|scope| {
+            let globals = self.lua.globals();
+            let Some(fixed_update_fn) =
+                globals.get::<_, Option<Function>>("fixed_update")?
+            else {
+                return Ok(());
+            };
+            let ctx = create_scoped_context(&self.lua, scope, context)?;
+            fixed_update_fn.call::<_, ()>((ctx, fixed_dt))?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            error!("fixed_update\n{}", err);
+            self.update_error = Some(err.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Call the render callbacks of the opened secondary windows, then hand
+    /// their cameras to the renderer.
+    pub fn render_windows(&mut self, context: &mut Context) {
+        if self.update_error.is_some() || context.render_state.views.is_empty()
+        {
+            return;
+        }
+
+        let names = context
+            .render_state
+            .views
+            .iter()
+            .map(|view| view.name.clone())
+            .collect::<Vec<_>>();
+        let result = self
+            .lua
+            .scope(|scope| {
+                let windows =
+                    self.lua.named_registry_value::<Table>("windows")?;
+                let ctx = create_scoped_context(&self.lua, scope, context)?;
+                for name in names {
+                    let Some(window) =
+                        windows.raw_get::<_, Option<Table>>(name)?
+                    else {
+                        continue;
+                    };
+                    if let Some(render_fn) =
+                        window.raw_get::<_, Option<Function>>("render")?
+                    {
+                        let camera =
+                            window.raw_get::<_, AnyUserData>("camera")?;
+                        render_fn.call::<_, ()>((ctx.clone(), camera))?;
+                    }
+                }
+                Ok(())
+            })
+            .and_then(|_| sync_window_cameras(&self.lua, context.render_state));
+        if let Err(err) = result {
+            error!("render_windows\n{}", err);
+            self.update_error = Some(err.to_string());
+        }
+    }
+
+    /// Forget the camera and render callback of a closed secondary window.
+    pub fn close_window(&self, name: &str) {
+        let result = self
+            .lua
+            .named_registry_value::<Table>("windows")
+            .and_then(|windows| windows.raw_set(name, Nil));
+        if let Err(err) = result {
+            error!("close_window\n{}", err);
+        }
+    }
+
+    /// Set the material params of the handles.
+    pub fn apply_material_params(&self, render_state: &mut RenderState) {
+        material::apply_params(&self.lua, render_state);
+    }
+
+    /// Hand the scene cameras with a viewport to the renderer.
+    pub fn sync_viewports(&self, render_state: &mut RenderState) {
+        if let Err(err) = sync_viewports(&self.lua, render_state) {
+            error!("viewports\n{}", err);
+        }
+    }
+
+    /// Move the entities to their pushed target transforms at the time.
+    pub fn apply_interpolation(&self, entities: &mut Entities, time: f32) {
+        if let Some(mut interpolation) =
+            self.lua.app_data_mut::<Interpolation>()
+        {
+            interpolation.apply(entities, time);
+        }
+    }
+
+    /// Evaluate the entity constraints added by the scripts.
+    pub fn apply_constraints(&self, entities: &mut Entities, dt: f32) {
+        if let Some(mut constraints) = self.lua.app_data_mut::<Constraints>() {
+            constraints.apply(entities, dt);
+        }
+    }
+
+    /// Write the simulated bodies into the script transforms.
+    #[cfg(feature = "physics")]
+    pub fn sync_physics(&self, physics: &mut Physics) {
+        if let Err(err) = sync_rigid_bodies(&self.lua, physics) {
+            error!("physics\n{}", err);
+        }
+    }
+}
+
+/// `require` looks the modules up from the project directory.
+fn set_require_root(lua: &Lua) -> mlua::Result<()> {
+    let dir = assets::project_dir();
+    let path = ["?.luau", "?.lua"]
+        .map(|template| dir.join(template).display().to_string())
+        .join(";");
+    lua.globals().get::<_, Table>("package")?.set("path", path)
+}
+
+/// Ids of the luau files under the scripts directory, none when it cannot
+/// be read.
+fn scan_scripts() -> BTreeSet<String> {
+    match import::sources("scripts", &["luau"]) {
+        Ok(sources) => sources.into_iter().map(|(id, _)| id).collect(),
+        Err(err) => {
+            error!("scripts\n{:?}", err);
+            BTreeSet::new()
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, ffi::c_void};
+
+use glam::{Quat, Vec3};
+use mlua::{AnyUserData, Error, IntoLua, Lua, Result, Table, Value};
+use serde::{Deserialize, Serialize};
+
+/// Copy of a Lua value kept on the rust side.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Persisted {
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Vec3(Vec3),
+    Quat(Quat),
+    Table(Vec<(Persisted, Persisted)>),
+}
+
+/// Nesting of the tables past which a value is refused.
+const MAX_DEPTH: usize = 64;
+
+impl Persisted {
+    /// Tables are copied deeply, none when the value is nil. A table
+    /// containing itself or nested too deeply is an error.
+    pub fn from_lua(value: Value) -> Result<Option<Self>> {
+        Self::from_lua_nested(value, &mut Vec::new())
+    }
+
+    /// Copy of the value inside the tables being copied.
+    fn from_lua_nested(
+        value: Value,
+        parents: &mut Vec<*const c_void>,
+    ) -> Result<Option<Self>> {
+        Ok(Some(match value {
+            Value::Nil => return Ok(None),
+            Value::Boolean(b) => Self::Boolean(b),
+            Value::Integer(i) => Self::Number(i as f64),
+            Value::Number(n) => Self::Number(n),
+            Value::String(s) => Self::String(s.to_str()?.to_string()),
+            Value::Table(table) => {
+                let pointer = table.to_pointer();
+                if parents.contains(&pointer) {
+                    return Err(Error::runtime(
+                        "a table containing itself cannot be persisted",
+                    ));
+                }
+                if parents.len() >= MAX_DEPTH {
+                    return Err(Error::runtime(format!(
+                        "tables nested deeper than {} cannot be persisted",
+                        MAX_DEPTH
+                    )));
+                }
+                parents.push(pointer);
+                let mut pairs = Vec::new();
+                for pair in table.pairs::<Value, Value>() {
+                    let (key, value) = pair?;
+                    let key = Self::from_lua_nested(key, parents)?;
+                    let value = Self::from_lua_nested(value, parents)?;
+                    pairs.extend(key.zip(value));
+                }
+                parents.pop();
+                Self::Table(pairs)
+            }
+            Value::UserData(ud) => Self::from_userdata(&ud)?,
+            _ => {
+                return Err(Error::runtime(format!(
+                    "a {} cannot be persisted",
+                    value.type_name()
+                )))
+            }
+        }))
+    }
+
+    fn from_userdata(ud: &AnyUserData) -> Result<Self> {
+        if let Ok(vec) = ud.borrow::<Vec3>() {
+            return Ok(Self::Vec3(*vec));
+        }
+        if let Ok(vec) = ud.borrow::<&mut Vec3>() {
+            return Ok(Self::Vec3(**vec));
+        }
+        if let Ok(quat) = ud.borrow::<Quat>() {
+            return Ok(Self::Quat(*quat));
+        }
+        if let Ok(quat) = ud.borrow::<&mut Quat>() {
+            return Ok(Self::Quat(**quat));
+        }
+        Err(Error::runtime(
+            "only Vec3 and Quat userdata can be persisted",
+        ))
+    }
+
+    pub fn to_lua<'lua>(&self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        match self {
+            Self::Boolean(b) => b.into_lua(lua),
+            Self::Number(n) => n.into_lua(lua),
+            Self::String(s) => s.as_str().into_lua(lua),
+            Self::Vec3(vec) => AnyUserData::wrap(*vec).into_lua(lua),
+            Self::Quat(quat) => AnyUserData::wrap(*quat).into_lua(lua),
+            Self::Table(pairs) => {
+                let table = lua.create_table()?;
+                for (key, value) in pairs {
+                    table.raw_set(key.to_lua(lua)?, value.to_lua(lua)?)?;
+                }
+                table.into_lua(lua)
+            }
+        }
+    }
+}
+
+/// Values of the `persist` global, kept out of the Lua state so reloads
+/// and scripts overwriting their globals do not lose them. Reads return
+/// copies, a value changed in place must be assigned again.
+#[derive(Default)]
+struct Persist(HashMap<String, Persisted>);
+
+pub fn register_persist(lua: &Lua) -> Result<()> {
+    lua.set_app_data(Persist::default());
+    let metatable = lua.create_table()?;
+    metatable.set(
+        "__index",
+        lua.create_function(|lua, (_, key): (Table, String)| {
+            let value =
+                lua.app_data_ref::<Persist>().unwrap().0.get(&key).cloned();
+            match value {
+                Some(value) => value.to_lua(lua),
+                None => Ok(Value::Nil),
+            }
+        })?,
+    )?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|lua, (_, key, value): (Table, String, Value)| {
+            let value = Persisted::from_lua(value)?;
+            let mut persist = lua.app_data_mut::<Persist>().unwrap();
+            match value {
+                Some(value) => persist.0.insert(key, value),
+                None => persist.0.remove(&key),
+            };
+            Ok(())
+        })?,
+    )?;
+    let table = lua.create_table()?;
+    table.set_metatable(Some(metatable));
+    lua.globals().set("persist", table)
+}
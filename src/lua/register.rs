@@ -1,41 +1,105 @@
 use std::{
+    collections::BTreeMap,
     fmt,
     ops::{Add, Div, Mul, Sub},
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
-use glam::{Quat, Vec3};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+#[cfg(feature = "physics")]
+use log::error;
 use log::info;
 use mlua::{
-    AnyUserData, Error, Function, Lua, MetaMethod, Result, Scope, Table,
-    UserDataFields, UserDataMethods, UserDataRef, UserDataRegistry, Value,
+    AnyUserData, Error, FromLua, Function, IntoLua, Lua, MetaMethod, Result,
+    Scope, Table, UserDataFields, UserDataMethods, UserDataRef, Value,
     Variadic,
 };
-use winit::window::{CursorGrabMode, Window};
+#[cfg(feature = "physics")]
+use rapier3d::prelude::RigidBodyHandle;
+use winit::{
+    dpi::LogicalSize,
+    window::{CursorGrabMode, Icon, UserAttentionType, Window},
+};
 
+#[cfg(feature = "audio")]
+use crate::audio::Audio;
+#[cfg(feature = "mic")]
+use crate::mic::Mic;
+#[cfg(feature = "physics")]
+use crate::physics::{BodySettings, Physics, ShapeKind};
+#[cfg(feature = "ui")]
+use crate::ui::{Panel, Ui};
 use crate::{
-    input::Inputs,
+    app::{AppState, WindowRequest},
+    assets,
+    bounds::Bounds,
+    camera_controller::{CameraController, ControllerKind},
+    camera_path::{CameraPath, PathState},
+    constraint::{Constraint, Constraints, Target},
+    entity::{Entity, EntityId},
+    input::{parse_key, Inputs},
+    interpolation::Interpolation,
     register_fields, register_getters, register_methods_mut,
     register_to_string,
     render::{
-        bundle::{lights, model},
+        adapter,
+        buffers::BufferData,
+        bundle::{
+            effect::{self, EffectPlane},
+            globals::{Ambient, Fog},
+            impostor::ImpostorSettings,
+            lights, minimap, model,
+            outline::Outline,
+            output,
+            pathtrace::Renderer,
+            points::{PointBatch, PointBuffer},
+            probes,
+        },
         camera::Camera,
-        material::simple::{self, SimpleMaterial},
+        export::ExportSettings,
+        material::{
+            custom::UniformBlock,
+            simple::{self, SimpleMaterial},
+        },
+        quality::Quality,
+        recording::RecordingSettings,
         state::RenderState,
+        stats::Stats,
+        texture::{Filter, ImageReader, SamplerSettings, Wrap},
     },
+    rope::RopeSettings,
     scene::Scene,
+    snapshot::Snapshot,
     transform::Transform,
+    wind::Wind,
+    window,
+};
+
+use super::{
+    api::{self, register_type, Api, ApiRegistry},
+    arena::{self, register_arena},
+    collision::register_collision,
+    component::{self, register_components},
+    events::{self, register_events},
+    material::{param_arg, register_material, MaterialHandle},
+    persist::{register_persist, Persisted},
+    scene_switch::{self, register_scene_switch},
+    scheduler::{register_scheduler, start_tween, TweenField, TweenTarget},
+    shared::register_shared,
+    timeline::register_timeline,
+    Context,
 };
 
 fn register_vec3_methods_mut<T: std::borrow::BorrowMut<Vec3> + fmt::Debug>(
-    reg: &mut UserDataRegistry<T>,
+    reg: &mut ApiRegistry<T>,
 ) {
     register_fields!(reg, T, { x, y, z });
     register_to_string!(reg);
     let mut reg_meta_op = |method: MetaMethod, op: fn(Vec3, Vec3) -> Vec3| {
         reg.add_meta_function(
             method,
-            move |_, (this, other): (UserDataRef<T>, Value)| {
+            move |lua, (this, other): (UserDataRef<T>, Value)| {
                 let this = this.borrow();
                 let to_vec3 = |value: f32| Vec3::splat(value);
                 let result = match other {
@@ -54,7 +118,7 @@ fn register_vec3_methods_mut<T: std::borrow::BorrowMut<Vec3> + fmt::Debug>(
                         ))
                     }
                 };
-                Ok(AnyUserData::wrap(result))
+                arena::vec3(lua, result)
             },
         );
     };
@@ -62,6 +126,9 @@ fn register_vec3_methods_mut<T: std::borrow::BorrowMut<Vec3> + fmt::Debug>(
     reg_meta_op(MetaMethod::Sub, Vec3::sub);
     reg_meta_op(MetaMethod::Mul, Vec3::mul);
     reg_meta_op(MetaMethod::Div, Vec3::div);
+    reg.add_method("copy", |_, this, _: ()| {
+        Ok(AnyUserData::wrap(*this.borrow()))
+    });
 }
 
 fn register_vec3(lua: &Lua) -> Result<()> {
@@ -79,6 +146,12 @@ fn register_vec3(lua: &Lua) -> Result<()> {
             Ok(AnyUserData::wrap(Vec3::splat(val)))
         })?,
     )?;
+    table.set(
+        "temp",
+        lua.create_function(|lua, (x, y, z): (f32, f32, f32)| {
+            arena::temp_vec3(lua, Vec3::new(x, y, z))
+        })?,
+    )?;
     table.set("X", AnyUserData::wrap(Vec3::X))?;
     table.set("Y", AnyUserData::wrap(Vec3::Y))?;
     table.set("Z", AnyUserData::wrap(Vec3::Z))?;
@@ -94,10 +167,155 @@ fn register_quat(lua: &Lua) -> Result<()> {
     lua.globals().set("Quat", table)
 }
 
+/// Owned vectors and the ones borrowed from a field.
+pub(super) fn vec3_arg(value: &AnyUserData) -> Result<Vec3> {
+    if let Ok(vec) = value.borrow::<Vec3>() {
+        return Ok(*vec);
+    }
+    Ok(**value.borrow::<&mut Vec3>()?)
+}
+
+fn probe(
+    probes: &mut probes::ReflectionProbes,
+    id: u32,
+) -> Result<&mut probes::ProbeSettings> {
+    probes
+        .probes
+        .get_mut(&id)
+        .ok_or_else(|| Error::runtime(format!("no reflection probe {}", id)))
+}
+
+fn attenuation_arg(name: Option<String>) -> Result<lights::Attenuation> {
+    match name {
+        Some(name) => lights::Attenuation::from_name(&name)
+            .ok_or(Error::runtime("unknown attenuation")),
+        None => Ok(lights::Attenuation::Radius),
+    }
+}
+
+/// `{ pos, radius, color?, intensity?, attenuation? }`, white at full
+/// intensity by default.
+fn point_light_arg(options: &Table) -> Result<lights::PointLight> {
+    let pos = vec3_arg(&options.raw_get::<_, AnyUserData>("pos")?)?;
+    let color = match options.raw_get::<_, Option<AnyUserData>>("color")? {
+        Some(color) => vec3_arg(&color)?,
+        None => Vec3::ONE,
+    };
+    let intensity = options.raw_get::<_, Option<f32>>("intensity")?;
+    if intensity.is_some_and(|intensity| intensity < 0.0) {
+        return Err(Error::runtime("light intensity must not be negative"));
+    }
+    let light = lights::PointLight::new(
+        pos,
+        options.raw_get::<_, f32>("radius")?,
+        attenuation_arg(options.raw_get("attenuation")?)?,
+    );
+    Ok(light.with_color(color, intensity.unwrap_or(1.0)))
+}
+
+/// World matrix and rotation of a model, from a transform or a raw matrix.
+fn model_matrix(value: &AnyUserData) -> Result<(Mat4, Quat)> {
+    if let Ok(matrix) = value.borrow::<Mat4>() {
+        let (_, rotation, _) = matrix.to_scale_rotation_translation();
+        return Ok((*matrix, rotation));
+    }
+    let transform = match value.borrow::<Transform>() {
+        Ok(transform) => *transform,
+        Err(_) => **value.borrow::<&mut Transform>()?,
+    };
+    Ok((transform.build_matrix(), transform.rot))
+}
+
+fn register_mat4(lua: &Lua) -> Result<()> {
+    register_type::<Mat4>(lua, "Mat4", |reg| {
+        register_to_string!(reg);
+        reg.add_method("inverse", |_, this, ()| {
+            Ok(AnyUserData::wrap(this.inverse()))
+        });
+        reg.add_method("transpose", |_, this, ()| {
+            Ok(AnyUserData::wrap(this.transpose()))
+        });
+        reg.add_method("transform_point", |_, this, point: AnyUserData| {
+            Ok(AnyUserData::wrap(this.transform_point3(vec3_arg(&point)?)))
+        });
+        reg.add_method("transform_vector", |_, this, vector: AnyUserData| {
+            Ok(AnyUserData::wrap(
+                this.transform_vector3(vec3_arg(&vector)?),
+            ))
+        });
+        reg.add_meta_function(
+            MetaMethod::Mul,
+            |lua, (this, other): (UserDataRef<Mat4>, AnyUserData)| {
+                if let Ok(other) = other.borrow::<Mat4>() {
+                    return AnyUserData::wrap(*this * *other).into_lua(lua);
+                }
+                let point = this.transform_point3(vec3_arg(&other)?);
+                AnyUserData::wrap(point).into_lua(lua)
+            },
+        );
+    })?;
+
+    let table = lua.create_table()?;
+    table.set("IDENTITY", AnyUserData::wrap(Mat4::IDENTITY))?;
+    table.set(
+        "from_transform",
+        lua.create_function(|_, transform: AnyUserData| {
+            Ok(AnyUserData::wrap(model_matrix(&transform)?.0))
+        })?,
+    )?;
+    table.set(
+        "translation",
+        lua.create_function(|_, translation: AnyUserData| {
+            Ok(AnyUserData::wrap(Mat4::from_translation(vec3_arg(
+                &translation,
+            )?)))
+        })?,
+    )?;
+    table.set(
+        "scale",
+        lua.create_function(|_, scale: AnyUserData| {
+            Ok(AnyUserData::wrap(Mat4::from_scale(vec3_arg(&scale)?)))
+        })?,
+    )?;
+    table.set(
+        "rotation",
+        lua.create_function(|_, (axis, angle): (AnyUserData, f32)| {
+            let axis = vec3_arg(&axis)?.normalize();
+            Ok(AnyUserData::wrap(Mat4::from_axis_angle(axis, angle)))
+        })?,
+    )?;
+    table.set(
+        "look_at",
+        lua.create_function(
+            |_, (eye, target, up): (AnyUserData, AnyUserData, AnyUserData)| {
+                Ok(AnyUserData::wrap(Mat4::look_at_rh(
+                    vec3_arg(&eye)?,
+                    vec3_arg(&target)?,
+                    vec3_arg(&up)?,
+                )))
+            },
+        )?,
+    )?;
+    table.set(
+        "perspective",
+        lua.create_function(
+            |_, (fovy, aspect_ratio, znear, zfar): (f32, f32, f32, f32)| {
+                Ok(AnyUserData::wrap(Mat4::perspective_rh(
+                    fovy.to_radians(),
+                    aspect_ratio,
+                    znear,
+                    zfar,
+                )))
+            },
+        )?,
+    )?;
+    lua.globals().set("Mat4", table)
+}
+
 fn register_transform_methods_mut<
     T: std::borrow::BorrowMut<Transform> + fmt::Debug,
 >(
-    reg: &mut UserDataRegistry<T>,
+    reg: &mut ApiRegistry<T>,
 ) {
     register_fields!(reg, T, {}, userdata: { pos: Vec3, rot: Quat, scale: Vec3 });
     register_to_string!(reg);
@@ -121,6 +339,13 @@ fn register_transform_methods_mut<
             Ok(())
         },
     );
+    reg.add_method(
+        "transformed_bounds",
+        |_, this, bounds: UserDataRef<Bounds>| {
+            let matrix = this.borrow().build_matrix();
+            Ok(AnyUserData::wrap(bounds.transformed(&matrix)))
+        },
+    );
 }
 
 fn register_transform(lua: &Lua) -> Result<()> {
@@ -135,46 +360,648 @@ fn register_transform(lua: &Lua) -> Result<()> {
     lua.globals().set("Transform", table)
 }
 
+fn register_bounds(lua: &Lua) -> Result<()> {
+    register_type::<Bounds>(lua, "Bounds", |reg| {
+        register_to_string!(reg);
+        reg.add_field_method_get("min", |_, this| {
+            Ok(AnyUserData::wrap(this.aabb.min))
+        });
+        reg.add_field_method_get("max", |_, this| {
+            Ok(AnyUserData::wrap(this.aabb.max))
+        });
+        reg.add_field_method_get("center", |_, this| {
+            Ok(AnyUserData::wrap(this.sphere.center))
+        });
+        reg.add_field_method_get("radius", |_, this| Ok(this.sphere.radius));
+        reg.add_method("contains", |_, this, point: AnyUserData| {
+            Ok(this.contains(vec3_arg(&point)?))
+        });
+        reg.add_method("intersects", |_, this, other: UserDataRef<Bounds>| {
+            Ok(this.intersects(&other))
+        });
+    })
+}
+
+/// Position of a rope end, either a point or a transform.
+fn anchor_pos(value: &AnyUserData) -> Result<Vec3> {
+    if let Ok(pos) = value.borrow::<Vec3>() {
+        return Ok(*pos);
+    }
+    if let Ok(pos) = value.borrow::<&mut Vec3>() {
+        return Ok(**pos);
+    }
+    if let Ok(transform) = value.borrow::<Transform>() {
+        return Ok(transform.pos);
+    }
+    Ok(value.borrow::<&mut Transform>()?.pos)
+}
+
 fn register_camera_methods_mut<
     T: std::borrow::BorrowMut<Camera> + fmt::Debug,
 >(
-    reg: &mut UserDataRegistry<T>,
+    reg: &mut ApiRegistry<T>,
 ) {
     register_fields!(reg, T, { fovy }, userdata: { transform: Transform });
+    reg.add_field_method_get("controller", |_, this| {
+        Ok(this
+            .borrow()
+            .controller
+            .as_ref()
+            .map(CameraController::name))
+    });
+    reg.add_method_mut(
+        "attach_controller",
+        |lua, this, (kind, options): (String, Option<Table>)| {
+            let options = options.unwrap_or(lua.create_table()?);
+            let target =
+                match options.raw_get::<_, Option<AnyUserData>>("target")? {
+                    Some(target) => constraint_target(&target)?,
+                    None => Target::Point(Vec3::ZERO),
+                };
+            let kind = match kind.as_bytes() {
+                b"orbit" => ControllerKind::Orbit {
+                    distance: options
+                        .raw_get::<_, Option<f32>>("distance")?
+                        .unwrap_or(0.0),
+                    pitch: 0.0,
+                    target,
+                    yaw: 0.0,
+                },
+                b"fly" => ControllerKind::Fly,
+                b"follow" => ControllerKind::Follow {
+                    offset: options
+                        .raw_get::<_, Option<UserDataRef<Vec3>>>("offset")?
+                        .map_or(Vec3::new(0.0, 2.0, 5.0), |offset| *offset),
+                    target,
+                },
+                _ => return Err(Error::runtime("unknown camera controller")),
+            };
+            let mut controller = CameraController::new(kind);
+            if let Some(damping) = options.raw_get("damping")? {
+                controller.damping = damping;
+            }
+            if let Some(sensitivity) = options.raw_get("sensitivity")? {
+                controller.sensitivity = sensitivity;
+            }
+            if let Some(speed) = options.raw_get("speed")? {
+                controller.speed = speed;
+            }
+            this.borrow_mut().controller = Some(controller);
+            Ok(())
+        },
+    );
+    reg.add_method_mut("detach_controller", |_, this, ()| {
+        this.borrow_mut().controller = None;
+        Ok(())
+    });
+    reg.add_field_method_get("path", |_, this| {
+        Ok(match this.borrow().path {
+            Some(PathState::Recording { .. }) => Some("recording"),
+            Some(PathState::Playing { .. }) => Some("playing"),
+            None => None,
+        })
+    });
+    reg.add_method_mut("record_path", |_, this, name: String| {
+        this.borrow_mut().path = Some(PathState::Recording {
+            elapsed: 0.0,
+            name,
+            path: CameraPath::default(),
+        });
+        Ok(())
+    });
+    reg.add_method_mut("stop_record", |_, this, ()| {
+        let camera = this.borrow_mut();
+        if !matches!(camera.path, Some(PathState::Recording { .. })) {
+            return Err(Error::runtime("camera is not recording"));
+        }
+        let path = camera.path.take().unwrap();
+        path.finish_recording(&camera.transform, camera.fovy)
+            .map_err(Error::runtime)
+    });
+    reg.add_method_mut(
+        "play_path",
+        |lua, this, (name, options): (String, Option<Table>)| {
+            let options = options.unwrap_or(lua.create_table()?);
+            let path = CameraPath::load(&name).map_err(Error::runtime)?;
+            this.borrow_mut().path = Some(PathState::Playing {
+                looping: options
+                    .raw_get::<_, Option<bool>>("loop")?
+                    .unwrap_or(false),
+                path,
+                speed: options
+                    .raw_get::<_, Option<f32>>("speed")?
+                    .unwrap_or(1.0),
+                time: 0.0,
+            });
+            Ok(())
+        },
+    );
+    reg.add_method_mut("stop_path", |_, this, ()| {
+        let camera = this.borrow_mut();
+        if matches!(camera.path, Some(PathState::Playing { .. })) {
+            camera.path = None;
+        }
+        Ok(())
+    });
 }
 
 fn register_camera(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("scene_cameras", lua.create_table()?)?;
     register_methods_mut!(lua, Camera, register_camera_methods_mut);
     Ok(())
 }
 
 fn register_scene_methods_mut<T: std::borrow::BorrowMut<Scene> + fmt::Debug>(
-    reg: &mut UserDataRegistry<T>,
+    reg: &mut ApiRegistry<T>,
 ) {
     register_getters!(reg, T, {}, userdata: { camera: Camera });
     reg.add_method_mut(
         "batch_model",
+        |lua,
+         this,
+         (mesh_id, material_id, transform, options): (
+            String,
+            Option<String>,
+            AnyUserData,
+            Value,
+        )| {
+            let (matrix, rotation) = model_matrix(&transform)?;
+            let mut instance = model::Instance::new(matrix, rotation);
+            // A number is the seed, kept for the scripts predating options.
+            let (seed, tint, custom, layer, outline) = match options {
+                Value::Table(options) => (
+                    options.raw_get::<_, Option<u32>>("seed")?,
+                    options.raw_get::<_, Option<AnyUserData>>("tint")?,
+                    options.raw_get::<_, Option<[f32; 4]>>("custom")?,
+                    options.raw_get::<_, Option<String>>("layer")?,
+                    options.raw_get::<_, Option<AnyUserData>>("outline")?,
+                ),
+                Value::Nil => (None, None, None, None, None),
+                seed => {
+                    (Some(u32::from_lua(seed, lua)?), None, None, None, None)
+                }
+            };
+            if let Some(seed) = seed {
+                instance = instance.with_seed(seed);
+            }
+            if let Some(tint) = tint {
+                instance = instance.with_tint(vec3_arg(&tint)?);
+            }
+            if let Some(custom) = custom {
+                instance = instance.with_custom(Vec4::from_array(custom));
+            }
+            let scene: &mut Scene = this.borrow_mut();
+            let layer = scene
+                .model_batches
+                .layer_bit(layer.as_deref().unwrap_or(model::DEFAULT_LAYER))
+                .ok_or_else(|| {
+                    Error::runtime(format!(
+                        "a scene has at most {} layers",
+                        model::MAX_LAYERS
+                    ))
+                })?;
+            if let Some(color) = outline {
+                scene.outlines.push(Outline {
+                    layer,
+                    mesh_id: mesh_id.clone(),
+                    instance: instance.with_tint(vec3_arg(&color)?),
+                });
+            }
+            scene.model_batches.add_to_layer(
+                mesh_id,
+                material_id.unwrap_or(model::DEFAULT_MATERIAL.to_string()),
+                layer,
+                instance,
+            );
+            Ok(())
+        },
+    );
+    // Uploaded once and drawn every frame until freed, without culling.
+    reg.add_method_mut(
+        "create_static_batch",
         |_,
          this,
-         (mesh_id, material_id, transform): (
+         (static_id, mesh_id, material_id, transforms): (
+            String,
             String,
             Option<String>,
-            UserDataRef<Transform>,
+            Vec<AnyUserData>,
+        )| {
+            let instances = transforms
+                .iter()
+                .map(|transform| {
+                    let (matrix, rotation) = model_matrix(transform)?;
+                    Ok(model::Instance::new(matrix, rotation))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let scene: &mut Scene = this.borrow_mut();
+            scene.model_batches.add_static(
+                static_id,
+                mesh_id,
+                material_id.unwrap_or(model::DEFAULT_MATERIAL.to_string()),
+                instances,
+            );
+            Ok(())
+        },
+    );
+    reg.add_method_mut("free_static_batch", |_, this, static_id: String| {
+        let scene: &mut Scene = this.borrow_mut();
+        Ok(scene.model_batches.remove_static(&static_id))
+    });
+    // Drawn with the `vs_main` and `fs_main` of the shader, the params are
+    // its `draw.params` values in order.
+    reg.add_method_mut(
+        "batch_effect_plane",
+        |_,
+         this,
+         (shader_id, transform, params): (
+            String,
+            AnyUserData,
+            Option<Vec<f32>>,
+        )| {
+            let params = params.unwrap_or_default();
+            if params.len() > effect::MAX_PARAMS {
+                return Err(Error::runtime(format!(
+                    "effect planes take at most {} params",
+                    effect::MAX_PARAMS
+                )));
+            }
+            let mut values = [0.0; effect::MAX_PARAMS];
+            values[..params.len()].copy_from_slice(&params);
+            let (matrix, _) = model_matrix(&transform)?;
+            this.borrow_mut().effect_planes.push(EffectPlane {
+                matrix,
+                params: values,
+                shader_id,
+            });
+            Ok(())
+        },
+    );
+    reg.add_method_mut(
+        "set_instance_sort",
+        |_,
+         this,
+         (mesh_id, material_id, mode, axis): (
+            String,
+            Option<String>,
+            Option<String>,
+            Option<UserDataRef<Vec3>>,
         )| {
-            this.borrow_mut().model_batches.add_model(
+            let sort = match mode.as_deref() {
+                None => None,
+                Some("back_to_front") => Some(model::InstanceSort::BackToFront),
+                Some("front_to_back") => Some(model::InstanceSort::FrontToBack),
+                Some("axis") => {
+                    let axis =
+                        axis.ok_or(Error::runtime("axis sort needs an axis"))?;
+                    Some(model::InstanceSort::Axis(*axis))
+                }
+                Some(_) => return Err(Error::runtime("unknown sort mode")),
+            };
+            this.borrow_mut().model_batches.set_sort(
                 mesh_id,
                 material_id.unwrap_or(model::DEFAULT_MATERIAL.to_string()),
-                model::Instance::new(transform.build_matrix(), transform.rot),
+                sort,
             );
             Ok(())
         },
     );
+    // `(pos, radius, attenuation?)` or a table of the same names with an
+    // optional color and intensity.
     reg.add_method_mut(
         "point_light",
-        |_, this, (pos, radius): (UserDataRef<Vec3>, f32)| {
+        |_,
+         this,
+         (light, radius, attenuation): (
+            Value,
+            Option<f32>,
+            Option<String>,
+        )| {
+            let light = match light {
+                Value::Table(options) => point_light_arg(&options)?,
+                Value::UserData(pos) => lights::PointLight::new(
+                    vec3_arg(&pos)?,
+                    radius.ok_or(Error::runtime("point light needs a radius"))?,
+                    attenuation_arg(attenuation)?,
+                ),
+                _ => return Err(Error::runtime("invalid point light")),
+            };
+            this.borrow_mut().point_lights.push(light);
+            Ok(())
+        },
+    );
+    reg.add_method_mut(
+        "set_ambient",
+        |_, this, (color, intensity): (AnyUserData, Option<f32>)| {
+            this.borrow_mut().ambient = Ambient {
+                color: vec3_arg(&color)?,
+                intensity: intensity.unwrap_or(1.0),
+            };
+            Ok(())
+        },
+    );
+    // `{ color, start, end }` for a linear fog, `{ color, density }` for an
+    // exponential one, nil to clear it.
+    reg.add_method_mut("set_fog", |_, this, options: Option<Table>| {
+        let Some(options) = options else {
+            this.borrow_mut().fog = None;
+            return Ok(());
+        };
+        let color = vec3_arg(&options.raw_get::<_, AnyUserData>("color")?)?;
+        let density = options.raw_get::<_, Option<f32>>("density")?;
+        let fog = match density {
+            Some(density) => Fog::Exponential { color, density },
+            None => {
+                let start = options.raw_get::<_, f32>("start")?;
+                let end = options.raw_get::<_, f32>("end")?;
+                if end <= start {
+                    return Err(Error::runtime("fog must end after its start"));
+                }
+                Fog::Linear { color, start, end }
+            }
+        };
+        this.borrow_mut().fog = Some(fog);
+        Ok(())
+    });
+    reg.add_method_mut(
+        "add_reflection_probe",
+        |_, this, (pos, resolution): (AnyUserData, Option<u32>)| {
+            let resolution = resolution.unwrap_or(128);
+            if resolution == 0 || resolution > probes::MAX_RESOLUTION {
+                return Err(Error::runtime(format!(
+                    "probe resolution must be between 1 and {}",
+                    probes::MAX_RESOLUTION
+                )));
+            }
+            let pos = vec3_arg(&pos)?;
+            Ok(this.borrow_mut().reflection_probes.add(pos, resolution))
+        },
+    );
+    // Seconds between two renders of the probe, nil to only render it when
+    // refreshed.
+    reg.add_method_mut(
+        "set_probe_interval",
+        |_, this, (id, interval): (u32, Option<f32>)| {
+            let scene = this.borrow_mut();
+            probe(&mut scene.reflection_probes, id)?.interval = interval;
+            Ok(())
+        },
+    );
+    reg.add_method_mut("refresh_probe", |_, this, id: u32| {
+        probe(&mut this.borrow_mut().reflection_probes, id)?.refresh = true;
+        Ok(())
+    });
+    reg.add_method_mut(
+        "set_probe_pos",
+        |_, this, (id, pos): (u32, AnyUserData)| {
+            let scene = this.borrow_mut();
+            let probe = probe(&mut scene.reflection_probes, id)?;
+            probe.pos = vec3_arg(&pos)?;
+            probe.refresh = true;
+            Ok(())
+        },
+    );
+    reg.add_method_mut("remove_reflection_probe", |_, this, id: u32| {
+        this.borrow_mut().reflection_probes.probes.remove(&id);
+        Ok(())
+    });
+    reg.add_method_mut(
+        "spawn",
+        |lua,
+         this,
+         (mesh_id, material_id, transform): (
+            String,
+            Option<String>,
+            Option<UserDataRef<Transform>>,
+        )| {
+            let id = this.borrow_mut().entities.spawn(Entity {
+                material_id: material_id
+                    .unwrap_or(model::DEFAULT_MATERIAL.to_string()),
+                mesh_id,
+                morph_weights: BTreeMap::new(),
+                parent: None,
+                transform: transform.map_or(Transform::default(), |t| *t),
+            });
+            lua.create_any_userdata(id)
+        },
+    );
+    reg.add_method_mut(
+        "despawn",
+        |lua, this, entity: UserDataRef<EntityId>| {
+            this.borrow_mut().entities.despawn(*entity);
+            lua.named_registry_value::<Table>("entity_data")?
+                .raw_set(entity.0, Value::Nil)
+        },
+    );
+    reg.add_method("transform", |_, this, entity: UserDataRef<EntityId>| {
+        Ok(this
+            .borrow()
+            .entities
+            .get(*entity)
+            .map(|entity| AnyUserData::wrap(entity.transform)))
+    });
+    reg.add_method_mut(
+        "set_transform",
+        |_,
+         this,
+         (entity, transform): (
+            UserDataRef<EntityId>,
+            UserDataRef<Transform>,
+        )| {
+            let entity = this
+                .borrow_mut()
+                .entities
+                .get_mut(*entity)
+                .ok_or(Error::runtime("entity was despawned"))?;
+            entity.transform = *transform;
+            Ok(())
+        },
+    );
+    // Zero removes the weight, the entity is drawn with its mesh again
+    // once all are.
+    reg.add_method_mut(
+        "set_morph_weight",
+        |_,
+         this,
+         (entity, name, weight): (UserDataRef<EntityId>, String, f32)| {
+            let entity = this
+                .borrow_mut()
+                .entities
+                .get_mut(*entity)
+                .ok_or(Error::runtime("entity was despawned"))?;
+            match weight == 0.0 {
+                true => entity.morph_weights.remove(&name),
+                false => entity.morph_weights.insert(name, weight),
+            };
+            Ok(())
+        },
+    );
+    reg.add_method(
+        "morph_weight",
+        |_, this, (entity, name): (UserDataRef<EntityId>, String)| {
+            Ok(this.borrow().entities.get(*entity).map(|entity| {
+                entity.morph_weights.get(&name).copied().unwrap_or(0.0)
+            }))
+        },
+    );
+    reg.add_method_mut(
+        "set_parent",
+        |_,
+         this,
+         (entity, parent): (
+            UserDataRef<EntityId>,
+            Option<UserDataRef<EntityId>>,
+        )| {
             this.borrow_mut()
-                .point_lights
-                .push(lights::PointLight { pos: *pos, radius });
+                .entities
+                .set_parent(*entity, parent.map(|parent| *parent))
+                .map_err(Error::runtime)
+        },
+    );
+    reg.add_method("parent", |lua, this, entity: UserDataRef<EntityId>| {
+        this.borrow()
+            .entities
+            .get(*entity)
+            .and_then(|entity| entity.parent)
+            .map(|parent| lua.create_any_userdata(parent))
+            .transpose()
+    });
+    reg.add_method(
+        "local_to_world",
+        |_, this, entity: UserDataRef<EntityId>| {
+            Ok(this
+                .borrow()
+                .entities
+                .local_to_world(*entity)
+                .map(AnyUserData::wrap))
+        },
+    );
+    reg.add_method("save", |_, this, (path, data): (String, Value)| {
+        let data = Persisted::from_lua(data)?
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(Error::external)?;
+        Snapshot::capture(this.borrow(), data)
+            .save(Path::new(&path))
+            .map_err(|err| Error::runtime(format!("{:?}", err)))
+    });
+    reg.add_method_mut("load", |lua, this, path: String| {
+        let snapshot = Snapshot::load(Path::new(&path))
+            .map_err(|err| Error::runtime(format!("{:?}", err)))?;
+        scene_switch::apply(lua, snapshot, this.borrow_mut())
+    });
+    // Drawn once a viewport is set, the camera stays when added again.
+    reg.add_method("add_camera", |lua, _, name: String| {
+        let cameras = lua.named_registry_value::<Table>("scene_cameras")?;
+        if let Some(entry) =
+            cameras.raw_get::<_, Option<Table>>(name.as_str())?
+        {
+            return entry.raw_get::<_, AnyUserData>("camera");
+        }
+        let camera = lua.create_any_userdata(Camera::new())?;
+        let entry = lua.create_table()?;
+        entry.raw_set("camera", camera.clone())?;
+        cameras.raw_set(name, entry)?;
+        Ok(camera)
+    });
+    reg.add_method("remove_camera", |lua, _, name: String| {
+        lua.named_registry_value::<Table>("scene_cameras")?
+            .raw_set(name, Value::Nil)
+    });
+    reg.add_method(
+        "switch_to",
+        |lua, _, (name, options): (String, Option<Table>)| {
+            scene_switch::switch_to(lua, &name, options)
+        },
+    );
+    reg.add_method("switching", |lua, _, ()| Ok(scene_switch::switching(lua)));
+    reg.add_method_mut(
+        "points",
+        |_,
+         this,
+         (buffer, size, color, values): (
+            AnyUserData,
+            f32,
+            Option<UserDataRef<Vec3>>,
+            Option<Table>,
+        )| {
+            let world = match values {
+                Some(values) => {
+                    values.raw_get::<_, Option<bool>>("world")?.unwrap_or(false)
+                }
+                None => false,
+            };
+            this.borrow_mut().points.push(PointBatch {
+                buffer: buffer.borrow::<Arc<PointBuffer>>()?.clone(),
+                color: color.map_or(Vec3::ONE, |c| *c).extend(1.0),
+                lines: false,
+                size,
+                world,
+            });
+            Ok(())
+        },
+    );
+    reg.add_method_mut(
+        "lines",
+        |_,
+         this,
+         (buffer, width, color): (
+            UserDataRef<Arc<PointBuffer>>,
+            f32,
+            Option<UserDataRef<Vec3>>,
+        )| {
+            this.borrow_mut().points.push(PointBatch {
+                buffer: buffer.clone(),
+                color: color.map_or(Vec3::ONE, |c| *c).extend(1.0),
+                lines: true,
+                size: width,
+                world: false,
+            });
+            Ok(())
+        },
+    );
+    reg.add_method("pick", |lua, this, (x, y): (f32, f32)| {
+        let Some(pick) = this.borrow().pick(Vec2::new(x, y)) else {
+            return Ok(None);
+        };
+        let table = lua.create_table()?;
+        table.set("mesh", pick.mesh_id)?;
+        table.set("material", pick.material_id)?;
+        table.set("instance", pick.instance + 1)?;
+        table.set("distance", pick.distance)?;
+        table.set("point", AnyUserData::wrap(pick.point))?;
+        table.set("normal", AnyUserData::wrap(pick.normal))?;
+        Ok(Some(table))
+    });
+    reg.add_method_mut(
+        "rope",
+        |_, this, (a, b, values): (AnyUserData, AnyUserData, Option<Table>)| {
+            let default = RopeSettings::default();
+            let mut key = None;
+            let settings = match values {
+                None => default,
+                Some(values) => {
+                    key = values.raw_get("key")?;
+                    RopeSettings {
+                        length: values.raw_get("length")?,
+                        material_id: values
+                            .raw_get::<_, Option<String>>("material")?
+                            .unwrap_or(default.material_id),
+                        radius: values
+                            .raw_get::<_, Option<f32>>("radius")?
+                            .unwrap_or(default.radius),
+                        segments: values
+                            .raw_get::<_, Option<usize>>("segments")?
+                            .unwrap_or(default.segments),
+                    }
+                }
+            };
+            this.borrow_mut().ropes.attach(
+                key,
+                anchor_pos(&a)?,
+                anchor_pos(&b)?,
+                settings,
+            );
             Ok(())
         },
     );
@@ -182,27 +1009,209 @@ fn register_scene_methods_mut<T: std::borrow::BorrowMut<Scene> + fmt::Debug>(
 
 fn register_scene(lua: &Lua) -> Result<()> {
     register_methods_mut!(lua, Scene, register_scene_methods_mut);
-    Ok(())
+    register_entity(lua)?;
+    register_point_buffer(lua)
 }
 
-fn register_inputs(lua: &Lua) -> Result<()> {
-    lua.register_userdata_type::<Inputs>(|reg| {
-        reg.add_method("cursor_in_window", |_, this, _: ()| {
-            Ok(this.cursor_in_window)
-        });
-        reg.add_method("focused", |_, this, _: ()| Ok(this.focused));
-        reg.add_method("pressed", |_, this, action: String| {
-            Ok(this.action_pressed(&action))
-        });
-        reg.add_method("just_pressed", |_, this, action: String| {
-            Ok(this.action_just_pressed(&action))
+fn register_point_buffer(lua: &Lua) -> Result<()> {
+    register_type::<Arc<PointBuffer>>(lua, "PointBuffer", |reg| {
+        reg.add_field_method_get("len", |_, this| Ok(this.points.len()));
+    })?;
+    let table = lua.create_table()?;
+    table.set(
+        "new",
+        lua.create_function(|lua, points: Vec<UserDataRef<Vec3>>| {
+            let points = points.iter().map(|point| **point).collect();
+            lua.create_any_userdata(Arc::new(PointBuffer::new(points)))
+        })?,
+    )?;
+    lua.globals().set("PointBuffer", table)
+}
+
+/// Script data of an entity, kept in the registry so it survives reloads.
+fn entity_data(lua: &Lua, id: EntityId) -> Result<Table<'_>> {
+    let entity_data = lua.named_registry_value::<Table>("entity_data")?;
+    if let Some(data) = entity_data.raw_get::<_, Option<Table>>(id.0)? {
+        return Ok(data);
+    }
+    let data = lua.create_table()?;
+    entity_data.raw_set(id.0, data.clone())?;
+    Ok(data)
+}
+
+/// Constraint target, either an entity or a point.
+fn constraint_target(value: &AnyUserData) -> Result<Target> {
+    if let Ok(id) = value.borrow::<EntityId>() {
+        return Ok(Target::Entity(*id));
+    }
+    if let Ok(pos) = value.borrow::<Vec3>() {
+        return Ok(Target::Point(*pos));
+    }
+    Ok(Target::Point(**value.borrow::<&mut Vec3>()?))
+}
+
+fn add_constraint(lua: &Lua, id: EntityId, constraint: Constraint) {
+    lua.app_data_mut::<Constraints>()
+        .unwrap()
+        .add(id, constraint);
+}
+
+fn register_entity(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("entity_data", lua.create_table()?)?;
+    lua.set_app_data(Constraints::default());
+    lua.set_app_data(Interpolation::default());
+    register_type::<EntityId>(lua, "Entity", |reg| {
+        register_to_string!(reg);
+        reg.add_field_method_get("id", |_, this| Ok(this.0));
+        reg.add_field_method_get("data", |lua, this| entity_data(lua, *this));
+        reg.add_field_method_set("data", |lua, this, data: Table| {
+            lua.named_registry_value::<Table>("entity_data")?
+                .raw_set(this.0, data)
         });
-        reg.add_method("mouse_pressed", |_, this, button: String| {
-            let state = match button.as_bytes() {
-                b"left" => this.mouse_pressed(0),
-                b"right" => this.mouse_pressed(1),
-                _ => return Err(Error::runtime("Invalid mouse button")),
-            };
+        reg.add_meta_method(
+            MetaMethod::Eq,
+            |_, this, other: UserDataRef<EntityId>| Ok(*this == *other),
+        );
+        reg.add_method(
+            "constrain_look_at",
+            |lua, this, target: AnyUserData| {
+                let target = constraint_target(&target)?;
+                add_constraint(lua, *this, Constraint::LookAt(target));
+                Ok(())
+            },
+        );
+        reg.add_method(
+            "constrain_follow_path",
+            |lua, this, (path, speed): (Vec<UserDataRef<Vec3>>, f32)| {
+                if path.is_empty() {
+                    return Err(Error::runtime("path must have points"));
+                }
+                let path = path.iter().map(|point| **point).collect();
+                add_constraint(
+                    lua,
+                    *this,
+                    Constraint::FollowPath {
+                        distance: 0.0,
+                        path,
+                        speed,
+                    },
+                );
+                Ok(())
+            },
+        );
+        reg.add_method(
+            "copy_position",
+            |lua,
+             this,
+             (source, offset): (
+                UserDataRef<EntityId>,
+                Option<UserDataRef<Vec3>>,
+            )| {
+                add_constraint(
+                    lua,
+                    *this,
+                    Constraint::CopyPosition {
+                        offset: offset.map_or(Vec3::ZERO, |offset| *offset),
+                        source: *source,
+                    },
+                );
+                Ok(())
+            },
+        );
+        reg.add_method(
+            "tween",
+            |lua,
+             this,
+             (field, to, duration, easing): (
+                String,
+                UserDataRef<Vec3>,
+                f32,
+                Value,
+            )| {
+                let field = TweenField::from_name(&field)
+                    .ok_or(Error::runtime("unknown tween field"))?;
+                start_tween(
+                    lua,
+                    TweenTarget::Entity(*this, field),
+                    *to,
+                    duration,
+                    easing,
+                )
+            },
+        );
+        reg.add_method("clear_constraints", |lua, this, _: ()| {
+            lua.app_data_mut::<Constraints>().unwrap().clear(*this);
+            Ok(())
+        });
+        // The timestamp is in the clock of the elapsed time given to update.
+        reg.add_method(
+            "push_target_transform",
+            |lua, this, (transform, time): (UserDataRef<Transform>, f32)| {
+                lua.app_data_mut::<Interpolation>()
+                    .unwrap()
+                    .push(*this, *transform, time);
+                Ok(())
+            },
+        );
+        reg.add_method("set_interpolation_delay", |lua, this, delay: f32| {
+            lua.app_data_mut::<Interpolation>()
+                .unwrap()
+                .set_delay(*this, delay);
+            Ok(())
+        });
+        reg.add_method("clear_target_transforms", |lua, this, _: ()| {
+            lua.app_data_mut::<Interpolation>().unwrap().clear(*this);
+            Ok(())
+        });
+        reg.add_method(
+            "attach_script",
+            |lua, this, (module, state): (String, Option<Table>)| {
+                let state = match state {
+                    Some(state) => state,
+                    None => lua.create_table()?,
+                };
+                component::attach(lua, *this, module, state)
+            },
+        );
+        reg.add_method("detach_script", |lua, this, module: String| {
+            component::detach(lua, *this, &module)
+        });
+    })
+}
+
+fn register_inputs(lua: &Lua) -> Result<()> {
+    register_type::<Inputs>(lua, "Inputs", |reg| {
+        reg.add_method("cursor_in_window", |_, this, _: ()| {
+            Ok(this.cursor_in_window)
+        });
+        reg.add_method("focused", |_, this, _: ()| Ok(this.focused));
+        reg.add_method("pressed", |_, this, action: String| {
+            Ok(this.action_pressed(&action))
+        });
+        reg.add_method("just_pressed", |_, this, action: String| {
+            Ok(this.action_just_pressed(&action))
+        });
+        reg.add_method_mut(
+            "register_action",
+            |_, this, (action, keys): (String, Vec<String>)| {
+                let codes = keys
+                    .iter()
+                    .map(|key| {
+                        parse_key(key).ok_or_else(|| {
+                            Error::runtime(format!("Invalid key {}", key))
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                this.register_action(&action, codes);
+                Ok(())
+            },
+        );
+        reg.add_method("mouse_pressed", |_, this, button: String| {
+            let state = match button.as_bytes() {
+                b"left" => this.mouse_pressed(0),
+                b"right" => this.mouse_pressed(1),
+                _ => return Err(Error::runtime("Invalid mouse button")),
+            };
             Ok(state)
         });
         reg.add_method("mouse_just_pressed", |_, this, button: String| {
@@ -213,6 +1222,10 @@ fn register_inputs(lua: &Lua) -> Result<()> {
             };
             Ok(state)
         });
+        reg.add_method("cursor_position", |lua, this, _: ()| {
+            let pos = this.cursor_pos;
+            lua.create_table_from([("x", pos.x), ("y", pos.y)])
+        });
         reg.add_method("mouse_delta", |lua, this, _: ()| {
             let delta = this.mouse_delta;
             let table = lua.create_table_from(
@@ -220,11 +1233,12 @@ fn register_inputs(lua: &Lua) -> Result<()> {
             )?;
             Ok(table)
         });
+        reg.add_method("scroll_delta", |_, this, ()| Ok(this.scroll_delta));
     })
 }
 
 fn register_window(lua: &Lua) -> Result<()> {
-    lua.register_userdata_type::<Arc<Window>>(|reg| {
+    register_type::<Arc<Window>>(lua, "Window", |reg| {
         reg.add_method("grab_cursor", |_, this, _: ()| {
             this.set_cursor_grab(CursorGrabMode::Locked)
                 .map_err(Error::runtime)?;
@@ -237,45 +1251,1152 @@ fn register_window(lua: &Lua) -> Result<()> {
             this.set_cursor_visible(true);
             Ok(())
         });
+        reg.add_method("set_icon", |lua, this, texture_id: String| {
+            let image = lua
+                .app_data_ref::<ImageReader>()
+                .ok_or_else(|| Error::runtime("Textures are not ready"))?
+                .read(&texture_id)
+                .map_err(Error::runtime)?
+                .to_rgba8();
+            let (width, height) = image.dimensions();
+            let icon = Icon::from_rgba(image.into_raw(), width, height)
+                .map_err(Error::runtime)?;
+            this.set_window_icon(Some(icon));
+            Ok(())
+        });
+        reg.add_method("request_attention", |_, this, kind: Option<String>| {
+            let kind = match kind.as_deref().map(str::as_bytes) {
+                None | Some(b"informational") => {
+                    UserAttentionType::Informational
+                }
+                Some(b"critical") => UserAttentionType::Critical,
+                _ => return Err(Error::runtime("Invalid attention type")),
+            };
+            this.request_user_attention(Some(kind));
+            Ok(())
+        });
+        reg.add_method("set_title", |_, this, title: String| {
+            this.set_title(&title);
+            Ok(())
+        });
+        // Logical size, the surface follows at the end of the update.
+        reg.add_method("set_size", |_, this, (width, height): (u32, u32)| {
+            if width == 0 || height == 0 {
+                return Err(Error::runtime("window size must not be empty"));
+            }
+            let _ = this.request_inner_size(LogicalSize::new(width, height));
+            Ok(())
+        });
+        reg.add_method("set_fullscreen", |_, this, enabled: bool| {
+            this.set_fullscreen(window::fullscreen(enabled));
+            Ok(())
+        });
+        reg.add_method("fullscreen", |_, this, _: ()| {
+            Ok(this.fullscreen().is_some())
+        });
+        reg.add_method("set_decorations", |_, this, enabled: bool| {
+            this.set_decorations(enabled);
+            Ok(())
+        });
+        reg.add_method("set_on_top", |_, this, on_top: bool| {
+            this.set_window_level(window::window_level(on_top));
+            Ok(())
+        });
+        // Taskbar progress is not exposed by winit, shown in the title
+        // instead.
+        reg.add_method("set_progress", |_, this, progress: Option<f32>| {
+            let title = this.title();
+            let title = match title.rsplit_once(" [") {
+                Some((base, suffix)) if suffix.ends_with("%]") => base,
+                _ => &title,
+            };
+            match progress {
+                Some(progress) => this.set_title(&format!(
+                    "{} [{:.0}%]",
+                    title,
+                    progress.clamp(0.0, 1.0) * 100.0
+                )),
+                None => this.set_title(title),
+            }
+            Ok(())
+        });
+    })
+}
+
+fn register_wind(lua: &Lua) -> Result<()> {
+    register_type::<Wind>(lua, "Wind", |reg| {
+        reg.add_method_mut(
+            "set",
+            |_, this, (direction, strength): (UserDataRef<Vec3>, f32)| {
+                this.set(*direction, strength);
+                Ok(())
+            },
+        );
+        reg.add_method_mut(
+            "set_gusts",
+            |_, this, (gustiness, frequency): (f32, f32)| {
+                this.gustiness = gustiness.max(0.0);
+                this.gust_frequency = frequency.max(0.0);
+                Ok(())
+            },
+        );
+        reg.add_method("direction", |_, this, _: ()| {
+            Ok(AnyUserData::wrap(this.direction))
+        });
+        reg.add_method("strength", |_, this, _: ()| Ok(this.strength));
+        reg.add_method("at", |_, this, position: UserDataRef<Vec3>| {
+            Ok(AnyUserData::wrap(this.at(*position)))
+        });
     })
 }
 
+fn register_app(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("windows", lua.create_table()?)?;
+    register_type::<AppState>(lua, "App", |reg| {
+        reg.add_method_mut("create_window", |lua, this, values: Table| {
+            let title = values
+                .raw_get::<_, Option<String>>("title")?
+                .unwrap_or("window".to_string());
+            let size = values
+                .raw_get::<_, Option<[u32; 2]>>("size")?
+                .unwrap_or([400, 300]);
+            let windows = lua.named_registry_value::<Table>("windows")?;
+            let camera = match windows
+                .raw_get::<_, Option<Table>>(title.as_str())?
+            {
+                Some(window) => window.raw_get::<_, AnyUserData>("camera")?,
+                None => lua.create_any_userdata(Camera::new())?,
+            };
+            let window = lua.create_table()?;
+            window.raw_set("camera", camera.clone())?;
+            window.raw_set(
+                "render",
+                values.raw_get::<_, Option<Function>>("render")?,
+            )?;
+            windows.raw_set(title.as_str(), window)?;
+            this.window_requests.push(WindowRequest { size, title });
+            Ok(camera)
+        });
+        reg.add_method_mut("set_fixed_rate", |_, this, rate: f32| {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(Error::runtime(
+                    "fixed rate must be finite and positive",
+                ));
+            }
+            this.fixed_rate = rate;
+            Ok(())
+        });
+        reg.add_field_method_get("fixed_rate", |_, this| Ok(this.fixed_rate));
+        reg.add_method_mut("set_time_scale", |_, this, scale: f32| {
+            if !scale.is_finite() || scale < 0.0 {
+                return Err(Error::runtime(
+                    "time scale must be finite and not negative",
+                ));
+            }
+            this.time_scale = scale.min(AppState::MAX_TIME_SCALE);
+            Ok(())
+        });
+        reg.add_field_method_get("time_scale", |_, this| Ok(this.time_scale));
+        reg.add_method_mut("pause", |_, this, ()| {
+            this.paused = true;
+            Ok(())
+        });
+        reg.add_method_mut("resume", |_, this, ()| {
+            this.paused = false;
+            Ok(())
+        });
+        reg.add_method_mut("step_frame", |_, this, ()| {
+            this.step_frame();
+            Ok(())
+        });
+        reg.add_field_method_get("paused", |_, this| Ok(this.paused));
+        reg.add_method_mut(
+            "export_frames",
+            |_, this, (dir, options): (String, Option<Table>)| {
+                if this.exporting || !this.export_requests.is_empty() {
+                    return Err(Error::runtime("already exporting frames"));
+                }
+                let mut settings = ExportSettings {
+                    dir: PathBuf::from(dir),
+                    duration: 5.0,
+                    fps: 60.0,
+                };
+                if let Some(options) = options {
+                    if let Some(fps) = options.raw_get("fps")? {
+                        settings.fps = fps;
+                    }
+                    if let Some(duration) = options.raw_get("duration")? {
+                        settings.duration = duration;
+                    }
+                }
+                let valid = |value: f32| value.is_finite() && value > 0.0;
+                if !valid(settings.fps) || !valid(settings.duration) {
+                    return Err(Error::runtime(
+                        "fps and duration must be finite and positive",
+                    ));
+                }
+                this.export_requests.push(settings);
+                Ok(())
+            },
+        );
+        reg.add_method_mut("set_power_mode", |_, this, values: Table| {
+            let settings = &mut this.power.settings;
+            let fps = |key| -> Result<Option<Option<f32>>> {
+                let fps = values.raw_get::<_, Option<f32>>(key)?;
+                if fps.is_some_and(|fps| fps < 0.0) {
+                    return Err(Error::runtime("fps must not be negative"));
+                }
+                Ok(fps.map(|fps| (fps > 0.0).then_some(fps)))
+            };
+            if let Some(fps) = fps("max_fps")? {
+                settings.max_fps = fps;
+            }
+            if let Some(fps) = fps("battery_fps")? {
+                settings.battery_fps = fps;
+            }
+            if let Some(fps) = fps("unfocused_fps")? {
+                settings.unfocused_fps = fps;
+            }
+            if let Some(fps) = fps("hidden_fps")? {
+                settings.hidden_fps = fps;
+            }
+            if let Some(preset) =
+                values.raw_get::<_, Option<String>>("battery_quality")?
+            {
+                let quality = Quality::from_name(&preset)
+                    .ok_or(Error::runtime("unknown quality preset"))?;
+                settings.battery_quality = Some(quality);
+            }
+            Ok(())
+        });
+        reg.add_field_method_get("on_battery", |_, this| {
+            Ok(this.power.on_battery)
+        });
+    })
+}
+
+/// Run a script callback filling an egui container, the panel it adds the
+/// widgets to only lives during the call.
+#[cfg(feature = "ui")]
+fn fill_container(
+    lua: &Lua,
+    container: &mut egui::Ui,
+    f: &Function,
+) -> Result<()> {
+    let mut panel = Panel::new(container);
+    let result = lua.scope(|scope| {
+        f.call::<_, ()>(scope.create_any_userdata_ref_mut(&mut panel)?)
+    });
+    panel.finish(container);
+    result
+}
+
+#[cfg(feature = "ui")]
+fn register_ui(lua: &Lua) -> Result<()> {
+    register_type::<Ui>(lua, "Ui", |reg| {
+        reg.add_method_mut("show_stats", |_, this, visible: bool| {
+            this.show_stats = visible;
+            Ok(())
+        });
+        reg.add_function(
+            "window",
+            |lua, (this, title, f): (AnyUserData, String, Function)| {
+                // Not borrowed during the callback, it can use the ui too.
+                let ctx = this.borrow::<Ui>()?.ctx.clone();
+                let mut result = Ok(());
+                egui::Window::new(title).show(&ctx, |container| {
+                    result = fill_container(lua, container, &f);
+                });
+                result
+            },
+        );
+    })?;
+    register_type::<Panel>(lua, "UiPanel", |reg| {
+        reg.add_method_mut("label", |_, this, text: String| {
+            this.ui.label(text);
+            Ok(())
+        });
+        reg.add_method_mut("button", |_, this, label: String| {
+            Ok(this.ui.button(label).clicked())
+        });
+        reg.add_method_mut(
+            "checkbox",
+            |_, this, (label, default): (String, Option<bool>)| {
+                Ok(this.checkbox(&label, default.unwrap_or(false)))
+            },
+        );
+        reg.add_method_mut(
+            "slider",
+            |_,
+             this,
+             (label, min, max, default): (
+                String,
+                f64,
+                f64,
+                Option<f64>,
+            )| {
+                Ok(this.slider(&label, min..=max, default.unwrap_or(min)))
+            },
+        );
+    })
+}
+
+#[cfg(feature = "audio")]
+fn register_audio(lua: &Lua) -> Result<()> {
+    register_type::<Audio>(lua, "Audio", |reg| {
+        reg.add_method("play", |_, this, sound_id: String| {
+            this.play(&sound_id).map_err(Error::runtime)
+        });
+        reg.add_method_mut(
+            "play_at",
+            |_, this, (sound_id, pos): (String, UserDataRef<Vec3>)| {
+                this.play_at(&sound_id, *pos).map_err(Error::runtime)
+            },
+        );
+    })
+}
+
+#[cfg(feature = "mic")]
+fn register_mic(lua: &Lua) -> Result<()> {
+    register_type::<Mic>(lua, "Mic", |reg| {
+        reg.add_method_mut("level", |_, this, ()| {
+            this.request();
+            Ok(this.level())
+        });
+        reg.add_method_mut("spectrum", |_, this, bands: Option<usize>| {
+            this.request();
+            Ok(this.spectrum(bands.unwrap_or(16).max(1)))
+        });
+    })
+}
+
+#[cfg(feature = "physics")]
+fn register_physics(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("rigid_bodies", lua.create_table()?)?;
+    register_type::<RigidBodyHandle>(lua, "RigidBody", |_| {})?;
+    register_type::<Physics>(lua, "Physics", |reg| {
+        reg.add_method_mut(
+            "add_rigid_body",
+            |lua, this, (transform, values): (AnyUserData, Option<Table>)| {
+                let default = BodySettings::default();
+                let settings = match values {
+                    None => default,
+                    Some(values) => BodySettings {
+                        fixed: values
+                            .raw_get::<_, Option<bool>>("fixed")?
+                            .unwrap_or(default.fixed),
+                        mass: values
+                            .raw_get::<_, Option<f32>>("mass")?
+                            .unwrap_or(default.mass),
+                        shape: match values
+                            .raw_get::<_, Option<String>>("shape")?
+                            .as_deref()
+                            .map(str::as_bytes)
+                        {
+                            None | Some(b"box") => ShapeKind::Box,
+                            Some(b"capsule") => ShapeKind::Capsule,
+                            Some(b"sphere") => ShapeKind::Sphere,
+                            _ => return Err(Error::runtime("unknown shape")),
+                        },
+                        size: values
+                            .raw_get::<_, Option<UserDataRef<Vec3>>>("size")?
+                            .map_or(default.size, |size| *size),
+                    },
+                };
+                let handle = this.add_rigid_body(
+                    &with_transform(&transform, |t| *t)?,
+                    &settings,
+                );
+                let body = lua.create_any_userdata(handle)?;
+                lua.named_registry_value::<Table>("rigid_bodies")?
+                    .raw_set(body.clone(), transform)?;
+                Ok(body)
+            },
+        );
+        reg.add_method_mut(
+            "remove_rigid_body",
+            |lua, this, body: AnyUserData| {
+                this.remove_rigid_body(*body.borrow::<RigidBodyHandle>()?);
+                lua.named_registry_value::<Table>("rigid_bodies")?
+                    .raw_set(body, Value::Nil)
+            },
+        );
+        reg.add_method_mut("clear", |lua, this, _: ()| {
+            this.clear();
+            lua.set_named_registry_value("rigid_bodies", lua.create_table()?)
+        });
+        reg.add_method(
+            "raycast",
+            |lua,
+             this,
+             (origin, dir, max_dist): (
+                UserDataRef<Vec3>,
+                UserDataRef<Vec3>,
+                f32,
+            )| {
+                let Some(hit) = this.raycast(*origin, *dir, max_dist) else {
+                    return Ok(None);
+                };
+                let table = lua.create_table()?;
+                table.set("distance", hit.distance)?;
+                table.set("normal", AnyUserData::wrap(hit.normal))?;
+                table.set("point", AnyUserData::wrap(hit.point))?;
+                if let Some(body) = hit.body {
+                    table.set("body", lua.create_any_userdata(body)?)?;
+                }
+                Ok(Some(table))
+            },
+        );
+    })
+}
+
+/// Access a transform userdata, owned or referencing an engine field.
+#[cfg(feature = "physics")]
+fn with_transform<R>(
+    value: &AnyUserData,
+    f: impl FnOnce(&mut Transform) -> R,
+) -> Result<R> {
+    if let Ok(mut transform) = value.borrow_mut::<Transform>() {
+        return Ok(f(&mut transform));
+    }
+    let mut transform = value.borrow_mut::<&mut Transform>()?;
+    Ok(f(&mut transform))
+}
+
+/// Hand the cameras added to the scene that have a viewport to the
+/// renderer.
+pub fn sync_viewports(lua: &Lua, render_state: &mut RenderState) -> Result<()> {
+    let cameras = lua.named_registry_value::<Table>("scene_cameras")?;
+    let mut viewports = Vec::new();
+    for pair in cameras.pairs::<String, Table>() {
+        let (name, entry) = pair?;
+        let Some(rect) = entry.raw_get::<_, Option<[f32; 4]>>("viewport")?
+        else {
+            continue;
+        };
+        let camera = entry.raw_get::<_, UserDataRef<Camera>>("camera")?;
+        viewports.push((name, camera.clone(), rect));
+    }
+    // Table order is not stable, the names give the drawing order.
+    viewports.sort_by(|a, b| a.0.cmp(&b.0));
+    render_state.set_viewports(viewports);
+    Ok(())
+}
+
+/// Copy the secondary window cameras to the views drawing them.
+pub fn sync_window_cameras(
+    lua: &Lua,
+    render_state: &mut RenderState,
+) -> Result<()> {
+    let windows = lua.named_registry_value::<Table>("windows")?;
+    for view in &mut render_state.views {
+        if let Some(window) =
+            windows.raw_get::<_, Option<Table>>(view.name.as_str())?
+        {
+            let camera = window.raw_get::<_, UserDataRef<Camera>>("camera")?;
+            view.camera = camera.clone();
+        }
+    }
+    Ok(())
+}
+
+/// Copy the simulated bodies back to the transforms they were created from.
+#[cfg(feature = "physics")]
+pub fn sync_rigid_bodies(lua: &Lua, physics: &mut Physics) -> Result<()> {
+    let bodies = lua.named_registry_value::<Table>("rigid_bodies")?;
+    for pair in bodies.clone().pairs::<AnyUserData, AnyUserData>() {
+        let (body, transform) = pair?;
+        let handle = *body.borrow::<RigidBodyHandle>()?;
+        let Some((pos, rot)) = physics.transform(handle) else {
+            bodies.raw_set(body, Value::Nil)?;
+            continue;
+        };
+        // A dead transform removes its own body, the others keep syncing.
+        if let Err(err) = with_transform(&transform, |t| {
+            t.pos = pos;
+            t.rot = rot;
+        }) {
+            error!("rigid body sync\n{}", err);
+            physics.remove_rigid_body(handle);
+            bodies.raw_set(body, Value::Nil)?;
+        }
+    }
+    Ok(())
+}
+
+fn register_buffer_data(lua: &Lua) -> Result<()> {
+    register_type::<BufferData>(lua, "BufferData", |reg| {
+        reg.add_field_method_get("len", |_, this| Ok(this.0.len()));
+        reg.add_method("f32", |_, this, index: usize| {
+            Ok(this
+                .element(index.saturating_sub(1))
+                .map(f32::from_le_bytes))
+        });
+        reg.add_method("u32", |_, this, index: usize| {
+            Ok(this
+                .element(index.saturating_sub(1))
+                .map(u32::from_le_bytes))
+        });
+        reg.add_method("i32", |_, this, index: usize| {
+            Ok(this
+                .element(index.saturating_sub(1))
+                .map(i32::from_le_bytes))
+        });
+        reg.add_method("to_f32", |_, this, ()| {
+            Ok(this
+                .0
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect::<Vec<_>>())
+        });
+        reg.add_method("to_u32", |_, this, ()| {
+            Ok(this
+                .0
+                .chunks_exact(4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect::<Vec<_>>())
+        });
+        reg.add_method("to_i32", |_, this, ()| {
+            Ok(this
+                .0
+                .chunks_exact(4)
+                .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect::<Vec<_>>())
+        });
+        // Raw content, for the `string.unpack` formats of other types.
+        reg.add_method("bytes", |lua, this, ()| lua.create_string(&this.0));
+    })
+}
+
+fn loaded_state(
+    this: &RenderState,
+    kind: Option<&str>,
+    id: &str,
+) -> Result<bool> {
+    let kind = kind.unwrap_or("mesh");
+    this.is_loaded(kind, id)
+        .ok_or_else(|| Error::runtime(format!("unknown asset kind {}", kind)))
+}
+
 fn register_render_state(lua: &Lua) -> Result<()> {
-    lua.register_userdata_type::<RenderState>(|reg| {
+    lua.set_named_registry_value("readbacks", lua.create_table()?)?;
+    lua.set_named_registry_value("screenshots", lua.create_table()?)?;
+    register_buffer_data(lua)?;
+    register_type::<RenderState>(lua, "Graphics", |reg| {
         reg.add_method_mut("load_mesh", |_, this, mesh_id: String| {
             this.meshes.load(&mesh_id);
             Ok(())
         });
+        // Kind is "mesh" by default, "texture" or "shader".
+        reg.add_method(
+            "is_loaded",
+            |_, this, (id, kind): (String, Option<String>)| {
+                loaded_state(this, kind.as_deref(), &id)
+            },
+        );
+        reg.add_method(
+            "on_loaded",
+            |lua, this, (id, callback, kind): (String, Function, Option<String>)| {
+                let loaded = loaded_state(this, kind.as_deref(), &id)?;
+                let kind = kind.as_deref().unwrap_or("mesh");
+                let failed = this.has_failed(kind, &id);
+                events::on_loaded(lua, kind, &id, callback, loaded, failed)
+            },
+        );
+        // Lists of ids by kind, `{ meshes, textures, shaders }`.
+        reg.add_method_mut("preload", |_, this, values: Table| {
+            for (key, kind) in [
+                ("meshes", "mesh"),
+                ("textures", "texture"),
+                ("shaders", "shader"),
+            ] {
+                let ids = values.raw_get::<_, Option<Vec<String>>>(key)?;
+                for id in ids.unwrap_or_default() {
+                    this.preload(kind, &id);
+                }
+            }
+            Ok(())
+        });
+        reg.add_method("loading_progress", |_, this, ()| {
+            Ok(this.loading_progress())
+        });
+        // Assets loaded for the first time during the last frame.
+        reg.add_method("loaded", |lua, this, ()| {
+            this.last_loaded
+                .iter()
+                .map(|(kind, id)| {
+                    let table = lua.create_table()?;
+                    table.raw_set("kind", *kind)?;
+                    table.raw_set("id", id.as_str())?;
+                    Ok(table)
+                })
+                .collect::<Result<Vec<_>>>()
+        });
+        // Nil until the mesh is loaded, or when it has no vertices.
+        reg.add_method("mesh_bounds", |_, this, mesh_id: String| {
+            Ok(this
+                .meshes
+                .get(&mesh_id)
+                .filter(|mesh| !mesh.positions.is_empty())
+                .map(|mesh| AnyUserData::wrap(mesh.bounds())))
+        });
+        // Nil until the mesh is loaded.
+        reg.add_method("morph_targets", |_, this, mesh_id: String| {
+            Ok(this.meshes.get(&mesh_id).map(|mesh| {
+                mesh.morphs.as_ref().map_or(Vec::new(), |morphs| {
+                    morphs.names().map(str::to_string).collect()
+                })
+            }))
+        });
         reg.add_method_mut(
             "add_material",
-            |_, this, (material_type, values): (String, Table)| {
+            |lua, this, (material_type, values): (String, Table)| {
                 let key = values
                     .raw_get::<_, String>("key")
                     .map_err(|_| Error::runtime("material must have key"))?;
+                let shader_id = values
+                    .raw_get::<_, String>("shader")
+                    .unwrap_or(model::DEFAULT_SHADER.to_string());
+                let texture_id = values
+                    .raw_get::<_, String>("texture")
+                    .unwrap_or(model::DEFAULT_TEXTURE.to_string());
+                let constants = values
+                    .raw_get::<_, Option<BTreeMap<String, f64>>>("constants")?
+                    .unwrap_or_default();
 
                 match material_type.as_bytes() {
                     b"simple" => {
-                        let shader_id = values
-                            .raw_get::<_, String>("shader")
-                            .unwrap_or(model::DEFAULT_SHADER.to_string());
-                        let texture_id = values
-                            .raw_get::<_, String>("texture")
-                            .unwrap_or(model::DEFAULT_TEXTURE.to_string());
-
-                        this.shaders.load(&shader_id);
-                        this.bundles.model.register_shader(&shader_id);
-                        this.textures.load(&texture_id);
-
-                        this.materials.add(
-                            &key,
-                            SimpleMaterial::new(&shader_id, &texture_id),
+                        let material = this.simple_material(
+                            &shader_id,
+                            &texture_id,
+                            &constants,
                         );
+                        this.materials.add(&key, material);
+                    }
+                    b"custom" => {
+                        let block = values
+                            .raw_get::<_, Option<Vec<String>>>("block")?
+                            .ok_or(Error::runtime(
+                                "custom material needs block",
+                            ))?;
+                        let block =
+                            UniformBlock::parse(&block).map_err(|err| {
+                                Error::runtime(format!("{}", err))
+                            })?;
+                        let material = this.custom_material(
+                            &shader_id,
+                            &texture_id,
+                            &constants,
+                            block,
+                        );
+                        this.materials.add(&key, material);
                     }
                     _ => return Err(Error::runtime("unknown material type")),
                 };
+                lua.create_any_userdata(MaterialHandle(key))
+            },
+        );
+        reg.add_method("material", |lua, this, key: String| {
+            this.materials
+                .get_shader_id(&key)
+                .map(|_| lua.create_any_userdata(MaterialHandle(key)))
+                .transpose()
+        });
+        // The offset and size are fractions of the window, clipped to it,
+        // nil draws the scene camera on the whole window and hides the
+        // other ones.
+        reg.add_method_mut(
+            "set_viewport",
+            |lua, this, (camera, rect): (AnyUserData, Option<[f32; 4]>)| {
+                if rect.is_some_and(|rect| rect.iter().any(|v| !v.is_finite()))
+                {
+                    return Err(Error::runtime("viewport must be finite"));
+                }
+                let rect = rect.map(|[x, y, width, height]| {
+                    let (x, y) = (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0));
+                    [x, y, width.min(1.0 - x), height.min(1.0 - y)]
+                });
+                if rect.is_some_and(|rect| rect[2] <= 0.0 || rect[3] <= 0.0) {
+                    return Err(Error::runtime(
+                        "viewport must have a positive size inside the window",
+                    ));
+                }
+                if camera.is::<&mut Camera>() {
+                    this.main_viewport = rect.unwrap_or([0.0, 0.0, 1.0, 1.0]);
+                    return Ok(());
+                }
+                let cameras =
+                    lua.named_registry_value::<Table>("scene_cameras")?;
+                for pair in cameras.pairs::<String, Table>() {
+                    let (_, entry) = pair?;
+                    if entry.raw_get::<_, AnyUserData>("camera")? == camera {
+                        return entry.raw_set("viewport", rect);
+                    }
+                }
+                Err(Error::runtime("camera was not added to the scene"))
+            },
+        );
+        reg.add_method_mut("set_render_mode", |_, this, mode: String| {
+            let mode = match mode.as_bytes() {
+                b"lit" => model::RenderMode::Lit,
+                b"wireframe" => model::RenderMode::Wireframe,
+                b"normals" => model::RenderMode::Normals,
+                b"depth" => model::RenderMode::Depth,
+                _ => return Err(Error::runtime("unknown render mode")),
+            };
+            let line_mode = wgpu::Features::POLYGON_MODE_LINE;
+            if mode == model::RenderMode::Wireframe
+                && !this.device.features().contains(line_mode)
+            {
+                return Err(Error::runtime("wireframe is not supported"));
+            }
+            this.bundles.model.mode = mode;
+            Ok(())
+        });
+        reg.add_method_mut(
+            "set_dynamic_resolution",
+            |_, this, (enabled, values): (bool, Option<Table>)| {
+                if !enabled {
+                    this.set_dynamic_resolution(None);
+                    return Ok(());
+                }
+                let (mut min_scale, mut max_scale, mut target_fps) =
+                    (0.5, 1.0, 60.0);
+                if let Some(values) = values {
+                    min_scale = values
+                        .raw_get::<_, Option<f32>>("min")?
+                        .unwrap_or(min_scale);
+                    max_scale = values
+                        .raw_get::<_, Option<f32>>("max")?
+                        .unwrap_or(max_scale);
+                    target_fps = values
+                        .raw_get::<_, Option<f32>>("target_fps")?
+                        .unwrap_or(target_fps);
+                }
+                if !(0.0 < min_scale && min_scale <= max_scale) {
+                    return Err(Error::runtime("invalid scale bounds"));
+                }
+                this.set_dynamic_resolution(Some(
+                    output::DynamicResolution::new(
+                        min_scale,
+                        max_scale,
+                        1.0 / target_fps,
+                    ),
+                ));
                 Ok(())
             },
         );
+        reg.add_method_mut(
+            "compare_screenshot",
+            |lua, this, (golden, callback, threshold): (String, Function, Option<f32>)| {
+                let id = this
+                    .screenshots
+                    .request(&golden, threshold.unwrap_or(0.01));
+                lua.named_registry_value::<Table>("screenshots")?
+                    .raw_set(id, callback)
+            },
+        );
+        reg.add_method_mut(
+            "record_animation",
+            |_, this, (path, seconds, options): (String, f32, Option<Table>)| {
+                let mut settings = RecordingSettings::default();
+                if let Some(options) = options {
+                    if let Some(fps) = options.raw_get("fps")? {
+                        settings.fps = fps;
+                    }
+                    if let Some(width) = options.raw_get("width")? {
+                        settings.width = width;
+                    }
+                    settings.caption = options.raw_get("caption")?;
+                }
+                this.recorder.start(Path::new(&path), seconds, settings);
+                Ok(())
+            },
+        );
+        reg.add_method("recording", |_, this, _: ()| {
+            Ok(this.recorder.is_recording())
+        });
+        reg.add_method_mut("set_memory_budget", |_, this, mb: Option<f64>| {
+            this.memory_budget = mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+            Ok(())
+        });
+        reg.add_method_mut("set_depth_prepass", |_, this, enabled: bool| {
+            this.bundles.model.depth_prepass = enabled;
+            Ok(())
+        });
+        reg.add_method_mut("set_indirect_draws", |_, this, enabled: bool| {
+            this.set_indirect_draws(enabled).map_err(Error::runtime)
+        });
+        reg.add_method("adapter_info", |lua, this, _: ()| {
+            let info = this.adapter_info();
+            let limits = this.limits();
+            let table = lua.create_table()?;
+            table.set("name", info.name)?;
+            table.set("backend", adapter::backend_name(info.backend))?;
+            table.set("device_type", format!("{:?}", info.device_type))?;
+            table.set("driver", info.driver)?;
+            let limits_table = lua.create_table()?;
+            limits_table.set(
+                "max_texture_dimension_2d",
+                limits.max_texture_dimension_2d,
+            )?;
+            limits_table.set("max_buffer_size", limits.max_buffer_size)?;
+            limits_table.set(
+                "max_storage_buffer_binding_size",
+                limits.max_storage_buffer_binding_size,
+            )?;
+            limits_table.set("max_bind_groups", limits.max_bind_groups)?;
+            limits_table
+                .set("max_vertex_attributes", limits.max_vertex_attributes)?;
+            table.set("limits", limits_table)?;
+            Ok(table)
+        });
+        reg.add_method_mut(
+            "set_renderer",
+            |_, this, (name, options): (String, Option<Table>)| {
+                let renderer = Renderer::from_name(&name)
+                    .ok_or(Error::runtime("unknown renderer"))?;
+                if let Some(options) = options {
+                    if let Some(bounces) = options.raw_get("bounces")? {
+                        this.bundles.pathtrace.bounces = bounces;
+                    }
+                }
+                this.set_renderer(renderer);
+                Ok(())
+            },
+        );
+        reg.add_method("renderer", |_, this, _: ()| {
+            Ok(this.bundles.pathtrace.renderer.name())
+        });
+        reg.add_method("traced_samples", |_, this, _: ()| {
+            Ok(this.bundles.pathtrace.frames)
+        });
+        reg.add_method_mut(
+            "set_accumulation",
+            |_, this, (enabled, options): (bool, Option<Table>)| {
+                if let Some(options) = options {
+                    let accumulation = &mut this.bundles.accumulation;
+                    if let Some(max_frames) = options.raw_get("max_frames")? {
+                        accumulation.max_frames = max_frames;
+                    }
+                    if let Some(size) =
+                        options.raw_get::<_, Option<f32>>("light_size")?
+                    {
+                        accumulation.light_size = size.max(0.0);
+                    }
+                    if let Some(distance) = options
+                        .raw_get::<_, Option<f32>>("occlusion_distance")?
+                    {
+                        accumulation.occlusion_distance = distance.max(0.0);
+                    }
+                }
+                this.set_accumulation(enabled);
+                Ok(())
+            },
+        );
+        reg.add_method("accumulation", |_, this, _: ()| {
+            let accumulation = &this.bundles.accumulation;
+            Ok(accumulation.enabled().then_some(accumulation.frames))
+        });
+        reg.add_method_mut("reset_accumulation", |_, this, ()| {
+            this.bundles.accumulation.reset();
+            Ok(())
+        });
+        reg.add_method_mut("set_present_mode", |_, this, mode: String| {
+            let mode = match mode.as_bytes() {
+                b"immediate" => wgpu::PresentMode::Immediate,
+                b"mailbox" => wgpu::PresentMode::Mailbox,
+                b"fifo" => wgpu::PresentMode::Fifo,
+                _ => return Err(Error::runtime("unknown present mode")),
+            };
+            this.set_present_mode(mode).map_err(Error::runtime)
+        });
+        reg.add_method("present_mode", |_, this, _: ()| {
+            Ok(match this.present_mode() {
+                wgpu::PresentMode::Immediate => "immediate",
+                wgpu::PresentMode::Mailbox => "mailbox",
+                _ => "fifo",
+            })
+        });
+        reg.add_method_mut(
+            "set_layer_visible",
+            |_, this, (layer, visible): (String, bool)| {
+                match visible {
+                    true => this.hidden_layers.remove(&layer),
+                    false => this.hidden_layers.insert(layer),
+                };
+                Ok(())
+            },
+        );
+        reg.add_method("layer_visible", |_, this, layer: String| {
+            Ok(!this.hidden_layers.contains(&layer))
+        });
+        reg.add_method_mut("set_frame_cap", |_, this, fps: Option<f32>| {
+            if fps.is_some_and(|fps| fps <= 0.0) {
+                return Err(Error::runtime("frame cap must be positive"));
+            }
+            this.frame_cap = fps;
+            Ok(())
+        });
+        reg.add_method_mut("set_quality", |_, this, preset: String| {
+            let quality = Quality::from_name(&preset)
+                .ok_or(Error::runtime("unknown quality preset"))?;
+            this.set_quality(quality);
+            Ok(())
+        });
+        reg.add_method("quality", |_, this, _: ()| {
+            Ok(this.quality().map(Quality::name))
+        });
+        reg.add_method_mut("isolate_light", |_, this, index: Option<u32>| {
+            this.bundles.lights.isolated_light =
+                index.map(|index| index.saturating_sub(1));
+            Ok(())
+        });
+        reg.add_method_mut(
+            "isolate_material",
+            |_, this, material_id: Option<String>| {
+                if let Some(material_id) = &material_id {
+                    if this.materials.get_shader_id(material_id).is_none() {
+                        return Err(Error::runtime(format!(
+                            "unknown material {}",
+                            material_id
+                        )));
+                    }
+                }
+                this.bundles.model.isolated_material = material_id;
+                Ok(())
+            },
+        );
+        reg.add_method_mut(
+            "set_fullscreen_shader",
+            |_, this, shader_id: Option<String>| {
+                this.set_fullscreen_shader(shader_id);
+                Ok(())
+            },
+        );
+        reg.add_method("fullscreen_shader", |_, this, ()| {
+            Ok(this.bundles.fullscreen.shader_id().map(str::to_string))
+        });
+        reg.add_method_mut("set_exposure", |_, this, ev: f32| {
+            this.set_exposure(ev);
+            Ok(())
+        });
+        reg.add_method("exposure", |_, this, ()| {
+            Ok(this.bundles.output.exposure())
+        });
+        reg.add_method_mut(
+            "create_buffer",
+            |_, this, (name, size): (String, u64)| {
+                this.create_buffer(&name, size);
+                Ok(())
+            },
+        );
+        reg.add_method(
+            "write_buffer",
+            |_, this, (name, values, offset): (String, Vec<f32>, Option<u64>)| {
+                this.write_buffer(
+                    &name,
+                    offset.unwrap_or(0),
+                    bytemuck::cast_slice(&values),
+                )
+                .map_err(|err| Error::runtime(err.to_string()))
+            },
+        );
+        reg.add_method_mut(
+            "read_buffer",
+            |lua, this, (name, callback): (String, Function)| {
+                let id = this
+                    .buffers
+                    .request_read(&name)
+                    .map_err(|err| Error::runtime(err.to_string()))?;
+                lua.named_registry_value::<Table>("readbacks")?
+                    .raw_set(id, callback)
+            },
+        );
+        reg.add_method_mut("minimap", |_, this, values: Option<Table>| {
+            let Some(values) = values else {
+                this.bundles.minimap.settings = None;
+                return Ok(None);
+            };
+            let default = minimap::Settings::default();
+            let size = values
+                .raw_get::<_, Option<u32>>("size")?
+                .unwrap_or(default.size);
+            let max_size = this.limits().max_texture_dimension_2d;
+            if size == 0 || size > max_size {
+                return Err(Error::runtime(format!(
+                    "minimap size must be between 1 and {}",
+                    max_size
+                )));
+            }
+            let extent = values
+                .raw_get::<_, Option<f32>>("extent")?
+                .unwrap_or(default.extent);
+            let height = values
+                .raw_get::<_, Option<f32>>("height")?
+                .unwrap_or(default.height);
+            for (name, value) in [("extent", extent), ("height", height)] {
+                if !(value.is_finite() && value > 0.0) {
+                    return Err(Error::runtime(format!(
+                        "minimap {} must be finite and positive",
+                        name
+                    )));
+                }
+            }
+            this.bundles.minimap.settings = Some(minimap::Settings {
+                extent,
+                height,
+                include: values.raw_get("include")?,
+                size,
+            });
+            Ok(Some(minimap::TEXTURE_ID))
+        });
+        reg.add_method_mut(
+            "load_lods",
+            |_, this, (id, lods, distances): (String, Vec<String>, Vec<f32>)| {
+                this.meshes
+                    .load_lods(&id, lods, distances)
+                    .map_err(Error::runtime)
+            },
+        );
+        reg.add_method_mut(
+            "bake_impostor",
+            |_,
+             this,
+             (mesh_id, material_id, options): (
+                String,
+                String,
+                Option<Table>,
+            )| {
+                let mut settings = ImpostorSettings::default();
+                if let Some(options) = options {
+                    if let Some(distance) = options.raw_get("distance")? {
+                        settings.distance = distance;
+                    }
+                    if let Some(size) = options.raw_get("size")? {
+                        settings.size = size;
+                    }
+                    if let Some(views) = options.raw_get("views")? {
+                        settings.views = views;
+                    }
+                }
+                this.bundles
+                    .impostor
+                    .request(&mesh_id, &material_id, settings);
+                Ok(())
+            },
+        );
+        reg.add_method_mut("remove_impostor", |_, this, mesh_id: String| {
+            this.bundles.impostor.remove(&mesh_id);
+            Ok(())
+        });
+        reg.add_method_mut(
+            "set_texture_sampler",
+            |_, this, (texture_id, values): (String, Table)| {
+                let filter = |key| -> Result<Option<Filter>> {
+                    let Some(filter) =
+                        values.raw_get::<_, Option<String>>(key)?
+                    else {
+                        return Ok(None);
+                    };
+                    match filter.as_bytes() {
+                        b"linear" => Ok(Some(Filter::Linear)),
+                        b"nearest" => Ok(Some(Filter::Nearest)),
+                        _ => Err(Error::runtime("unknown filter")),
+                    }
+                };
+                let default = SamplerSettings::default();
+                let wrap = match values
+                    .raw_get::<_, Option<String>>("wrap")?
+                    .as_deref()
+                    .map(str::as_bytes)
+                {
+                    None => default.wrap,
+                    Some(b"repeat") => Wrap::Repeat,
+                    Some(b"clamp") => Wrap::Clamp,
+                    Some(b"mirror") => Wrap::Mirror,
+                    Some(_) => return Err(Error::runtime("unknown wrap mode")),
+                };
+                let settings = SamplerSettings {
+                    anisotropy: values
+                        .raw_get::<_, Option<u16>>("anisotropy")?
+                        .unwrap_or(default.anisotropy),
+                    filter: filter("filter")?.unwrap_or(default.filter),
+                    mipmap_filter: filter("mipmap_filter")?
+                        .unwrap_or(default.mipmap_filter),
+                    wrap,
+                };
+                this.textures
+                    .set_sampler(&this.device, &texture_id, settings);
+                Ok(())
+            },
+        );
+        reg.add_method_mut("load_point_cloud", |_, this, cloud_id: String| {
+            this.point_clouds.load(&cloud_id);
+            Ok(())
+        });
+        reg.add_method("point_cloud", |lua, this, cloud_id: String| {
+            let Some(cloud) = this.point_clouds.get(&cloud_id) else {
+                return Ok(None);
+            };
+            let chunks = cloud
+                .chunks
+                .iter()
+                .map(|chunk| lua.create_any_userdata(chunk.clone()))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Some(chunks))
+        });
+        reg.add_method_mut("load_heightfield", |_, this, tile_id: String| {
+            this.terrains.load(&tile_id);
+            Ok(())
+        });
+        reg.add_method_mut(
+            "build_terrain",
+            |_, this, (mesh_id, values): (String, Table)| {
+                let tiles = values
+                    .raw_get::<_, Vec<Vec<String>>>("tiles")
+                    .map_err(|_| Error::runtime("terrain must have tiles"))?;
+                let overlap = values
+                    .raw_get::<_, Option<bool>>("overlap")?
+                    .unwrap_or(false);
+                let spacing =
+                    values.raw_get::<_, Option<f32>>("spacing")?.unwrap_or(1.0);
+                let vertical_scale = values
+                    .raw_get::<_, Option<f32>>("vertical_scale")?
+                    .unwrap_or(1.0);
+                if spacing <= 0.0 {
+                    return Err(Error::runtime("spacing must be positive"));
+                }
+                this.build_terrain(
+                    &mesh_id,
+                    &tiles,
+                    overlap,
+                    spacing,
+                    vertical_scale,
+                )
+                .map_err(Error::runtime)
+            },
+        );
+        reg.add_method(
+            "terrain_height",
+            |_, this, (mesh_id, x, z): (String, f32, f32)| {
+                let terrain = this
+                    .terrains
+                    .terrains
+                    .get(&mesh_id)
+                    .ok_or(Error::runtime("terrain does not exist"))?;
+                Ok(terrain.height_at(x, z))
+            },
+        );
+        reg.add_method_mut(
+            "set_material_param",
+            |_, this, (material_id, name, value): (String, String, Value)| {
+                let value = param_arg(value)?;
+                this.materials
+                    .set_param(&material_id, &name, value)
+                    .map_err(Error::runtime)
+            },
+        );
         reg.add_method_mut(
             "material_data",
             |_, this, (material_id, values): (String, Table)| {
@@ -292,7 +2413,17 @@ fn register_render_state(lua: &Lua) -> Result<()> {
                         .raw_get::<_, UserDataRef<Vec3>>("color")
                         .map(|v| *v)
                         .unwrap_or(u.color);
-                    material.uniform = simple::Uniform { color };
+                    let hue_variation = values
+                        .raw_get::<_, Option<f32>>("hue_variation")?
+                        .unwrap_or(u.hue_variation);
+                    let brightness_variation = values
+                        .raw_get::<_, Option<f32>>("brightness_variation")?
+                        .unwrap_or(u.brightness_variation);
+                    material.uniform = simple::Uniform {
+                        color,
+                        hue_variation,
+                        brightness_variation,
+                    };
                 }
 
                 Ok(())
@@ -301,6 +2432,50 @@ fn register_render_state(lua: &Lua) -> Result<()> {
     })
 }
 
+fn register_stats(lua: &Lua) -> Result<()> {
+    register_type::<Stats>(lua, "Stats", |reg| {
+        register_to_string!(reg);
+        reg.add_field_method_get("culled", |_, this| Ok(this.culled));
+        reg.add_field_method_get("draw_calls", |_, this| Ok(this.draw_calls));
+        reg.add_field_method_get("entities", |_, this| Ok(this.entities));
+        reg.add_field_method_get("frame_time", |_, this| Ok(this.frame_time));
+        reg.add_field_method_get("instances", |_, this| Ok(this.instances));
+        reg.add_field_method_get("lights", |_, this| Ok(this.lights));
+        reg.add_field_method_get("surface_format", |_, this| {
+            Ok(format!("{:?}", this.surface_format))
+        });
+        reg.add_field_method_get("colorspace", |_, this| Ok(this.colorspace()));
+        reg.add_field_method_get("srgb_conversion", |_, this| {
+            Ok(this.srgb_conversion)
+        });
+        reg.add_field_method_get("gpu_time", |_, this| Ok(this.gpu_time));
+        reg.add_field_method_get("render_scale", |_, this| {
+            Ok(this.render_scale)
+        });
+        reg.add_field_method_get("mesh_memory", |_, this| Ok(this.mesh_memory));
+        reg.add_field_method_get("buffer_memory", |_, this| {
+            Ok(this.buffer_memory)
+        });
+        reg.add_field_method_get("frame_times", |_, this| {
+            Ok(this.frame_times.iter().copied().collect::<Vec<_>>())
+        });
+        reg.add_field_method_get("pipelines", |lua, this| {
+            let table = lua.create_table()?;
+            for (shader_id, instances) in &this.pipelines {
+                table.raw_set(shader_id.as_str(), *instances)?;
+            }
+            Ok(table)
+        });
+        reg.add_field_method_get("texture_memory", |_, this| {
+            Ok(this.texture_memory)
+        });
+        reg.add_field_method_get("vram_estimate", |_, this| {
+            Ok(this.vram_estimate())
+        });
+        reg.add_method("hud_line", |_, this, ()| Ok(this.hud_line()));
+    })
+}
+
 fn register_cached_tables(lua: &Lua) -> Result<()> {
     lua.set_named_registry_value("cached_tables", lua.create_table()?)?;
     lua.globals().set(
@@ -320,29 +2495,114 @@ fn register_cached_tables(lua: &Lua) -> Result<()> {
 pub fn create_scoped_context<'scope>(
     lua: &'scope Lua,
     scope: &Scope<'_, 'scope>,
-    scene: &'scope mut Scene,
-    inputs: &'scope Inputs,
-    window: Arc<Window>,
-    render_state: &'scope mut RenderState,
+    context: &'scope mut Context,
 ) -> Result<Table<'scope>> {
     let ctx = lua.create_table()?;
-    ctx.set("scene", scope.create_any_userdata_ref_mut(scene)?)?;
-    ctx.set("inputs", scope.create_any_userdata_ref(inputs)?)?;
-    ctx.set("window", scope.create_any_userdata(window)?)?;
-    ctx.set("graphics", scope.create_any_userdata_ref_mut(render_state)?)?;
+    ctx.set("app", scope.create_any_userdata_ref_mut(context.app)?)?;
+    ctx.set("scene", scope.create_any_userdata_ref_mut(context.scene)?)?;
+    ctx.set("inputs", scope.create_any_userdata_ref_mut(context.inputs)?)?;
+    ctx.set("window", scope.create_any_userdata(context.window.clone())?)?;
+    ctx.set("wind", scope.create_any_userdata_ref_mut(context.wind)?)?;
+    ctx.set(
+        "collision",
+        scope.create_any_userdata_ref_mut(context.collision)?,
+    )?;
+    ctx.set(
+        "stats",
+        AnyUserData::wrap(context.render_state.stats.clone()),
+    )?;
+    ctx.set(
+        "graphics",
+        scope.create_any_userdata_ref_mut(context.render_state)?,
+    )?;
+    #[cfg(feature = "audio")]
+    ctx.set("audio", scope.create_any_userdata_ref_mut(context.audio)?)?;
+    #[cfg(feature = "mic")]
+    ctx.set("mic", scope.create_any_userdata_ref_mut(context.mic)?)?;
+    #[cfg(feature = "ui")]
+    ctx.set("ui", scope.create_any_userdata_ref_mut(context.ui)?)?;
+    #[cfg(feature = "physics")]
+    ctx.set(
+        "physics",
+        scope.create_any_userdata_ref_mut(context.physics)?,
+    )?;
+    api::record_context(lua, &ctx)?;
     Ok(ctx)
 }
 
+fn register_engine(lua: &Lua) -> Result<()> {
+    let table = lua.create_table()?;
+    table.set(
+        "dump_api",
+        lua.create_function(|lua, path: String| {
+            let api = lua.app_data_ref::<Api>().unwrap();
+            api.write(&path).map_err(Error::runtime)?;
+            info!("Lua api written to {}", path);
+            Ok(())
+        })?,
+    )?;
+    table.set(
+        "mount",
+        lua.create_function(|_, (namespace, root): (String, String)| {
+            assets::mount(&namespace, Path::new(&root))
+                .map_err(Error::runtime)?;
+            info!("Assets of {} mounted from {}", namespace, root);
+            Ok(())
+        })?,
+    )?;
+    table.set(
+        "set_math_arena",
+        lua.create_function(|lua, enabled: bool| {
+            arena::set_enabled(lua, enabled);
+            Ok(())
+        })?,
+    )?;
+    lua.globals().set("engine", table)
+}
+
+pub fn set_engine_args(lua: &Lua, args: Vec<String>) -> Result<()> {
+    lua.globals()
+        .get::<_, Table>("engine")?
+        .set("args", lua.create_sequence_from(args)?)
+}
+
 pub fn register_types_globals(lua: &Lua) -> Result<()> {
+    let builtins = api::global_names(lua)?;
+    lua.set_app_data(Api::default());
+
+    register_arena(lua)?;
     register_vec3(lua)?;
     register_quat(lua)?;
+    register_mat4(lua)?;
     register_transform(lua)?;
+    register_bounds(lua)?;
     register_camera(lua)?;
     register_scene(lua)?;
     register_inputs(lua)?;
     register_window(lua)?;
+    register_wind(lua)?;
+    register_collision(lua)?;
     register_render_state(lua)?;
+    register_app(lua)?;
+    #[cfg(feature = "audio")]
+    register_audio(lua)?;
+    #[cfg(feature = "mic")]
+    register_mic(lua)?;
+    #[cfg(feature = "ui")]
+    register_ui(lua)?;
+    #[cfg(feature = "physics")]
+    register_physics(lua)?;
+    register_stats(lua)?;
     register_cached_tables(lua)?;
+    register_shared(lua)?;
+    register_events(lua)?;
+    register_scheduler(lua)?;
+    register_components(lua)?;
+    register_persist(lua)?;
+    register_timeline(lua)?;
+    register_scene_switch(lua)?;
+    register_material(lua)?;
+    register_engine(lua)?;
 
     lua.globals().set(
         "print",
@@ -352,5 +2612,6 @@ pub fn register_types_globals(lua: &Lua) -> Result<()> {
             info!("{}", args.join(", "));
             Ok(())
         }),
-    )
+    )?;
+    api::record_globals(lua, &builtins)
 }
@@ -0,0 +1,138 @@
+use log::{error, info};
+use mlua::{
+    AnyUserData, Error, Function, Lua, RegistryKey, Result, Table, Value,
+};
+
+use crate::{
+    constraint::Constraints,
+    interpolation::Interpolation,
+    render::state::RenderState,
+    scene::Scene,
+    scene_switch::{SceneSwitch, Step},
+    snapshot::Snapshot,
+};
+
+use super::{component, persist::Persisted, scheduler};
+
+/// Scene being switched to by the scripts, one at a time.
+#[derive(Default)]
+struct Switching(Option<(SceneSwitch, Option<RegistryKey>)>);
+
+pub fn register_scene_switch(lua: &Lua) -> Result<()> {
+    lua.set_app_data(Switching::default());
+    Ok(())
+}
+
+/// Replace the scene with the snapshot, the entity data, components,
+/// constraints, interpolated transforms and entity tweens of the scripts
+/// are reset as the ids now name other entities. Returns the saved data and lights, the lights
+/// only last this frame so the scripts submit them again.
+pub fn apply<'lua>(
+    lua: &'lua Lua,
+    snapshot: Snapshot,
+    scene: &mut Scene,
+) -> Result<(Value<'lua>, Table<'lua>)> {
+    let data = match &snapshot.data {
+        Some(data) => serde_json::from_value::<Persisted>(data.clone())
+            .map_err(Error::external)?
+            .to_lua(lua)?,
+        None => Value::Nil,
+    };
+    let lights = lua.create_table()?;
+    for light in &snapshot.lights {
+        let table = lua.create_table()?;
+        table.set("pos", AnyUserData::wrap(light.pos))?;
+        table.set("radius", light.radius)?;
+        table.set("color", AnyUserData::wrap(light.color))?;
+        table.set("intensity", light.intensity)?;
+        table.set("attenuation", light.attenuation.name())?;
+        lights.push(table)?;
+    }
+    snapshot.apply(scene);
+    lua.set_named_registry_value("entity_data", lua.create_table()?)?;
+    component::clear(lua)?;
+    *lua.app_data_mut::<Constraints>().unwrap() = Constraints::default();
+    *lua.app_data_mut::<Interpolation>().unwrap() = Interpolation::default();
+    scheduler::cancel_entity_tweens(lua)?;
+    Ok((data, lights))
+}
+
+/// Start loading `assets/scenes/<name>.json`, replacing a switch still
+/// loading. The options are the transition, `"cut"` or `"fade"`, its
+/// duration and an `on_switch(ctx, data, lights)` callback.
+pub fn switch_to(lua: &Lua, name: &str, options: Option<Table>) -> Result<()> {
+    let (mut fade, mut on_switch) = (None, None);
+    if let Some(options) = options {
+        let duration = options
+            .raw_get::<_, Option<f32>>("duration")?
+            .unwrap_or(1.0);
+        let transition = options
+            .raw_get::<_, Option<String>>("transition")?
+            .unwrap_or("cut".to_string());
+        fade = match transition.as_str() {
+            "cut" => None,
+            "fade" => Some(duration / 2.0),
+            _ => return Err(Error::runtime("unknown transition")),
+        };
+        on_switch = options
+            .raw_get::<_, Option<Function>>("on_switch")?
+            .map(|callback| lua.create_registry_value(callback))
+            .transpose()?;
+    }
+    info!("Switching to scene {}", name);
+    lua.app_data_mut::<Switching>().unwrap().0 =
+        Some((SceneSwitch::start(name, fade), on_switch));
+    Ok(())
+}
+
+pub fn switching(lua: &Lua) -> Option<String> {
+    let switching = lua.app_data_ref::<Switching>().unwrap();
+    switching.0.as_ref().map(|(switch, _)| switch.name.clone())
+}
+
+/// Scene swapped in this frame, with the arguments of its callback.
+pub struct Switched<'lua> {
+    pub data: Value<'lua>,
+    pub lights: Table<'lua>,
+    pub on_switch: Option<Function<'lua>>,
+}
+
+/// Advance the switch, swapping the scene once it is loaded. A scene
+/// failing to load is logged and the current one stays.
+pub fn update<'lua>(
+    lua: &'lua Lua,
+    scene: &mut Scene,
+    render_state: &mut RenderState,
+    dt: f32,
+) -> Result<Option<Switched<'lua>>> {
+    let mut switching = lua.app_data_mut::<Switching>().unwrap();
+    let Some((switch, on_switch)) = &mut switching.0 else {
+        return Ok(None);
+    };
+    let snapshot = match switch.update(render_state, dt) {
+        Ok(Step::Swap(snapshot)) => snapshot,
+        Ok(Step::Loading | Step::FadingIn) => return Ok(None),
+        Ok(Step::Done) => {
+            switching.0 = None;
+            return Ok(None);
+        }
+        Err(err) => {
+            error!("scene {}\n{:?}", switch.name, err);
+            render_state.set_fade(0.0);
+            switching.0 = None;
+            return Ok(None);
+        }
+    };
+    info!("Scene switched: {}", switch.name);
+    let on_switch = on_switch
+        .take()
+        .map(|key| lua.registry_value::<Function>(&key))
+        .transpose()?;
+    drop(switching);
+    let (data, lights) = apply(lua, snapshot, scene)?;
+    Ok(Some(Switched {
+        data,
+        lights,
+        on_switch,
+    }))
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+
+use glam::Vec3;
+use log::error;
+use mlua::{
+    AnyUserData, AppDataRefMut, Error, Function, Lua, MetaMethod, Nil, Result,
+    Table, Thread, ThreadStatus, UserDataMethods, UserDataRef, Value,
+};
+
+use crate::{
+    entity::{Entities, EntityId},
+    register_to_string,
+};
+
+use super::{api::register_type, limits};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Self::Linear),
+            "ease_in" => Some(Self::EaseIn),
+            "ease_out" => Some(Self::EaseOut),
+            "ease_in_out" => Some(Self::EaseInOut),
+            _ => None,
+        }
+    }
+
+    /// Progress along the tween for a time in [0, 1].
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t,
+            Self::EaseOut => t * (2.0 - t),
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+struct Task {
+    id: u32,
+    /// Chunk the function was defined in, the task stops when it reloads.
+    source: String,
+    /// Seconds before it resumes.
+    wait: f32,
+}
+
+/// Transform field of an entity moved by a tween.
+#[derive(Debug, Clone, Copy)]
+pub enum TweenField {
+    Pos,
+    Scale,
+}
+
+impl TweenField {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pos" => Some(Self::Pos),
+            "scale" => Some(Self::Scale),
+            _ => None,
+        }
+    }
+}
+
+/// Vector moved by a tween. An entity field is looked up every frame
+/// rather than kept as a reference, which only lives for its frame.
+pub enum TweenTarget<'lua> {
+    Entity(EntityId, TweenField),
+    /// Owned vector, kept in the `tweens` registry table by id.
+    Vec3(AnyUserData<'lua>),
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Entity(EntityId, TweenField),
+    Vec3,
+}
+
+#[derive(Clone, Copy)]
+struct Tween {
+    duration: f32,
+    /// None for a function, kept in the `easings` registry table by id.
+    easing: Option<Easing>,
+    elapsed: f32,
+    /// Read from an entity on the first frame the tween runs.
+    from: Option<Vec3>,
+    id: u32,
+    target: Target,
+    to: Vec3,
+}
+
+/// Coroutines and tweens started by the scripts, kept in the app data so
+/// they outlive the reloads of the scripts that did not start them. The
+/// threads, tweened vectors and easing functions are in the `tasks`,
+/// `tweens` and `easings` registry tables by id.
+#[derive(Default)]
+struct Scheduler {
+    next_id: u32,
+    tasks: Vec<Task>,
+    tweens: Vec<Tween>,
+}
+
+impl Scheduler {
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn running(&self, id: u32) -> bool {
+        self.tasks.iter().any(|task| task.id == id)
+            || self.tweens.iter().any(|tween| tween.id == id)
+    }
+}
+
+/// Task or tween started by `schedule` or `tween`.
+#[derive(Clone, Copy)]
+pub struct TaskHandle(u32);
+
+impl fmt::Debug for TaskHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TaskHandle({})", self.0)
+    }
+}
+
+pub fn register_scheduler(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("tasks", lua.create_table()?)?;
+    lua.set_named_registry_value("tweens", lua.create_table()?)?;
+    lua.set_named_registry_value("easings", lua.create_table()?)?;
+    lua.set_app_data(Scheduler::default());
+    register_type::<TaskHandle>(lua, "TaskHandle", |reg| {
+        register_to_string!(reg);
+        reg.add_method("running", |lua, this, _: ()| {
+            Ok(scheduler(lua).running(this.0))
+        });
+        reg.add_method("cancel", |lua, this, _: ()| cancel(lua, this.0));
+    })?;
+
+    let globals = lua.globals();
+    globals.set(
+        "schedule",
+        lua.create_function(|lua, function: Function| {
+            let source = function.info().source.unwrap_or_default();
+            let thread = lua.create_thread(function)?;
+            let id = scheduler(lua).next_id();
+            lua.named_registry_value::<Table>("tasks")?
+                .raw_set(id, thread)?;
+            scheduler(lua).tasks.push(Task {
+                id,
+                source,
+                wait: 0.0,
+            });
+            Ok(AnyUserData::wrap(TaskHandle(id)))
+        })?,
+    )?;
+    // Yields the seconds to the scheduler, the task resumes with the
+    // context of that frame.
+    let coroutine = globals.get::<_, Table>("coroutine")?;
+    globals.set("wait", coroutine.get::<_, Function>("yield")?)?;
+    globals.set(
+        "tween",
+        lua.create_function(
+            |lua,
+             (target, to, duration, easing): (
+                AnyUserData,
+                UserDataRef<Vec3>,
+                f32,
+                Value,
+            )| {
+                if !target.is::<Vec3>() {
+                    return Err(Error::runtime(
+                        "tween an owned Vec3, or an entity with entity:tween",
+                    ));
+                }
+                start_tween(
+                    lua,
+                    TweenTarget::Vec3(target),
+                    *to,
+                    duration,
+                    easing,
+                )
+            },
+        )?,
+    )
+}
+
+fn scheduler(lua: &Lua) -> AppDataRefMut<'_, Scheduler> {
+    lua.app_data_mut::<Scheduler>().unwrap()
+}
+
+/// Move the target to the vector over the duration, with an easing name or
+/// function, linear by default.
+pub fn start_tween<'lua>(
+    lua: &'lua Lua,
+    target: TweenTarget<'lua>,
+    to: Vec3,
+    duration: f32,
+    easing: Value<'lua>,
+) -> Result<AnyUserData<'lua>> {
+    let id = scheduler(lua).next_id();
+    let easing = match easing {
+        Value::Nil => Some(Easing::Linear),
+        Value::String(name) => Some(
+            Easing::from_name(name.to_str()?)
+                .ok_or(Error::runtime("unknown easing"))?,
+        ),
+        Value::Function(function) => {
+            lua.named_registry_value::<Table>("easings")?
+                .raw_set(id, function)?;
+            None
+        }
+        _ => return Err(Error::runtime("easing must be a name or function")),
+    };
+    let (target, from) = match target {
+        TweenTarget::Entity(entity, field) => {
+            (Target::Entity(entity, field), None)
+        }
+        TweenTarget::Vec3(vec) => {
+            let from = *vec.borrow::<Vec3>()?;
+            lua.named_registry_value::<Table>("tweens")?
+                .raw_set(id, vec)?;
+            (Target::Vec3, Some(from))
+        }
+    };
+    scheduler(lua).tweens.push(Tween {
+        duration: duration.max(0.0),
+        easing,
+        elapsed: 0.0,
+        from,
+        id,
+        target,
+        to,
+    });
+    lua.create_any_userdata(TaskHandle(id))
+}
+
+fn entity_field(
+    entities: &mut Entities,
+    id: EntityId,
+    field: TweenField,
+) -> Option<&mut Vec3> {
+    let transform = &mut entities.get_mut(id)?.transform;
+    Some(match field {
+        TweenField::Pos => &mut transform.pos,
+        TweenField::Scale => &mut transform.scale,
+    })
+}
+
+/// Progress given by an easing function, a result that is not a number
+/// falls back to linear.
+fn ease_with(easings: &Table, id: u32, t: f32) -> Result<f32> {
+    let function = easings.raw_get::<_, Function>(id)?;
+    Ok(match function.call::<_, Value>(t)? {
+        Value::Number(progress) => progress as f32,
+        Value::Integer(progress) => progress as f32,
+        _ => t,
+    })
+}
+
+fn cancel(lua: &Lua, id: u32) -> Result<()> {
+    {
+        let mut scheduler = scheduler(lua);
+        scheduler.tasks.retain(|task| task.id != id);
+        scheduler.tweens.retain(|tween| tween.id != id);
+    }
+    lua.named_registry_value::<Table>("tasks")?
+        .raw_set(id, Nil)?;
+    lua.named_registry_value::<Table>("tweens")?
+        .raw_set(id, Nil)?;
+    lua.named_registry_value::<Table>("easings")?
+        .raw_set(id, Nil)
+}
+
+/// Stop the tweens of the entities, when their ids are given to others.
+pub fn cancel_entity_tweens(lua: &Lua) -> Result<()> {
+    let ids = scheduler(lua)
+        .tweens
+        .iter()
+        .filter(|tween| matches!(tween.target, Target::Entity(..)))
+        .map(|tween| tween.id)
+        .collect::<Vec<_>>();
+    for id in ids {
+        cancel(lua, id)?;
+    }
+    Ok(())
+}
+
+/// Stop the tasks whose function was defined in the chunk.
+pub fn cancel_source(lua: &Lua, source: &str) -> Result<()> {
+    let ids = scheduler(lua)
+        .tasks
+        .iter()
+        .filter(|task| task.source == source)
+        .map(|task| task.id)
+        .collect::<Vec<_>>();
+    for id in ids {
+        cancel(lua, id)?;
+    }
+    Ok(())
+}
+
+/// Advance the tweens, a tween whose entity was despawned is stopped.
+pub fn run_tweens(lua: &Lua, entities: &mut Entities, dt: f32) -> Result<()> {
+    let vectors = lua.named_registry_value::<Table>("tweens")?;
+    let easings = lua.named_registry_value::<Table>("easings")?;
+    // Copied out, the easing functions may start or cancel tweens.
+    let tweens = scheduler(lua).tweens.clone();
+    let mut finished = Vec::new();
+    for mut tween in tweens {
+        tween.elapsed += dt;
+        let t = match tween.duration > 0.0 {
+            true => (tween.elapsed / tween.duration).min(1.0),
+            false => 1.0,
+        };
+        let progress = match tween.easing {
+            Some(easing) => easing.apply(t),
+            None => match ease_with(&easings, tween.id, t) {
+                Ok(progress) => progress,
+                // Stopped rather than failing every frame.
+                Err(err) => {
+                    error!("easing\n{}", err);
+                    finished.push(tween.id);
+                    continue;
+                }
+            },
+        };
+        let written = match tween.target {
+            Target::Entity(entity, field) => {
+                entity_field(entities, entity, field).map(|vec| {
+                    let from = *tween.from.get_or_insert(*vec);
+                    *vec = from.lerp(tween.to, progress);
+                })
+            }
+            Target::Vec3 => vectors
+                .raw_get::<_, AnyUserData>(tween.id)
+                .and_then(|vec| {
+                    let from = tween.from.unwrap_or_default();
+                    *vec.borrow_mut::<Vec3>()? = from.lerp(tween.to, progress);
+                    Ok(())
+                })
+                .ok(),
+        };
+        if written.is_none() || t >= 1.0 {
+            finished.push(tween.id);
+        }
+        // Cancelled by an easing function.
+        if let Some(entry) = scheduler(lua)
+            .tweens
+            .iter_mut()
+            .find(|entry| entry.id == tween.id)
+        {
+            *entry = tween;
+        }
+    }
+    for id in finished {
+        cancel(lua, id)?;
+    }
+    Ok(())
+}
+
+/// Resume the tasks done waiting with the context. A failing task is
+/// stopped without affecting the others.
+pub fn run<'lua>(lua: &'lua Lua, ctx: &Table<'lua>, dt: f32) -> Result<()> {
+    let due = scheduler(lua)
+        .tasks
+        .iter_mut()
+        .filter_map(|task| {
+            task.wait -= dt;
+            (task.wait <= 0.0).then_some(task.id)
+        })
+        .collect::<Vec<_>>();
+    let tasks = lua.named_registry_value::<Table>("tasks")?;
+    for id in due {
+        // Cancelled by a task resumed before it.
+        let Some(thread) = tasks.raw_get::<_, Option<Thread>>(id)? else {
+            continue;
+        };
+        limits::reset_budget(lua);
+        let wait = thread
+            .resume::<_, Option<f32>>(ctx.clone())
+            .inspect_err(|err| error!("task\n{}", err));
+        match (wait, thread.status()) {
+            (Ok(wait), ThreadStatus::Resumable) => {
+                let mut scheduler = scheduler(lua);
+                if let Some(task) =
+                    scheduler.tasks.iter_mut().find(|task| task.id == id)
+                {
+                    task.wait = wait.unwrap_or(0.0);
+                }
+            }
+            _ => cancel(lua, id)?,
+        }
+    }
+    Ok(())
+}
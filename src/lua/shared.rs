@@ -0,0 +1,118 @@
+use mlua::{Function, Lua, Nil, Result, Table, Value};
+
+/// Set while the watchers are called, the changes they make are queued.
+struct Notifying(bool);
+
+fn values(lua: &Lua) -> Result<Table<'_>> {
+    lua.named_registry_value::<Table>("shared_values")
+}
+
+/// Watchers of a key, by the script that registered them.
+fn watchers<'lua>(lua: &'lua Lua, key: &str) -> Result<Table<'lua>> {
+    let watchers = lua.named_registry_value::<Table>("shared_watchers")?;
+    if let Some(key_watchers) = watchers.raw_get::<_, Option<Table>>(key)? {
+        return Ok(key_watchers);
+    }
+    let key_watchers = lua.create_table()?;
+    watchers.raw_set(key, key_watchers.clone())?;
+    Ok(key_watchers)
+}
+
+/// Chunk name of the script calling into rust, a reloaded script replaces
+/// the watchers it registered before.
+pub fn caller(lua: &Lua) -> String {
+    lua.inspect_stack(1)
+        .and_then(|debug| debug.source().source.map(|s| s.into_owned()))
+        .unwrap_or_default()
+}
+
+/// Changes are notified in order once the current watchers return.
+fn set(lua: &Lua, key: String, value: Value) -> Result<()> {
+    let values = values(lua)?;
+    let old = values.raw_get::<_, Value>(key.as_str())?;
+    if old == value {
+        return Ok(());
+    }
+    values.raw_set(key.as_str(), value.clone())?;
+    let pending = lua.named_registry_value::<Table>("shared_pending")?;
+    pending.raw_push(lua.create_sequence_from([
+        Value::String(lua.create_string(&key)?),
+        value,
+        old,
+    ])?)?;
+
+    if lua.app_data_ref::<Notifying>().unwrap().0 {
+        return Ok(());
+    }
+    lua.app_data_mut::<Notifying>().unwrap().0 = true;
+    let result = (|| {
+        while pending.raw_len() > 0 {
+            let change = pending.raw_get::<_, Table>(1)?;
+            pending.raw_remove(1)?;
+            let key = change.raw_get::<_, String>(1)?;
+            let value = change.raw_get::<_, Value>(2)?;
+            let old = change.raw_get::<_, Value>(3)?;
+            let key_watchers = watchers(lua, &key)?
+                .pairs::<String, Function>()
+                .collect::<Result<Vec<_>>>()?;
+            for (_, watcher) in key_watchers {
+                watcher.call::<_, ()>((value.clone(), old.clone()))?;
+            }
+        }
+        Ok(())
+    })();
+    lua.app_data_mut::<Notifying>().unwrap().0 = false;
+    if result.is_err() {
+        pending.clear()?;
+    }
+    result
+}
+
+/// Values shared between the scripts, kept across reloads.
+pub fn register_shared(lua: &Lua) -> Result<()> {
+    lua.set_named_registry_value("shared_values", lua.create_table()?)?;
+    lua.set_named_registry_value("shared_watchers", lua.create_table()?)?;
+    lua.set_named_registry_value("shared_pending", lua.create_table()?)?;
+    lua.set_app_data(Notifying(false));
+
+    let table = lua.create_table()?;
+    table.set(
+        "get",
+        lua.create_function(|lua, key: String| {
+            values(lua)?.raw_get::<_, Value>(key)
+        })?,
+    )?;
+    table.set(
+        "set",
+        lua.create_function(|lua, (key, value): (String, Value)| {
+            set(lua, key, value)
+        })?,
+    )?;
+    table.set(
+        "update",
+        lua.create_function(|lua, (key, update): (String, Function)| {
+            let old = values(lua)?.raw_get::<_, Value>(key.as_str())?;
+            let value = update.call::<_, Value>(old)?;
+            set(lua, key, value.clone())?;
+            Ok(value)
+        })?,
+    )?;
+    table.set(
+        "watch",
+        lua.create_function(|lua, (key, watcher): (String, Function)| {
+            watchers(lua, &key)?.raw_set(caller(lua), watcher.clone())?;
+            let value = values(lua)?.raw_get::<_, Value>(key)?;
+            if !value.is_nil() {
+                watcher.call::<_, ()>((value, Nil))?;
+            }
+            Ok(())
+        })?,
+    )?;
+    table.set(
+        "unwatch",
+        lua.create_function(|lua, key: String| {
+            watchers(lua, &key)?.raw_set(caller(lua), Nil)
+        })?,
+    )?;
+    lua.globals().set("shared", table)
+}
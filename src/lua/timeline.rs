@@ -0,0 +1,73 @@
+use log::info;
+use mlua::{Error, Lua, Result, Table};
+
+use crate::{
+    render::state::RenderState,
+    scene::Scene,
+    timeline::{Playback, Timeline},
+};
+
+/// Timeline played by the `timeline` global, one at a time.
+#[derive(Default)]
+struct Timelines(Option<Playback>);
+
+pub fn register_timeline(lua: &Lua) -> Result<()> {
+    lua.set_app_data(Timelines::default());
+    let table = lua.create_table()?;
+    table.set(
+        "play",
+        lua.create_function(
+            |lua, (_, name, options): (Table, String, Option<Table>)| {
+                let looping = match options {
+                    Some(options) => options
+                        .raw_get::<_, Option<bool>>("loop")?
+                        .unwrap_or(false),
+                    None => false,
+                };
+                let timeline = Timeline::load(&name)
+                    .map_err(|err| Error::runtime(format!("{:?}", err)))?;
+                info!("Playing timeline {}", name);
+                lua.app_data_mut::<Timelines>().unwrap().0 =
+                    Some(Playback::new(&name, timeline, looping));
+                Ok(())
+            },
+        )?,
+    )?;
+    table.set(
+        "stop",
+        lua.create_function(|lua, _: Table| {
+            lua.app_data_mut::<Timelines>().unwrap().0 = None;
+            Ok(())
+        })?,
+    )?;
+    table.set(
+        "playing",
+        lua.create_function(|lua, _: Table| {
+            let timelines = lua.app_data_ref::<Timelines>().unwrap();
+            Ok(timelines.0.as_ref().map(|playback| playback.name.clone()))
+        })?,
+    )?;
+    table.set(
+        "time",
+        lua.create_function(|lua, _: Table| {
+            let timelines = lua.app_data_ref::<Timelines>().unwrap();
+            Ok(timelines.0.as_ref().map(|playback| playback.time))
+        })?,
+    )?;
+    lua.globals().set("timeline", table)
+}
+
+/// Advance the timeline being played and apply it.
+pub fn update(
+    lua: &Lua,
+    scene: &mut Scene,
+    render_state: &mut RenderState,
+    dt: f32,
+) {
+    let mut timelines = lua.app_data_mut::<Timelines>().unwrap();
+    if let Some(playback) = &mut timelines.0 {
+        if !playback.update(scene, render_state, dt) {
+            timelines.0 = None;
+        }
+    }
+}
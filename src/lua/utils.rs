@@ -25,8 +25,9 @@ macro_rules! unsafe_mut_field {
 #[macro_export]
 macro_rules! register_methods_mut {
     ($lua:expr, $type:ty,  $method:expr) => {{
-        $lua.register_userdata_type::<$type>($method)?;
-        $lua.register_userdata_type::<&mut $type>($method)?;
+        let name = stringify!($type);
+        $crate::lua::api::register_type::<$type>($lua, name, $method)?;
+        $crate::lua::api::register_type::<&mut $type>($lua, name, $method)?;
     }};
 }
 
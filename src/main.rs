@@ -1,27 +1,133 @@
-use std::env;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
 use app::App;
 use env_logger::Env;
 use input::UserEvent;
+use log::error;
+use project::Project;
+use render::adapter::AdapterOptions;
+use window::WindowOptions;
 use winit::event_loop::{self, EventLoop};
 
 mod app;
+mod assets;
+#[cfg(feature = "audio")]
+mod audio;
+mod bounds;
+mod camera_controller;
+mod camera_path;
+mod collision;
+mod constraint;
+mod entity;
+mod events;
+mod import;
 mod input;
+mod interpolation;
 mod lua;
+#[cfg(feature = "mic")]
+mod mic;
+#[cfg(feature = "physics")]
+mod physics;
+mod power;
+mod project;
 mod render;
+mod rope;
 mod scene;
+mod scene_switch;
+mod snapshot;
+mod timeline;
 mod transform;
+#[cfg(feature = "ui")]
+mod ui;
+mod viewer;
+mod wind;
+mod window;
 
 fn main() {
     env_logger::Builder::from_env(
         Env::default().filter_or("RUST_LOG", "wgpu_lua_fun=info,wgpu=warn"),
     )
     .init();
-    let not_on_top = env::var("NOT_ON_TOP").is_ok();
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let adapter_options = match AdapterOptions::from_args(&mut args) {
+        Ok(options) => options,
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+    let project = match Project::from_args(&mut args) {
+        Ok(project) => project,
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+    assets::set_root(project.dir.clone(), project.asset_root());
+    let mut window_options = match WindowOptions::from_args(&mut args) {
+        Ok(options) => options,
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+    if env::var("NOT_ON_TOP").is_ok() {
+        window_options.on_top = false;
+    }
+
+    // `--import` preprocesses the assets and exits.
+    if args.first().map(String::as_str) == Some("--import") {
+        if let Err(err) = import::run(&adapter_options) {
+            error!("import\n{:?}", err);
+        }
+        return;
+    }
+
+    // `--emit-defs [path]` writes the luau definitions of the bindings once
+    // the init ran, then exits.
+    let mut emit_defs = None;
+    if args.first().map(String::as_str) == Some("--emit-defs") {
+        args.remove(0);
+        let path = match args.first() {
+            Some(path) if !path.starts_with("--") => args.remove(0),
+            _ => "types.d.luau".to_string(),
+        };
+        emit_defs = Some(PathBuf::from(path));
+    }
+
+    // `view <model>` shows a model file instead of running the main script.
+    let entry_point = match args.first().map(String::as_str) {
+        Some("view") => {
+            let Some(path) = args.get(1) else {
+                error!("usage: view <model.obj|model.gltf>");
+                return;
+            };
+            match viewer::mount_model(Path::new(path)) {
+                Ok(mesh_id) => args = vec![mesh_id],
+                Err(err) => {
+                    error!("view\n{:?}", err);
+                    return;
+                }
+            }
+            viewer::ENTRY_POINT
+        }
+        _ => &project.entry_point,
+    };
 
     let event_loop = EventLoop::<UserEvent>::with_user_event().build().unwrap();
     event_loop.set_control_flow(event_loop::ControlFlow::Poll);
 
-    let mut app = App::new(event_loop.create_proxy(), not_on_top);
+    let mut app = App::new(
+        event_loop.create_proxy(),
+        adapter_options,
+        window_options,
+        entry_point,
+        args,
+        emit_defs,
+        &project.lua,
+    );
     event_loop.run_app(&mut app).unwrap();
 }
@@ -0,0 +1,171 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, Sample, SampleFormat, SizedSample,
+};
+use log::{error, info, warn};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+/// Samples analysed each frame, about 20ms at 48kHz.
+const FFT_SIZE: usize = 1024;
+/// Lowest frequency of the spectrum bands.
+const MIN_FREQUENCY: f32 = 20.0;
+
+type Samples = Arc<Mutex<VecDeque<f32>>>;
+
+/// Default input device, opened the first time the scripts read it.
+pub struct Mic {
+    fft: Arc<dyn Fft<f32>>,
+    level: f32,
+    /// Magnitude of the frequency bins, up to the nyquist frequency.
+    magnitudes: Vec<f32>,
+    requested: bool,
+    sample_rate: f32,
+    samples: Samples,
+    /// None until requested or when no input is available.
+    stream: Option<cpal::Stream>,
+}
+
+impl Mic {
+    pub fn new() -> Self {
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            level: 0.0,
+            magnitudes: vec![0.0; FFT_SIZE / 2],
+            requested: false,
+            sample_rate: 48000.0,
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(FFT_SIZE))),
+            stream: None,
+        }
+    }
+
+    /// Open the default input, only tried once.
+    pub fn request(&mut self) {
+        if self.requested {
+            return;
+        }
+        self.requested = true;
+        match self.open() {
+            Ok(stream) => self.stream = Some(stream),
+            Err(err) => warn!("No audio input: {:?}", err),
+        }
+    }
+
+    fn open(&mut self) -> Result<cpal::Stream> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .context("no input device")?;
+        let config = device.default_input_config()?;
+        self.sample_rate = config.sample_rate().0 as f32;
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => {
+                build_stream::<f32>(&device, &config, self.samples.clone())?
+            }
+            SampleFormat::I16 => {
+                build_stream::<i16>(&device, &config, self.samples.clone())?
+            }
+            SampleFormat::U16 => {
+                build_stream::<u16>(&device, &config, self.samples.clone())?
+            }
+            format => bail!("unsupported sample format {}", format),
+        };
+        stream.play()?;
+        info!("Audio input: {}", device.name().unwrap_or_default());
+        Ok(stream)
+    }
+
+    /// Analyse the latest samples.
+    pub fn update(&mut self) {
+        if self.stream.is_none() {
+            return;
+        }
+        let mut buffer = {
+            let samples = self.samples.lock().unwrap();
+            samples
+                .iter()
+                .map(|sample| Complex::new(*sample, 0.0))
+                .collect::<Vec<_>>()
+        };
+        if buffer.is_empty() {
+            return;
+        }
+        self.level = (buffer.iter().map(|s| s.re * s.re).sum::<f32>()
+            / buffer.len() as f32)
+            .sqrt();
+
+        // Hann window, its gain is compensated in the magnitudes.
+        buffer.resize(FFT_SIZE, Complex::default());
+        for (i, sample) in buffer.iter_mut().enumerate() {
+            let phase = std::f32::consts::TAU * i as f32 / FFT_SIZE as f32;
+            *sample *= 0.5 - 0.5 * phase.cos();
+        }
+        self.fft.process(&mut buffer);
+        for (magnitude, bin) in self.magnitudes.iter_mut().zip(&buffer) {
+            *magnitude = bin.norm() * 4.0 / FFT_SIZE as f32;
+        }
+    }
+
+    /// Root mean square of the latest samples.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Mean magnitude in log spaced bands, from 20Hz to the nyquist
+    /// frequency.
+    pub fn spectrum(&self, bands: usize) -> Vec<f32> {
+        let bin_width = self.sample_rate / FFT_SIZE as f32;
+        let first = (MIN_FREQUENCY / bin_width).max(1.0);
+        let last = self.magnitudes.len() as f32;
+        let mut start = first as usize;
+        (1..=bands)
+            .map(|band| {
+                let t = band as f32 / bands as f32;
+                let end =
+                    ((first * (last / first).powf(t)) as usize).max(start + 1);
+                let bins = &self.magnitudes[start.min(self.magnitudes.len())
+                    ..end.min(self.magnitudes.len())];
+                start = end;
+                match bins.len() {
+                    0 => 0.0,
+                    len => bins.iter().sum::<f32>() / len as f32,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Downmix the frames to mono and keep the latest ones.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    samples: Samples,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels() as usize;
+    let stream = device.build_input_stream(
+        &config.config(),
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let mut samples = samples.lock().unwrap();
+            for frame in data.chunks(channels) {
+                let mono =
+                    frame.iter().map(|s| f32::from_sample(*s)).sum::<f32>()
+                        / channels as f32;
+                if samples.len() == FFT_SIZE {
+                    samples.pop_front();
+                }
+                samples.push_back(mono);
+            }
+        },
+        |err| error!("audio input\n{}", err),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,187 @@
+use glam::{Quat, Vec3};
+use rapier3d::prelude::*;
+
+use crate::transform::Transform;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ShapeKind {
+    #[default]
+    Box,
+    Capsule,
+    Sphere,
+}
+
+#[derive(Debug, Clone)]
+pub struct BodySettings {
+    pub fixed: bool,
+    pub mass: f32,
+    pub shape: ShapeKind,
+    /// Full extents of the shape, spheres and capsules use x as diameter.
+    pub size: Vec3,
+}
+
+impl Default for BodySettings {
+    fn default() -> Self {
+        Self {
+            fixed: false,
+            mass: 1.0,
+            shape: ShapeKind::default(),
+            size: Vec3::ONE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RaycastHit {
+    pub body: Option<RigidBodyHandle>,
+    pub distance: f32,
+    pub normal: Vec3,
+    pub point: Vec3,
+}
+
+pub struct Physics {
+    bodies: RigidBodySet,
+    broad_phase: DefaultBroadPhase,
+    ccd_solver: CCDSolver,
+    colliders: ColliderSet,
+    gravity: Vector<f32>,
+    impulse_joints: ImpulseJointSet,
+    integration_parameters: IntegrationParameters,
+    islands: IslandManager,
+    multibody_joints: MultibodyJointSet,
+    narrow_phase: NarrowPhase,
+    pipeline: PhysicsPipeline,
+    query_pipeline: QueryPipeline,
+}
+
+impl Physics {
+    pub fn new() -> Self {
+        Self {
+            bodies: RigidBodySet::new(),
+            broad_phase: DefaultBroadPhase::new(),
+            ccd_solver: CCDSolver::new(),
+            colliders: ColliderSet::new(),
+            gravity: vector![0.0, -9.81, 0.0],
+            impulse_joints: ImpulseJointSet::new(),
+            integration_parameters: IntegrationParameters::default(),
+            islands: IslandManager::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            narrow_phase: NarrowPhase::new(),
+            pipeline: PhysicsPipeline::new(),
+            query_pipeline: QueryPipeline::new(),
+        }
+    }
+
+    pub fn add_rigid_body(
+        &mut self,
+        transform: &Transform,
+        settings: &BodySettings,
+    ) -> RigidBodyHandle {
+        let builder = match settings.fixed {
+            true => RigidBodyBuilder::fixed(),
+            false => RigidBodyBuilder::dynamic(),
+        };
+        let (axis, angle) = transform.rot.to_axis_angle();
+        let body = builder
+            .translation(to_vector(transform.pos))
+            .rotation(to_vector(axis * angle))
+            .build();
+        let handle = self.bodies.insert(body);
+
+        let half = settings.size * transform.scale * 0.5;
+        let collider = match settings.shape {
+            ShapeKind::Box => ColliderBuilder::cuboid(half.x, half.y, half.z),
+            ShapeKind::Capsule => {
+                ColliderBuilder::capsule_y((half.y - half.x).max(0.0), half.x)
+            }
+            ShapeKind::Sphere => ColliderBuilder::ball(half.x),
+        };
+        self.colliders.insert_with_parent(
+            collider.mass(settings.mass).build(),
+            handle,
+            &mut self.bodies,
+        );
+        handle
+    }
+
+    pub fn remove_rigid_body(&mut self, handle: RigidBodyHandle) {
+        self.bodies.remove(
+            handle,
+            &mut self.islands,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            true,
+        );
+    }
+
+    pub fn clear(&mut self) {
+        let handles = self.bodies.iter().map(|(h, _)| h).collect::<Vec<_>>();
+        for handle in handles {
+            self.remove_rigid_body(handle);
+        }
+    }
+
+    /// Advance the simulation by one step, called at the fixed rate.
+    pub fn step(&mut self, dt: f32) {
+        self.integration_parameters.dt = dt;
+        self.pipeline.step(
+            &self.gravity,
+            &self.integration_parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            Some(&mut self.query_pipeline),
+            &(),
+            &(),
+        );
+    }
+
+    pub fn transform(&self, handle: RigidBodyHandle) -> Option<(Vec3, Quat)> {
+        let body = self.bodies.get(handle)?;
+        let rot = body.rotation();
+        Some((
+            from_vector(body.translation()),
+            Quat::from_xyzw(rot.i, rot.j, rot.k, rot.w),
+        ))
+    }
+
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        dir: Vec3,
+        max_dist: f32,
+    ) -> Option<RaycastHit> {
+        let ray = Ray::new(
+            to_vector(origin).into(),
+            to_vector(dir.normalize_or_zero()),
+        );
+        let (collider, hit) = self.query_pipeline.cast_ray_and_get_normal(
+            &self.bodies,
+            &self.colliders,
+            &ray,
+            max_dist,
+            true,
+            QueryFilter::default(),
+        )?;
+        Some(RaycastHit {
+            body: self.colliders.get(collider).and_then(|c| c.parent()),
+            distance: hit.time_of_impact,
+            normal: from_vector(&hit.normal),
+            point: from_vector(&ray.point_at(hit.time_of_impact).coords),
+        })
+    }
+}
+
+fn to_vector(v: Vec3) -> Vector<f32> {
+    vector![v.x, v.y, v.z]
+}
+
+fn from_vector(v: &Vector<f32>) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
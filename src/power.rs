@@ -0,0 +1,134 @@
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use crate::render::quality::Quality;
+
+/// How often the battery state is read.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Frame rates to save power, none to draw as fast as possible.
+#[derive(Debug, Clone, Default)]
+pub struct PowerSettings {
+    pub max_fps: Option<f32>,
+    /// Replaces the frame cap on battery.
+    pub battery_fps: Option<f32>,
+    /// Preset switched to on battery, the previous one is restored after.
+    pub battery_quality: Option<Quality>,
+    /// Frame rate when unfocused, none to only draw on window events.
+    pub unfocused_fps: Option<f32>,
+    /// Update rate when minimized or occluded, nothing is drawn, none to
+    /// pause.
+    pub hidden_fps: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    Continuous,
+    Capped(Duration),
+    OnEvents,
+    /// Update without drawing.
+    Hidden(Duration),
+    Paused,
+}
+
+#[derive(Debug)]
+pub struct Power {
+    last_check: Instant,
+    pub on_battery: bool,
+    /// Preset in use before switching to the battery one.
+    restore_quality: Option<Quality>,
+    pub settings: PowerSettings,
+}
+
+impl Power {
+    pub fn new() -> Self {
+        // Checked on the first update, the scripts see the change once
+        // they are loaded.
+        let now = Instant::now();
+        Self {
+            last_check: now.checked_sub(CHECK_INTERVAL).unwrap_or(now),
+            on_battery: false,
+            restore_quality: None,
+            settings: PowerSettings::default(),
+        }
+    }
+
+    /// Returns whether the battery state changed since the last check.
+    pub fn update(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let on_battery = on_battery();
+        let changed = on_battery != self.on_battery;
+        self.on_battery = on_battery;
+        changed
+    }
+
+    /// Preset to switch to after the battery state changed.
+    pub fn switch_quality(
+        &mut self,
+        current: Option<Quality>,
+    ) -> Option<Quality> {
+        let battery_quality = self.settings.battery_quality?;
+        match self.on_battery {
+            true => {
+                self.restore_quality = current;
+                Some(battery_quality)
+            }
+            false => self.restore_quality.take(),
+        }
+    }
+
+    pub fn pacing(&self, focused: bool, hidden: bool) -> Pacing {
+        if hidden {
+            return match self.settings.hidden_fps {
+                Some(fps) => Pacing::Hidden(Duration::from_secs_f32(1.0 / fps)),
+                None => Pacing::Paused,
+            };
+        }
+        let fps = match (focused, self.on_battery) {
+            (false, _) => match self.settings.unfocused_fps {
+                Some(fps) => Some(fps),
+                None => return Pacing::OnEvents,
+            },
+            (true, true) => self.settings.battery_fps.or(self.settings.max_fps),
+            (true, false) => self.settings.max_fps,
+        };
+        match fps {
+            Some(fps) => Pacing::Capped(Duration::from_secs_f32(1.0 / fps)),
+            None => Pacing::Continuous,
+        }
+    }
+}
+
+impl Pacing {
+    /// Draw no faster than the frame cap, lower power caps are kept.
+    pub fn with_cap(self, fps: Option<f32>) -> Self {
+        let Some(fps) = fps else {
+            return self;
+        };
+        let cap = Duration::from_secs_f32(1.0 / fps);
+        match self {
+            Pacing::Continuous => Pacing::Capped(cap),
+            Pacing::Capped(interval) => Pacing::Capped(interval.max(cap)),
+            pacing => pacing,
+        }
+    }
+}
+
+/// Only known on linux, from the discharging batteries. Other platforms are
+/// never seen on battery.
+fn on_battery() -> bool {
+    let Ok(entries) = fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        let read = |name| fs::read_to_string(path.join(name));
+        read("type").is_ok_and(|kind| kind.trim() == "Battery")
+            && read("status").is_ok_and(|status| status.trim() == "Discharging")
+    })
+}
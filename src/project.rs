@@ -0,0 +1,69 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{lua::limits::Limits, render::adapter::flag_value};
+
+const MANIFEST: &str = "project.toml";
+
+/// Asset root, entry point and script limits of a sketch, from the
+/// `project.toml` of the project directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    /// Relative to the project directory.
+    pub assets: PathBuf,
+    /// Where the scripts `require` modules from, not part of the manifest.
+    #[serde(skip)]
+    pub dir: PathBuf,
+    /// Script run at startup, in the `scripts` of the assets.
+    pub entry_point: String,
+    pub lua: Limits,
+}
+
+impl Default for Project {
+    fn default() -> Self {
+        Self {
+            assets: PathBuf::from("assets"),
+            dir: PathBuf::new(),
+            entry_point: "main".to_string(),
+            lua: Limits::default(),
+        }
+    }
+}
+
+impl Project {
+    /// Take `--project <dir>` out of the arguments, the working directory
+    /// is the project otherwise. Without manifest the defaults are used.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self> {
+        let dir = match args.iter().position(|arg| arg == "--project") {
+            Some(i) => PathBuf::from(flag_value(args, i)?),
+            None => PathBuf::new(),
+        };
+        let mut project = Self::load(&dir)?;
+        project.dir = dir;
+        let root = project.asset_root();
+        if !root.is_dir() {
+            anyhow::bail!("{} is not a directory", root.display());
+        }
+        Ok(project)
+    }
+
+    pub fn asset_root(&self) -> PathBuf {
+        self.dir.join(&self.assets)
+    }
+
+    fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(&path)?;
+        toml::from_str(&data)
+            .with_context(|| format!("parsing {}", path.display()))
+    }
+}
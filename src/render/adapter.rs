@@ -0,0 +1,99 @@
+use anyhow::{bail, Context, Result};
+
+/// Adapter picked from the command line flags, the default one otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct AdapterOptions {
+    pub backends: Option<wgpu::Backends>,
+    /// Part of the adapter name, case insensitive.
+    pub name: Option<String>,
+    pub low_power: bool,
+}
+
+impl AdapterOptions {
+    /// Take `--backend <name>`, `--adapter <name>` and `--low-power` out of
+    /// the arguments.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self> {
+        let mut options = Self::default();
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--backend" => {
+                    let name = flag_value(args, i)?;
+                    options.backends = Some(parse_backend(&name)?);
+                }
+                "--adapter" => options.name = Some(flag_value(args, i)?),
+                "--low-power" => {
+                    options.low_power = true;
+                    args.remove(i);
+                }
+                _ => i += 1,
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn create_instance(&self) -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends.unwrap_or(wgpu::Backends::all()),
+            ..Default::default()
+        })
+    }
+
+    pub async fn request_adapter(
+        &self,
+        instance: &wgpu::Instance,
+    ) -> Result<wgpu::Adapter> {
+        if let Some(name) = &self.name {
+            let name = name.to_lowercase();
+            let backends = self.backends.unwrap_or(wgpu::Backends::all());
+            return instance
+                .enumerate_adapters(backends)
+                .into_iter()
+                .find(|adapter| {
+                    adapter.get_info().name.to_lowercase().contains(&name)
+                })
+                .with_context(|| format!("no adapter named {:?}", name));
+        }
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: match self.low_power {
+                    true => wgpu::PowerPreference::LowPower,
+                    false => wgpu::PowerPreference::default(),
+                },
+                ..Default::default()
+            })
+            .await
+            .context("no adapter found")
+    }
+}
+
+/// Value following the flag at `i`, both are removed from the arguments.
+pub fn flag_value(args: &mut Vec<String>, i: usize) -> Result<String> {
+    if i + 1 >= args.len() {
+        bail!("{} expects a value", args[i]);
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(value)
+}
+
+fn parse_backend(name: &str) -> Result<wgpu::Backends> {
+    Ok(match name.to_lowercase().as_str() {
+        "vulkan" => wgpu::Backends::VULKAN,
+        "metal" => wgpu::Backends::METAL,
+        "dx12" => wgpu::Backends::DX12,
+        "gl" | "opengl" => wgpu::Backends::GL,
+        _ => bail!("unknown backend {}", name),
+    })
+}
+
+pub fn backend_name(backend: wgpu::Backend) -> &'static str {
+    match backend {
+        wgpu::Backend::Vulkan => "vulkan",
+        wgpu::Backend::Metal => "metal",
+        wgpu::Backend::Dx12 => "dx12",
+        wgpu::Backend::Gl => "gl",
+        wgpu::Backend::BrowserWebGpu => "webgpu",
+        wgpu::Backend::Empty => "empty",
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use anyhow::{bail, Result};
+
+/// Content of a buffer read back, handed to the scripts.
+pub struct BufferData(pub Vec<u8>);
+
+impl BufferData {
+    /// Little endian element at an index, none when out of bounds.
+    pub fn element<const N: usize>(&self, index: usize) -> Option<[u8; N]> {
+        let bytes = self.0.get(index * N..(index + 1) * N)?;
+        bytes.try_into().ok()
+    }
+}
+
+/// Copy waiting in a readback queue, read from its staging buffer.
+pub trait Staged {
+    fn staging(&self) -> &wgpu::Buffer;
+}
+
+impl<K, T: Staged> Staged for (K, T) {
+    fn staging(&self) -> &wgpu::Buffer {
+        self.1.staging()
+    }
+}
+
+type Mapped = (u64, Result<(), wgpu::BufferAsyncError>);
+
+/// Copies to staging buffers recorded during a frame, mapped once submitted
+/// and handed back once mapped.
+pub struct ReadbackQueue<T> {
+    mapped_rx: Receiver<Mapped>,
+    mapped_tx: Sender<Mapped>,
+    /// Never reused, a copy dropped while mapping can not be mistaken for
+    /// a later one.
+    next_id: u64,
+    mapping: Vec<(u64, T)>,
+    recorded: Vec<T>,
+}
+
+impl<T: Staged> ReadbackQueue<T> {
+    pub fn new() -> Self {
+        let (mapped_tx, mapped_rx) = channel();
+        Self {
+            mapped_rx,
+            mapped_tx,
+            next_id: 0,
+            mapping: Vec::new(),
+            recorded: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, copy: T) {
+        self.recorded.push(copy);
+    }
+
+    /// Map the staging buffers, to call once the copies are submitted.
+    pub fn map_recorded(&mut self) {
+        for copy in self.recorded.drain(..) {
+            let (id, mapped_tx) = (self.next_id, self.mapped_tx.clone());
+            self.next_id += 1;
+            copy.staging().slice(..).map_async(
+                wgpu::MapMode::Read,
+                move |result| {
+                    let _ = mapped_tx.send((id, result));
+                },
+            );
+            self.mapping.push((id, copy));
+        }
+    }
+
+    /// Copies mapped since the last call, in the order they mapped.
+    pub fn take_mapped(
+        &mut self,
+        device: &wgpu::Device,
+    ) -> Vec<(T, Result<(), wgpu::BufferAsyncError>)> {
+        if self.mapping.is_empty() {
+            return Vec::new();
+        }
+        device.poll(wgpu::Maintain::Poll);
+        let mut mapped = Vec::new();
+        while let Ok((id, result)) = self.mapped_rx.try_recv() {
+            let Some(index) =
+                self.mapping.iter().position(|(other, _)| *other == id)
+            else {
+                continue;
+            };
+            mapped.push((self.mapping.swap_remove(index).1, result));
+        }
+        mapped
+    }
+
+    pub fn has_recorded(&self) -> bool {
+        !self.recorded.is_empty()
+    }
+
+    pub fn is_mapping(&self) -> bool {
+        !self.mapping.is_empty()
+    }
+
+    /// Nothing recorded or mapping.
+    pub fn is_empty(&self) -> bool {
+        self.recorded.is_empty() && self.mapping.is_empty()
+    }
+
+    /// Forget the copies, the ones mapping are ignored once mapped.
+    pub fn clear(&mut self) {
+        self.recorded.clear();
+        self.mapping.clear();
+    }
+}
+
+/// Copy of a buffer waiting for its staging buffer to be mapped.
+struct Readback {
+    id: u64,
+    staging: wgpu::Buffer,
+}
+
+impl Staged for Readback {
+    fn staging(&self) -> &wgpu::Buffer {
+        &self.staging
+    }
+}
+
+/// Storage buffers shared by name between the scripts and the shaders, read
+/// back asynchronously.
+pub struct NamedBuffers {
+    buffers: HashMap<String, wgpu::Buffer>,
+    next_id: u64,
+    readbacks: ReadbackQueue<Readback>,
+    requested: Vec<(String, u64)>,
+}
+
+impl NamedBuffers {
+    pub fn new() -> Self {
+        Self {
+            buffers: HashMap::new(),
+            next_id: 0,
+            readbacks: ReadbackQueue::new(),
+            requested: Vec::new(),
+        }
+    }
+
+    /// Replace the buffer, the size is rounded up to a multiple of 4 bytes.
+    pub fn create(&mut self, device: &wgpu::Device, name: &str, size: u64) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{}_buffer", name)),
+            size: size.div_ceil(4).max(1) * 4,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.buffers.insert(name.to_string(), buffer);
+    }
+
+    // is_multiple_of is too recent for the toolchains this still builds with.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn write(
+        &self,
+        queue: &wgpu::Queue,
+        name: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let Some(buffer) = self.buffers.get(name) else {
+            bail!("buffer {} does not exist", name);
+        };
+        if offset % 4 != 0 || offset + data.len() as u64 > buffer.size() {
+            bail!("write out of the bounds of buffer {}", name);
+        }
+        queue.write_buffer(buffer, offset, data);
+        Ok(())
+    }
+
+    /// Returns the id the data will be delivered with.
+    pub fn request_read(&mut self, name: &str) -> Result<u64> {
+        if !self.buffers.contains_key(name) {
+            bail!("buffer {} does not exist", name);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.requested.push((name.to_string(), id));
+        Ok(id)
+    }
+
+    /// Copy the requested buffers to staging buffers.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        for (name, id) in self.requested.drain(..) {
+            let Some(buffer) = self.buffers.get(&name) else {
+                continue;
+            };
+            let staging = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{}_readback", name)),
+                size: buffer.size(),
+                usage: wgpu::BufferUsages::MAP_READ
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_buffer_to_buffer(
+                buffer,
+                0,
+                &staging,
+                0,
+                buffer.size(),
+            );
+            self.readbacks.push(Readback { id, staging });
+        }
+    }
+
+    pub fn map_recorded(&mut self) {
+        self.readbacks.map_recorded();
+    }
+
+    /// Data of the buffers mapped since the last call, by request id.
+    pub fn take_mapped(
+        &mut self,
+        device: &wgpu::Device,
+    ) -> Vec<(u64, Result<Vec<u8>>)> {
+        self.readbacks
+            .take_mapped(device)
+            .into_iter()
+            .map(|(readback, result)| {
+                let data = result.map_err(anyhow::Error::from).map(|_| {
+                    let data =
+                        readback.staging.slice(..).get_mapped_range().to_vec();
+                    readback.staging.unmap();
+                    data
+                });
+                (readback.id, data)
+            })
+            .collect()
+    }
+}
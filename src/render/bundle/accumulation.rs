@@ -0,0 +1,332 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use encase::ShaderType;
+use glam::Vec2;
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    render::{
+        bvh::Bvh, material::MaterialManager, mesh::MeshAssets,
+        shader::ShaderAssets, texture::Texture,
+    },
+    scene::Scene,
+};
+
+use super::{output, pathtrace, AsyncPipeline, Layouts};
+
+pub const SHADER: &str = "accumulate";
+
+/// Progressive preview, the scene is drawn with a sub-pixel jitter and
+/// averaged over the frames while the camera and the scene do not change.
+/// Each frame also traces one shadow ray per light toward a random point of
+/// the light and one ambient occlusion ray against a bvh of the entities,
+/// the average gives soft shadows and occlusion.
+pub struct Bundle {
+    enabled: bool,
+    /// Frames averaged in the history so far.
+    pub frames: u32,
+    /// History tonemapped by the output pass instead of the scene target.
+    history: Option<(Texture, wgpu::BindGroup)>,
+    /// Radius of the lights for the soft shadows.
+    pub light_size: f32,
+    /// Frames averaged at most, the history is kept as is after.
+    pub max_frames: u32,
+    /// Bvh nodes and triangles, bound with the lights.
+    pub nodes: wgpu::Buffer,
+    /// Distance within which the geometry occludes the ambient light.
+    pub occlusion_distance: f32,
+    pipeline: AsyncPipeline<wgpu::RenderPipeline>,
+    /// Entities and lights the bvh was built from.
+    scene_signature: u64,
+    /// Camera and scene state the history was accumulated from.
+    signature: u64,
+    /// Scene target read by the accumulation pass.
+    source: Option<wgpu::BindGroup>,
+    pub triangles: wgpu::Buffer,
+    /// Visibility traced by the main camera.
+    pub uniform: wgpu::Buffer,
+    /// Nothing traced, for the other cameras and the probes.
+    pub untraced_uniform: wgpu::Buffer,
+}
+
+impl Bundle {
+    pub fn new(device: &wgpu::Device, shaders: &mut ShaderAssets) -> Self {
+        shaders.load(SHADER);
+        let (nodes, triangles) =
+            pathtrace::bvh_buffers(device, Bvh::build(Vec::new()));
+        let create_uniform = |label| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: &Visibility::default().as_bytes(),
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST,
+            })
+        };
+        Self {
+            enabled: false,
+            frames: 0,
+            history: None,
+            light_size: 0.2,
+            max_frames: 256,
+            nodes,
+            occlusion_distance: 1.0,
+            pipeline: AsyncPipeline::default(),
+            scene_signature: 0,
+            signature: 0,
+            source: None,
+            triangles,
+            uniform: create_uniform("visibility_buffer"),
+            untraced_uniform: create_uniform("untraced_visibility_buffer"),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The history is created at the next resize.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.reset();
+        if !enabled {
+            self.history = None;
+            self.source = None;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.frames = 0;
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipeline.receive() {
+            info!("Accumulation pipeline loaded");
+            self.reset();
+        }
+        if shaders.frame_reloaded.as_deref() != Some(SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(SHADER).unwrap();
+        let layout = create_layout(device, layouts);
+        let device = device.clone();
+        self.pipeline
+            .build(move || create_pipeline(&device, &layout, &module));
+    }
+
+    /// Recreate the history at the scene target size.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        output: &output::Bundle,
+        [width, height]: [u32; 2],
+    ) {
+        self.reset();
+        let Some(target) = output.target.as_ref().filter(|_| self.enabled)
+        else {
+            return;
+        };
+        let history = Texture::create_target(
+            device,
+            width,
+            height,
+            output::TARGET_FORMAT,
+            "accumulation",
+        );
+        let bind_group = output.bind_target(device, layouts, &history);
+        self.history = Some((history, bind_group));
+        self.source = Some(output.bind_target(device, layouts, target));
+    }
+
+    /// Restart when the camera, the lights or the instances changed,
+    /// rebuilding the bvh when the entities did. Returns the clip space
+    /// jitter of this frame, none when not accumulating.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        size: [u32; 2],
+    ) -> Option<Vec2> {
+        if self.enabled {
+            self.restart_on_change(device, scene, meshes, materials);
+        }
+        let traced = self.enabled && self.frames < self.max_frames;
+        let visibility = Visibility {
+            frame: self.frames,
+            traced: traced as u32,
+            light_size: self.light_size,
+            occlusion_distance: self.occlusion_distance,
+        };
+        queue.write_buffer(&self.uniform, 0, &visibility.as_bytes());
+        if !traced {
+            return None;
+        }
+        let offset =
+            Vec2::new(halton(self.frames + 1, 2), halton(self.frames + 1, 3))
+                - 0.5;
+        Some(offset * 2.0 / Vec2::new(size[0] as f32, size[1] as f32))
+    }
+
+    fn restart_on_change(
+        &mut self,
+        device: &wgpu::Device,
+        scene: &Scene,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+    ) {
+        let scene_signature = pathtrace::scene_signature(scene, meshes);
+        if scene_signature != self.scene_signature {
+            self.scene_signature = scene_signature;
+            (self.nodes, self.triangles) = pathtrace::bvh_buffers(
+                device,
+                pathtrace::build_bvh(scene, meshes, materials),
+            );
+        }
+        let mut hasher = DefaultHasher::new();
+        scene_signature.hash(&mut hasher);
+        pathtrace::camera_signature(scene).hash(&mut hasher);
+        scene.model_batches.signature().hash(&mut hasher);
+        let signature = hasher.finish();
+        if signature != self.signature {
+            self.signature = signature;
+            self.reset();
+        }
+    }
+
+    /// Blend the scene target into the history, with a weight giving the
+    /// running average.
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let (Some(pipeline), Some((history, _)), Some(source)) =
+            (self.pipeline.get(), &self.history, &self.source)
+        else {
+            return;
+        };
+        if !self.enabled || self.frames >= self.max_frames {
+            return;
+        }
+        let weight = 1.0 / (self.frames + 1) as f64;
+        let mut rpass =
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("accumulation_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &history.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+        rpass.set_pipeline(pipeline);
+        rpass.set_blend_constant(wgpu::Color {
+            r: weight,
+            g: weight,
+            b: weight,
+            a: weight,
+        });
+        rpass.set_bind_group(0, source, &[]);
+        rpass.draw(0..3, 0..1);
+        self.frames += 1;
+    }
+
+    /// Bind group of the history for the output pass, once a frame is in.
+    pub fn output_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        match (&self.history, self.enabled && self.frames > 0) {
+            (Some((_, bind_group)), true) => Some(bind_group),
+            _ => None,
+        }
+    }
+}
+
+/// Per frame seed and settings of the rays traced by the model shader.
+#[derive(ShaderType, Default)]
+struct Visibility {
+    frame: u32,
+    traced: u32,
+    light_size: f32,
+    occlusion_distance: f32,
+}
+
+impl Visibility {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+/// Low discrepancy sequence in [0, 1), spreads the jitter over the pixel.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let (mut result, mut fraction) = (0.0, 1.0);
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("accumulation_layout"),
+        bind_group_layouts: &[&layouts.output.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let weighted = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Constant,
+        dst_factor: wgpu::BlendFactor::OneMinusConstant,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("accumulation_pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output::TARGET_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: weighted,
+                    alpha: weighted,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
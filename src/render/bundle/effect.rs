@@ -0,0 +1,283 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use encase::ShaderType;
+use glam::{Mat4, Vec3, Vec4};
+use log::{error, info};
+use wgpu::util::DeviceExt;
+
+use crate::render::{shader::ShaderAssets, texture::Texture};
+
+use super::{AsyncPipeline, Layouts};
+
+/// Values of the `params` uniform, four vec4.
+pub const MAX_PARAMS: usize = 16;
+
+/// Unit quad on the xz plane drawn with a shader of the scripts, its
+/// `vs_main` expands the six vertices.
+#[derive(Debug, Clone)]
+pub struct EffectPlane {
+    pub matrix: Mat4,
+    pub params: [f32; MAX_PARAMS],
+    pub shader_id: String,
+}
+
+struct Draw {
+    shader_id: String,
+    slot: usize,
+}
+
+/// Uniform of a drawn plane, kept for the next frames.
+struct Slot {
+    bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+}
+
+/// Animated planes blended over the scene, after the opaque models.
+pub struct Bundle {
+    draws: Vec<Draw>,
+    /// Shaders missing an entry point, skipped until they reload.
+    invalid: HashSet<String>,
+    pipelines: HashMap<String, AsyncPipeline<wgpu::RenderPipeline>>,
+    /// Shaders batched at least once, their pipelines built once loaded.
+    requested: HashSet<String>,
+    /// One per plane drawn, grown to the most planes drawn in a frame.
+    slots: Vec<Slot>,
+}
+
+impl Bundle {
+    pub fn new() -> Self {
+        Self {
+            draws: Vec::new(),
+            invalid: HashSet::new(),
+            pipelines: HashMap::new(),
+            requested: HashSet::new(),
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        format: wgpu::TextureFormat,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        for (shader_id, pipeline) in &mut self.pipelines {
+            if pipeline.receive() {
+                info!("Effect pipeline loaded: {}", shader_id);
+            }
+        }
+        for shader_id in &self.requested {
+            let reloaded = shaders.frame_reloaded.as_ref() == Some(shader_id);
+            if reloaded {
+                self.invalid.remove(shader_id);
+            }
+            if self.invalid.contains(shader_id) {
+                continue;
+            }
+            let requested = self
+                .pipelines
+                .get(shader_id)
+                .is_some_and(AsyncPipeline::is_requested);
+            if requested && !reloaded {
+                continue;
+            }
+            let Some(module) = shaders.get_shared(shader_id) else {
+                continue;
+            };
+            if !shaders.has_entry_point(shader_id, "vs_main")
+                || !shaders.has_entry_point(shader_id, "fs_main")
+            {
+                error!("effect shader {} needs vs_main and fs_main", shader_id);
+                self.invalid.insert(shader_id.clone());
+                self.pipelines.remove(shader_id);
+                continue;
+            }
+            let layout = create_layout(device, layouts);
+            let (device, id) = (device.clone(), shader_id.clone());
+            self.pipelines.entry(shader_id.clone()).or_default().build(
+                move || create_pipeline(&device, format, &layout, &module, &id),
+            );
+        }
+    }
+
+    /// Sorted back to front from the camera, the shaders not loaded yet
+    /// are requested.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &Layouts,
+        shaders: &mut ShaderAssets,
+        camera_pos: Vec3,
+        planes: &[EffectPlane],
+    ) {
+        self.draws.clear();
+        let mut planes = planes.iter().collect::<Vec<_>>();
+        planes.sort_by(|a, b| {
+            let distance = |plane: &EffectPlane| {
+                plane.matrix.w_axis.truncate().distance_squared(camera_pos)
+            };
+            distance(b).total_cmp(&distance(a))
+        });
+        for plane in planes {
+            if self.requested.insert(plane.shader_id.clone()) {
+                shaders.load(&plane.shader_id);
+            }
+            let loaded = self
+                .pipelines
+                .get(&plane.shader_id)
+                .is_some_and(|pipeline| pipeline.get().is_some());
+            if !loaded {
+                continue;
+            }
+            let uniform = Uniform {
+                world: plane.matrix,
+                params: [0, 1, 2, 3]
+                    .map(|i| Vec4::from_slice(&plane.params[i * 4..i * 4 + 4])),
+            };
+            let slot = self.draws.len();
+            match self.slots.get(slot) {
+                Some(slot) => {
+                    queue.write_buffer(&slot.buffer, 0, &uniform.as_bytes())
+                }
+                None => {
+                    let buffer = device.create_buffer_init(
+                        &wgpu::util::BufferInitDescriptor {
+                            label: Some("effect_uniform_buffer"),
+                            contents: &uniform.as_bytes(),
+                            usage: wgpu::BufferUsages::UNIFORM
+                                | wgpu::BufferUsages::COPY_DST,
+                        },
+                    );
+                    self.slots.push(Slot {
+                        bind_group: layouts.effect.bind(device, &buffer),
+                        buffer,
+                    });
+                }
+            }
+            self.draws.push(Draw {
+                shader_id: plane.shader_id.clone(),
+                slot,
+            });
+        }
+    }
+
+    /// Draw in a pass with the globals bound.
+    pub fn render(&self, rpass: &mut wgpu::RenderPass) -> u32 {
+        for draw in &self.draws {
+            rpass.set_pipeline(self.pipelines[&draw.shader_id].get().unwrap());
+            rpass.set_bind_group(1, &self.slots[draw.slot].bind_group, &[]);
+            rpass.draw(0..6, 0..1);
+        }
+        self.draws.len() as u32
+    }
+}
+
+#[derive(ShaderType)]
+struct Uniform {
+    world: Mat4,
+    params: [Vec4; 4],
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("effect_layout"),
+        bind_group_layouts: &[&layouts.globals.layout, &layouts.effect.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    shader_id: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("effect_{}_pipeline", shader_id)),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        // Seen from both sides.
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("effect_layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        uniform: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("effect_bind_group"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            }],
+        })
+    }
+}
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use encase::ShaderType;
+use glam::{Vec2, Vec4};
+use log::{error, info};
+use wgpu::util::DeviceExt;
+
+use crate::render::shader::ShaderAssets;
+
+use super::{output, AsyncPipeline, Layouts};
+
+/// Vertex stage shared by the fullscreen shaders, a triangle covering the
+/// surface.
+pub const SHADER: &str = "fullscreen";
+
+/// Fragment shader of the scripts drawn over the whole surface in place of
+/// the scene, with ShaderToy like inputs.
+pub struct Bundle {
+    bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+    /// Position of the last click, none until the first one.
+    click: Option<Vec2>,
+    /// Cursor position while the button is held, kept once released.
+    drag: Vec2,
+    frame: u32,
+    /// The shader misses fs_main, skipped until it reloads.
+    invalid: bool,
+    pressed: bool,
+    pipeline: AsyncPipeline<wgpu::RenderPipeline>,
+    shader_id: Option<String>,
+    srgb_conversion: bool,
+}
+
+impl Bundle {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layouts: &Layouts,
+        shaders: &mut ShaderAssets,
+    ) -> Self {
+        let buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("fullscreen_buffer"),
+                contents: &Uniform::default().as_bytes(),
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST,
+            });
+        shaders.load(SHADER);
+        Self {
+            bind_group: layouts.fullscreen.bind(device, &buffer),
+            buffer,
+            click: None,
+            drag: Vec2::ZERO,
+            frame: 0,
+            invalid: false,
+            pressed: false,
+            pipeline: AsyncPipeline::default(),
+            shader_id: None,
+            srgb_conversion: output::needs_srgb_conversion(config.format),
+        }
+    }
+
+    pub fn shader_id(&self) -> Option<&str> {
+        self.shader_id.as_deref()
+    }
+
+    /// None goes back to the scene, the frame count restarts.
+    pub fn set_shader(
+        &mut self,
+        shaders: &mut ShaderAssets,
+        shader_id: Option<String>,
+    ) {
+        if let Some(shader_id) = &shader_id {
+            shaders.load(shader_id);
+        }
+        self.frame = 0;
+        self.invalid = false;
+        self.pipeline.clear();
+        self.shader_id = shader_id;
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        config: &wgpu::SurfaceConfiguration,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipeline.receive() {
+            self.frame = 0;
+            info!(
+                "Fullscreen pipeline loaded: {}",
+                self.shader_id.as_deref().unwrap_or_default()
+            );
+        }
+        let Some(shader_id) = &self.shader_id else {
+            return;
+        };
+        let reloaded = shaders
+            .frame_reloaded
+            .as_ref()
+            .is_some_and(|id| id == shader_id || id == SHADER);
+        if reloaded {
+            self.invalid = false;
+        }
+        if self.invalid || (self.pipeline.is_requested() && !reloaded) {
+            return;
+        }
+        let (Some(vertex), Some(fragment)) =
+            (shaders.get_shared(SHADER), shaders.get_shared(shader_id))
+        else {
+            return;
+        };
+        if !shaders.has_entry_point(shader_id, "fs_main") {
+            error!("fullscreen shader {} needs fs_main", shader_id);
+            self.invalid = true;
+            self.pipeline.clear();
+            return;
+        }
+        let layout = create_layout(device, layouts);
+        let (device, format) = (device.clone(), config.format);
+        let shader_id = shader_id.clone();
+        self.pipeline.build(move || {
+            create_pipeline(
+                &device, format, &layout, &vertex, &fragment, &shader_id,
+            )
+        });
+    }
+
+    /// Follow the left mouse button, in pixels from the top left.
+    pub fn track_mouse(&mut self, cursor: Vec2, pressed: bool) {
+        if pressed {
+            if !self.pressed {
+                self.click = Some(cursor);
+            }
+            self.drag = cursor;
+        }
+        self.pressed = pressed;
+    }
+
+    pub fn prepare(
+        &mut self,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        elapsed: f32,
+        dt: f32,
+    ) {
+        if self.pipeline.get().is_none() {
+            return;
+        }
+        // Negative click coordinates once the button is released.
+        let click = self.click.map_or(Vec2::ZERO, |click| match self.pressed {
+            true => click,
+            false => -click,
+        });
+        let uniform = Uniform {
+            resolution: Vec2::new(config.width as f32, config.height as f32),
+            time: elapsed,
+            time_delta: dt,
+            mouse: Vec4::new(self.drag.x, self.drag.y, click.x, click.y),
+            frame: self.frame,
+            encode_srgb: self.srgb_conversion as u32,
+        };
+        queue.write_buffer(&self.buffer, 0, &uniform.as_bytes());
+        self.frame += 1;
+    }
+
+    /// Returns false when no fullscreen shader is ready, the scene is drawn
+    /// instead.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> bool {
+        let Some(pipeline) = self.pipeline.get() else {
+            return false;
+        };
+        let mut rpass =
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("fullscreen_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+        true
+    }
+}
+
+#[derive(ShaderType, Default)]
+struct Uniform {
+    resolution: Vec2,
+    time: f32,
+    time_delta: f32,
+    mouse: Vec4,
+    frame: u32,
+    encode_srgb: u32,
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("fullscreen_layout"),
+        bind_group_layouts: &[&layouts.fullscreen.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    pipeline_layout: &wgpu::PipelineLayout,
+    vertex: &wgpu::ShaderModule,
+    fragment: &wgpu::ShaderModule,
+    shader_id: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("fullscreen_{}_pipeline", shader_id)),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: vertex,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: fragment,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("fullscreen_layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        uniform: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fullscreen_bind_group"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            }],
+        })
+    }
+}
@@ -1,11 +1,63 @@
 use encase::ShaderType;
-use glam::Mat4;
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use wgpu::util::DeviceExt;
 
-use crate::render::camera::Camera;
+use crate::{render::camera::Camera, wind::Wind};
 
 use super::Layouts;
 
+/// Light added to every lit model, whatever the point lights.
+#[derive(Debug, Clone, Copy)]
+pub struct Ambient {
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl Default for Ambient {
+    fn default() -> Self {
+        Self {
+            color: Vec3::splat(0.03),
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Distance fog of the lit models, blending toward its color away from
+/// the camera.
+#[derive(Debug, Clone, Copy)]
+pub enum Fog {
+    /// None before the start distance, full past the end one.
+    Linear { color: Vec3, start: f32, end: f32 },
+    /// Thickens by the density per unit of distance.
+    Exponential { color: Vec3, density: f32 },
+}
+
+impl Fog {
+    /// Color in xyz and the mode in w, zero without fog, then the start,
+    /// end and density.
+    fn uniform(fog: Option<Self>) -> (Vec4, Vec4) {
+        match fog {
+            None => (Vec4::ZERO, Vec4::ZERO),
+            Some(Self::Linear { color, start, end }) => {
+                (color.extend(1.0), Vec4::new(start, end, 0.0, 0.0))
+            }
+            Some(Self::Exponential { color, density }) => {
+                (color.extend(2.0), Vec4::new(0.0, 0.0, density, 0.0))
+            }
+        }
+    }
+}
+
+/// Frame state shared by every view.
+#[derive(Debug, Clone, Copy)]
+pub struct Environment {
+    pub ambient: Ambient,
+    /// Seconds since the start.
+    pub elapsed: f32,
+    pub fog: Option<Fog>,
+    pub wind: Wind,
+}
+
 pub struct Bundle {
     pub bind_group: wgpu::BindGroup,
     buffer: wgpu::Buffer,
@@ -24,18 +76,43 @@ impl Bundle {
         Self { bind_group, buffer }
     }
 
+    /// The jitter offsets the projection in clip space.
     pub fn prepare(
         &self,
         queue: &wgpu::Queue,
-        config: &wgpu::SurfaceConfiguration,
-        elapsed: f32,
+        aspect_ratio: f32,
+        environment: &Environment,
         camera: &Camera,
+        jitter: Vec2,
+    ) {
+        self.prepare_matrices(
+            queue,
+            environment,
+            Mat4::from_translation(jitter.extend(0.0))
+                * camera.build_projection(aspect_ratio),
+            camera.build_view(),
+        );
+    }
+
+    pub fn prepare_matrices(
+        &self,
+        queue: &wgpu::Queue,
+        environment: &Environment,
+        clip_view: Mat4,
+        view_world: Mat4,
     ) {
-        let aspect_ratio = config.width as f32 / config.height as f32;
+        let (wind, gusts) = environment.wind.uniform();
+        let (fog_color, fog) = Fog::uniform(environment.fog);
+        let ambient = environment.ambient;
         let uniform = Uniform {
-            elapsed,
-            clip_view: camera.build_projection(aspect_ratio),
-            view_world: camera.build_view(),
+            clip_view,
+            view_world,
+            elapsed: environment.elapsed,
+            wind,
+            gusts,
+            ambient: ambient.color.extend(ambient.intensity),
+            fog_color,
+            fog,
         };
         queue.write_buffer(&self.buffer, 0, &uniform.as_bytes());
     }
@@ -46,6 +123,16 @@ pub struct Uniform {
     clip_view: Mat4,
     view_world: Mat4,
     elapsed: f32,
+    /// Velocity in xyz, gustiness in w.
+    wind: Vec4,
+    /// Gust frequency, scale and wind time.
+    gusts: Vec4,
+    /// Color in xyz, intensity in w.
+    ambient: Vec4,
+    /// Color in xyz, mode in w.
+    fog_color: Vec4,
+    /// Start, end and density.
+    fog: Vec4,
 }
 
 impl Uniform {
@@ -0,0 +1,447 @@
+use std::{collections::HashMap, f32::consts::TAU, sync::Arc};
+
+use encase::{ShaderType, UniformBuffer};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    bounds::Aabb,
+    render::{
+        material::MaterialManager,
+        mesh::{Mesh, MeshAssets, VertexTrait},
+        shader::ShaderAssets,
+        texture::{Texture, TextureAssets},
+    },
+};
+
+use super::{model, AsyncPipeline, Layouts};
+
+/// Material shader drawing the baked views as a billboard.
+pub const SHADER: &str = "impostor";
+pub const BAKE_SHADER: &str = "impostor_bake";
+/// Views baked around a mesh at most, the size of the uniform array.
+pub const MAX_VIEWS: u32 = 16;
+
+const ATLAS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImpostorSettings {
+    /// Camera distance the impostor replaces the mesh from.
+    pub distance: f32,
+    /// Pixels on the side of each view.
+    pub size: u32,
+    /// Views evenly spread around the vertical axis.
+    pub views: u32,
+}
+
+impl Default for ImpostorSettings {
+    fn default() -> Self {
+        Self {
+            distance: 50.0,
+            size: 128,
+            views: 8,
+        }
+    }
+}
+
+/// Baked impostor of a mesh drawn with a material, its mesh and material
+/// share the `impostor:<mesh_id>` id.
+pub struct Impostor {
+    pub distance: f32,
+    pub impostor_id: String,
+    pub material_id: String,
+}
+
+struct Request {
+    material_id: String,
+    mesh_id: String,
+    settings: ImpostorSettings,
+}
+
+/// Atlas and billboard quad of a baked mesh, inserted with the assets.
+pub struct Baked {
+    pub impostor: Impostor,
+    pub mesh: Mesh,
+    pub mesh_id: String,
+    pub texture: Texture,
+}
+
+#[derive(Default, ShaderType)]
+struct Uniform {
+    view_clips: [Mat4; MAX_VIEWS as usize],
+    uv_rect: Vec4,
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = UniformBuffer::new(Vec::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+/// Bakes meshes into atlases of views around them, drawn as billboards
+/// far from the camera.
+pub struct Bundle {
+    /// Baked impostors by the mesh they replace.
+    pub impostors: HashMap<String, Impostor>,
+    pipeline: AsyncPipeline<wgpu::RenderPipeline>,
+    /// Baked once their mesh and material texture are loaded.
+    requests: Vec<Request>,
+}
+
+impl Bundle {
+    pub fn new(shaders: &mut ShaderAssets, model: &mut model::Bundle) -> Self {
+        shaders.load(SHADER);
+        shaders.load(BAKE_SHADER);
+        model.register_shader(SHADER);
+        Self {
+            impostors: HashMap::new(),
+            pipeline: AsyncPipeline::default(),
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipeline.receive() {
+            info!("Impostor bake pipeline loaded");
+        }
+        if shaders.frame_reloaded.as_deref() != Some(BAKE_SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(BAKE_SHADER).unwrap();
+        let layout = create_layout(device, layouts);
+        let device = device.clone();
+        self.pipeline
+            .build(move || create_pipeline(&device, &layout, &module));
+    }
+
+    /// Bake the mesh drawn with the material, replacing any previous
+    /// impostor of the mesh.
+    pub fn request(
+        &mut self,
+        mesh_id: &str,
+        material_id: &str,
+        settings: ImpostorSettings,
+    ) {
+        self.requests.retain(|request| request.mesh_id != mesh_id);
+        self.requests.push(Request {
+            material_id: material_id.to_string(),
+            mesh_id: mesh_id.to_string(),
+            settings,
+        });
+    }
+
+    pub fn remove(&mut self, mesh_id: &str) {
+        self.requests.retain(|request| request.mesh_id != mesh_id);
+        self.impostors.remove(mesh_id);
+    }
+
+    /// Bake the requests whose assets are loaded, the others are kept for
+    /// the next frames.
+    pub fn bake(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &Layouts,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        textures: &TextureAssets,
+    ) -> Vec<Baked> {
+        let Some(pipeline) = self.pipeline.get() else {
+            return Vec::new();
+        };
+        let mut baked = Vec::new();
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("impostor_encoder"),
+            });
+        self.requests.retain(|request| {
+            let Some(texture_id) =
+                materials.get_texture_id(&request.material_id)
+            else {
+                return true;
+            };
+            let (Some(mesh), Some(texture)) =
+                (meshes.get(&request.mesh_id), textures.get(&texture_id))
+            else {
+                return true;
+            };
+            let views = request.settings.views.clamp(1, MAX_VIEWS);
+            let size = request.settings.size.max(1);
+            let frame = Frame::around(mesh);
+            let uniform = Uniform {
+                view_clips: std::array::from_fn(|view| {
+                    frame.view_clip(view as u32, views)
+                }),
+                uv_rect: textures.uv_rect(&texture_id),
+            };
+            let impostor_id = format!("impostor:{}", request.mesh_id);
+            let atlas = Texture::create_target(
+                device,
+                size * views,
+                size,
+                ATLAS_FORMAT,
+                &impostor_id,
+            );
+            let depth = Texture::create_depth(device, size * views, size);
+            let buffer =
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("impostor_buffer"),
+                    contents: &uniform.as_bytes(),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+            let bind_group =
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("impostor_bind_group"),
+                    layout: &layouts.impostor.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(
+                                &texture.view,
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Sampler(
+                                &texture.sampler,
+                            ),
+                        },
+                    ],
+                });
+            {
+                let mut rpass =
+                    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("impostor_pass"),
+                        color_attachments: &[Some(
+                            wgpu::RenderPassColorAttachment {
+                                view: &atlas.view,
+                                resolve_target: None,
+                                ops: wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(
+                                        wgpu::Color::TRANSPARENT,
+                                    ),
+                                    store: wgpu::StoreOp::Store,
+                                },
+                            },
+                        )],
+                        depth_stencil_attachment: Some(
+                            wgpu::RenderPassDepthStencilAttachment {
+                                view: &depth.view,
+                                depth_ops: Some(wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(1.0),
+                                    store: wgpu::StoreOp::Discard,
+                                }),
+                                stencil_ops: None,
+                            },
+                        ),
+                        ..Default::default()
+                    });
+                rpass.set_pipeline(pipeline);
+                rpass.set_bind_group(0, &bind_group, &[]);
+                rpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                rpass.set_index_buffer(
+                    mesh.index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+                for view in 0..views {
+                    rpass.set_viewport(
+                        (view * size) as f32,
+                        0.0,
+                        size as f32,
+                        size as f32,
+                        0.0,
+                        1.0,
+                    );
+                    rpass.draw_indexed(mesh.index_range(), 0, view..view + 1);
+                }
+            }
+            info!(
+                "Impostor baked for mesh {} with {} views",
+                request.mesh_id, views
+            );
+            baked.push(Baked {
+                impostor: Impostor {
+                    distance: request.settings.distance,
+                    impostor_id: impostor_id.clone(),
+                    material_id: request.material_id.clone(),
+                },
+                mesh: frame.billboard(device, &impostor_id),
+                mesh_id: request.mesh_id.clone(),
+                texture: atlas,
+            });
+            false
+        });
+        if !baked.is_empty() {
+            queue.submit(Some(encoder.finish()));
+        }
+        baked
+    }
+}
+
+/// Square covering the mesh from every horizontal direction, centered on
+/// its bounds.
+struct Frame {
+    center: Vec3,
+    /// Half of the side of the square.
+    extent: f32,
+}
+
+impl Frame {
+    fn around(mesh: &Mesh) -> Self {
+        let center = mesh.aabb.center();
+        let radius = mesh
+            .positions
+            .iter()
+            .map(|p| Vec2::new(p.x - center.x, p.z - center.z).length())
+            .fold(0.0, f32::max);
+        let height = mesh.aabb.extents().y;
+        Self {
+            center,
+            extent: radius.max(height).max(1e-3),
+        }
+    }
+
+    /// Orthographic view from the direction at the turn `view / views`
+    /// around the vertical axis, the billboard shader picks the same.
+    fn view_clip(&self, view: u32, views: u32) -> Mat4 {
+        let angle = view as f32 / views as f32 * TAU;
+        let direction = Vec3::new(angle.sin(), 0.0, angle.cos());
+        let eye = self.center + direction * self.extent * 2.0;
+        let world_view = Mat4::look_at_rh(eye, self.center, Vec3::Y);
+        let e = self.extent;
+        Mat4::orthographic_rh(-e, e, -e, e, 0.0, e * 4.0) * world_view
+    }
+
+    /// Quad drawn in the billboard plane, its normals hold the pivot the
+    /// shader turns it around.
+    fn billboard(&self, device: &wgpu::Device, label: &str) -> Mesh {
+        let (c, e) = (self.center, self.extent);
+        let pivot = [c.x, 0.0, c.z];
+        let corners =
+            [(-e, c.y + e), (e, c.y + e), (e, c.y - e), (-e, c.y - e)];
+        let tex_coords = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let vertices = corners
+            .iter()
+            .zip(tex_coords)
+            .map(|(&(x, y), tex_coord)| model::Vertex {
+                position: [x, y, 0.0],
+                tex_coord,
+                normal: pivot,
+            })
+            .collect::<Vec<_>>();
+        let indices = [0, 3, 2, 0, 2, 1];
+        // Bounds of the quad turned around the pivot.
+        let aabb = Aabb {
+            min: Vec3::new(c.x - e, c.y - e, c.z - e),
+            max: Vec3::new(c.x + e, c.y + e, c.z + e),
+        };
+        Mesh::new(device, &vertices, &indices, aabb, label)
+    }
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("impostor_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float {
+                                filterable: true,
+                            },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering,
+                        ),
+                        count: None,
+                    },
+                ],
+            });
+        Self { layout }
+    }
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("impostor_pipeline_layout"),
+        bind_group_layouts: &[&layouts.impostor.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("impostor_pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[model::Vertex::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: ATLAS_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
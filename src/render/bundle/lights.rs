@@ -1,44 +1,168 @@
+use bytemuck::cast_slice;
 use encase::{ArrayLength, ShaderType};
-use glam::Vec3;
+use glam::{UVec4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 use wgpu::util::DeviceExt;
 
-use super::Layouts;
+use crate::{
+    render::clusters::{Clusters, Grid, GRID},
+    scene::Scene,
+};
+
+use super::{accumulation, probes, Layouts};
+
+/// Brightness of the objects outside of the isolated material.
+const DIMMED: f32 = 0.15;
 
 pub struct Bundle {
+    /// Lights clustered for the main camera.
     pub bind_group: wgpu::BindGroup,
+    cluster_lights_buffer: wgpu::Buffer,
+    clusters_buffer: wgpu::Buffer,
+    /// Same lights, for the objects dimmed by the material isolation.
+    pub dimmed_bind_group: wgpu::BindGroup,
+    dimmed_buffer: wgpu::Buffer,
+    grid_buffer: wgpu::Buffer,
+    isolation_buffer: wgpu::Buffer,
+    /// Only this light contributes, without the ambient term.
+    pub isolated_light: Option<u32>,
     point_lights_buffer: wgpu::Buffer,
+    /// Every light for each fragment, for the other cameras.
+    pub unclustered_bind_group: wgpu::BindGroup,
+    unclustered_buffer: wgpu::Buffer,
 }
 
 impl Bundle {
-    pub fn new(device: &wgpu::Device, layouts: &Layouts) -> Self {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        probes: &probes::Bundle,
+        accumulation: &accumulation::Bundle,
+    ) -> Self {
         let point_lights_buffer = Self::create_point_lights_buffer(
             device,
             &PointLightData::default(),
         );
-        let bind_group = layouts.lights.bind(device, &point_lights_buffer);
+        let isolation_buffer =
+            Self::create_isolation_buffer(device, "isolation_buffer");
+        let dimmed_buffer =
+            Self::create_isolation_buffer(device, "dimmed_isolation_buffer");
+        let grid_buffer = Self::create_grid_buffer(device, "grid_buffer");
+        let unclustered_buffer =
+            Self::create_grid_buffer(device, "unclustered_grid_buffer");
+        let clusters_buffer = Self::create_storage_buffer(
+            device,
+            "clusters_buffer",
+            cast_slice(&[0u32; 2]),
+        );
+        let cluster_lights_buffer = Self::create_storage_buffer(
+            device,
+            "cluster_lights_buffer",
+            cast_slice(&[0u32]),
+        );
+        let shared = Shared {
+            point_lights: &point_lights_buffer,
+            clusters: &clusters_buffer,
+            cluster_lights: &cluster_lights_buffer,
+            probes,
+            accumulation,
+        };
+        let bind = |isolation, grid| {
+            layouts.lights.bind(
+                device,
+                &shared,
+                isolation,
+                grid,
+                probes.placeholder(),
+                &accumulation.untraced_uniform,
+            )
+        };
         Self {
-            bind_group,
+            bind_group: bind(&isolation_buffer, &grid_buffer),
+            dimmed_bind_group: bind(&dimmed_buffer, &grid_buffer),
+            unclustered_bind_group: bind(
+                &isolation_buffer,
+                &unclustered_buffer,
+            ),
+            cluster_lights_buffer,
+            clusters_buffer,
+            dimmed_buffer,
+            grid_buffer,
+            isolation_buffer,
+            isolated_light: None,
             point_lights_buffer,
+            unclustered_buffer,
         }
     }
 
+    fn create_isolation_buffer(
+        device: &wgpu::Device,
+        label: &str,
+    ) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: &Isolation::default().as_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    fn create_grid_buffer(device: &wgpu::Device, label: &str) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: &ClusterGrid::default().as_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
     fn create_point_lights_buffer(
         device: &wgpu::Device,
         data: &PointLightData,
+    ) -> wgpu::Buffer {
+        Self::create_storage_buffer(
+            device,
+            "point_lights_buffer",
+            &data.as_bytes(),
+        )
+    }
+
+    fn create_storage_buffer(
+        device: &wgpu::Device,
+        label: &str,
+        contents: &[u8],
     ) -> wgpu::Buffer {
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("point_lights_buffer"),
-            contents: &data.as_bytes(),
+            label: Some(label),
+            contents,
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         })
     }
 
+    /// Cluster the lights for the scene camera, rendered at the target
+    /// size. The bind groups follow once the probes are prepared.
     pub fn prepare(
         &mut self,
         device: &wgpu::Device,
-        layouts: &Layouts,
-        point_lights: &[PointLight],
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        aspect_ratio: f32,
+        target_size: [u32; 2],
     ) {
+        let (point_lights, camera) = (&scene.point_lights, &scene.camera);
+        let light = self.isolated_light.map_or(-1, |light| light as i32);
+        for (buffer, dim) in
+            [(&self.isolation_buffer, 1.0), (&self.dimmed_buffer, DIMMED)]
+        {
+            queue.write_buffer(buffer, 0, &Isolation { light, dim }.as_bytes());
+        }
+        let grid = Grid::new(camera, aspect_ratio);
+        let cluster_grid = ClusterGrid {
+            size: GRID.extend(1),
+            screen: Vec2::new(target_size[0] as f32, target_size[1] as f32),
+            near: grid.near,
+            slice_scale: grid.slice_scale(),
+        };
+        queue.write_buffer(&self.grid_buffer, 0, &cluster_grid.as_bytes());
+
         self.point_lights_buffer = Self::create_point_lights_buffer(
             device,
             &PointLightData {
@@ -46,15 +170,145 @@ impl Bundle {
                 data: point_lights.to_vec(),
             },
         );
+        let mut clusters = Clusters::build(point_lights, camera, aspect_ratio);
+        // Storage buffers can not be empty.
+        if clusters.indices.is_empty() {
+            clusters.indices.push(0);
+        }
+        self.clusters_buffer = Self::create_storage_buffer(
+            device,
+            "clusters_buffer",
+            cast_slice(&clusters.ranges),
+        );
+        self.cluster_lights_buffer = Self::create_storage_buffer(
+            device,
+            "cluster_lights_buffer",
+            cast_slice(&clusters.indices),
+        );
+    }
+
+    /// Bind the buffers of the frame, the main camera sampling the probe
+    /// bound for it and tracing the accumulation rays. The other cameras and
+    /// the probes themselves get neither.
+    pub fn bind(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        probes: &probes::Bundle,
+        accumulation: &accumulation::Bundle,
+    ) {
+        let shared = Shared {
+            point_lights: &self.point_lights_buffer,
+            clusters: &self.clusters_buffer,
+            cluster_lights: &self.cluster_lights_buffer,
+            probes,
+            accumulation,
+        };
+        let bind = |isolation, grid, probe, visibility| {
+            layouts
+                .lights
+                .bind(device, &shared, isolation, grid, probe, visibility)
+        };
+        let (probe, visibility) = (probes.bound_view(), &accumulation.uniform);
         self.bind_group =
-            layouts.lights.bind(device, &self.point_lights_buffer);
+            bind(&self.isolation_buffer, &self.grid_buffer, probe, visibility);
+        self.dimmed_bind_group =
+            bind(&self.dimmed_buffer, &self.grid_buffer, probe, visibility);
+        self.unclustered_bind_group = bind(
+            &self.isolation_buffer,
+            &self.unclustered_buffer,
+            probes.placeholder(),
+            &accumulation.untraced_uniform,
+        );
     }
 }
 
-#[derive(Debug, Default, ShaderType, Clone)]
+/// Buffers shared by the bind groups of the lights.
+pub struct Shared<'a> {
+    pub point_lights: &'a wgpu::Buffer,
+    pub clusters: &'a wgpu::Buffer,
+    pub cluster_lights: &'a wgpu::Buffer,
+    pub probes: &'a probes::Bundle,
+    pub accumulation: &'a accumulation::Bundle,
+}
+
+/// Distance falloff of a light, all of them reach zero at the radius.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Attenuation {
+    #[default]
+    Radius,
+    InverseSquare,
+    Linear,
+    Exponential,
+}
+
+impl Attenuation {
+    /// In the order of the shader constants.
+    const ALL: [Self; 4] = [
+        Self::Radius,
+        Self::InverseSquare,
+        Self::Linear,
+        Self::Exponential,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "radius" => Some(Self::Radius),
+            "inverse_square" => Some(Self::InverseSquare),
+            "linear" => Some(Self::Linear),
+            "exponential" => Some(Self::Exponential),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Radius => "radius",
+            Self::InverseSquare => "inverse_square",
+            Self::Linear => "linear",
+            Self::Exponential => "exponential",
+        }
+    }
+}
+
+#[derive(Debug, ShaderType, Clone)]
 pub struct PointLight {
     pub pos: Vec3,
     pub radius: f32,
+    pub color: Vec3,
+    pub intensity: f32,
+    attenuation: u32,
+}
+
+impl PointLight {
+    /// White, at full intensity.
+    pub fn new(pos: Vec3, radius: f32, attenuation: Attenuation) -> Self {
+        Self {
+            pos,
+            radius,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            attenuation: attenuation as u32,
+        }
+    }
+
+    pub fn with_color(mut self, color: Vec3, intensity: f32) -> Self {
+        self.color = color;
+        self.intensity = intensity;
+        self
+    }
+
+    /// Falloff model, as the shader constant.
+    pub fn attenuation(&self) -> u32 {
+        self.attenuation
+    }
+
+    pub fn attenuation_mode(&self) -> Attenuation {
+        Attenuation::ALL[self.attenuation as usize]
+    }
 }
 
 #[derive(Default, ShaderType)]
@@ -72,6 +326,48 @@ impl PointLightData {
     }
 }
 
+/// Debug isolation, a negative light keeps all of them.
+#[derive(ShaderType)]
+struct Isolation {
+    light: i32,
+    dim: f32,
+}
+
+impl Default for Isolation {
+    fn default() -> Self {
+        Self {
+            light: -1,
+            dim: 1.0,
+        }
+    }
+}
+
+impl Isolation {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+/// Cells of the clusters, the w of the size is zero to go through every
+/// light instead.
+#[derive(Default, ShaderType)]
+struct ClusterGrid {
+    size: UVec4,
+    screen: Vec2,
+    near: f32,
+    slice_scale: f32,
+}
+
+impl ClusterGrid {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
 pub struct Layout {
     pub layout: wgpu::BindGroupLayout,
 }
@@ -81,18 +377,128 @@ impl Layout {
         let layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("lights_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage {
-                            read_only: true,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
                         },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float {
+                                filterable: true,
+                            },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering,
+                        ),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
         Self { layout }
     }
@@ -100,15 +506,63 @@ impl Layout {
     pub fn bind(
         &self,
         device: &wgpu::Device,
-        point_lights_buffer: &wgpu::Buffer,
+        shared: &Shared,
+        isolation_buffer: &wgpu::Buffer,
+        grid_buffer: &wgpu::Buffer,
+        probe: &wgpu::TextureView,
+        visibility: &wgpu::Buffer,
     ) -> wgpu::BindGroup {
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("lights_bind_group"),
             layout: &self.layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: point_lights_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: shared.point_lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: isolation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: grid_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: shared.clusters.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: shared.cluster_lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(probe),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(
+                        &shared.probes.sampler,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: shared.probes.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: shared.accumulation.nodes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: shared.accumulation.triangles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: visibility.as_entire_binding(),
+                },
+            ],
         })
     }
 }
@@ -0,0 +1,155 @@
+use glam::{Mat4, Vec3};
+
+use crate::render::{
+    camera::Camera,
+    material::MaterialManager,
+    texture::{Texture, TextureAssets},
+};
+
+use super::{
+    globals::{self, Environment},
+    Layouts,
+};
+
+/// Prefixed so no texture file can take the id.
+pub const TEXTURE_ID: &str = "@minimap";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// Half size of the area covered around the camera, in world units.
+    pub extent: f32,
+    pub height: f32,
+    /// Materials drawn in the minimap, every material when none.
+    pub include: Option<Vec<String>>,
+    pub size: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            extent: 50.0,
+            height: 100.0,
+            include: None,
+            size: 256,
+        }
+    }
+}
+
+pub struct Bundle {
+    depth: Option<Texture>,
+    pub globals: globals::Bundle,
+    pub settings: Option<Settings>,
+    size: u32,
+}
+
+impl Bundle {
+    pub fn new(device: &wgpu::Device, layouts: &Layouts) -> Self {
+        Self {
+            depth: None,
+            globals: globals::Bundle::new(device, layouts),
+            settings: None,
+            size: 0,
+        }
+    }
+
+    /// Update the top-down view around the camera, returns true when the
+    /// minimap texture got recreated.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        textures: &mut TextureAssets,
+        environment: &Environment,
+        camera: &Camera,
+    ) -> bool {
+        let Some(settings) = &self.settings else {
+            return false;
+        };
+
+        let center = camera.transform.pos;
+        let eye = center + Vec3::Y * settings.height;
+        let extent = settings.extent;
+        self.globals.prepare_matrices(
+            queue,
+            environment,
+            Mat4::orthographic_rh(
+                -extent,
+                extent,
+                -extent,
+                extent,
+                0.0,
+                settings.height * 2.0,
+            ),
+            Mat4::look_at_rh(eye, center, -Vec3::Z),
+        );
+
+        if self.size == settings.size {
+            return false;
+        }
+        self.size = settings.size;
+        self.depth = Some(Texture::create_depth(device, self.size, self.size));
+        textures.insert(
+            TEXTURE_ID,
+            Texture::create_target(
+                device, self.size, self.size, format, TEXTURE_ID,
+            ),
+        );
+        true
+    }
+
+    pub fn begin_pass<'encoder>(
+        &self,
+        encoder: &'encoder mut wgpu::CommandEncoder,
+        textures: &TextureAssets,
+    ) -> Option<wgpu::RenderPass<'encoder>> {
+        let (Some(_), Some(depth), Some(target)) =
+            (&self.settings, &self.depth, textures.get(TEXTURE_ID))
+        else {
+            return None;
+        };
+
+        let mut rpass =
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("minimap_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(
+                    wgpu::RenderPassDepthStencilAttachment {
+                        view: &depth.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    },
+                ),
+                ..Default::default()
+            });
+        rpass.set_bind_group(0, &self.globals.bind_group, &[]);
+        Some(rpass)
+    }
+
+    /// Whether the material is drawn in the minimap, never the ones sampling
+    /// the minimap itself.
+    pub fn includes(
+        &self,
+        materials: &MaterialManager,
+        material_id: &str,
+    ) -> bool {
+        let texture_id = materials.get_texture_id(material_id);
+        if texture_id.as_deref() == Some(TEXTURE_ID) {
+            return false;
+        }
+        match self.settings.as_ref().and_then(|s| s.include.as_ref()) {
+            Some(include) => include.iter().any(|id| id == material_id),
+            None => true,
+        }
+    }
+}
@@ -1,55 +1,196 @@
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+};
+
 use super::{
     material::MaterialManager, shader::ShaderAssets, texture::TextureAssets,
 };
+use crate::app::get_pool;
 
+pub mod accumulation;
+pub mod effect;
+pub mod fullscreen;
 pub mod globals;
+pub mod impostor;
 pub mod lights;
+pub mod minimap;
 pub mod model;
+pub mod outline;
+pub mod output;
+pub mod pathtrace;
+pub mod points;
+pub mod probes;
+#[cfg(feature = "ui")]
+pub mod ui;
 
 pub struct Layouts {
+    effect: effect::Layout,
+    fullscreen: fullscreen::Layout,
     globals: globals::Layout,
+    impostor: impostor::Layout,
+    instances: model::InstancesLayout,
     lights: lights::Layout,
     model: model::Layout,
+    outline: outline::Layout,
+    output: output::Layout,
+    pathtrace: pathtrace::Layout,
+    points: points::Layout,
+    probes: probes::Layout,
 }
 
 impl Layouts {
     pub fn new(device: &wgpu::Device) -> Self {
         Self {
+            effect: effect::Layout::new(device),
+            fullscreen: fullscreen::Layout::new(device),
             globals: globals::Layout::new(device),
+            impostor: impostor::Layout::new(device),
+            instances: model::InstancesLayout::new(device),
             lights: lights::Layout::new(device),
             model: model::Layout::new(device),
+            outline: outline::Layout::new(device),
+            output: output::Layout::new(device),
+            pathtrace: pathtrace::Layout::new(device),
+            points: points::Layout::new(device),
+            probes: probes::Layout::new(device),
+        }
+    }
+}
+
+/// Pipeline built on the thread pool, the previous one keeps drawing until
+/// the new one is ready.
+pub struct AsyncPipeline<T> {
+    built_rx: Receiver<(u64, T)>,
+    built_tx: Sender<(u64, T)>,
+    /// Builds started, the pipelines of the older ones are dropped.
+    generation: u64,
+    pipeline: Option<T>,
+    requested: bool,
+}
+
+impl<T> Default for AsyncPipeline<T> {
+    fn default() -> Self {
+        let (built_tx, built_rx) = channel();
+        Self {
+            built_rx,
+            built_tx,
+            generation: 0,
+            pipeline: None,
+            requested: false,
         }
     }
 }
 
+impl<T: Send + 'static> AsyncPipeline<T> {
+    pub fn get(&self) -> Option<&T> {
+        self.pipeline.as_ref()
+    }
+
+    /// Whether a pipeline is there or being built.
+    pub fn is_requested(&self) -> bool {
+        self.requested
+    }
+
+    pub fn build(&mut self, build: impl FnOnce() -> T + Send + 'static) {
+        self.generation += 1;
+        self.requested = true;
+        let (generation, built_tx) = (self.generation, self.built_tx.clone());
+        get_pool().execute(move || {
+            let _ = built_tx.send((generation, build()));
+        });
+    }
+
+    /// Drop the pipeline and the ones being built.
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.pipeline = None;
+        self.requested = false;
+    }
+
+    /// Swap in the last pipeline built, returns whether there was one.
+    pub fn receive(&mut self) -> bool {
+        let mut received = false;
+        while let Ok((generation, pipeline)) = self.built_rx.try_recv() {
+            if generation == self.generation {
+                self.pipeline = Some(pipeline);
+                received = true;
+            }
+        }
+        received
+    }
+}
+
 pub struct Bundles {
+    pub accumulation: accumulation::Bundle,
+    pub effect: effect::Bundle,
+    pub fullscreen: fullscreen::Bundle,
     pub globals: globals::Bundle,
+    pub impostor: impostor::Bundle,
     pub lights: lights::Bundle,
+    pub minimap: minimap::Bundle,
     pub model: model::Bundle,
+    pub outline: outline::Bundle,
+    pub output: output::Bundle,
+    pub pathtrace: pathtrace::Bundle,
+    pub points: points::Bundle,
+    pub probes: probes::Bundle,
+    #[cfg(feature = "ui")]
+    pub ui: ui::Bundle,
 }
 
 impl Bundles {
     pub fn new(
         device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
         layouts: &Layouts,
         shaders: &mut ShaderAssets,
         textures: &mut TextureAssets,
         materials: &mut MaterialManager,
     ) -> Self {
+        let mut model = model::Bundle::new(shaders, textures, materials);
+        let probes = probes::Bundle::new(device, layouts, shaders);
+        let accumulation = accumulation::Bundle::new(device, shaders);
+        let lights =
+            lights::Bundle::new(device, layouts, &probes, &accumulation);
         Self {
+            accumulation,
+            effect: effect::Bundle::new(),
+            fullscreen: fullscreen::Bundle::new(
+                device, config, layouts, shaders,
+            ),
             globals: globals::Bundle::new(device, layouts),
-            lights: lights::Bundle::new(device, layouts),
-            model: model::Bundle::new(shaders, textures, materials),
+            impostor: impostor::Bundle::new(shaders, &mut model),
+            lights,
+            minimap: minimap::Bundle::new(device, layouts),
+            model,
+            outline: outline::Bundle::new(shaders),
+            output: output::Bundle::new(device, config, layouts, shaders),
+            pathtrace: pathtrace::Bundle::new(device, shaders),
+            points: points::Bundle::new(shaders),
+            probes,
+            #[cfg(feature = "ui")]
+            ui: ui::Bundle::new(device, config),
         }
     }
 
     pub fn hot_reload(
         &mut self,
-        device: &wgpu::Device,
+        device: &Arc<wgpu::Device>,
         config: &wgpu::SurfaceConfiguration,
         layouts: &Layouts,
         shaders: &mut ShaderAssets,
     ) {
-        self.model.hot_reload(device, config, layouts, shaders);
+        let format = self.output.scene_format();
+        self.model.hot_reload(device, format, layouts, shaders);
+        self.points.hot_reload(device, format, layouts, shaders);
+        self.effect.hot_reload(device, format, layouts, shaders);
+        self.outline.hot_reload(device, format, layouts, shaders);
+        self.output.hot_reload(device, config, layouts, shaders);
+        self.fullscreen.hot_reload(device, config, layouts, shaders);
+        self.accumulation.hot_reload(device, layouts, shaders);
+        self.pathtrace.hot_reload(device, layouts, shaders);
+        self.impostor.hot_reload(device, layouts, shaders);
+        self.probes.hot_reload(device, layouts, shaders);
     }
 }
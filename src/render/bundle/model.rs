@@ -1,18 +1,28 @@
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
     mem,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
 };
 
 use bytemuck::{cast_slice, Pod, Zeroable};
-use glam::{Mat3, Mat4, Quat};
-use log::info;
-use wgpu::util::DeviceExt;
-
-use crate::render::{
-    material::{simple::SimpleMaterial, MaterialManager},
-    mesh::{MeshAssets, VertexTrait},
-    shader::ShaderAssets,
-    texture::{Texture, TextureAssets},
+use glam::{Mat3, Mat4, Quat, Vec3, Vec4};
+use log::{info, warn};
+use wgpu::util::{DeviceExt, DrawIndexedIndirectArgs};
+
+use crate::{
+    app::get_pool,
+    bounds::{Aabb, Frustum},
+    render::{
+        bundle::impostor::Impostor,
+        material::{simple::SimpleMaterial, MaterialManager},
+        mesh::{MeshAssets, VertexTrait},
+        shader::{ShaderAssets, FALLBACK_SHADER},
+        texture::{Texture, TextureAssets},
+    },
 };
 
 use super::Layouts;
@@ -20,10 +30,95 @@ use super::Layouts;
 pub const DEFAULT_SHADER: &str = "model";
 pub const DEFAULT_TEXTURE: &str = "white";
 pub const DEFAULT_MATERIAL: &str = "model";
+pub const DEBUG_SHADER: &str = "debug";
+/// Layer of the models batched without one.
+pub const DEFAULT_LAYER: &str = "default";
+/// Layers a scene can name, one bit of the batch key each.
+pub const MAX_LAYERS: usize = 32;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RenderMode {
+    #[default]
+    Lit,
+    Wireframe,
+    Normals,
+    Depth,
+}
+
+const INDIRECT_ARGS_SIZE: u64 =
+    mem::size_of::<DrawIndexedIndirectArgs>() as u64;
+
+/// Entry point of the variant drawing meshes with extra vertex attributes.
+pub const EXTRA_ENTRY_POINT: &str = "vs_extra";
+/// Entry point reading the instances from the storage buffer at group 3,
+/// laid out as in the model shader. Shaders that declare it draw the batches
+/// of the meshes without extra vertex data this way.
+pub const STORAGE_ENTRY_POINT: &str = "vs_storage";
+
+/// How a pipeline uses the depth buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum DepthTest {
+    Write,
+    /// Depth only, no fragment stage.
+    Prepass,
+    /// Shade only the fragments left by the prepass.
+    Equal,
+}
+
+#[derive(Hash, PartialEq, Eq)]
+struct PipelineKey {
+    shader_id: String,
+    mode: RenderMode,
+    /// Reads the vertex colors and second uv channel.
+    extra: bool,
+    depth: DepthTest,
+    /// Reads the instances from a storage buffer.
+    storage: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DrawPass {
+    /// Depth of the visible instances.
+    Prepass,
+    /// Visible instances, against the prepass depth when there is one.
+    Main,
+    /// Every instance, culled or not.
+    All,
+}
+
+/// Pipeline built on the thread pool, for a generation of its shader.
+type BuiltPipeline = (u64, PipelineKey, Pipeline);
+
+/// Shader with values for its pipeline overridable constants, built as
+/// pipelines of their own.
+struct Variant {
+    shader_id: String,
+    constants: BTreeMap<String, f64>,
+}
 
 pub struct Bundle {
-    pub pipelines: HashMap<String, Pipeline>,
+    built_rx: Receiver<BuiltPipeline>,
+    built_tx: Sender<BuiltPipeline>,
+    /// Lit meshes are drawn in a depth only pass first, the fragments are
+    /// then shaded once each.
+    pub depth_prepass: bool,
+    /// Reloads of each shader, the pipelines of older ones are dropped.
+    generations: HashMap<String, u64>,
+    /// Draw counts come from an indirect buffer written once per frame.
+    pub indirect: bool,
+    /// The indirect draws of the batches sharing their mesh and material
+    /// are submitted together, when the device supports it.
+    pub multi_draw: bool,
+    /// The other materials are drawn dimmed.
+    pub isolated_material: Option<String>,
+    pub mode: RenderMode,
+    /// Variants registered since the last frame, built once their shader
+    /// is loaded.
+    pending_variants: Vec<String>,
+    pipelines: HashMap<PipelineKey, Pipeline>,
     registered_shaders: HashSet<String>,
+    storage_shaders: HashSet<String>,
+    variants: HashMap<String, Variant>,
 }
 
 impl Bundle {
@@ -37,40 +132,262 @@ impl Bundle {
         let mut registered_shaders = HashSet::new();
         registered_shaders.insert(DEFAULT_SHADER.to_string());
         shaders.load(DEFAULT_SHADER);
+        shaders.load(DEBUG_SHADER);
 
         let material = SimpleMaterial::new(DEFAULT_SHADER, DEFAULT_TEXTURE);
         materials.add(DEFAULT_MATERIAL, material);
 
+        let (built_tx, built_rx) = channel();
         Self {
+            built_rx,
+            built_tx,
+            depth_prepass: false,
+            generations: HashMap::new(),
+            indirect: false,
+            isolated_material: None,
+            multi_draw: false,
+            mode: RenderMode::default(),
+            pending_variants: Vec::new(),
             pipelines: HashMap::new(),
             registered_shaders,
+            storage_shaders: HashSet::new(),
+            variants: HashMap::new(),
+        }
+    }
+
+    /// Swap in the pipelines built since the last frame, the previous ones
+    /// are used until then.
+    fn receive_pipelines(&mut self) {
+        while let Ok((generation, key, pipeline)) = self.built_rx.try_recv() {
+            if self.generations.get(&key.shader_id) == Some(&generation) {
+                self.pipelines.insert(key, pipeline);
+            }
         }
     }
 
+    /// Whether the vertex instances are drawn from the buffer shared by
+    /// the multi draws.
+    fn shares_instances(&self) -> bool {
+        self.indirect && self.multi_draw
+    }
+
+    /// Whether the main pass is preceded by a depth prepass.
+    pub fn uses_prepass(&self) -> bool {
+        self.depth_prepass && self.mode == RenderMode::Lit
+    }
+
+    /// Whether the materials of the shader read their instances from a
+    /// storage buffer, unless their mesh has extra vertex data.
+    pub fn uses_storage(&self, shader_id: &str) -> bool {
+        self.storage_shaders.contains(shader_id)
+    }
+
+    /// Pipeline used to draw a material with the given shader, debug modes
+    /// share the same pipeline for every material. Meshes with extra
+    /// attributes fall back to the base variant when the shader has none.
+    fn get_pipeline(
+        &self,
+        shader_id: &str,
+        extra: bool,
+        storage: bool,
+        pass: DrawPass,
+    ) -> Option<&Pipeline> {
+        let shader_id = match self.mode {
+            RenderMode::Lit | RenderMode::Wireframe => shader_id,
+            RenderMode::Normals | RenderMode::Depth => DEBUG_SHADER,
+        };
+        let depth = match (pass, self.uses_prepass()) {
+            (DrawPass::Prepass, _) => DepthTest::Prepass,
+            (DrawPass::Main, true) => DepthTest::Equal,
+            _ => DepthTest::Write,
+        };
+        let mut key = PipelineKey {
+            shader_id: shader_id.to_string(),
+            mode: self.mode,
+            extra,
+            depth,
+            storage,
+        };
+        self.pipelines.get(&key).or_else(|| {
+            key.extra = false;
+            self.pipelines.get(&key)
+        })
+    }
+
     pub fn hot_reload(
         &mut self,
-        device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        device: &Arc<wgpu::Device>,
+        format: wgpu::TextureFormat,
         layouts: &Layouts,
         shaders: &mut ShaderAssets,
     ) {
-        let Some(shader_id) = &shaders.frame_reloaded else {
+        self.receive_pipelines();
+        let mut pipeline_ids = mem::take(&mut self.pending_variants);
+        // Failed shaders are built with the fallback one once it is there.
+        let mut shader_ids = shaders
+            .frame_reloaded
+            .iter()
+            .chain(&shaders.frame_failed)
+            .collect::<Vec<_>>();
+        if shaders.frame_reloaded.as_deref() == Some(FALLBACK_SHADER) {
+            shader_ids.extend(shaders.failed());
+        }
+        for shader_id in shader_ids {
+            pipeline_ids.push(shader_id.clone());
+            pipeline_ids.extend(
+                self.variants
+                    .iter()
+                    .filter(|(_, variant)| variant.shader_id == *shader_id)
+                    .map(|(id, _)| id.clone()),
+            );
+        }
+        pipeline_ids.sort();
+        pipeline_ids.dedup();
+        for pipeline_id in pipeline_ids {
+            self.build_pipelines(device, format, layouts, shaders, pipeline_id);
+        }
+    }
+
+    /// Pipelines of a shader or of one of its variants, nothing is built
+    /// until the shader is loaded.
+    fn build_pipelines(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        format: wgpu::TextureFormat,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+        pipeline_id: String,
+    ) {
+        let (shader_id, mut constants) = match self.variants.get(&pipeline_id) {
+            Some(variant) => {
+                (variant.shader_id.clone(), variant.constants.clone())
+            }
+            None => (pipeline_id.clone(), BTreeMap::new()),
+        };
+        let Some(module) = shaders
+            .get_shared(&shader_id)
+            .or_else(|| shaders.fallback(&shader_id))
+        else {
             return;
         };
+        // Unknown constants fail the pipeline creation.
+        constants.retain(|name, _| {
+            let declared = shaders.declares_override(&shader_id, name);
+            if !declared {
+                warn!("shader {} has no override {}", shader_id, name);
+            }
+            declared
+        });
+        let constants: Arc<HashMap<_, _>> =
+            Arc::new(constants.into_iter().collect());
 
-        if self.registered_shaders.contains(shader_id) {
-            info!("Pipeline loaded with shader: {}", shader_id);
-            let module = shaders.get(shader_id).unwrap();
-            self.pipelines.insert(
-                shader_id.clone(),
-                Pipeline::new(device, config, layouts, module, shader_id),
-            );
+        let modes = if pipeline_id == DEBUG_SHADER {
+            info!("Debug pipelines loaded");
+            vec![RenderMode::Normals, RenderMode::Depth]
+        } else if self.registered_shaders.contains(&pipeline_id) {
+            info!("Pipeline loaded with shader: {}", pipeline_id);
+            let mut modes = vec![RenderMode::Lit];
+            if device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE)
+            {
+                modes.push(RenderMode::Wireframe);
+            }
+            modes
+        } else {
+            return;
+        };
+
+        let has_extra = shaders.has_entry_point(&shader_id, EXTRA_ENTRY_POINT);
+        let storage = shaders.has_entry_point(&shader_id, STORAGE_ENTRY_POINT);
+        // Variants as (extra, storage), the vertex instances ones are kept
+        // for the meshes with extra data and the debug shader, drawing the
+        // batches of every material.
+        let mut variants = vec![(false, false)];
+        if has_extra {
+            variants.push((true, false));
+        }
+        if storage {
+            variants.push((false, true));
+        }
+        match storage && pipeline_id != DEBUG_SHADER {
+            true => self.storage_shaders.insert(pipeline_id.clone()),
+            false => self.storage_shaders.remove(&pipeline_id),
+        };
+        let mut keys = Vec::new();
+        for mode in modes {
+            let depths = match mode {
+                RenderMode::Lit => {
+                    vec![DepthTest::Write, DepthTest::Prepass, DepthTest::Equal]
+                }
+                _ => vec![DepthTest::Write],
+            };
+            for ((extra, storage), depth) in variants
+                .iter()
+                .flat_map(|variant| depths.iter().map(move |d| (*variant, *d)))
+            {
+                keys.push(PipelineKey {
+                    shader_id: pipeline_id.clone(),
+                    mode,
+                    extra,
+                    depth,
+                    storage,
+                });
+            }
+        }
+
+        self.pipelines.retain(|key, _| {
+            key.shader_id != pipeline_id || keys.contains(key)
+        });
+        let generation = self.generations.entry(pipeline_id).or_default();
+        *generation += 1;
+        let generation = *generation;
+        for key in keys {
+            let layout = Pipeline::layout(device, layouts, &key);
+            let (device, module) = (device.clone(), module.clone());
+            let constants = constants.clone();
+            let built_tx = self.built_tx.clone();
+            get_pool().execute(move || {
+                let pipeline = Pipeline::new(
+                    &device, format, &layout, &module, &key, &constants,
+                );
+                let _ = built_tx.send((generation, key, pipeline));
+            });
         }
     }
 
     pub fn register_shader(&mut self, shader_id: &str) {
         self.registered_shaders.insert(shader_id.to_string());
     }
+
+    /// Id the materials draw with to get the shader built with the values
+    /// of its `override` constants, the shader itself when there are none.
+    pub fn register_variant(
+        &mut self,
+        shader_id: &str,
+        constants: &BTreeMap<String, f64>,
+    ) -> String {
+        self.register_shader(shader_id);
+        if constants.is_empty() {
+            return shader_id.to_string();
+        }
+        let values: Vec<_> = constants
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        let pipeline_id = format!("{}{{{}}}", shader_id, values.join(","));
+        if self.registered_shaders.insert(pipeline_id.clone()) {
+            self.variants.insert(
+                pipeline_id.clone(),
+                Variant {
+                    shader_id: shader_id.to_string(),
+                    constants: constants.clone(),
+                },
+            );
+            self.pending_variants.push(pipeline_id.clone());
+        }
+        pipeline_id
+    }
 }
 
 #[repr(C)]
@@ -96,30 +413,94 @@ impl VertexTrait for Vertex {
     }
 }
 
+/// Optional attributes, in their own buffer so meshes without them keep the
+/// base layout.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct VertexExtra {
+    pub color: [f32; 4],
+    pub tex_coord2: [f32; 2],
+}
+
+impl VertexTrait for VertexExtra {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            10 => Float32x4,
+            11 => Float32x2
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Instance {
     pub world_local: [[f32; 4]; 4],
     pub normal: [[f32; 3]; 3],
+    /// Random bits for per instance variations in the shaders.
+    pub seed: u32,
+    /// Multiplies the vertex color.
+    pub tint: [f32; 3],
+    /// Free for the shaders of the material.
+    pub custom: [f32; 4],
+}
+
+/// PCG hash, spreads consecutive values over the whole range.
+pub fn hash_seed(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
 }
 
 impl Instance {
+    /// The seed comes from the position, stable as long as the instance
+    /// does not move.
     pub fn new(transform: Mat4, rotation: Quat) -> Self {
+        let position = transform.w_axis.truncate();
+        let seed = position
+            .to_array()
+            .iter()
+            .fold(0, |seed, v| hash_seed(seed ^ v.to_bits()));
         Self {
             world_local: transform.to_cols_array_2d(),
             normal: Mat3::from_quat(rotation).to_cols_array_2d(),
+            seed,
+            tint: [1.0; 3],
+            custom: [0.0; 4],
         }
     }
 
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        const ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = hash_seed(seed);
+        self
+    }
+
+    pub fn with_tint(mut self, tint: Vec3) -> Self {
+        self.tint = tint.to_array();
+        self
+    }
+
+    pub fn with_custom(mut self, custom: Vec4) -> Self {
+        self.custom = custom.to_array();
+        self
+    }
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 10] = wgpu::vertex_attr_array![
             3 => Float32x4,
             4 => Float32x4,
             5 => Float32x4,
             6 => Float32x4,
             7 => Float32x3,
             8 => Float32x3,
-            9 => Float32x3
+            9 => Float32x3,
+            12 => Uint32,
+            13 => Float32x3,
+            14 => Float32x4
         ];
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
@@ -129,6 +510,30 @@ impl Instance {
     }
 }
 
+/// Instance as laid out in a storage buffer, the normal matrix columns are
+/// padded to 16 bytes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct StorageInstance {
+    world_local: [[f32; 4]; 4],
+    normal: [[f32; 4]; 3],
+    tint: [f32; 3],
+    seed: u32,
+    custom: [f32; 4],
+}
+
+impl From<&Instance> for StorageInstance {
+    fn from(instance: &Instance) -> Self {
+        Self {
+            world_local: instance.world_local,
+            normal: instance.normal.map(|[x, y, z]| [x, y, z, 0.0]),
+            tint: instance.tint,
+            seed: instance.seed,
+            custom: instance.custom,
+        }
+    }
+}
+
 struct MaterialData {
     bind_group: wgpu::BindGroup,
     buffer: wgpu::Buffer,
@@ -137,19 +542,81 @@ struct MaterialData {
 #[derive(Default)]
 struct InstanceArray {
     buffer: Option<wgpu::Buffer>,
+    /// Storage buffer of the instances, instead of the vertex buffer.
+    storage: Option<wgpu::BindGroup>,
     data: Vec<Instance>,
+    /// Offset of the visible instances draw arguments, the ones of all
+    /// instances follow the visible ones of every batch.
+    indirect: Option<u64>,
+    /// Offset in the instance buffer shared by the multi draws.
+    first_instance: Option<u32>,
+    /// Instances in the camera frustum, kept at the start of `data`.
+    visible: usize,
 }
 
-#[derive(Hash, PartialEq, Eq)]
+/// Batches drawn with a single multi draw, their arguments follow each
+/// other.
+struct DrawRun {
+    /// Key of the first batch, the others share its mesh and material.
+    key: Key,
+    /// Index of the first draw arguments.
+    first: u64,
+    count: u32,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
 struct Key {
+    /// Bit of the layer.
+    layer: u32,
     mesh_id: String,
     material_id: String,
+    /// Id of the static batch, kept across frames until freed.
+    static_id: Option<String>,
+}
+
+/// Draw order of the instances of a batch, submission order otherwise.
+#[derive(Debug, Clone, Copy)]
+pub enum InstanceSort {
+    BackToFront,
+    FrontToBack,
+    /// Increasing position along the axis.
+    Axis(Vec3),
+}
+
+/// Instance hit by a ray, the index is in submission order for its batch.
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub distance: f32,
+    pub instance: usize,
+    pub material_id: String,
+    pub mesh_id: String,
+    pub normal: Vec3,
+    pub point: Vec3,
 }
 
 #[derive(Default)]
 pub struct Batches {
+    pub culled: u32,
+    /// Layers skipped when drawing, a bit each.
+    hidden: u32,
+    /// Draw arguments of every batch, when drawing indirectly. Kept across
+    /// frames, grown when the batches do not fit.
+    indirect_buffer: Option<wgpu::Buffer>,
+    /// Offset from the visible instances draw arguments to the ones of all
+    /// instances.
+    indirect_all: u64,
+    /// Vertex instances of every batch drawn with multi draws.
+    instance_buffer: Option<wgpu::Buffer>,
+    /// Names of the layers by bit, kept across frames.
+    layers: Vec<String>,
     materials: HashMap<String, MaterialData>,
     instances: HashMap<Key, InstanceArray>,
+    runs: Vec<DrawRun>,
+    /// Static batches added or removed so far.
+    static_changes: u64,
+    /// Kept across frames by mesh and material, applied to the drawn mesh
+    /// after lod selection.
+    sorts: HashMap<(String, String), InstanceSort>,
 }
 
 impl Batches {
@@ -158,14 +625,377 @@ impl Batches {
         mesh_id: String,
         material_id: String,
         instance: Instance,
+    ) {
+        let layer = self.default_layer_bit();
+        self.add_to_layer(mesh_id, material_id, layer, instance);
+    }
+
+    /// Batch the instance in a layer, a bit from `layer_bit`.
+    pub fn add_to_layer(
+        &mut self,
+        mesh_id: String,
+        material_id: String,
+        layer: u32,
+        instance: Instance,
     ) {
         let key = Key {
+            layer,
             mesh_id,
             material_id,
+            static_id: None,
         };
         self.instances.entry(key).or_default().data.push(instance);
     }
 
+    /// Keep the instances drawn every frame until freed, their buffer is
+    /// only uploaded once. They are neither culled, sorted nor swapped for
+    /// lods, impostors or submeshes. Replaces the static batch of the id.
+    pub fn add_static(
+        &mut self,
+        static_id: String,
+        mesh_id: String,
+        material_id: String,
+        data: Vec<Instance>,
+    ) {
+        self.remove_static(&static_id);
+        self.static_changes += 1;
+        let key = Key {
+            layer: self.default_layer_bit(),
+            mesh_id,
+            material_id,
+            static_id: Some(static_id),
+        };
+        let visible = data.len();
+        let instances = InstanceArray {
+            data,
+            visible,
+            ..Default::default()
+        };
+        self.instances.insert(key, instances);
+    }
+
+    /// Returns whether there was a static batch of the id.
+    pub fn remove_static(&mut self, static_id: &str) -> bool {
+        let count = self.instances.len();
+        self.instances
+            .retain(|key, _| key.static_id.as_deref() != Some(static_id));
+        self.static_changes += 1;
+        self.instances.len() != count
+    }
+
+    /// Bit of the named layer, none once every bit is taken.
+    pub fn layer_bit(&mut self, name: &str) -> Option<u32> {
+        if self.layers.is_empty() {
+            self.layers.push(DEFAULT_LAYER.to_string());
+        }
+        let index = match self.layers.iter().position(|layer| layer == name) {
+            Some(index) => index,
+            None if self.layers.len() < MAX_LAYERS => {
+                self.layers.push(name.to_string());
+                self.layers.len() - 1
+            }
+            None => return None,
+        };
+        Some(1 << index)
+    }
+
+    /// Always available, the default layer is the first one taken.
+    fn default_layer_bit(&mut self) -> u32 {
+        self.layer_bit(DEFAULT_LAYER).unwrap()
+    }
+
+    pub fn layer_hidden(&self, layer: u32) -> bool {
+        self.hidden & layer != 0
+    }
+
+    /// Skip the layers of the names when drawing and picking.
+    pub fn set_hidden_layers<'a>(
+        &mut self,
+        names: impl IntoIterator<Item = &'a String>,
+    ) {
+        self.hidden = names
+            .into_iter()
+            .filter_map(|name| self.layers.iter().position(|l| l == name))
+            .fold(0, |hidden, index| hidden | 1 << index);
+    }
+
+    pub fn mesh_ids(&self) -> impl Iterator<Item = &String> {
+        self.instances.keys().map(|key| &key.mesh_id)
+    }
+
+    pub fn material_ids(&self) -> impl Iterator<Item = &String> {
+        self.instances.keys().map(|key| &key.material_id)
+    }
+
+    /// Hash of the batched instances, independent of the batch order. The
+    /// static batches only change when added or removed, their instances
+    /// are skipped.
+    pub fn signature(&self) -> u64 {
+        self.instances
+            .iter()
+            .filter(|(key, _)| key.static_id.is_none())
+            .map(|(key, instances)| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                cast_slice::<Instance, u8>(&instances.data).hash(&mut hasher);
+                hasher.finish()
+            })
+            .fold(self.static_changes, u64::wrapping_add)
+    }
+
+    /// Instances batched this frame, culled or not.
+    pub fn instance_count(&self) -> usize {
+        self.instances.values().map(|array| array.data.len()).sum()
+    }
+
+    /// Instances batched this frame by shader, sorted by name. Materials
+    /// still loading are left out.
+    pub fn shader_instances(
+        &self,
+        materials: &MaterialManager,
+    ) -> Vec<(String, u32)> {
+        let mut counts = BTreeMap::new();
+        for (key, instances) in &self.instances {
+            if let Some(shader_id) = materials.get_shader_id(&key.material_id) {
+                *counts.entry(shader_id).or_default() +=
+                    instances.data.len() as u32;
+            }
+        }
+        counts.into_iter().collect()
+    }
+
+    /// Bytes of the instance, material and indirect buffers.
+    pub fn buffer_memory(&self) -> u64 {
+        let instances = self
+            .instances
+            .values()
+            .filter_map(|instances| instances.buffer.as_ref())
+            .map(wgpu::Buffer::size);
+        let materials = self.materials.values().map(|data| data.buffer.size());
+        let indirect = self.indirect_buffer.as_ref().map(wgpu::Buffer::size);
+        let shared = self.instance_buffer.as_ref().map(wgpu::Buffer::size);
+        instances
+            .chain(materials)
+            .chain(indirect)
+            .chain(shared)
+            .sum()
+    }
+
+    /// Closest instance whose mesh bounds are hit by the world ray.
+    pub fn pick(
+        &self,
+        bounds: &HashMap<String, Aabb>,
+        origin: Vec3,
+        dir: Vec3,
+    ) -> Option<Pick> {
+        let mut closest: Option<Pick> = None;
+        for (key, instances) in &self.instances {
+            if self.layer_hidden(key.layer) {
+                continue;
+            }
+            let Some(aabb) = bounds.get(&key.mesh_id) else {
+                continue;
+            };
+            for (index, instance) in instances.data.iter().enumerate() {
+                let local_world =
+                    Mat4::from_cols_array_2d(&instance.world_local).inverse();
+                let Some((distance, normal)) = aabb.ray_intersection(
+                    local_world.transform_point3(origin),
+                    local_world.transform_vector3(dir),
+                ) else {
+                    continue;
+                };
+                if closest.as_ref().is_some_and(|c| c.distance <= distance) {
+                    continue;
+                }
+                closest = Some(Pick {
+                    distance,
+                    instance: index,
+                    material_id: key.material_id.clone(),
+                    mesh_id: key.mesh_id.clone(),
+                    normal: local_world
+                        .transpose()
+                        .transform_vector3(normal)
+                        .normalize(),
+                    point: origin + dir * distance,
+                });
+            }
+        }
+        closest
+    }
+
+    /// Move the instances farther than the impostor distance of their mesh
+    /// and material to the baked billboard.
+    pub fn select_impostors(
+        &mut self,
+        impostors: &HashMap<String, Impostor>,
+        camera_pos: Vec3,
+    ) {
+        let keys = self
+            .instances
+            .keys()
+            .filter(|key| {
+                key.static_id.is_none()
+                    && impostors.get(&key.mesh_id).is_some_and(|impostor| {
+                        impostor.material_id == key.material_id
+                    })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            let impostor = &impostors[&key.mesh_id];
+            let instances = self.instances.remove(&key).unwrap();
+            for instance in instances.data {
+                let pos = Vec3::from_slice(&instance.world_local[3]);
+                let (mesh_id, material_id) =
+                    match pos.distance(camera_pos) < impostor.distance {
+                        true => (&key.mesh_id, &key.material_id),
+                        false => (&impostor.impostor_id, &impostor.impostor_id),
+                    };
+                self.add_to_layer(
+                    mesh_id.clone(),
+                    material_id.clone(),
+                    key.layer,
+                    instance,
+                );
+            }
+        }
+    }
+
+    /// Batch the instances of models with lods under the mesh matching their
+    /// distance to the camera.
+    pub fn select_lods(&mut self, meshes: &MeshAssets, camera_pos: Vec3) {
+        let keys = self
+            .instances
+            .keys()
+            .filter(|key| {
+                key.static_id.is_none() && meshes.has_lods(&key.mesh_id)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            let instances = self.instances.remove(&key).unwrap();
+            for instance in instances.data {
+                let pos = Vec3::from_slice(&instance.world_local[3]);
+                let distance = pos.distance(camera_pos);
+                let Some(mesh_id) = meshes.lod(&key.mesh_id, distance) else {
+                    continue;
+                };
+                self.add_to_layer(
+                    mesh_id.to_string(),
+                    key.material_id.clone(),
+                    key.layer,
+                    instance,
+                );
+            }
+        }
+    }
+
+    /// Batch the instances of meshes with materials of their own and the
+    /// default material under each submesh, with its material.
+    pub fn select_submeshes(&mut self, meshes: &MeshAssets) {
+        let keys = self
+            .instances
+            .keys()
+            .filter(|key| {
+                key.static_id.is_none()
+                    && key.material_id == DEFAULT_MATERIAL
+                    && meshes
+                        .get(&key.mesh_id)
+                        .is_some_and(|mesh| !mesh.submeshes.is_empty())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            let instances = self.instances.remove(&key).unwrap();
+            for part_id in &meshes.get(&key.mesh_id).unwrap().submeshes {
+                for instance in &instances.data {
+                    self.add_to_layer(
+                        part_id.clone(),
+                        part_id.clone(),
+                        key.layer,
+                        *instance,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Move the instances whose mesh bounds are inside the frustum to the
+    /// front, the camera only draws those while other views draw them all.
+    pub fn cull(&mut self, meshes: &MeshAssets, frustum: &Frustum) {
+        self.culled = 0;
+        for (key, instances) in &mut self.instances {
+            instances.visible = instances.data.len();
+            if key.static_id.is_some() {
+                continue;
+            }
+            let Some(mesh) = meshes.get(&key.mesh_id) else {
+                continue;
+            };
+            let (mut visible, culled): (Vec<_>, Vec<_>) =
+                instances.data.drain(..).partition(|instance| {
+                    let world_local =
+                        Mat4::from_cols_array_2d(&instance.world_local);
+                    frustum
+                        .intersects_aabb(&mesh.aabb.transformed(&world_local))
+                });
+            instances.visible = visible.len();
+            self.culled += culled.len() as u32;
+            visible.extend(culled);
+            instances.data = visible;
+        }
+    }
+
+    pub fn set_sort(
+        &mut self,
+        mesh_id: String,
+        material_id: String,
+        sort: Option<InstanceSort>,
+    ) {
+        let key = (mesh_id, material_id);
+        match sort {
+            Some(sort) => self.sorts.insert(key, sort),
+            None => self.sorts.remove(&key),
+        };
+    }
+
+    /// Order the visible and the culled instances of the sorted batches,
+    /// after culling.
+    pub fn sort(&mut self, camera_pos: Vec3) {
+        if self.sorts.is_empty() {
+            return;
+        }
+        for (key, instances) in &mut self.instances {
+            if key.static_id.is_some() {
+                continue;
+            }
+            let sort_key = (key.mesh_id.clone(), key.material_id.clone());
+            let Some(sort) = self.sorts.get(&sort_key) else {
+                continue;
+            };
+            let order = |instance: &Instance| {
+                let pos = Vec3::from_slice(&instance.world_local[3]);
+                match sort {
+                    InstanceSort::BackToFront => -pos.distance(camera_pos),
+                    InstanceSort::FrontToBack => pos.distance(camera_pos),
+                    InstanceSort::Axis(axis) => pos.dot(*axis),
+                }
+            };
+            let (visible, culled) =
+                instances.data.split_at_mut(instances.visible);
+            for part in [visible, culled] {
+                part.sort_by(|a, b| order(a).total_cmp(&order(b)));
+            }
+        }
+    }
+
+    /// Forget the material bind groups, to pick up recreated textures.
+    pub fn invalidate_materials(&mut self) {
+        self.materials.clear();
+    }
+
+    /// Write the changed material uniforms and bind the new materials.
     pub fn prepare(
         &mut self,
         device: &wgpu::Device,
@@ -174,6 +1004,17 @@ impl Batches {
         textures: &TextureAssets,
         materials: &MaterialManager,
     ) {
+        for (material_id, material_data) in &self.materials {
+            if !materials.is_dirty(material_id) {
+                continue;
+            }
+            if let Some(uniform_data) =
+                materials.get_uniform_data_bytes(material_id)
+            {
+                queue.write_buffer(&material_data.buffer, 0, &uniform_data);
+            }
+        }
+
         for (key, instances) in &mut self.instances {
             if instances.data.is_empty() {
                 continue;
@@ -188,14 +1029,7 @@ impl Batches {
                 materials.get_uniform_data_bytes(&key.material_id),
             ) {
                 match self.materials.entry(key.material_id.clone()) {
-                    Entry::Occupied(entry) => {
-                        let material_data = entry.get();
-                        queue.write_buffer(
-                            &material_data.buffer,
-                            0,
-                            &uniform_data,
-                        );
-                    }
+                    Entry::Occupied(_) => (),
                     Entry::Vacant(entry) => {
                         let buffer = device.create_buffer_init(
                             &wgpu::util::BufferInitDescriptor {
@@ -208,13 +1042,81 @@ impl Batches {
                                     | wgpu::BufferUsages::COPY_DST,
                             },
                         );
-                        let bind_group =
-                            layouts.model.bind(device, &buffer, texture);
+                        let uv_rect = textures.uv_rect(&texture_id);
+                        let uv_buffer = device.create_buffer_init(
+                            &wgpu::util::BufferInitDescriptor {
+                                label: Some(&format!(
+                                    "model_{}_uv_rect",
+                                    key.material_id
+                                )),
+                                contents: cast_slice(&uv_rect.to_array()),
+                                usage: wgpu::BufferUsages::UNIFORM,
+                            },
+                        );
+                        let bind_group = layouts
+                            .model
+                            .bind(device, &buffer, texture, &uv_buffer);
                         entry.insert(MaterialData { bind_group, buffer });
                     }
                 };
             }
+        }
+    }
+
+    /// Upload the instances of the batches, to a storage buffer for the
+    /// shaders reading them from one.
+    pub fn prepare_instances(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        bundle: &Bundle,
+    ) {
+        for (key, instances) in &mut self.instances {
+            if instances.data.is_empty() {
+                continue;
+            }
 
+            // The extra vertex data only has a vertex instances variant.
+            let storage = materials
+                .get_shader_id(&key.material_id)
+                .is_some_and(|shader_id| bundle.uses_storage(&shader_id))
+                && !mesh_extra(meshes, key);
+            let uploaded = match storage {
+                true => instances.storage.is_some(),
+                false => instances.buffer.is_some(),
+            };
+            if !storage && bundle.shares_instances() {
+                instances.buffer = None;
+                instances.storage = None;
+                continue;
+            }
+            if key.static_id.is_some() && uploaded {
+                continue;
+            }
+            if storage {
+                let data = instances
+                    .data
+                    .iter()
+                    .map(StorageInstance::from)
+                    .collect::<Vec<_>>();
+                let buffer = device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!(
+                            "model_{}_instance_storage",
+                            key.material_id
+                        )),
+                        contents: cast_slice(&data),
+                        usage: wgpu::BufferUsages::STORAGE,
+                    },
+                );
+                instances.buffer = None;
+                instances.storage =
+                    Some(layouts.instances.bind(device, &buffer));
+                continue;
+            }
+            instances.storage = None;
             instances.buffer = Some(device.create_buffer_init(
                 &wgpu::util::BufferInitDescriptor {
                     label: Some(&format!("model_{}_instance", key.material_id)),
@@ -225,15 +1127,182 @@ impl Batches {
         }
     }
 
+    /// Write the draw arguments of the batches, after culling. With multi
+    /// draws the vertex instances go in a shared buffer, the batches
+    /// sharing their mesh and material are then drawn together.
+    pub fn prepare_indirect(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        meshes: &MeshAssets,
+        bundle: &Bundle,
+    ) {
+        self.runs.clear();
+        for instances in self.instances.values_mut() {
+            instances.indirect = None;
+            instances.first_instance = None;
+        }
+        if !bundle.indirect {
+            self.indirect_buffer = None;
+            self.instance_buffer = None;
+            return;
+        }
+
+        let mut keys = self
+            .instances
+            .iter()
+            .filter(|(key, instances)| {
+                !instances.data.is_empty() && meshes.get(&key.mesh_id).is_some()
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        keys.sort_by(|a, b| {
+            (&a.material_id, &a.mesh_id).cmp(&(&b.material_id, &b.mesh_id))
+        });
+
+        let mut visible = Vec::new();
+        let mut all = Vec::new();
+        let mut shared = Vec::new();
+        for key in keys {
+            let hidden = self.layer_hidden(key.layer);
+            let instances = self.instances.get_mut(&key).unwrap();
+            let mesh = meshes.get(&key.mesh_id).unwrap();
+            let index = visible.len() as u64;
+            instances.indirect = Some(index * INDIRECT_ARGS_SIZE);
+            if bundle.shares_instances() && instances.storage.is_none() {
+                instances.first_instance = Some(shared.len() as u32);
+                shared.extend_from_slice(&instances.data);
+                match self.runs.last_mut() {
+                    Some(run)
+                        if run.key.material_id == key.material_id
+                            && run.key.mesh_id == key.mesh_id =>
+                    {
+                        run.count += 1
+                    }
+                    _ => self.runs.push(DrawRun {
+                        key: key.clone(),
+                        first: index,
+                        count: 1,
+                    }),
+                }
+            }
+            // Hidden layers are skipped by the multi draws this way.
+            let args = |count: usize| DrawIndexedIndirectArgs {
+                index_count: mesh.num_indices,
+                instance_count: if hidden { 0 } else { count as u32 },
+                first_index: mesh.first_index,
+                base_vertex: 0,
+                first_instance: instances.first_instance.unwrap_or(0),
+            };
+            visible.push(args(instances.visible));
+            all.push(args(instances.data.len()));
+        }
+        if visible.is_empty() {
+            return;
+        }
+
+        self.indirect_all = visible.len() as u64 * INDIRECT_ARGS_SIZE;
+        let args = visible
+            .iter()
+            .chain(&all)
+            .flat_map(|args| args.as_bytes())
+            .copied()
+            .collect::<Vec<_>>();
+        write_growing(
+            device,
+            queue,
+            &mut self.indirect_buffer,
+            "model_indirect",
+            wgpu::BufferUsages::INDIRECT,
+            &args,
+        );
+        if !shared.is_empty() {
+            write_growing(
+                device,
+                queue,
+                &mut self.instance_buffer,
+                "model_shared_instance",
+                wgpu::BufferUsages::VERTEX,
+                cast_slice(&shared),
+            );
+        }
+    }
+
+    /// Returns the number of draw calls, as the other render functions.
     pub fn render(
         &self,
         rpass: &mut wgpu::RenderPass,
         bundle: &Bundle,
         meshes: &MeshAssets,
         materials: &MaterialManager,
-    ) {
+    ) -> u32 {
+        self.draw(rpass, bundle, meshes, materials, DrawPass::Main, |_| true)
+    }
+
+    /// Render the visible instances of the materials accepted by the filter.
+    pub fn render_where(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        bundle: &Bundle,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        filter: impl Fn(&str) -> bool,
+    ) -> u32 {
+        self.draw(rpass, bundle, meshes, materials, DrawPass::Main, filter)
+    }
+
+    /// Depth of the visible instances, only when the bundle uses a prepass.
+    pub fn render_depth(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        bundle: &Bundle,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+    ) -> u32 {
+        if !bundle.uses_prepass() {
+            return 0;
+        }
+        let pass = DrawPass::Prepass;
+        self.draw(rpass, bundle, meshes, materials, pass, |_| true)
+    }
+
+    /// Render every instance, culled or not, of the materials accepted by
+    /// the filter.
+    pub fn render_filtered(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        bundle: &Bundle,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        filter: impl Fn(&str) -> bool,
+    ) -> u32 {
+        self.draw(rpass, bundle, meshes, materials, DrawPass::All, filter)
+    }
+
+    fn draw(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        bundle: &Bundle,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        pass: DrawPass,
+        filter: impl Fn(&str) -> bool,
+    ) -> u32 {
+        let mut draws =
+            self.draw_runs(rpass, bundle, meshes, materials, pass, &filter);
         for (key, instances) in &self.instances {
-            if instances.data.is_empty() {
+            // Drawn with their run.
+            if instances.first_instance.is_some() {
+                continue;
+            }
+            let count = match pass {
+                DrawPass::Prepass | DrawPass::Main => instances.visible,
+                DrawPass::All => instances.data.len(),
+            };
+            if count == 0
+                || self.layer_hidden(key.layer)
+                || !filter(&key.material_id)
+            {
                 continue;
             }
 
@@ -241,42 +1310,133 @@ impl Batches {
             else {
                 continue;
             };
-            let (
-                Some(mesh),
-                Some(material_data),
-                Some(pipeline),
-                Some(instances_buffer),
-            ) = (
+            let storage = instances.storage.is_some();
+            let (Some(mesh), Some(material_data), Some(pipeline)) = (
                 meshes.get(&key.mesh_id),
                 self.materials.get(&key.material_id),
-                bundle.pipelines.get(&shader_id),
-                &instances.buffer,
-            )
+                bundle.get_pipeline(
+                    &shader_id,
+                    mesh_extra(meshes, key),
+                    storage,
+                    pass,
+                ),
+            ) else {
+                continue;
+            };
+
+            rpass.set_pipeline(&pipeline.pipeline);
+            rpass.set_bind_group(2, &material_data.bind_group, &[]);
+            rpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            rpass.set_index_buffer(
+                mesh.index_buffer.slice(..),
+                wgpu::IndexFormat::Uint32,
+            );
+            match (&instances.storage, &instances.buffer) {
+                (Some(storage), _) => rpass.set_bind_group(3, storage, &[]),
+                (None, Some(buffer)) => {
+                    rpass.set_vertex_buffer(1, buffer.slice(..))
+                }
+                (None, None) => continue,
+            }
+            if let (true, Some(extra_buffer)) =
+                (pipeline.extra, &mesh.extra_buffer)
+            {
+                rpass.set_vertex_buffer(2, extra_buffer.slice(..));
+            }
+            match (&self.indirect_buffer, instances.indirect) {
+                (Some(buffer), Some(offset)) => {
+                    let offset = match pass {
+                        DrawPass::Prepass | DrawPass::Main => offset,
+                        DrawPass::All => offset + self.indirect_all,
+                    };
+                    rpass.draw_indexed_indirect(buffer, offset);
+                }
+                _ => rpass.draw_indexed(mesh.index_range(), 0, 0..count as u32),
+            }
+            draws += 1;
+        }
+        draws
+    }
+
+    /// Draw the batches sharing the instance buffer, a multi draw for the
+    /// ones with the same mesh and material.
+    fn draw_runs(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        bundle: &Bundle,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+        pass: DrawPass,
+        filter: &impl Fn(&str) -> bool,
+    ) -> u32 {
+        let (Some(indirect_buffer), Some(instance_buffer)) =
+            (&self.indirect_buffer, &self.instance_buffer)
+        else {
+            return 0;
+        };
+        let mut draws = 0;
+        for run in &self.runs {
+            let key = &run.key;
+            if !filter(&key.material_id) {
+                continue;
+            }
+            let Some(shader_id) = materials.get_shader_id(&key.material_id)
             else {
                 continue;
             };
+            let (Some(mesh), Some(material_data), Some(pipeline)) = (
+                meshes.get(&key.mesh_id),
+                self.materials.get(&key.material_id),
+                bundle.get_pipeline(
+                    &shader_id,
+                    mesh_extra(meshes, key),
+                    false,
+                    pass,
+                ),
+            ) else {
+                continue;
+            };
 
             rpass.set_pipeline(&pipeline.pipeline);
             rpass.set_bind_group(2, &material_data.bind_group, &[]);
             rpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            rpass.set_vertex_buffer(1, instance_buffer.slice(..));
             rpass.set_index_buffer(
                 mesh.index_buffer.slice(..),
                 wgpu::IndexFormat::Uint32,
             );
-            rpass.set_vertex_buffer(1, instances_buffer.slice(..));
-            rpass.draw_indexed(
-                0..mesh.num_indices,
-                0,
-                0..instances.data.len() as u32,
+            if let (true, Some(extra_buffer)) =
+                (pipeline.extra, &mesh.extra_buffer)
+            {
+                rpass.set_vertex_buffer(2, extra_buffer.slice(..));
+            }
+            let offset = run.first * INDIRECT_ARGS_SIZE;
+            let offset = match pass {
+                DrawPass::Prepass | DrawPass::Main => offset,
+                DrawPass::All => offset + self.indirect_all,
+            };
+            rpass.multi_draw_indexed_indirect(
+                indirect_buffer,
+                offset,
+                run.count,
             );
+            draws += 1;
         }
+        draws
     }
 
+    /// Forget the instances batched this frame, the static ones stay.
     pub fn clear(&mut self) {
-        self.instances.clear();
+        self.instances.retain(|key, _| key.static_id.is_some());
     }
 }
 
+fn mesh_extra(meshes: &MeshAssets, key: &Key) -> bool {
+    meshes
+        .get(&key.mesh_id)
+        .is_some_and(|mesh| mesh.extra_buffer.is_some())
+}
+
 pub struct Layout {
     pub layout: wgpu::BindGroupLayout,
 }
@@ -315,6 +1475,17 @@ impl Layout {
                         ),
                         count: None,
                     },
+                    // Region of the texture when it is in an atlas.
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
         Self { layout }
@@ -325,6 +1496,7 @@ impl Layout {
         device: &wgpu::Device,
         uniform: &wgpu::Buffer,
         texture: &Texture,
+        uv_rect: &wgpu::Buffer,
     ) -> wgpu::BindGroup {
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("model_bind_group"),
@@ -344,59 +1516,159 @@ impl Layout {
                     binding: 2,
                     resource: wgpu::BindingResource::Sampler(&texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uv_rect.as_entire_binding(),
+                },
             ],
         })
     }
 }
 
+/// Storage buffer of the instances of a batch.
+pub struct InstancesLayout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl InstancesLayout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("model_instances_layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: true,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        instances: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("model_instances_bind_group"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: instances.as_entire_binding(),
+            }],
+        })
+    }
+}
+
 pub struct Pipeline {
+    extra: bool,
     pub pipeline: wgpu::RenderPipeline,
 }
 
 impl Pipeline {
-    pub fn new(
+    /// Created on the render thread, the layouts are not shared.
+    fn layout(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
         layouts: &Layouts,
+        key: &PipelineKey,
+    ) -> wgpu::PipelineLayout {
+        let mut bind_group_layouts = vec![
+            &layouts.globals.layout,
+            &layouts.lights.layout,
+            &layouts.model.layout,
+        ];
+        if key.storage {
+            bind_group_layouts.push(&layouts.instances.layout);
+        }
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("model_layout"),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        })
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        pipeline_layout: &wgpu::PipelineLayout,
         module: &wgpu::ShaderModule,
-        label: &str,
+        key: &PipelineKey,
+        constants: &HashMap<String, f64>,
     ) -> Self {
-        let pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("model_layout"),
-                bind_group_layouts: &[
-                    &layouts.globals.layout,
-                    &layouts.lights.layout,
-                    &layouts.model.layout,
-                ],
-                push_constant_ranges: &[],
-            });
+        let mode = key.mode;
+        let compilation_options = wgpu::PipelineCompilationOptions {
+            constants,
+            ..Default::default()
+        };
+        let (vs_entry_point, buffers) = match (key.storage, key.extra) {
+            (true, _) => (STORAGE_ENTRY_POINT, vec![Vertex::desc()]),
+            (false, true) => (
+                EXTRA_ENTRY_POINT,
+                vec![Vertex::desc(), Instance::desc(), VertexExtra::desc()],
+            ),
+            (false, false) => {
+                ("vs_main", vec![Vertex::desc(), Instance::desc()])
+            }
+        };
+        let fs_entry_point = match mode {
+            RenderMode::Lit | RenderMode::Wireframe => "fs_main",
+            RenderMode::Normals => "fs_normals",
+            RenderMode::Depth => "fs_depth",
+        };
+        let polygon_mode = match mode {
+            RenderMode::Wireframe => wgpu::PolygonMode::Line,
+            _ => wgpu::PolygonMode::Fill,
+        };
+        let targets = [Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        let fragment = match key.depth {
+            DepthTest::Prepass => None,
+            DepthTest::Write | DepthTest::Equal => Some(wgpu::FragmentState {
+                module,
+                entry_point: fs_entry_point,
+                compilation_options: compilation_options.clone(),
+                targets: &targets,
+            }),
+        };
+        let (depth_write_enabled, depth_compare) = match key.depth {
+            DepthTest::Write | DepthTest::Prepass => {
+                (true, wgpu::CompareFunction::Less)
+            }
+            DepthTest::Equal => (false, wgpu::CompareFunction::LessEqual),
+        };
 
         let pipeline =
             device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some(&format!("model_{}_pipeline", label)),
-                layout: Some(&pipeline_layout),
+                label: Some(&format!(
+                    "model_{}_{:?}_{:?}_pipeline",
+                    key.shader_id, mode, key.depth
+                )),
+                layout: Some(pipeline_layout),
                 vertex: wgpu::VertexState {
                     module,
-                    entry_point: "vs_main",
-                    buffers: &[Vertex::desc(), Instance::desc()],
-                    compilation_options: Default::default(),
+                    entry_point: vs_entry_point,
+                    buffers: &buffers,
+                    compilation_options,
+                },
+                fragment,
+                primitive: wgpu::PrimitiveState {
+                    polygon_mode,
+                    ..Default::default()
                 },
-                fragment: Some(wgpu::FragmentState {
-                    module,
-                    entry_point: "fs_main",
-                    compilation_options: Default::default(),
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState::default(),
                 depth_stencil: Some(wgpu::DepthStencilState {
                     format: Texture::DEPTH_FORMAT,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
+                    depth_write_enabled,
+                    depth_compare,
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
                 }),
@@ -406,6 +1678,31 @@ impl Pipeline {
                 cache: None,
             });
 
-        Self { pipeline }
+        Self {
+            extra: key.extra,
+            pipeline,
+        }
+    }
+}
+
+/// Write the data at the start of the buffer, recreated twice as large
+/// when it does not fit.
+fn write_growing(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &mut Option<wgpu::Buffer>,
+    label: &str,
+    usage: wgpu::BufferUsages,
+    data: &[u8],
+) {
+    let size = data.len() as u64;
+    if buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+        *buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size.next_power_of_two(),
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
     }
+    queue.write_buffer(buffer.as_ref().unwrap(), 0, data);
 }
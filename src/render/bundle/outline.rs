@@ -0,0 +1,357 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use bytemuck::cast_slice;
+use encase::ShaderType;
+use glam::Vec2;
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::render::{
+    mesh::{MeshAssets, VertexTrait},
+    shader::ShaderAssets,
+};
+
+use super::{model, AsyncPipeline, Layouts};
+
+pub const SHADER: &str = "outline";
+
+/// Outline thickness in pixels of the target.
+const WIDTH: f32 = 3.0;
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Stencil8;
+
+/// Model batched with an outline this frame, the tint of the instance is
+/// the outline color.
+#[derive(Debug, Clone)]
+pub struct Outline {
+    /// Bit of the batch layer, not drawn while the layer is hidden.
+    pub layer: u32,
+    pub mesh_id: String,
+    pub instance: model::Instance,
+}
+
+struct Draw {
+    count: u32,
+    instances: wgpu::Buffer,
+    mesh_id: String,
+}
+
+/// Outlines drawn over the scene in two steps: the outlined models mark the
+/// stencil, then their shells expanded along the normals are colored where
+/// the stencil is left unmarked.
+pub struct Bundle {
+    bind_group: Option<wgpu::BindGroup>,
+    draws: Vec<Draw>,
+    /// The mask then the outline pipeline.
+    pipelines: AsyncPipeline<[wgpu::RenderPipeline; 2]>,
+    stencil: Option<(wgpu::TextureView, [u32; 2])>,
+}
+
+impl Bundle {
+    pub fn new(shaders: &mut ShaderAssets) -> Self {
+        shaders.load(SHADER);
+        Self {
+            bind_group: None,
+            draws: Vec::new(),
+            pipelines: AsyncPipeline::default(),
+            stencil: None,
+        }
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        format: wgpu::TextureFormat,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipelines.receive() {
+            info!("Outline pipelines loaded");
+        }
+        if shaders.frame_reloaded.as_deref() != Some(SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(SHADER).unwrap();
+        let layout = create_layout(device, layouts);
+        let device = device.clone();
+        self.pipelines.build(move || {
+            [Step::Mask, Step::Outline].map(|step| {
+                create_pipeline(&device, format, &layout, &module, step)
+            })
+        });
+    }
+
+    /// Upload the instances by mesh, the stencil follows the target size.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        target_size: [u32; 2],
+        outlines: &[Outline],
+        hidden: impl Fn(u32) -> bool,
+    ) {
+        self.draws.clear();
+        let mut by_mesh = BTreeMap::<&str, Vec<model::Instance>>::new();
+        for outline in outlines.iter().filter(|o| !hidden(o.layer)) {
+            by_mesh
+                .entry(&outline.mesh_id)
+                .or_default()
+                .push(outline.instance);
+        }
+        if by_mesh.is_empty() {
+            return;
+        }
+
+        if self.stencil.as_ref().map(|(_, size)| *size) != Some(target_size) {
+            self.stencil =
+                Some((create_stencil(device, target_size), target_size));
+            let uniform = Uniform {
+                viewport: Vec2::from_array(target_size.map(|v| v as f32)),
+                width: WIDTH,
+            };
+            let buffer =
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("outline_uniform_buffer"),
+                    contents: &uniform.as_bytes(),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+            self.bind_group = Some(layouts.outline.bind(device, &buffer));
+        }
+        for (mesh_id, instances) in by_mesh {
+            let buffer =
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("outline_{}_instance", mesh_id)),
+                    contents: cast_slice(&instances),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+            self.draws.push(Draw {
+                count: instances.len() as u32,
+                instances: buffer,
+                mesh_id: mesh_id.to_string(),
+            });
+        }
+    }
+
+    /// Draw over the color target in a pass of its own, returns the number
+    /// of draw calls.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        globals: &wgpu::BindGroup,
+        meshes: &MeshAssets,
+    ) -> u32 {
+        let (Some(pipelines), Some(bind_group), Some((stencil, _))) =
+            (self.pipelines.get(), &self.bind_group, &self.stencil)
+        else {
+            return 0;
+        };
+        if self.draws.is_empty() {
+            return 0;
+        }
+
+        let mut rpass =
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("outline_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(
+                    wgpu::RenderPassDepthStencilAttachment {
+                        view: stencil,
+                        depth_ops: None,
+                        stencil_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(0),
+                            store: wgpu::StoreOp::Discard,
+                        }),
+                    },
+                ),
+                ..Default::default()
+            });
+        rpass.set_bind_group(0, globals, &[]);
+        rpass.set_bind_group(1, bind_group, &[]);
+        rpass.set_stencil_reference(1);
+        let mut draw_calls = 0;
+        // Every mask first, so the shells skip all the outlined models.
+        for pipeline in pipelines {
+            rpass.set_pipeline(pipeline);
+            for draw in &self.draws {
+                let Some(mesh) = meshes.get(&draw.mesh_id) else {
+                    continue;
+                };
+                rpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                rpass.set_vertex_buffer(1, draw.instances.slice(..));
+                rpass.set_index_buffer(
+                    mesh.index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+                rpass.draw_indexed(mesh.index_range(), 0, 0..draw.count);
+                draw_calls += 1;
+            }
+        }
+        draw_calls
+    }
+}
+
+#[derive(ShaderType)]
+struct Uniform {
+    viewport: Vec2,
+    width: f32,
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    /// Mark the stencil under the models, without color.
+    Mask,
+    /// Color the expanded shells outside the marked stencil.
+    Outline,
+}
+
+fn create_stencil(device: &wgpu::Device, size: [u32; 2]) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("outline_stencil"),
+        size: wgpu::Extent3d {
+            width: size[0],
+            height: size[1],
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: STENCIL_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("outline_layout"),
+        bind_group_layouts: &[&layouts.globals.layout, &layouts.outline.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    step: Step,
+) -> wgpu::RenderPipeline {
+    let (entry_point, write_mask, stencil_face) = match step {
+        Step::Mask => (
+            "vs_mask",
+            wgpu::ColorWrites::empty(),
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::Always,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+        ),
+        Step::Outline => (
+            "vs_outline",
+            wgpu::ColorWrites::ALL,
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::NotEqual,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Keep,
+            },
+        ),
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{}_pipeline", entry_point)),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point,
+            buffers: &[model::Vertex::desc(), model::Instance::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask,
+            })],
+        }),
+        // Both sides, the shells of open meshes too.
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState {
+                front: stencil_face,
+                back: stencil_face,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("outline_layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        uniform: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("outline_bind_group"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            }],
+        })
+    }
+}
@@ -0,0 +1,420 @@
+use std::sync::Arc;
+
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::render::{shader::ShaderAssets, texture::Texture};
+
+use super::{AsyncPipeline, Layouts};
+
+pub const SHADER: &str = "output";
+/// Linear format the scene is rendered into before being tonemapped, keeps
+/// the light above 1.
+pub const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Whether colors written to the surface must be encoded to sRGB by hand.
+pub fn needs_srgb_conversion(format: wgpu::TextureFormat) -> bool {
+    !format.is_srgb()
+        && !matches!(
+            format,
+            wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float
+        )
+}
+
+/// Scale the scene resolution to keep the gpu time of a frame in budget.
+#[derive(Debug, Clone)]
+pub struct DynamicResolution {
+    cooldown: f32,
+    frame_time: f32,
+    pub max_scale: f32,
+    pub min_scale: f32,
+    pub scale: f32,
+    pub target_frame_time: f32,
+}
+
+impl DynamicResolution {
+    const COOLDOWN: f32 = 0.5;
+    const STEP: f32 = 0.1;
+
+    pub fn new(min_scale: f32, max_scale: f32, target_frame_time: f32) -> Self {
+        Self {
+            cooldown: Self::COOLDOWN,
+            frame_time: target_frame_time,
+            max_scale,
+            min_scale,
+            scale: max_scale,
+            target_frame_time,
+        }
+    }
+
+    /// Feed the last frame time, returns whether the scale changed.
+    fn update(&mut self, dt: f32, frame_time: f32) -> bool {
+        self.frame_time += (frame_time - self.frame_time) * 0.1;
+        self.cooldown -= dt;
+        if self.cooldown > 0.0 {
+            return false;
+        }
+
+        // The pixel count grows with the square of the scale, keep a margin
+        // before going up to avoid bouncing between two steps.
+        let budget = self.target_frame_time * 0.9;
+        let scale = if self.frame_time > budget {
+            self.scale - Self::STEP
+        } else if self.frame_time < budget * 0.7 {
+            self.scale + Self::STEP
+        } else {
+            self.scale
+        }
+        .clamp(self.min_scale, self.max_scale);
+
+        if (scale - self.scale).abs() < f32::EPSILON {
+            return false;
+        }
+        self.cooldown = Self::COOLDOWN;
+        self.scale = scale;
+        true
+    }
+}
+
+/// Final pass tonemapping the scene target to the surface, also encodes to
+/// sRGB when the surface format does not do it itself.
+pub struct Bundle {
+    bind_group: Option<wgpu::BindGroup>,
+    pub dynamic: Option<DynamicResolution>,
+    /// Exposure value in stops, 0 keeps the scene radiance.
+    exposure: f32,
+    exposure_buffer: wgpu::Buffer,
+    /// Blend to black, 1 is fully faded out.
+    fade: f32,
+    pipeline: AsyncPipeline<wgpu::RenderPipeline>,
+    srgb_conversion: bool,
+    pub target: Option<Texture>,
+}
+
+impl Bundle {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layouts: &Layouts,
+        shaders: &mut ShaderAssets,
+    ) -> Self {
+        let srgb_conversion = needs_srgb_conversion(config.format);
+        if srgb_conversion {
+            info!("Surface format {:?} is not sRGB", config.format);
+        }
+        let exposure_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("exposure_buffer"),
+                contents: bytemuck::cast_slice(&exposure_data(0.0, 0.0)),
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST,
+            });
+        let mut bundle = Self {
+            bind_group: None,
+            dynamic: None,
+            exposure: 0.0,
+            exposure_buffer,
+            fade: 0.0,
+            pipeline: AsyncPipeline::default(),
+            srgb_conversion,
+            target: None,
+        };
+        shaders.load(SHADER);
+        bundle.resize(device, config, layouts);
+        bundle
+    }
+
+    /// Format the scene pipelines must render to.
+    pub fn scene_format(&self) -> wgpu::TextureFormat {
+        TARGET_FORMAT
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, ev: f32) {
+        self.exposure = ev;
+        self.write_exposure(queue);
+    }
+
+    pub fn set_fade(&mut self, queue: &wgpu::Queue, fade: f32) {
+        self.fade = fade.clamp(0.0, 1.0);
+        self.write_exposure(queue);
+    }
+
+    fn write_exposure(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.exposure_buffer,
+            0,
+            bytemuck::cast_slice(&exposure_data(self.exposure, self.fade)),
+        );
+    }
+
+    /// Bind a target of another window to tonemap it with this pass.
+    pub fn bind_target(
+        &self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        target: &Texture,
+    ) -> wgpu::BindGroup {
+        layouts.output.bind(device, target, &self.exposure_buffer)
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.dynamic.as_ref().map_or(1.0, |dynamic| dynamic.scale)
+    }
+
+    pub fn set_dynamic(&mut self, dynamic: Option<DynamicResolution>) {
+        self.dynamic = dynamic;
+    }
+
+    /// Update the dynamic resolution, returns whether the target must be
+    /// resized.
+    pub fn update(&mut self, dt: f32, frame_time: f32) -> bool {
+        self.dynamic
+            .as_mut()
+            .is_some_and(|dynamic| dynamic.update(dt, frame_time))
+    }
+
+    /// Size the scene is rendered at.
+    pub fn target_size(&self, config: &wgpu::SurfaceConfiguration) -> [u32; 2] {
+        let scale = self.scale();
+        [config.width, config.height]
+            .map(|size| ((size as f32 * scale) as u32).max(1))
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layouts: &Layouts,
+    ) {
+        let [width, height] = self.target_size(config);
+        let target = Texture::create_target(
+            device,
+            width,
+            height,
+            TARGET_FORMAT,
+            "output",
+        );
+        self.bind_group = Some(self.bind_target(device, layouts, &target));
+        self.target = Some(target);
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        config: &wgpu::SurfaceConfiguration,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipeline.receive() {
+            info!("Output pipeline loaded");
+        }
+        if shaders.frame_reloaded.as_deref() != Some(SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(SHADER).unwrap();
+        let entry_point = match self.srgb_conversion {
+            true => "fs_main",
+            false => "fs_tonemap",
+        };
+        let layout = create_layout(device, layouts);
+        let (device, format) = (device.clone(), config.format);
+        self.pipeline.build(move || {
+            create_pipeline(&device, format, &layout, &module, entry_point)
+        });
+    }
+
+    /// The scene target is drawn in the rect in pixels, the whole surface
+    /// when there is none.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        surface_view: &wgpu::TextureView,
+        rect: Option<[f32; 4]>,
+    ) {
+        if let Some(bind_group) = &self.bind_group {
+            self.render_target(encoder, bind_group, surface_view, rect);
+        }
+    }
+
+    /// Tonemap a target bound with `bind_target`, the rest of the surface
+    /// is cleared.
+    pub fn render_target(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        surface_view: &wgpu::TextureView,
+        rect: Option<[f32; 4]>,
+    ) {
+        let load = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        self.draw(encoder, bind_group, surface_view, load, rect);
+    }
+
+    /// Tonemap a target over the rect in pixels, keeping the rest.
+    pub fn render_over(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        surface_view: &wgpu::TextureView,
+        rect: [f32; 4],
+    ) {
+        let load = wgpu::LoadOp::Load;
+        self.draw(encoder, bind_group, surface_view, load, Some(rect));
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        surface_view: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        rect: Option<[f32; 4]>,
+    ) {
+        let Some(pipeline) = self.pipeline.get() else {
+            return;
+        };
+
+        let mut rpass =
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("output_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: surface_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+        if let Some([x, y, width, height]) = rect {
+            rpass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("output_layout"),
+        bind_group_layouts: &[&layouts.output.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("output_pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point,
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Exposure multiplier and fade, padded to the uniform alignment.
+fn exposure_data(ev: f32, fade: f32) -> [f32; 4] {
+    [2f32.powf(ev), fade, 0.0, 0.0]
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("output_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::default(),
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering,
+                        ),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        texture: &Texture,
+        exposure: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("output_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use bytemuck::{Pod, Zeroable};
+use encase::ShaderType;
+use glam::{Mat4, UVec2, Vec3};
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    render::{
+        bvh::{Bvh, Triangle},
+        material::{simple::SimpleMaterial, MaterialManager},
+        mesh::MeshAssets,
+        shader::ShaderAssets,
+        texture::Texture,
+    },
+    scene::Scene,
+};
+
+use super::{lights::PointLight, output, AsyncPipeline, Layouts};
+
+pub const SHADER: &str = "pathtrace";
+/// Rgba16float can be written from a compute shader without extra features.
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Renderer {
+    Raster,
+    /// Compute path tracer over the entities, for reference images.
+    PathTrace,
+}
+
+impl Renderer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "raster" => Some(Self::Raster),
+            "pathtrace" => Some(Self::PathTrace),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Raster => "raster",
+            Self::PathTrace => "pathtrace",
+        }
+    }
+}
+
+#[derive(Default, ShaderType)]
+struct Uniform {
+    world_clip: Mat4,
+    camera_pos: Vec3,
+    frame: u32,
+    size: UVec2,
+    light_count: u32,
+    bounces: u32,
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct GpuLight {
+    position: [f32; 3],
+    radius: f32,
+    color: [f32; 3],
+    intensity: f32,
+    attenuation: u32,
+    _padding: [u32; 3],
+}
+
+/// Scene uploaded for the path tracer, rebuilt when the entities move.
+struct SceneBuffers {
+    lights: wgpu::Buffer,
+    light_count: u32,
+    nodes: wgpu::Buffer,
+    triangles: wgpu::Buffer,
+}
+
+/// Progressive path tracer, one sample per pixel and frame averaged while
+/// the camera and the scene are still. Materials are approximated by the
+/// color of the simple ones, without their texture.
+pub struct Bundle {
+    accumulation: Option<wgpu::Buffer>,
+    bind_group: Option<wgpu::BindGroup>,
+    /// Diffuse bounces after the first hit.
+    pub bounces: u32,
+    /// Samples averaged per pixel so far.
+    pub frames: u32,
+    output: Option<(Texture, wgpu::BindGroup)>,
+    pipeline: AsyncPipeline<wgpu::ComputePipeline>,
+    pub renderer: Renderer,
+    scene: Option<SceneBuffers>,
+    /// Entities and lights the scene buffers were built from.
+    scene_signature: u64,
+    /// Camera the samples were traced from.
+    camera_signature: u64,
+    size: [u32; 2],
+    uniform: wgpu::Buffer,
+}
+
+impl Bundle {
+    pub fn new(device: &wgpu::Device, shaders: &mut ShaderAssets) -> Self {
+        shaders.load(SHADER);
+        let uniform =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("pathtrace_uniform_buffer"),
+                contents: &Uniform::default().as_bytes(),
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST,
+            });
+        Self {
+            accumulation: None,
+            bind_group: None,
+            bounces: 2,
+            frames: 0,
+            output: None,
+            pipeline: AsyncPipeline::default(),
+            renderer: Renderer::Raster,
+            scene: None,
+            scene_signature: 0,
+            camera_signature: 0,
+            size: [0, 0],
+            uniform,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.renderer == Renderer::PathTrace
+    }
+
+    /// The buffers are created at the next resize.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        self.frames = 0;
+        if !self.enabled() {
+            self.accumulation = None;
+            self.bind_group = None;
+            self.output = None;
+            self.scene = None;
+        }
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipeline.receive() {
+            info!("Path tracer pipeline loaded");
+            self.frames = 0;
+        }
+        if shaders.frame_reloaded.as_deref() != Some(SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(SHADER).unwrap();
+        let pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("pathtrace_layout"),
+                bind_group_layouts: &[&layouts.pathtrace.layout],
+                push_constant_ranges: &[],
+            });
+        let device = device.clone();
+        self.pipeline.build(move || {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("pathtrace_pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: "cs_main",
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        });
+    }
+
+    /// Recreate the accumulation and the output at the scene target size.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        output: &output::Bundle,
+        size: [u32; 2],
+    ) {
+        self.frames = 0;
+        self.bind_group = None;
+        if !self.enabled() {
+            return;
+        }
+        let [width, height] = size;
+        self.size = size;
+        self.accumulation =
+            Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("pathtrace_accumulation_buffer"),
+                size: width as u64 * height as u64 * 16,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }));
+        let texture = Texture::create_storage(
+            device,
+            width,
+            height,
+            OUTPUT_FORMAT,
+            "pathtrace",
+        );
+        let bind_group = output.bind_target(device, layouts, &texture);
+        self.output = Some((texture, bind_group));
+    }
+
+    /// Rebuild the scene when the entities or the lights changed, restart
+    /// the average when the camera moved too.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &Layouts,
+        scene: &Scene,
+        meshes: &MeshAssets,
+        materials: &MaterialManager,
+    ) {
+        if !self.enabled() || self.output.is_none() {
+            return;
+        }
+        let scene_signature = scene_signature(scene, meshes);
+        if self.scene.is_none() || scene_signature != self.scene_signature {
+            self.scene_signature = scene_signature;
+            self.scene = Some(build_scene(device, scene, meshes, materials));
+            self.bind_group = None;
+            self.frames = 0;
+        }
+        let camera_signature = camera_signature(scene);
+        if camera_signature != self.camera_signature {
+            self.camera_signature = camera_signature;
+            self.frames = 0;
+        }
+        if self.bind_group.is_none() {
+            self.bind_group = Some(self.bind(device, layouts));
+        }
+
+        let [width, height] = self.size;
+        let camera = &scene.camera;
+        let clip_world = camera.build_projection(width as f32 / height as f32)
+            * camera.build_view();
+        let uniform = Uniform {
+            world_clip: clip_world.inverse(),
+            camera_pos: camera.transform.pos,
+            frame: self.frames,
+            size: UVec2::new(width, height),
+            light_count: self.scene.as_ref().map_or(0, |s| s.light_count),
+            bounces: self.bounces,
+        };
+        queue.write_buffer(&self.uniform, 0, &uniform.as_bytes());
+    }
+
+    fn bind(
+        &self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+    ) -> wgpu::BindGroup {
+        let scene = self.scene.as_ref().unwrap();
+        let (texture, _) = self.output.as_ref().unwrap();
+        let buffers = [
+            &self.uniform,
+            &scene.nodes,
+            &scene.triangles,
+            &scene.lights,
+            self.accumulation.as_ref().unwrap(),
+        ];
+        let mut entries = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>();
+        entries.push(wgpu::BindGroupEntry {
+            binding: buffers.len() as u32,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pathtrace_bind_group"),
+            layout: &layouts.pathtrace.layout,
+            entries: &entries,
+        })
+    }
+
+    /// Trace one more sample per pixel.
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let (Some(pipeline), Some(bind_group)) =
+            (self.pipeline.get(), &self.bind_group)
+        else {
+            return;
+        };
+        let mut cpass =
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("pathtrace_pass"),
+                timestamp_writes: None,
+            });
+        cpass.set_pipeline(pipeline);
+        cpass.set_bind_group(0, bind_group, &[]);
+        let [width, height] = self.size;
+        cpass.dispatch_workgroups(
+            width.div_ceil(WORKGROUP_SIZE),
+            height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+        self.frames += 1;
+    }
+
+    /// Bind group of the traced image for the output pass.
+    pub fn output_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        match (&self.output, self.frames > 0) {
+            (Some((_, bind_group)), true) => Some(bind_group),
+            _ => None,
+        }
+    }
+}
+
+pub(super) fn scene_signature(scene: &Scene, meshes: &MeshAssets) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (id, entity) in scene.entities.iter() {
+        (&entity.mesh_id, &entity.material_id).hash(&mut hasher);
+        meshes.get(&entity.mesh_id).is_some().hash(&mut hasher);
+        let matrix = scene.entities.local_to_world(id).unwrap();
+        matrix.to_cols_array().map(f32::to_bits).hash(&mut hasher);
+    }
+    for light in &scene.point_lights {
+        hash_light(light, &mut hasher);
+    }
+    hasher.finish()
+}
+
+fn hash_light(light: &PointLight, hasher: &mut DefaultHasher) {
+    light.pos.to_array().map(f32::to_bits).hash(hasher);
+    light.radius.to_bits().hash(hasher);
+    light.color.to_array().map(f32::to_bits).hash(hasher);
+    light.intensity.to_bits().hash(hasher);
+    light.attenuation().hash(hasher);
+}
+
+pub(super) fn camera_signature(scene: &Scene) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let camera = &scene.camera;
+    camera
+        .transform
+        .pos
+        .to_array()
+        .map(f32::to_bits)
+        .hash(&mut hasher);
+    camera
+        .transform
+        .rot
+        .to_array()
+        .map(f32::to_bits)
+        .hash(&mut hasher);
+    camera.fovy.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// World space triangles of the loaded entity meshes.
+pub(super) fn build_bvh(
+    scene: &Scene,
+    meshes: &MeshAssets,
+    materials: &MaterialManager,
+) -> Bvh {
+    let mut triangles = Vec::new();
+    for (id, entity) in scene.entities.iter() {
+        let Some(mesh) = meshes.get(&entity.mesh_id) else {
+            continue;
+        };
+        let albedo = materials
+            .get_any(&entity.material_id)
+            .and_then(|material| material.downcast_ref::<SimpleMaterial>())
+            .map_or(Vec3::splat(0.8), |material| material.uniform.color);
+        let world_local = scene.entities.local_to_world(id).unwrap();
+        let positions = mesh
+            .positions
+            .iter()
+            .map(|position| world_local.transform_point3(*position))
+            .collect::<Vec<_>>();
+        triangles.extend(mesh.indices.chunks_exact(3).map(|indices| {
+            let corners = [0, 1, 2].map(|i| positions[indices[i] as usize]);
+            Triangle::new(corners, albedo)
+        }));
+    }
+    Bvh::build(triangles)
+}
+
+/// Nodes and triangles storage buffers, an empty bvh gets a placeholder
+/// triangle, bindings can not be empty.
+pub(super) fn bvh_buffers(
+    device: &wgpu::Device,
+    bvh: Bvh,
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let triangles = match bvh.triangles.is_empty() {
+        true => vec![Triangle::zeroed()],
+        false => bvh.triangles,
+    };
+    let storage = |label, contents: &[u8]| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    };
+    (
+        storage("bvh_nodes_buffer", bytemuck::cast_slice(&bvh.nodes)),
+        storage("bvh_triangles_buffer", bytemuck::cast_slice(&triangles)),
+    )
+}
+
+/// Bvh of the scene with the lights, an empty light array gets a
+/// placeholder.
+fn build_scene(
+    device: &wgpu::Device,
+    scene: &Scene,
+    meshes: &MeshAssets,
+    materials: &MaterialManager,
+) -> SceneBuffers {
+    let (nodes, triangles) =
+        bvh_buffers(device, build_bvh(scene, meshes, materials));
+    let mut lights = scene
+        .point_lights
+        .iter()
+        .map(|light| GpuLight {
+            position: light.pos.into(),
+            radius: light.radius,
+            color: light.color.into(),
+            intensity: light.intensity,
+            attenuation: light.attenuation(),
+            _padding: [0; 3],
+        })
+        .collect::<Vec<_>>();
+    let light_count = lights.len() as u32;
+    if lights.is_empty() {
+        lights.push(GpuLight::zeroed());
+    }
+    SceneBuffers {
+        lights: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("pathtrace_lights_buffer"),
+            contents: bytemuck::cast_slice(&lights),
+            usage: wgpu::BufferUsages::STORAGE,
+        }),
+        light_count,
+        nodes,
+        triangles,
+    }
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let read_only = wgpu::BufferBindingType::Storage { read_only: true };
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("pathtrace_layout"),
+                entries: &[
+                    buffer(0, wgpu::BufferBindingType::Uniform),
+                    buffer(1, read_only),
+                    buffer(2, read_only),
+                    buffer(3, read_only),
+                    buffer(
+                        4,
+                        wgpu::BufferBindingType::Storage { read_only: false },
+                    ),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: OUTPUT_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        Self { layout }
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bytemuck::{Pod, Zeroable};
+use encase::ShaderType;
+use glam::{Vec2, Vec3, Vec4};
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::render::{shader::ShaderAssets, texture::Texture};
+
+use super::{AsyncPipeline, Layouts};
+
+pub const SHADER: &str = "points";
+
+static NEXT_BUFFER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Positions shared by the scripts and the renderer, uploaded once.
+#[derive(Debug)]
+pub struct PointBuffer {
+    /// Per point colors, multiplied with the batch color.
+    pub colors: Option<Vec<Vec3>>,
+    id: u64,
+    pub points: Vec<Vec3>,
+}
+
+impl PointBuffer {
+    pub fn new(points: Vec<Vec3>) -> Self {
+        Self::with_colors(points, None)
+    }
+
+    pub fn with_colors(points: Vec<Vec3>, colors: Option<Vec<Vec3>>) -> Self {
+        Self {
+            colors,
+            id: NEXT_BUFFER_ID.fetch_add(1, Ordering::Relaxed),
+            points,
+        }
+    }
+
+    fn gpu_data(&self) -> Vec<GpuPoint> {
+        self.points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let color = self.colors.as_ref().map_or(Vec3::ONE, |c| c[i]);
+                let [r, g, b] = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+                    .to_array()
+                    .map(|value| value.round() as u8);
+                GpuPoint {
+                    position: point.to_array(),
+                    color: u32::from_le_bytes([r, g, b, 255]),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Position with its color packed as rgba8.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct GpuPoint {
+    position: [f32; 3],
+    color: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PointBatch {
+    pub buffer: Arc<PointBuffer>,
+    pub color: Vec4,
+    /// Draw segments between consecutive points instead of points.
+    pub lines: bool,
+    /// Diameter or line width in pixels, or in world units for points.
+    pub size: f32,
+    pub world: bool,
+}
+
+struct Draw {
+    bind_group: wgpu::BindGroup,
+    lines: bool,
+    vertices: u32,
+}
+
+/// Points and thick lines expanded to quads from their positions in a
+/// storage buffer.
+pub struct Bundle {
+    buffers: HashMap<u64, wgpu::Buffer>,
+    draws: Vec<Draw>,
+    /// The points then the lines pipeline.
+    pipelines: AsyncPipeline<[wgpu::RenderPipeline; 2]>,
+}
+
+impl Bundle {
+    pub fn new(shaders: &mut ShaderAssets) -> Self {
+        shaders.load(SHADER);
+        Self {
+            buffers: HashMap::new(),
+            draws: Vec::new(),
+            pipelines: AsyncPipeline::default(),
+        }
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        format: wgpu::TextureFormat,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.pipelines.receive() {
+            info!("Points pipelines loaded");
+        }
+        if shaders.frame_reloaded.as_deref() != Some(SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(SHADER).unwrap();
+        let layout = create_layout(device, layouts);
+        let device = device.clone();
+        self.pipelines.build(move || {
+            ["points", "lines"].map(|kind| {
+                create_pipeline(&device, format, &layout, &module, kind)
+            })
+        });
+    }
+
+    /// Upload the buffers not seen yet, the ones no longer drawn are freed.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        viewport: Vec2,
+        batches: &[PointBatch],
+    ) {
+        self.buffers
+            .retain(|id, _| batches.iter().any(|batch| batch.buffer.id == *id));
+        self.draws.clear();
+        for batch in batches {
+            let points = &batch.buffer.points;
+            let vertices = match batch.lines {
+                true => points.len().saturating_sub(1) * 6,
+                false => points.len() * 6,
+            };
+            if vertices == 0 {
+                continue;
+            }
+            let positions =
+                self.buffers.entry(batch.buffer.id).or_insert_with(|| {
+                    let data = batch.buffer.gpu_data();
+                    device.create_buffer_init(
+                        &wgpu::util::BufferInitDescriptor {
+                            label: Some("points_positions_buffer"),
+                            contents: bytemuck::cast_slice(&data),
+                            usage: wgpu::BufferUsages::STORAGE,
+                        },
+                    )
+                });
+            let uniform = Uniform {
+                color: batch.color,
+                viewport,
+                size: batch.size,
+                world: (batch.world && !batch.lines) as u32,
+            };
+            let uniform_buffer =
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("points_uniform_buffer"),
+                    contents: &uniform.as_bytes(),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+            self.draws.push(Draw {
+                bind_group: layouts.points.bind(
+                    device,
+                    &uniform_buffer,
+                    positions,
+                ),
+                lines: batch.lines,
+                vertices: vertices as u32,
+            });
+        }
+    }
+
+    /// Draw in a pass with the globals bound.
+    pub fn render(&self, rpass: &mut wgpu::RenderPass) {
+        let Some([points_pipeline, lines_pipeline]) = self.pipelines.get()
+        else {
+            return;
+        };
+        for draw in &self.draws {
+            rpass.set_pipeline(match draw.lines {
+                true => lines_pipeline,
+                false => points_pipeline,
+            });
+            rpass.set_bind_group(1, &draw.bind_group, &[]);
+            rpass.draw(0..draw.vertices, 0..1);
+        }
+    }
+}
+
+#[derive(ShaderType)]
+struct Uniform {
+    color: Vec4,
+    viewport: Vec2,
+    size: f32,
+    world: u32,
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("points_layout"),
+        bind_group_layouts: &[&layouts.globals.layout, &layouts.points.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    kind: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{}_pipeline", kind)),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: &format!("vs_{}", kind),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: &format!("fs_{}", kind),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("points_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        uniform: &wgpu::Buffer,
+        positions: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("points_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: positions.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
@@ -0,0 +1,531 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    f32::consts::FRAC_PI_2,
+    sync::Arc,
+};
+
+use encase::ShaderType;
+use glam::{Mat4, Vec3};
+use log::info;
+use wgpu::util::DeviceExt;
+
+use crate::render::{shader::ShaderAssets, texture::Texture};
+
+use super::{
+    globals::{self, Environment},
+    output, AsyncPipeline, Layouts,
+};
+
+pub const SHADER: &str = "probe_mip";
+pub const MAX_RESOLUTION: u32 = 1024;
+
+const FORMAT: wgpu::TextureFormat = output::TARGET_FORMAT;
+const ZNEAR: f32 = 0.05;
+const ZFAR: f32 = 200.0;
+
+/// Direction and up of the cube faces, in the +x, -x, +y, -y, +z, -z layer
+/// order. The ups are the ones of a y up texture, the projection flips y.
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::NEG_Y),
+    (Vec3::NEG_X, Vec3::NEG_Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::Z, Vec3::NEG_Y),
+    (Vec3::NEG_Z, Vec3::NEG_Y),
+];
+
+/// Probe placed by the scripts.
+#[derive(Debug, Clone)]
+pub struct ProbeSettings {
+    pub pos: Vec3,
+    pub resolution: u32,
+    /// Seconds between two renders, only rendered on demand when none.
+    pub interval: Option<f32>,
+    /// Rendered on the next frame whatever the interval.
+    pub refresh: bool,
+}
+
+/// Reflection probes of the scene by id, kept across frames.
+#[derive(Debug, Default)]
+pub struct ReflectionProbes {
+    next_id: u32,
+    pub probes: BTreeMap<u32, ProbeSettings>,
+}
+
+impl ReflectionProbes {
+    /// Rendered on the next frame, then only when refreshed.
+    pub fn add(&mut self, pos: Vec3, resolution: u32) -> u32 {
+        self.next_id += 1;
+        self.probes.insert(
+            self.next_id,
+            ProbeSettings {
+                pos,
+                resolution,
+                interval: None,
+                refresh: true,
+            },
+        );
+        self.next_id
+    }
+}
+
+struct Probe {
+    depth: Texture,
+    /// Views of each level of each face, the scene is drawn into the first.
+    faces: Vec<Vec<wgpu::TextureView>>,
+    levels: u32,
+    /// Each level of each face, sampled to fill the next one.
+    mip_bind_groups: Vec<Vec<wgpu::BindGroup>>,
+    pos: Vec3,
+    /// Elapsed time of the last render, none before the first.
+    rendered: Option<f32>,
+    resolution: u32,
+    view: wgpu::TextureView,
+}
+
+impl Probe {
+    fn new(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        sampler: &wgpu::Sampler,
+        resolution: u32,
+    ) -> Self {
+        let levels = resolution.ilog2() + 1;
+        let texture = create_cube(device, resolution, levels);
+        let faces: Vec<Vec<_>> = (0..6)
+            .map(|face| {
+                (0..levels)
+                    .map(|level| {
+                        texture.create_view(&wgpu::TextureViewDescriptor {
+                            label: Some("reflection_probe_face"),
+                            dimension: Some(wgpu::TextureViewDimension::D2),
+                            base_mip_level: level,
+                            mip_level_count: Some(1),
+                            base_array_layer: face,
+                            array_layer_count: Some(1),
+                            ..Default::default()
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let mip_bind_groups = faces
+            .iter()
+            .map(|levels| {
+                levels
+                    .iter()
+                    .map(|view| layouts.probes.bind(device, view, sampler))
+                    .collect()
+            })
+            .collect();
+        Self {
+            depth: Texture::create_depth(device, resolution, resolution),
+            faces,
+            levels,
+            mip_bind_groups,
+            pos: Vec3::ZERO,
+            rendered: None,
+            resolution,
+            view: texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("reflection_probe"),
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn due(&self, settings: &ProbeSettings, elapsed: f32) -> bool {
+        match (self.rendered, settings.interval) {
+            _ if settings.refresh => true,
+            (None, _) => true,
+            (Some(rendered), Some(interval)) => elapsed - rendered >= interval,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Cubemaps of the scene rendered around the probes, at most one of them
+/// each frame. The one nearest to the camera is bound for the lighting,
+/// blurred down its mips for the rough surfaces.
+pub struct Bundle {
+    /// Globals of the cube faces, a buffer each as they are all drawn in
+    /// the same frame.
+    face_globals: [globals::Bundle; 6],
+    mip_pipeline: AsyncPipeline<wgpu::RenderPipeline>,
+    mip_sampler: wgpu::Sampler,
+    /// Black cube bound without probes.
+    placeholder: wgpu::TextureView,
+    probes: HashMap<u32, Probe>,
+    /// Probe bound for the lighting.
+    bound: Option<u32>,
+    /// Probe drawn this frame.
+    refreshing: Option<u32>,
+    pub sampler: wgpu::Sampler,
+    pub uniform_buffer: wgpu::Buffer,
+}
+
+impl Bundle {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        shaders: &mut ShaderAssets,
+    ) -> Self {
+        shaders.load(SHADER);
+        let linear = wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        };
+        Self {
+            face_globals: std::array::from_fn(|_| {
+                globals::Bundle::new(device, layouts)
+            }),
+            mip_pipeline: AsyncPipeline::default(),
+            mip_sampler: device.create_sampler(&linear),
+            placeholder: create_cube(device, 1, 1).create_view(
+                &wgpu::TextureViewDescriptor {
+                    label: Some("reflection_probe_placeholder"),
+                    dimension: Some(wgpu::TextureViewDimension::Cube),
+                    ..Default::default()
+                },
+            ),
+            probes: HashMap::new(),
+            bound: None,
+            refreshing: None,
+            sampler: device.create_sampler(&linear),
+            uniform_buffer: device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("reflection_probe_buffer"),
+                    contents: &Uniform::default().as_bytes(),
+                    usage: wgpu::BufferUsages::UNIFORM
+                        | wgpu::BufferUsages::COPY_DST,
+                },
+            ),
+        }
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        layouts: &Layouts,
+        shaders: &ShaderAssets,
+    ) {
+        if self.mip_pipeline.receive() {
+            info!("Reflection probe pipeline loaded");
+        }
+        if shaders.frame_reloaded.as_deref() != Some(SHADER) {
+            return;
+        }
+        let module = shaders.get_shared(SHADER).unwrap();
+        let layout = create_layout(device, layouts);
+        let device = device.clone();
+        self.mip_pipeline
+            .build(move || create_pipeline(&device, &layout, &module));
+    }
+
+    /// Cube sampled by the lighting, the placeholder without probe.
+    pub fn bound_view(&self) -> &wgpu::TextureView {
+        self.bound
+            .and_then(|id| self.probes.get(&id))
+            .map_or(&self.placeholder, |probe| &probe.view)
+    }
+
+    pub fn placeholder(&self) -> &wgpu::TextureView {
+        &self.placeholder
+    }
+
+    /// Follow the probes of the scene, pick the one to draw this frame,
+    /// the longest due, and the one to bind.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &Layouts,
+        environment: &Environment,
+        settings: &mut ReflectionProbes,
+        camera_pos: Vec3,
+    ) {
+        self.probes.retain(|id, probe| {
+            settings
+                .probes
+                .get(id)
+                .is_some_and(|settings| settings.resolution == probe.resolution)
+        });
+        for (id, probe) in &settings.probes {
+            self.probes.entry(*id).or_insert_with(|| {
+                Probe::new(device, layouts, &self.mip_sampler, probe.resolution)
+            });
+        }
+
+        // Left due until the mips can be blurred.
+        let elapsed = environment.elapsed;
+        let ready = self.mip_pipeline.get().is_some();
+        self.refreshing = settings
+            .probes
+            .iter_mut()
+            .filter(|(id, settings)| {
+                ready && self.probes[*id].due(settings, elapsed)
+            })
+            .min_by(|(a, _), (b, _)| {
+                let rendered =
+                    |id| self.probes[id].rendered.unwrap_or(f32::MIN);
+                rendered(a).total_cmp(&rendered(b))
+            })
+            .map(|(id, settings)| {
+                settings.refresh = false;
+                let probe = self.probes.get_mut(id).unwrap();
+                probe.rendered = Some(elapsed);
+                probe.pos = settings.pos;
+                *id
+            });
+        if let Some(probe) = self.refreshing.map(|id| &self.probes[&id]) {
+            let projection = Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0))
+                * Mat4::perspective_rh(FRAC_PI_2, 1.0, ZNEAR, ZFAR);
+            for (globals, (dir, up)) in self.face_globals.iter().zip(FACES) {
+                globals.prepare_matrices(
+                    queue,
+                    environment,
+                    projection,
+                    Mat4::look_at_rh(probe.pos, probe.pos + dir, up),
+                );
+            }
+        }
+
+        self.bound = self
+            .probes
+            .iter()
+            .filter(|(_, probe)| probe.rendered.is_some())
+            .min_by(|(_, a), (_, b)| {
+                a.pos
+                    .distance_squared(camera_pos)
+                    .total_cmp(&b.pos.distance_squared(camera_pos))
+            })
+            .map(|(id, _)| *id);
+        let uniform = match self.bound.map(|id| &self.probes[&id]) {
+            Some(probe) => Uniform {
+                pos: probe.pos,
+                levels: probe.levels as f32,
+            },
+            None => Uniform::default(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, &uniform.as_bytes());
+    }
+
+    /// Draw the faces of the probe due this frame with the draw callback,
+    /// then blur them down the mips. Returns the number of draw calls.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        mut draw: impl FnMut(&mut wgpu::RenderPass) -> u32,
+    ) -> u32 {
+        let (Some(probe), Some(mip_pipeline)) = (
+            self.refreshing.and_then(|id| self.probes.get(&id)),
+            self.mip_pipeline.get(),
+        ) else {
+            return 0;
+        };
+        let mut draw_calls = 0;
+        for (levels, globals) in probe.faces.iter().zip(&self.face_globals) {
+            let mut rpass =
+                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("reflection_probe_pass"),
+                    color_attachments: &[Some(
+                        wgpu::RenderPassColorAttachment {
+                            view: &levels[0],
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color {
+                                    r: 0.03,
+                                    g: 0.03,
+                                    b: 0.03,
+                                    a: 1.0,
+                                }),
+                                store: wgpu::StoreOp::Store,
+                            },
+                        },
+                    )],
+                    depth_stencil_attachment: Some(
+                        wgpu::RenderPassDepthStencilAttachment {
+                            view: &probe.depth.view,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: wgpu::StoreOp::Discard,
+                            }),
+                            stencil_ops: None,
+                        },
+                    ),
+                    ..Default::default()
+                });
+            rpass.set_bind_group(0, &globals.bind_group, &[]);
+            draw_calls += draw(&mut rpass);
+        }
+
+        for (levels, bind_groups) in
+            probe.faces.iter().zip(&probe.mip_bind_groups)
+        {
+            for level in 1..probe.levels as usize {
+                let mut rpass =
+                    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("reflection_probe_mip_pass"),
+                        color_attachments: &[Some(
+                            wgpu::RenderPassColorAttachment {
+                                view: &levels[level],
+                                resolve_target: None,
+                                ops: wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(
+                                        wgpu::Color::BLACK,
+                                    ),
+                                    store: wgpu::StoreOp::Store,
+                                },
+                            },
+                        )],
+                        ..Default::default()
+                    });
+                rpass.set_pipeline(mip_pipeline);
+                rpass.set_bind_group(0, &bind_groups[level - 1], &[]);
+                rpass.draw(0..3, 0..1);
+            }
+        }
+        draw_calls
+    }
+}
+
+/// Probe bound for the lighting, no mip levels without one.
+#[derive(Default, ShaderType)]
+struct Uniform {
+    pos: Vec3,
+    levels: f32,
+}
+
+impl Uniform {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
+
+fn create_cube(
+    device: &wgpu::Device,
+    resolution: u32,
+    levels: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("reflection_probe_texture"),
+        size: wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: levels,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn create_layout(
+    device: &wgpu::Device,
+    layouts: &Layouts,
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("probe_mip_layout"),
+        bind_group_layouts: &[&layouts.probes.layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("probe_mip_pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Level of a face sampled for the next one.
+pub struct Layout {
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Layout {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("probe_mip_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float {
+                                filterable: true,
+                            },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering,
+                        ),
+                        count: None,
+                    },
+                ],
+            });
+        Self { layout }
+    }
+
+    pub fn bind(
+        &self,
+        device: &wgpu::Device,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("probe_mip_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+}
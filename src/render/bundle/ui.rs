@@ -0,0 +1,74 @@
+use crate::ui::Ui;
+
+/// Overlay pass drawing the ui on top of the surface.
+pub struct Bundle {
+    renderer: egui_wgpu::Renderer,
+}
+
+impl Bundle {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        Self {
+            renderer: egui_wgpu::Renderer::new(
+                device,
+                config.format,
+                None,
+                1,
+                false,
+            ),
+        }
+    }
+
+    /// Returns the command buffers to submit before the encoder.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        ui: &mut Ui,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let Some(output) = ui.take_output() else {
+            return Vec::new();
+        };
+        let pixels_per_point = output.pixels_per_point;
+        let jobs = ui.ctx.tessellate(output.shapes, pixels_per_point);
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [config.width, config.height],
+            pixels_per_point,
+        };
+
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let commands = self
+            .renderer
+            .update_buffers(device, queue, encoder, &jobs, &screen);
+        {
+            let mut rpass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("ui_pass"),
+                    color_attachments: &[Some(
+                        wgpu::RenderPassColorAttachment {
+                            view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Load,
+                                store: wgpu::StoreOp::Store,
+                            },
+                        },
+                    )],
+                    ..Default::default()
+                })
+                .forget_lifetime();
+            self.renderer.render(&mut rpass, &jobs, &screen);
+        }
+        for id in &output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+        commands
+    }
+}
@@ -0,0 +1,101 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+
+use crate::bounds::Aabb;
+
+/// Triangles per leaf at most.
+const LEAF_SIZE: usize = 4;
+
+/// Internal nodes have their two children at `first` and `first + 1`,
+/// leaves have `count` triangles from `first`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Node {
+    min: [f32; 3],
+    first: u32,
+    max: [f32; 3],
+    count: u32,
+}
+
+/// World space corners, the albedo is stored in their w.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Triangle {
+    corners: [[f32; 4]; 3],
+}
+
+impl Triangle {
+    pub fn new(corners: [Vec3; 3], albedo: Vec3) -> Self {
+        Self {
+            corners: [0, 1, 2].map(|i| corners[i].extend(albedo[i]).into()),
+        }
+    }
+
+    fn corner(&self, i: usize) -> Vec3 {
+        Vec3::from_slice(&self.corners[i][..3])
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.corner(0) + self.corner(1) + self.corner(2)) / 3.0
+    }
+}
+
+/// Bounding volume hierarchy built on the cpu, split at the median
+/// centroid along the longest axis.
+pub struct Bvh {
+    pub nodes: Vec<Node>,
+    pub triangles: Vec<Triangle>,
+}
+
+impl Bvh {
+    pub fn build(triangles: Vec<Triangle>) -> Self {
+        let mut bvh = Self {
+            nodes: vec![Node::zeroed()],
+            triangles,
+        };
+        if bvh.triangles.is_empty() {
+            // Inverted bounds, rays never enter the root.
+            bvh.nodes[0].min = [f32::MAX; 3];
+            bvh.nodes[0].max = [-f32::MAX; 3];
+            return bvh;
+        }
+        bvh.split(0, 0, bvh.triangles.len());
+        bvh
+    }
+
+    fn split(&mut self, node: usize, first: usize, count: usize) {
+        let triangles = &mut self.triangles[first..first + count];
+        let aabb = Aabb::from_points(
+            triangles
+                .iter()
+                .flat_map(|t| [t.corner(0), t.corner(1), t.corner(2)]),
+        );
+        self.nodes[node].min = aabb.min.into();
+        self.nodes[node].max = aabb.max.into();
+        if count <= LEAF_SIZE {
+            self.nodes[node].first = first as u32;
+            self.nodes[node].count = count as u32;
+            return;
+        }
+
+        let centroids =
+            Aabb::from_points(triangles.iter().map(|t| t.centroid()));
+        let extents = centroids.extents();
+        let axis = match extents.max_element() {
+            longest if longest == extents.x => 0,
+            longest if longest == extents.y => 1,
+            _ => 2,
+        };
+        let half = count / 2;
+        triangles.select_nth_unstable_by(half, |a, b| {
+            a.centroid()[axis].total_cmp(&b.centroid()[axis])
+        });
+
+        let children = self.nodes.len();
+        self.nodes.extend([Node::zeroed(); 2]);
+        self.nodes[node].first = children as u32;
+        self.nodes[node].count = 0;
+        self.split(children, first, half);
+        self.split(children + 1, first + half, count - half);
+    }
+}
@@ -1,10 +1,17 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 
-use crate::transform::Transform;
+use crate::{
+    bounds::Frustum, camera_controller::CameraController,
+    camera_path::PathState, transform::Transform,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
+    /// Moves the transform each frame, the scripts can still override it.
+    pub controller: Option<CameraController>,
     pub fovy: f32,
+    /// Path being recorded or played, playing replaces the controller.
+    pub path: Option<PathState>,
     pub transform: Transform,
     pub zfar: f32,
     pub znear: f32,
@@ -13,7 +20,9 @@ pub struct Camera {
 impl Camera {
     pub fn new() -> Self {
         Self {
+            controller: None,
             fovy: 45.0,
+            path: None,
             transform: Transform::from_pos(Vec3::new(0.0, 0.0, 2.0)),
             znear: 0.1,
             zfar: 100.0,
@@ -32,4 +41,19 @@ impl Camera {
     pub fn build_view(&self) -> Mat4 {
         self.transform.build_matrix().inverse()
     }
+
+    /// World ray going through a point in normalized device coordinates.
+    pub fn ray(&self, ndc: Vec2, aspect_ratio: f32) -> (Vec3, Vec3) {
+        let world_clip =
+            (self.build_projection(aspect_ratio) * self.build_view()).inverse();
+        let near = world_clip.project_point3(ndc.extend(0.0));
+        let far = world_clip.project_point3(ndc.extend(1.0));
+        (near, (far - near).normalize())
+    }
+
+    pub fn build_frustum(&self, aspect_ratio: f32) -> Frustum {
+        Frustum::from_matrix(
+            &(self.build_projection(aspect_ratio) * self.build_view()),
+        )
+    }
 }
@@ -0,0 +1,163 @@
+use glam::{Mat4, UVec3, Vec3};
+
+use super::{bundle::lights::PointLight, camera::Camera};
+
+/// Screen tiles across and down, then depth slices.
+pub const GRID: UVec3 = UVec3::new(16, 9, 24);
+
+/// Lights of the main camera sorted into screen tiles and logarithmic depth
+/// slices, so a fragment only goes through the lights reaching its cell.
+pub struct Clusters {
+    /// Offset and count into the indices, per cell.
+    pub ranges: Vec<[u32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl Clusters {
+    pub fn build(
+        lights: &[PointLight],
+        camera: &Camera,
+        aspect_ratio: f32,
+    ) -> Self {
+        let grid = Grid::new(camera, aspect_ratio);
+        let view = camera.build_view();
+        let mut cells = vec![Vec::new(); (GRID.x * GRID.y * GRID.z) as usize];
+        for (index, light) in lights.iter().enumerate() {
+            grid.insert(&mut cells, index as u32, light, view);
+        }
+        let mut ranges = Vec::with_capacity(cells.len());
+        let mut indices = Vec::new();
+        for cell in cells {
+            ranges.push([indices.len() as u32, cell.len() as u32]);
+            indices.extend(cell);
+        }
+        Self { ranges, indices }
+    }
+}
+
+/// Slices of the main camera frustum, in view space.
+pub struct Grid {
+    pub far: f32,
+    pub near: f32,
+    /// Half extents of the frustum at a distance of one.
+    tan: (f32, f32),
+}
+
+impl Grid {
+    pub fn new(camera: &Camera, aspect_ratio: f32) -> Self {
+        let tan_y = (camera.fovy.to_radians() * 0.5).tan();
+        Self {
+            far: camera.zfar,
+            near: camera.znear,
+            tan: (tan_y * aspect_ratio, tan_y),
+        }
+    }
+
+    /// Slices per unit of the log of the distance, over the near plane.
+    pub fn slice_scale(&self) -> f32 {
+        GRID.z as f32 / (self.far / self.near).ln()
+    }
+
+    fn slice(&self, distance: f32) -> u32 {
+        let slice = (distance / self.near).ln() * self.slice_scale();
+        (slice.max(0.0) as u32).min(GRID.z - 1)
+    }
+
+    fn slice_distances(&self, slice: u32) -> (f32, f32) {
+        let ratio = self.far / self.near;
+        let at =
+            |slice: u32| self.near * ratio.powf(slice as f32 / GRID.z as f32);
+        (at(slice), at(slice + 1))
+    }
+
+    /// Push the light into the cells its sphere touches, going through the
+    /// tiles covered by its bounds in each slice.
+    fn insert(
+        &self,
+        cells: &mut [Vec<u32>],
+        index: u32,
+        light: &PointLight,
+        view: Mat4,
+    ) {
+        if light.radius <= 0.0 {
+            return;
+        }
+        let center = view.transform_point3(light.pos);
+        let radius = light.radius;
+        let nearest = (-center.z - radius).max(self.near);
+        let farthest = (-center.z + radius).min(self.far);
+        if nearest > farthest {
+            return;
+        }
+        for slice in self.slice(nearest)..=self.slice(farthest) {
+            let (start, end) = self.slice_distances(slice);
+            let depths = (start.max(nearest), end.min(farthest));
+            let columns =
+                tiles(center.x, radius, depths, self.tan.0, GRID.x, false);
+            let rows =
+                tiles(center.y, radius, depths, self.tan.1, GRID.y, true);
+            for row in rows {
+                for column in columns.clone() {
+                    let (min, max) = self.cell_bounds(column, row, slice);
+                    let closest = center.clamp(min, max);
+                    if closest.distance_squared(center) > radius * radius {
+                        continue;
+                    }
+                    let cell = column + row * GRID.x + slice * GRID.x * GRID.y;
+                    cells[cell as usize].push(index);
+                }
+            }
+        }
+    }
+
+    /// View space box of a cell, the camera looking down negative z.
+    fn cell_bounds(&self, column: u32, row: u32, slice: u32) -> (Vec3, Vec3) {
+        let (start, end) = self.slice_distances(slice);
+        let ndc =
+            |tile: u32, count: u32| tile as f32 / count as f32 * 2.0 - 1.0;
+        let x = (ndc(column, GRID.x), ndc(column + 1, GRID.x));
+        // Rows go down the screen.
+        let y = (-ndc(row + 1, GRID.y), -ndc(row, GRID.y));
+        let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        for distance in [start, end] {
+            for (ndc_x, ndc_y) in [(x.0, y.0), (x.1, y.1)] {
+                let point = Vec3::new(
+                    ndc_x * distance * self.tan.0,
+                    ndc_y * distance * self.tan.1,
+                    -distance,
+                );
+                min = min.min(point);
+                max = max.max(point);
+            }
+        }
+        (min, max)
+    }
+}
+
+/// Tiles along an axis covered by the interval around the center, between
+/// the two distances.
+fn tiles(
+    center: f32,
+    radius: f32,
+    (start, end): (f32, f32),
+    tan: f32,
+    count: u32,
+    flipped: bool,
+) -> std::ops::RangeInclusive<u32> {
+    let (mut low, mut high) = (f32::MAX, f32::MIN);
+    for value in [center - radius, center + radius] {
+        for distance in [start, end] {
+            let ndc = value / (distance * tan);
+            low = low.min(ndc);
+            high = high.max(ndc);
+        }
+    }
+    if flipped {
+        (low, high) = (-high, -low);
+    }
+    let tile = |ndc: f32| {
+        let tile = ((ndc + 1.0) * 0.5 * count as f32).floor();
+        tile.clamp(0.0, (count - 1) as f32) as u32
+    };
+    tile(low)..=tile(high)
+}
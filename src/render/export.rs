@@ -0,0 +1,209 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use log::{error, info};
+
+use crate::app::get_pool;
+
+use super::{buffers::ReadbackQueue, screenshot::Readback};
+
+/// Frames of an export, written as they are read back.
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub dir: PathBuf,
+    pub duration: f32,
+    pub fps: f32,
+}
+
+struct Export {
+    /// Frames copied so far, the next one is numbered after them.
+    captured: u32,
+    frames: u32,
+    /// Last time a frame was read back, or the export started.
+    progress: Instant,
+    settings: ExportSettings,
+    /// Frames written or failed on the thread pool.
+    written: Arc<AtomicU32>,
+}
+
+/// Every frame rendered for some seconds written as numbered pngs, then
+/// encoded into a video when ffmpeg is installed. The scripts see a fixed
+/// frame time meanwhile, however long the frames take.
+pub struct FrameExporter {
+    export: Option<Export>,
+    /// Frames by index.
+    readbacks: ReadbackQueue<(u32, Readback)>,
+}
+
+impl FrameExporter {
+    /// Without a frame read back for that long the device is assumed lost
+    /// and the export fails.
+    const READBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new() -> Self {
+        Self {
+            export: None,
+            readbacks: ReadbackQueue::new(),
+        }
+    }
+
+    /// An error while the last export is still being written.
+    pub fn start(&mut self, settings: ExportSettings) -> Result<()> {
+        if self.export.is_some() {
+            bail!("frames are already being exported");
+        }
+        fs::create_dir_all(&settings.dir)?;
+        // Left by an export that failed, their indices would clash.
+        self.readbacks.clear();
+        let frames = (settings.duration * settings.fps).ceil().max(1.0) as u32;
+        info!(
+            "Exporting {} frames at {} fps into {}",
+            frames,
+            settings.fps,
+            settings.dir.display()
+        );
+        self.export = Some(Export {
+            captured: 0,
+            frames,
+            progress: Instant::now(),
+            settings,
+            written: Arc::new(AtomicU32::new(0)),
+        });
+        Ok(())
+    }
+
+    pub fn is_exporting(&self) -> bool {
+        self.export.is_some()
+    }
+
+    /// Frame time of the scripts while frames are left to capture.
+    pub fn frame_dt(&self) -> Option<f32> {
+        self.export
+            .as_ref()
+            .filter(|export| export.captured < export.frames)
+            .map(|export| 1.0 / export.settings.fps)
+    }
+
+    pub fn frame_due(&self) -> bool {
+        self.frame_dt().is_some()
+    }
+
+    /// Copy a frame rendered into a texture of the surface format.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        let Some(export) = &mut self.export else {
+            return;
+        };
+        match Readback::copy(device, encoder, texture) {
+            Ok(readback) => {
+                self.readbacks.push((export.captured, readback));
+                export.captured += 1;
+            }
+            Err(err) => {
+                error!("frame export\n{:?}", err);
+                self.export = None;
+            }
+        }
+    }
+
+    pub fn map_recorded(&mut self) {
+        // Not waiting on anything until now.
+        if let Some(export) = &mut self.export {
+            if !self.readbacks.is_mapping() && self.readbacks.has_recorded() {
+                export.progress = Instant::now();
+            }
+        }
+        self.readbacks.map_recorded();
+    }
+
+    /// Write the frames mapped since the last call on the thread pool, the
+    /// video is encoded once every frame is written.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let Some(export) = &mut self.export else {
+            return;
+        };
+        for ((index, readback), result) in self.readbacks.take_mapped(device) {
+            export.progress = Instant::now();
+            let path = frame_path(&export.settings.dir, index);
+            let written = export.written.clone();
+            get_pool().execute(move || {
+                let result = result
+                    .map_err(anyhow::Error::from)
+                    .and_then(|()| Ok(readback.read().save(&path)?));
+                if let Err(err) = result {
+                    error!("frame export {}\n{:?}", path.display(), err);
+                }
+                written.fetch_add(1, Ordering::Release);
+            });
+        }
+
+        if self.readbacks.is_mapping()
+            && export.progress.elapsed() > Self::READBACK_TIMEOUT
+        {
+            error!(
+                "frame export {}\nno frame read back for {:?}, stopped",
+                export.settings.dir.display(),
+                Self::READBACK_TIMEOUT
+            );
+            self.readbacks.clear();
+            self.export = None;
+            return;
+        }
+        let done = export.captured == export.frames
+            && export.written.load(Ordering::Acquire) == export.frames;
+        if !done {
+            return;
+        }
+        let settings = self.export.take().unwrap().settings;
+        info!("Frames exported into {}", settings.dir.display());
+        get_pool().execute(move || encode_video(&settings));
+    }
+}
+
+fn frame_path(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("frame_{:05}.png", index))
+}
+
+/// Skipped without ffmpeg in the path, the pngs are kept either way.
+fn encode_video(settings: &ExportSettings) {
+    if Command::new("ffmpeg").arg("-version").output().is_err() {
+        info!("ffmpeg not found, only the frames are exported");
+        return;
+    }
+    let video = settings.dir.join("video.mp4");
+    let output = Command::new("ffmpeg")
+        .arg("-y")
+        .args(["-loglevel", "error"])
+        .args(["-framerate", &settings.fps.to_string()])
+        .arg("-i")
+        .arg(settings.dir.join("frame_%05d.png"))
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        // Even dimensions for yuv420p.
+        .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+        .arg(&video)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            info!("Video written to {}", video.display())
+        }
+        Ok(output) => error!(
+            "ffmpeg {}\n{}",
+            video.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(err) => error!("ffmpeg {}\n{:?}", video.display(), err),
+    }
+}
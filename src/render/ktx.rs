@@ -0,0 +1,214 @@
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+use assets_manager::{loader, Asset};
+use image::{imageops, imageops::FilterType, DynamicImage, GenericImageView};
+use ktx2::{Format, Reader, SupercompressionScheme};
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+pub struct Ktx2Source(pub Vec<u8>);
+
+impl From<Vec<u8>> for Ktx2Source {
+    fn from(value: Vec<u8>) -> Self {
+        Ktx2Source(value)
+    }
+}
+
+impl Asset for Ktx2Source {
+    const EXTENSION: &'static str = "ktx2";
+    type Loader = loader::LoadFrom<Vec<u8>, loader::BytesLoader>;
+}
+
+/// Texture variants tried before the generic one, `<id>-<suffix>`, when
+/// the adapter supports their formats.
+pub const VARIANTS: &[(&str, wgpu::Features)] = &[
+    ("bc", wgpu::Features::TEXTURE_COMPRESSION_BC),
+    ("astc", wgpu::Features::TEXTURE_COMPRESSION_ASTC),
+    ("etc2", wgpu::Features::TEXTURE_COMPRESSION_ETC2),
+];
+
+/// Block compressed levels uploaded as is, largest first.
+pub struct CompressedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Fails when the adapter can not sample the format, so the next
+    /// variant can be tried.
+    pub fn decode(data: &[u8], features: wgpu::Features) -> Result<Self> {
+        let reader = Reader::new(data).context("invalid ktx2")?;
+        let header = reader.header();
+        if header.pixel_depth > 1
+            || header.layer_count > 1
+            || header.face_count > 1
+        {
+            bail!("only 2d textures are supported");
+        }
+        let Some(format) = header.format else {
+            bail!("basis universal textures must be transcoded offline");
+        };
+        let format = texture_format(format)
+            .with_context(|| format!("unsupported format {:?}", format))?;
+        if !features.contains(format.required_features()) {
+            bail!("{:?} is not supported by the adapter", format);
+        }
+
+        let levels = reader
+            .levels()
+            .map(|level| match header.supercompression_scheme {
+                None => Ok(level.to_vec()),
+                Some(SupercompressionScheme::Zstandard) => {
+                    let mut decoded = Vec::new();
+                    ruzstd::StreamingDecoder::new(level)?
+                        .read_to_end(&mut decoded)?;
+                    Ok(decoded)
+                }
+                Some(scheme) => {
+                    bail!("unsupported supercompression {:?}", scheme)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            format,
+            width: header.pixel_width,
+            height: header.pixel_height,
+            levels,
+        })
+    }
+}
+
+fn texture_format(format: Format) -> Option<TextureFormat> {
+    let astc = |block, srgb| TextureFormat::Astc {
+        block,
+        channel: if srgb {
+            AstcChannel::UnormSrgb
+        } else {
+            AstcChannel::Unorm
+        },
+    };
+    let format = match format {
+        Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        Format::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        Format::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        Format::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        Format::ASTC_4x4_UNORM_BLOCK => astc(AstcBlock::B4x4, false),
+        Format::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4, true),
+        Format::ASTC_6x6_UNORM_BLOCK => astc(AstcBlock::B6x6, false),
+        Format::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6, true),
+        Format::ASTC_8x8_UNORM_BLOCK => astc(AstcBlock::B8x8, false),
+        Format::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8, true),
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Rgba8 srgb ktx2 with the whole mip chain, for the imported textures.
+pub fn encode_mipmapped(image: &DynamicImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut levels = vec![image.to_rgba8()];
+    let (mut level_width, mut level_height) = (width, height);
+    while level_width > 1 || level_height > 1 {
+        level_width = (level_width / 2).max(1);
+        level_height = (level_height / 2).max(1);
+        levels.push(imageops::resize(
+            levels.last().unwrap(),
+            level_width,
+            level_height,
+            FilterType::Triangle,
+        ));
+    }
+
+    const HEADER_SIZE: usize = 80;
+    const LEVEL_INDEX_SIZE: usize = 24;
+    let dfd = rgba8_srgb_dfd();
+    let dfd_offset = HEADER_SIZE + LEVEL_INDEX_SIZE * levels.len();
+    let mut data_offset = dfd_offset + dfd.len();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&IDENTIFIER);
+    for value in [
+        VK_FORMAT_R8G8B8A8_SRGB,
+        1, // type size
+        width,
+        height,
+        0, // depth
+        0, // layers
+        1, // faces
+        levels.len() as u32,
+        0, // supercompression
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0, // key values offset
+        0, // key values length
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // Supercompression global data offset and length.
+    out.extend_from_slice(&[0; 16]);
+
+    // The index lists the largest level first, the data stores it last.
+    let mut offsets = vec![0; levels.len()];
+    for (i, level) in levels.iter().enumerate().rev() {
+        offsets[i] = data_offset;
+        data_offset += level.len();
+    }
+    for (level, offset) in levels.iter().zip(&offsets) {
+        let length = level.len() as u64;
+        for value in [*offset as u64, length, length] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&dfd);
+    for level in levels.iter().rev() {
+        out.extend_from_slice(level);
+    }
+    out
+}
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+/// Basic data format descriptor of four 8 bits srgb channels, alpha linear.
+fn rgba8_srgb_dfd() -> Vec<u8> {
+    const BLOCK_SIZE: u16 = 24 + 16 * 4;
+    let mut dfd = Vec::new();
+    dfd.extend_from_slice(&(4 + BLOCK_SIZE as u32).to_le_bytes());
+    // Khronos vendor, basic descriptor type.
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    dfd.extend_from_slice(&2u16.to_le_bytes());
+    dfd.extend_from_slice(&BLOCK_SIZE.to_le_bytes());
+    // Rgbsda color model, bt709 primaries, srgb transfer, straight alpha.
+    dfd.extend_from_slice(&[1, 1, 2, 0]);
+    // Texel block of 1x1x1x1 and 4 bytes in the first plane.
+    dfd.extend_from_slice(&[0; 4]);
+    dfd.extend_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]);
+    for (channel, id) in [0u8, 1, 2, 15].into_iter().enumerate() {
+        // The alpha is not srgb encoded.
+        let linear = if id == 15 { 0x10 } else { 0 };
+        dfd.extend_from_slice(&(channel as u16 * 8).to_le_bytes());
+        dfd.extend_from_slice(&[7, id | linear]);
+        dfd.extend_from_slice(&[0; 4]);
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&255u32.to_le_bytes());
+    }
+    dfd
+}
@@ -0,0 +1,139 @@
+use anyhow::{bail, Result};
+use bytemuck::cast_slice;
+
+use super::{Material, Param};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    F32,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl FieldType {
+    /// The wgsl spellings, `vec3<f32>` and `vec3f` are the same type.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "f32" => Some(Self::F32),
+            "vec2" | "vec2f" | "vec2<f32>" => Some(Self::Vec2),
+            "vec3" | "vec3f" | "vec3<f32>" => Some(Self::Vec3),
+            "vec4" | "vec4f" | "vec4<f32>" => Some(Self::Vec4),
+            _ => None,
+        }
+    }
+
+    /// Alignment and size in a uniform buffer.
+    fn layout(self) -> (usize, usize) {
+        match self {
+            Self::F32 => (4, 4),
+            Self::Vec2 => (8, 8),
+            Self::Vec3 => (16, 12),
+            Self::Vec4 => (16, 16),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    offset: usize,
+    ty: FieldType,
+}
+
+/// Uniform struct declared at runtime as `name: type` fields, laid out
+/// like wgsl does so the shader declares the same struct.
+#[derive(Debug)]
+pub struct UniformBlock {
+    data: Vec<u8>,
+    fields: Vec<Field>,
+}
+
+impl UniformBlock {
+    /// Fields start at zero.
+    pub fn parse(declarations: &[String]) -> Result<Self> {
+        let mut fields = Vec::<Field>::new();
+        let mut size: usize = 0;
+        for declaration in declarations {
+            let Some((name, ty)) = declaration.split_once(':') else {
+                bail!("uniform field {} is not name: type", declaration);
+            };
+            let (name, ty) = (name.trim(), ty.trim());
+            let Some(ty) = FieldType::parse(ty) else {
+                bail!("uniform field {} has unknown type {}", name, ty);
+            };
+            if fields.iter().any(|field| field.name == name) {
+                bail!("uniform field {} is declared twice", name);
+            }
+            let (align, field_size) = ty.layout();
+            let offset = size.next_multiple_of(align);
+            size = offset + field_size;
+            fields.push(Field {
+                name: name.to_string(),
+                offset,
+                ty,
+            });
+        }
+        if fields.is_empty() {
+            bail!("uniform block has no field");
+        }
+        Ok(Self {
+            data: vec![0; size.next_multiple_of(16)],
+            fields,
+        })
+    }
+
+    /// Returns false when the block has no such field of that type.
+    pub fn set(&mut self, name: &str, value: Param) -> bool {
+        let Some(field) = self.fields.iter().find(|f| f.name == name) else {
+            return false;
+        };
+        let values = match (field.ty, value) {
+            (FieldType::F32, Param::Float(v)) => vec![v],
+            (FieldType::Vec2, Param::Vec2(v)) => v.to_array().to_vec(),
+            (FieldType::Vec3, Param::Vec3(v)) => v.to_array().to_vec(),
+            (FieldType::Vec4, Param::Vec4(v)) => v.to_array().to_vec(),
+            _ => return false,
+        };
+        let bytes = cast_slice::<f32, u8>(&values);
+        self.data[field.offset..field.offset + bytes.len()]
+            .copy_from_slice(bytes);
+        true
+    }
+}
+
+/// Material of a shader declaring its own uniform struct, without a rust
+/// type of its own.
+pub struct CustomMaterial {
+    pub block: UniformBlock,
+    shader_id: String,
+    texture_id: String,
+}
+
+impl CustomMaterial {
+    pub fn new(shader_id: &str, texture_id: &str, block: UniformBlock) -> Self {
+        Self {
+            block,
+            shader_id: shader_id.to_string(),
+            texture_id: texture_id.to_string(),
+        }
+    }
+}
+
+impl Material for CustomMaterial {
+    fn shader_id(&self) -> String {
+        self.shader_id.clone()
+    }
+
+    fn texture_id(&self) -> String {
+        self.texture_id.clone()
+    }
+
+    fn uniform_bytes(&self) -> Vec<u8> {
+        self.block.data.clone()
+    }
+
+    fn set_param(&mut self, name: &str, value: Param) -> bool {
+        self.block.set(name, value)
+    }
+}
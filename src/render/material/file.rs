@@ -0,0 +1,158 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Instant,
+};
+
+use assets_manager::{loader, Asset};
+use glam::{Vec2, Vec3, Vec4};
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::{
+    app::RELOAD_DEBOUNCE,
+    assets::AssetCaches,
+    render::bundle::model::{DEFAULT_SHADER, DEFAULT_TEXTURE},
+};
+
+use super::Param;
+
+pub struct MaterialSource(String);
+
+impl From<String> for MaterialSource {
+    fn from(value: String) -> Self {
+        MaterialSource(value)
+    }
+}
+
+impl Asset for MaterialSource {
+    const EXTENSION: &'static str = "ron";
+    type Loader = loader::LoadFrom<String, loader::StringLoader>;
+}
+
+fn default_shader() -> String {
+    DEFAULT_SHADER.to_string()
+}
+
+fn default_texture() -> String {
+    DEFAULT_TEXTURE.to_string()
+}
+
+/// Number or array of two to four numbers of a uniform field.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum UniformValue {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl UniformValue {
+    /// None when the values are of different types.
+    pub fn lerp(self, other: Self, t: f32) -> Option<Self> {
+        match (self, other) {
+            (Self::Float(a), Self::Float(b)) => {
+                Some(Self::Float(a + (b - a) * t))
+            }
+            (Self::Vec2(a), Self::Vec2(b)) => Some(Self::Vec2(
+                Vec2::from_array(a).lerp(Vec2::from_array(b), t).to_array(),
+            )),
+            (Self::Vec3(a), Self::Vec3(b)) => Some(Self::Vec3(
+                Vec3::from_array(a).lerp(Vec3::from_array(b), t).to_array(),
+            )),
+            (Self::Vec4(a), Self::Vec4(b)) => Some(Self::Vec4(
+                Vec4::from_array(a).lerp(Vec4::from_array(b), t).to_array(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl From<UniformValue> for Param {
+    fn from(value: UniformValue) -> Self {
+        match value {
+            UniformValue::Float(v) => Param::Float(v),
+            UniformValue::Vec2(v) => Param::Vec2(Vec2::from_array(v)),
+            UniformValue::Vec3(v) => Param::Vec3(Vec3::from_array(v)),
+            UniformValue::Vec4(v) => Param::Vec4(Vec4::from_array(v)),
+        }
+    }
+}
+
+/// Material declared in `assets/materials/<key>.ron`, uniform fields are
+/// numbers or arrays by name. With a block the shader declares its own
+/// uniform struct of these `name: type` fields, the simple one otherwise.
+#[derive(Debug, Deserialize)]
+pub struct MaterialDescriptor {
+    #[serde(default)]
+    pub block: Vec<String>,
+    /// Values of the `override` constants of the shader.
+    #[serde(default)]
+    pub constants: BTreeMap<String, f64>,
+    #[serde(default = "default_shader")]
+    pub shader_id: String,
+    #[serde(default = "default_texture")]
+    pub texture_id: String,
+    #[serde(default)]
+    uniforms: HashMap<String, UniformValue>,
+}
+
+impl MaterialDescriptor {
+    pub fn params(&self) -> impl Iterator<Item = (&str, Param)> {
+        self.uniforms
+            .iter()
+            .map(|(name, value)| (name.as_str(), Param::from(*value)))
+    }
+}
+
+/// Material files of the assets, every file of the directory is loaded
+/// and reloaded when it changes.
+pub struct MaterialFiles {
+    caches: AssetCaches,
+    last_reload: Instant,
+    /// Keys of the files read at least once.
+    loaded: HashSet<String>,
+}
+
+impl MaterialFiles {
+    pub fn new() -> Self {
+        Self {
+            caches: AssetCaches::new("materials"),
+            last_reload: Instant::now(),
+            loaded: HashSet::new(),
+        }
+    }
+
+    /// Descriptors of the new and changed files by key, the ones failing
+    /// to parse are logged and left as they were.
+    pub fn hot_reload(&mut self) -> Vec<(String, MaterialDescriptor)> {
+        self.caches.hot_reload();
+        let (cache, _) = self.caches.resolve("");
+        let Ok(dir) = cache.load_dir::<MaterialSource>("") else {
+            return Vec::new();
+        };
+        let debounced = self.last_reload.elapsed() >= RELOAD_DEBOUNCE;
+        let mut descriptors = Vec::new();
+        for id in dir.read().ids() {
+            let Ok(handle) = cache.load::<MaterialSource>(id) else {
+                continue;
+            };
+            let reloaded = debounced && handle.reloaded_global();
+            if self.loaded.contains(&**id) && !reloaded {
+                continue;
+            }
+            if reloaded {
+                self.last_reload = Instant::now();
+            }
+            self.loaded.insert(id.to_string());
+            match ron::from_str::<MaterialDescriptor>(&handle.read().0) {
+                Ok(descriptor) => {
+                    info!("Material loaded: {}", id);
+                    descriptors.push((id.to_string(), descriptor));
+                }
+                Err(err) => error!("material {}\n{}", id, err),
+            }
+        }
+        descriptors
+    }
+}
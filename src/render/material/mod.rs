@@ -1,26 +1,55 @@
-use std::{any::Any, collections::HashMap};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+};
 
+use anyhow::{bail, Result};
 use encase::{internal::WriteInto, ShaderType};
+use glam::{Vec2, Vec3, Vec4};
 
+pub mod custom;
+pub mod file;
 pub mod simple;
 
-pub trait Material: 'static {
-    type Uniform: ShaderType + WriteInto;
+/// Value of a uniform field set by name.
+#[derive(Debug, Clone, Copy)]
+pub enum Param {
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+}
 
+pub trait Material: 'static {
     fn shader_id(&self) -> String;
     fn texture_id(&self) -> String;
-    fn uniform_data(&self) -> &Self::Uniform;
+    /// Uniform as laid out in the shader.
+    fn uniform_bytes(&self) -> Vec<u8>;
+
+    /// Returns false when the uniform has no such field of that type.
+    fn set_param(&mut self, _name: &str, _value: Param) -> bool {
+        false
+    }
+}
+
+/// Bytes of a uniform struct declared in rust.
+pub fn encode_uniform<U: ShaderType + WriteInto>(uniform: &U) -> Vec<u8> {
+    let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
+    buffer.write(uniform).unwrap();
+    buffer.into_inner()
 }
 
 type GetShaderId = Box<dyn Fn(&Box<dyn Any>) -> String>;
 type GetTextureId = Box<dyn Fn(&Box<dyn Any>) -> String>;
 type GetUniformDataBytes = Box<dyn Fn(&Box<dyn Any>) -> Vec<u8>>;
+type SetParam = Box<dyn Fn(&mut Box<dyn Any>, &str, Param) -> bool>;
 
 pub struct InternalMaterial {
     get_shader_id: GetShaderId,
     get_texture_id: GetTextureId,
     get_uniform_data_bytes: GetUniformDataBytes,
     material: Box<dyn Any>,
+    set_param: SetParam,
 }
 
 impl InternalMaterial {
@@ -35,33 +64,78 @@ impl InternalMaterial {
         });
         let get_uniform_data_bytes: GetUniformDataBytes = Box::new(|any| {
             let material = any.downcast_ref::<M>().unwrap();
-            let mut buffer = encase::UniformBuffer::new(Vec::<u8>::new());
-            buffer.write(&material.uniform_data()).unwrap();
-            buffer.into_inner()
+            material.uniform_bytes()
+        });
+        let set_param: SetParam = Box::new(|any, name, value| {
+            let material = any.downcast_mut::<M>().unwrap();
+            material.set_param(name, value)
         });
         Self {
             get_shader_id,
             get_texture_id,
             get_uniform_data_bytes,
             material: Box::new(material),
+            set_param,
         }
     }
 }
 
 pub struct MaterialManager {
+    /// Materials whose uniform changed since the last upload.
+    dirty: HashSet<String>,
     materials: HashMap<String, InternalMaterial>,
+    /// A material got replaced since last taken, its texture may differ.
+    replaced: bool,
 }
 
 impl MaterialManager {
     pub fn new() -> Self {
         Self {
+            dirty: HashSet::new(),
             materials: HashMap::new(),
+            replaced: false,
         }
     }
 
     pub fn add<M: Material>(&mut self, key: &str, material: M) {
-        self.materials
+        let previous = self
+            .materials
             .insert(key.to_string(), InternalMaterial::new(material));
+        self.replaced |= previous.is_some();
+        self.dirty.insert(key.to_string());
+    }
+
+    pub fn take_replaced(&mut self) -> bool {
+        std::mem::take(&mut self.replaced)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.materials.contains_key(key)
+    }
+
+    pub fn is_dirty(&self, key: &str) -> bool {
+        self.dirty.contains(key)
+    }
+
+    /// Called once the uniforms got uploaded.
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    pub fn set_param(
+        &mut self,
+        key: &str,
+        name: &str,
+        value: Param,
+    ) -> Result<()> {
+        let Some(data) = self.materials.get_mut(key) else {
+            bail!("material {} does not exist", key);
+        };
+        if !(data.set_param)(&mut data.material, name, value) {
+            bail!("material {} has no {:?} param {}", key, value, name);
+        }
+        self.dirty.insert(key.to_string());
+        Ok(())
     }
 
     pub fn get_shader_id(&self, key: &str) -> Option<String> {
@@ -86,7 +160,10 @@ impl MaterialManager {
         self.materials.get(key).map(|data| &data.material)
     }
 
+    /// The material gets uploaded again, as it may be modified.
     pub fn get_mut_any(&mut self, key: &str) -> Option<&mut Box<dyn Any>> {
-        self.materials.get_mut(key).map(|data| &mut data.material)
+        let data = self.materials.get_mut(key)?;
+        self.dirty.insert(key.to_string());
+        Some(&mut data.material)
     }
 }
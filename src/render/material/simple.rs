@@ -1,11 +1,16 @@
 use encase::ShaderType;
 use glam::Vec3;
 
-use super::Material;
+use super::{encode_uniform, Material, Param};
 
-#[derive(ShaderType, Debug)]
+#[derive(ShaderType, Debug, Clone, Copy)]
 pub struct Uniform {
+    /// Multiplies the texture, white keeps it as is.
     pub color: Vec3,
+    /// Hue rotation range between instances, in turns.
+    pub hue_variation: f32,
+    /// Brightness range between instances, 0.2 is 20% darker at most.
+    pub brightness_variation: f32,
 }
 
 pub struct SimpleMaterial {
@@ -20,15 +25,15 @@ impl SimpleMaterial {
             shader_id: shader_id.to_string(),
             texture_id: texture_id.to_string(),
             uniform: Uniform {
-                color: Vec3::new(1.0, 0.2, 0.3),
+                color: Vec3::ONE,
+                hue_variation: 0.0,
+                brightness_variation: 0.0,
             },
         }
     }
 }
 
 impl Material for SimpleMaterial {
-    type Uniform = Uniform;
-
     fn shader_id(&self) -> String {
         self.shader_id.clone()
     }
@@ -37,7 +42,21 @@ impl Material for SimpleMaterial {
         self.texture_id.clone()
     }
 
-    fn uniform_data(&self) -> &Self::Uniform {
-        &self.uniform
+    fn uniform_bytes(&self) -> Vec<u8> {
+        encode_uniform(&self.uniform)
+    }
+
+    fn set_param(&mut self, name: &str, value: Param) -> bool {
+        match (name, value) {
+            ("color", Param::Vec3(color)) => self.uniform.color = color,
+            ("hue_variation", Param::Float(v)) => {
+                self.uniform.hue_variation = v
+            }
+            ("brightness_variation", Param::Float(v)) => {
+                self.uniform.brightness_variation = v
+            }
+            _ => return false,
+        }
+        true
     }
 }
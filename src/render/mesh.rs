@@ -1,7 +1,11 @@
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
-    io::Cursor,
-    ops::Deref,
+    fs,
+    io::{BufReader, Cursor},
+    mem::size_of,
+    ops::Range,
+    path::Path,
     sync::{
         mpsc::{channel, Receiver, Sender},
         Arc,
@@ -9,38 +13,103 @@ use std::{
     time::Instant,
 };
 
-use anyhow::Result;
-use assets_manager::{loader, Asset, AssetCache};
+use anyhow::{bail, Result};
+use assets_manager::{loader::Loader, Asset, BoxedError};
 use bytemuck::{cast_slice, Pod};
-use log::{error, info};
+use glam::Vec3;
+use log::{error, info, warn};
+use serde::Deserialize;
 use wgpu::util::DeviceExt;
 
-use crate::app::{get_pool, RELOAD_DEBOUNCE};
+use crate::{
+    app::{get_pool, RELOAD_DEBOUNCE},
+    assets::{self, AssetCaches},
+    bounds::{Aabb, Bounds, Sphere},
+    import,
+};
 
-use super::bundle::model;
+use super::{
+    bundle::model,
+    morph::{MorphTarget, Morphs},
+    primitives::{self, PRIMITIVES},
+    residency::Residency,
+};
 
 pub trait VertexTrait: Pod {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
 }
 
 pub struct Mesh {
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub aabb: Aabb,
+    /// Vertex colors and second uv channel, when the source has them.
+    pub extra_buffer: Option<Arc<wgpu::Buffer>>,
+    pub vertex_buffer: Arc<wgpu::Buffer>,
+    pub index_buffer: Arc<wgpu::Buffer>,
+    /// Start of the drawn indices, a submesh draws a range of the buffers
+    /// of its source mesh.
+    pub first_index: u32,
+    /// Copy of the triangles kept on the cpu, for the path tracer.
+    pub indices: Vec<u32>,
+    /// Vertices blended for the entities with morph weights, when the
+    /// source has morph targets.
+    pub morphs: Option<Arc<Morphs>>,
     pub num_indices: u32,
+    pub positions: Vec<Vec3>,
+    pub sphere: Sphere,
+    /// Mesh and material ids of the triangles by material of the source,
+    /// drawn in place of the mesh batched with the default material.
+    pub submeshes: Vec<String>,
 }
 
 impl Mesh {
-    pub fn new<Vertex: VertexTrait>(
+    pub fn new(
         device: &wgpu::Device,
-        vertices: &[Vertex],
+        vertices: &[model::Vertex],
         indices: &[u32],
+        aabb: Aabb,
         label: &str,
+    ) -> Self {
+        Self::with_usage(
+            device,
+            vertices,
+            indices,
+            aabb,
+            label,
+            wgpu::BufferUsages::VERTEX,
+        )
+    }
+
+    /// Vertices written again with `write_vertices`.
+    pub fn new_dynamic(
+        device: &wgpu::Device,
+        vertices: &[model::Vertex],
+        indices: &[u32],
+        aabb: Aabb,
+        label: &str,
+    ) -> Self {
+        Self::with_usage(
+            device,
+            vertices,
+            indices,
+            aabb,
+            label,
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        )
+    }
+
+    fn with_usage(
+        device: &wgpu::Device,
+        vertices: &[model::Vertex],
+        indices: &[u32],
+        aabb: Aabb,
+        label: &str,
+        usage: wgpu::BufferUsages,
     ) -> Self {
         let vertex_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("mesh_{}_vertex_buffer", label)),
                 contents: cast_slice(vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                usage,
             });
 
         let index_buffer =
@@ -50,57 +119,461 @@ impl Mesh {
                 usage: wgpu::BufferUsages::INDEX,
             });
 
+        let positions = vertices
+            .iter()
+            .map(|v| Vec3::from(v.position))
+            .collect::<Vec<_>>();
+        Self {
+            aabb,
+            extra_buffer: None,
+            vertex_buffer: Arc::new(vertex_buffer),
+            index_buffer: Arc::new(index_buffer),
+            first_index: 0,
+            indices: indices.to_vec(),
+            morphs: None,
+            num_indices: indices.len() as u32,
+            sphere: Sphere::from_points(&positions),
+            positions,
+            submeshes: Vec::new(),
+        }
+    }
+
+    pub fn with_extras(
+        mut self,
+        device: &wgpu::Device,
+        extras: &[model::VertexExtra],
+        label: &str,
+    ) -> Self {
+        self.extra_buffer = Some(Arc::new(device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("mesh_{}_extra_buffer", label)),
+                contents: cast_slice(extras),
+                usage: wgpu::BufferUsages::VERTEX,
+            },
+        )));
+        self
+    }
+
+    /// Triangles of the range of indices, drawn from the buffers of the
+    /// mesh rather than uploaded again. The cpu copy only has the vertices
+    /// they use.
+    fn part(&self, data: &MeshData, indices: &Range<usize>) -> Self {
+        let part = data.part(indices);
+        let positions = part
+            .vertices
+            .iter()
+            .map(|v| Vec3::from(v.position))
+            .collect::<Vec<_>>();
         Self {
-            vertex_buffer,
-            index_buffer,
+            aabb: Aabb::from_points(positions.iter().copied()),
+            extra_buffer: self.extra_buffer.clone(),
+            vertex_buffer: self.vertex_buffer.clone(),
+            index_buffer: self.index_buffer.clone(),
+            first_index: indices.start as u32,
+            indices: part.indices,
+            morphs: None,
             num_indices: indices.len() as u32,
+            sphere: Sphere::from_points(&positions),
+            positions,
+            submeshes: Vec::new(),
+        }
+    }
+
+    /// Indices drawn from the index buffer.
+    pub fn index_range(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.num_indices
+    }
+
+    /// Same vertex count, the mesh must be dynamic.
+    pub fn write_vertices(
+        &mut self,
+        queue: &wgpu::Queue,
+        vertices: &[model::Vertex],
+        aabb: Aabb,
+    ) {
+        queue.write_buffer(&self.vertex_buffer, 0, cast_slice(vertices));
+        self.aabb = aabb;
+        self.positions =
+            vertices.iter().map(|v| Vec3::from(v.position)).collect();
+        self.sphere = Sphere::from_points(&self.positions);
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            aabb: self.aabb,
+            sphere: self.sphere,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.vertex_buffer.size()
+            + self.index_buffer.size()
+            + self.extra_buffer.as_ref().map_or(0, |buffer| buffer.size())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum MeshFormat {
+    Gltf,
+    Obj,
+}
+
+impl MeshFormat {
+    fn from_extension(extension: &str) -> Self {
+        match extension {
+            "obj" => Self::Obj,
+            _ => Self::Gltf,
+        }
+    }
+}
+
+pub struct MeshSource {
+    data: Vec<u8>,
+    format: MeshFormat,
+}
+
+pub struct MeshLoader;
+impl Loader<MeshSource> for MeshLoader {
+    fn load(content: Cow<[u8]>, ext: &str) -> Result<MeshSource, BoxedError> {
+        Ok(MeshSource {
+            data: content.into_owned(),
+            format: MeshFormat::from_extension(ext),
+        })
+    }
+}
+
+impl Asset for MeshSource {
+    const EXTENSIONS: &'static [&'static str] = &["obj", "glb", "gltf"];
+    type Loader = MeshLoader;
+}
+
+/// Material of a mesh file, a simple material is created from it.
+#[derive(Debug, Clone)]
+pub struct SourceMaterial {
+    pub color: [f32; 3],
+    pub name: String,
+    /// File name of the diffuse texture, loaded from the textures.
+    pub texture_id: Option<String>,
+}
+
+impl Default for SourceMaterial {
+    fn default() -> Self {
+        Self {
+            color: [1.0; 3],
+            name: "default".to_string(),
+            texture_id: None,
         }
     }
 }
 
-pub struct ObjSource(String);
+/// Contiguous triangles of a mesh file using one of its materials.
+#[derive(Debug, Clone)]
+struct Group {
+    indices: Range<usize>,
+    material: SourceMaterial,
+}
+
+/// Vertices of a mesh file, the extras are only kept when the file has
+/// vertex colors or a second uv channel.
+#[derive(Default)]
+struct MeshData {
+    extras: Option<Vec<model::VertexExtra>>,
+    /// Every triangle is in a group when the file has materials.
+    groups: Vec<Group>,
+    indices: Vec<u32>,
+    /// Offsets of every vertex by target.
+    morphs: Vec<MorphTarget>,
+    vertices: Vec<model::Vertex>,
+}
+
+impl MeshData {
+    /// Files referenced by the mesh are relative to its directory.
+    fn parse(data: &[u8], format: MeshFormat, dir: &Path) -> Result<Self> {
+        match format {
+            MeshFormat::Obj => parse_obj(data, dir),
+            MeshFormat::Gltf => parse_gltf(data),
+        }
+    }
+
+    /// Vertices and triangles of a group only, without morph targets.
+    fn part(&self, indices: &Range<usize>) -> Self {
+        let mut remap = HashMap::<u32, u32>::new();
+        let mut part = Self {
+            extras: self.extras.as_ref().map(|_| Vec::new()),
+            ..Default::default()
+        };
+        for &index in &self.indices[indices.clone()] {
+            let new_index = *remap.entry(index).or_insert_with(|| {
+                let i = index as usize;
+                part.vertices.push(self.vertices[i]);
+                if let (Some(extras), Some(source)) =
+                    (&mut part.extras, &self.extras)
+                {
+                    extras.push(source[i]);
+                }
+                part.vertices.len() as u32 - 1
+            });
+            part.indices.push(new_index);
+        }
+        part
+    }
+
+    /// Merge the identical vertices and store them in the order the
+    /// triangles first use them, for the vertex caches.
+    fn optimize(&mut self) {
+        let mut remap = HashMap::<Vec<u8>, u32>::new();
+        let mut vertices = Vec::new();
+        let mut extras = self.extras.as_ref().map(|_| Vec::new());
+        let mut morphs = self
+            .morphs
+            .iter()
+            .map(|target| MorphTarget {
+                name: target.name.clone(),
+                normals: Vec::new(),
+                positions: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        for index in &mut self.indices {
+            let i = *index as usize;
+            let mut key = cast_slice(&self.vertices[i..i + 1]).to_vec();
+            if let Some(source) = &self.extras {
+                key.extend_from_slice(cast_slice(&source[i..i + 1]));
+            }
+            for target in &self.morphs {
+                key.extend_from_slice(cast_slice(&target.positions[i..i + 1]));
+                key.extend_from_slice(cast_slice(&target.normals[i..i + 1]));
+            }
+            *index = *remap.entry(key).or_insert_with(|| {
+                vertices.push(self.vertices[i]);
+                if let (Some(extras), Some(source)) =
+                    (&mut extras, &self.extras)
+                {
+                    extras.push(source[i]);
+                }
+                for (target, source) in morphs.iter_mut().zip(&self.morphs) {
+                    target.positions.push(source.positions[i]);
+                    target.normals.push(source.normals[i]);
+                }
+                vertices.len() as u32 - 1
+            });
+        }
+        self.vertices = vertices;
+        self.extras = extras;
+        self.morphs = morphs;
+    }
+
+    /// Counts then the raw vertices, extras and indices, followed by the
+    /// morph targets then the material groups when there are some.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = IMPORTED_MAGIC.to_vec();
+        for count in [
+            self.vertices.len(),
+            self.extras.as_ref().map_or(0, Vec::len),
+            self.indices.len(),
+        ] {
+            bytes.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(cast_slice(&self.vertices));
+        if let Some(extras) = &self.extras {
+            bytes.extend_from_slice(cast_slice(extras));
+        }
+        bytes.extend_from_slice(cast_slice(&self.indices));
+        if !self.morphs.is_empty() || !self.groups.is_empty() {
+            bytes.extend_from_slice(&(self.morphs.len() as u32).to_le_bytes());
+        }
+        for target in &self.morphs {
+            write_str(&mut bytes, &target.name);
+            bytes.extend_from_slice(cast_slice(&target.positions));
+            bytes.extend_from_slice(cast_slice(&target.normals));
+        }
+        if !self.groups.is_empty() {
+            bytes.extend_from_slice(&(self.groups.len() as u32).to_le_bytes());
+        }
+        for group in &self.groups {
+            let material = &group.material;
+            write_str(&mut bytes, &material.name);
+            bytes.extend_from_slice(cast_slice(&material.color));
+            write_str(&mut bytes, material.texture_id.as_deref().unwrap_or(""));
+            for bound in [group.indices.start, group.indices.end] {
+                bytes.extend_from_slice(&(bound as u32).to_le_bytes());
+            }
+        }
+        bytes
+    }
 
-impl From<String> for ObjSource {
-    fn from(value: String) -> Self {
-        ObjSource(value)
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some(counts) = bytes.strip_prefix(&IMPORTED_MAGIC) else {
+            bail!("not an imported mesh");
+        };
+        let mut rest = counts.get(12..).unwrap_or_default();
+        let [vertices, extras, indices] = [0, 1, 2].map(|i| {
+            counts.get(i * 4..i * 4 + 4).map_or(0, |count| {
+                u32::from_le_bytes(count.try_into().unwrap())
+            }) as usize
+        });
+        let mut take = |len: usize| -> Result<&[u8]> {
+            if rest.len() < len {
+                bail!("truncated imported mesh");
+            }
+            let (taken, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(taken)
+        };
+        let vertices = take(vertices * size_of::<model::Vertex>())?;
+        let extras = take(extras * size_of::<model::VertexExtra>())?;
+        let indices = take(indices * 4)?;
+        let morphs = take(4).map_or(0, read_u32);
+        let offsets_len = vertices.len() / size_of::<model::Vertex>() * 12;
+        let mut targets = Vec::new();
+        for _ in 0..morphs {
+            let name = take_str(&mut take)?;
+            targets.push(MorphTarget {
+                name,
+                positions: bytemuck::pod_collect_to_vec(take(offsets_len)?),
+                normals: bytemuck::pod_collect_to_vec(take(offsets_len)?),
+            });
+        }
+        let mut groups = Vec::new();
+        for _ in 0..take(4).map_or(0, read_u32) {
+            let name = take_str(&mut take)?;
+            let color = bytemuck::pod_read_unaligned::<[f32; 3]>(take(12)?);
+            let texture_id = take_str(&mut take)?;
+            let start = read_u32(take(4)?) as usize;
+            let end = read_u32(take(4)?) as usize;
+            groups.push(Group {
+                indices: start..end,
+                material: SourceMaterial {
+                    color,
+                    name,
+                    texture_id: (!texture_id.is_empty()).then_some(texture_id),
+                },
+            });
+        }
+        // The slices are not aligned, read them into owned vectors.
+        Ok(Self {
+            extras: (!extras.is_empty())
+                .then(|| bytemuck::pod_collect_to_vec(extras)),
+            groups,
+            indices: bytemuck::pod_collect_to_vec(indices),
+            morphs: targets,
+            vertices: bytemuck::pod_collect_to_vec(vertices),
+        })
+    }
+}
+
+/// Gpu buffers of the vertices, with the extras when the source has them.
+fn upload(device: &wgpu::Device, data: &MeshData, label: &str) -> Mesh {
+    let aabb =
+        Aabb::from_points(data.vertices.iter().map(|v| Vec3::from(v.position)));
+    let mesh = Mesh::new(device, &data.vertices, &data.indices, aabb, label);
+    match &data.extras {
+        Some(extras) => mesh.with_extras(device, extras, label),
+        None => mesh,
     }
 }
 
-impl Asset for ObjSource {
-    const EXTENSION: &'static str = "obj";
-    type Loader = loader::LoadFrom<String, loader::StringLoader>;
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
 }
 
-type LoadResult = (String, Result<(Box<Vec<model::Vertex>>, Box<Vec<u32>>)>);
+/// Length then bytes, as written by `write_str`.
+fn take_str<'a>(
+    take: &mut impl FnMut(usize) -> Result<&'a [u8]>,
+) -> Result<String> {
+    let len = read_u32(take(4)?);
+    Ok(String::from_utf8(take(len as usize)?.to_vec())?)
+}
+
+/// Parse and optimize a mesh file offline, for the import cache. The files
+/// it references are relative to `dir`.
+pub fn preprocess(data: &[u8], extension: &str, dir: &Path) -> Result<Vec<u8>> {
+    let mut mesh =
+        MeshData::parse(data, MeshFormat::from_extension(extension), dir)?;
+    mesh.optimize();
+    Ok(mesh.to_bytes())
+}
+
+/// Cube drawn in place of the meshes that failed to load.
+pub const FALLBACK_MESH: &str = "error";
+/// Extension of the meshes in the import cache.
+pub const IMPORTED_EXTENSION: &str = "mesh";
+const IMPORTED_MAGIC: [u8; 4] = *b"WLFM";
+
+type LoadResult = (String, Result<Box<MeshData>>);
+
+/// Meshes drawn in place of a logical model, each one from its camera
+/// distance on.
+struct Lods {
+    distances: Vec<f32>,
+    mesh_ids: Vec<String>,
+}
 
 pub struct MeshAssets {
-    cache: Arc<AssetCache>,
+    caches: AssetCaches,
+    /// Meshes loaded for the first time since the last call to
+    /// `take_first_loads`.
+    first_loads: Vec<String>,
+    /// Meshes that failed to load before ever loading, since the last call
+    /// to `take_first_failures`.
+    first_failures: Vec<String>,
     last_reload: Instant,
     load_rx: Receiver<LoadResult>,
     load_tx: Sender<LoadResult>,
+    /// Meshes whose last load failed, drawn as the fallback mesh.
+    failed: HashSet<String>,
+    /// Meshes created at runtime, not backed by a file.
+    generated: HashSet<String>,
     loaded: HashSet<String>,
+    lods: HashMap<String, Lods>,
     meshes: HashMap<String, Mesh>,
+    /// Source mesh of the submeshes.
+    parents: HashMap<String, String>,
+    /// Meshes loaded again since the last call to `take_reloaded`.
+    reloaded: Vec<String>,
+    pub residency: Residency,
+    source_materials: Vec<(String, SourceMaterial)>,
 }
 
 impl MeshAssets {
     pub fn new() -> Self {
         let (load_tx, load_rx) = channel();
         Self {
-            cache: Arc::new(AssetCache::new("assets/meshes").unwrap()),
+            caches: AssetCaches::new("meshes"),
+            first_loads: Vec::new(),
+            first_failures: Vec::new(),
             last_reload: Instant::now(),
             load_rx,
             load_tx,
+            failed: HashSet::new(),
+            generated: HashSet::new(),
             loaded: HashSet::new(),
+            lods: HashMap::new(),
             meshes: HashMap::new(),
+            parents: HashMap::new(),
+            reloaded: Vec::new(),
+            residency: Residency::default(),
+            source_materials: Vec::new(),
         }
     }
 
     pub fn hot_reload(&mut self, device: &wgpu::Device) {
-        self.cache.hot_reload();
+        self.caches.hot_reload();
 
         let keys = self.meshes.keys().cloned().collect::<Vec<_>>();
         for mesh_id in keys {
-            let handle = self.cache.load_expect::<ObjSource>(&mesh_id);
+            if self.generated.contains(&mesh_id) {
+                continue;
+            }
+            let (cache, local_id) = self.caches.resolve(&mesh_id);
+            // Imported meshes can be shipped without their source.
+            let Ok(handle) = cache.load::<MeshSource>(local_id) else {
+                continue;
+            };
             if self.last_reload.elapsed() >= RELOAD_DEBOUNCE
                 && handle.reloaded_global()
             {
@@ -111,72 +584,225 @@ impl MeshAssets {
 
         if let Ok((mesh_id, result)) = self.load_rx.try_recv() {
             match result {
-                Ok((vertices, indices)) => {
+                Ok(data) => {
                     info!("Mesh loaded: {}", mesh_id);
-                    self.meshes.insert(
-                        mesh_id.clone(),
-                        Mesh::new(device, &vertices, &indices, &mesh_id),
-                    );
+                    match self.meshes.contains_key(&mesh_id) {
+                        true => self.reloaded.push(mesh_id.clone()),
+                        false => self.first_loads.push(mesh_id.clone()),
+                    }
+                    self.failed.remove(&mesh_id);
+                    self.insert_loaded(device, &mesh_id, *data);
                 }
                 Err(err) => {
                     error!("load\n{:?}", err);
+                    warn!("Mesh {} drawn as {}", mesh_id, FALLBACK_MESH);
+                    if !self.meshes.contains_key(&mesh_id) {
+                        self.first_failures.push(mesh_id.clone());
+                    }
+                    self.failed.insert(mesh_id.clone());
                     self.loaded.remove(&mesh_id);
                 }
             };
         }
     }
 
+    /// The groups of the source become meshes of their own, their
+    /// materials are created by `take_source_materials`.
+    fn insert_loaded(
+        &mut self,
+        device: &wgpu::Device,
+        mesh_id: &str,
+        data: MeshData,
+    ) {
+        let mut mesh = upload(device, &data, mesh_id);
+        let bytes = mesh.bytes();
+        self.remove_submeshes(mesh_id);
+        for group in &data.groups {
+            let part_id = format!("{}/{}", mesh_id, group.material.name);
+            let part = mesh.part(&data, &group.indices);
+            self.insert(&part_id, part);
+            self.parents.insert(part_id.clone(), mesh_id.to_string());
+            self.source_materials
+                .push((part_id.clone(), group.material.clone()));
+            mesh.submeshes.push(part_id);
+        }
+        if !data.morphs.is_empty() {
+            mesh.morphs = Some(Arc::new(Morphs {
+                base: data.vertices,
+                extras: data.extras,
+                indices: data.indices,
+                targets: data.morphs,
+            }));
+        }
+        self.residency.insert(mesh_id, bytes);
+        self.meshes.insert(mesh_id.to_string(), mesh);
+    }
+
+    fn remove_submeshes(&mut self, mesh_id: &str) {
+        let Some(mesh) = self.meshes.get_mut(mesh_id) else {
+            return;
+        };
+        for part_id in std::mem::take(&mut mesh.submeshes) {
+            self.parents.remove(&part_id);
+            self.remove(&part_id);
+        }
+    }
+
+    pub fn take_reloaded(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.reloaded)
+    }
+
+    pub fn take_first_loads(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.first_loads)
+    }
+
+    pub fn take_first_failures(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.first_failures)
+    }
+
+    /// Materials of the groups loaded since the last call, by key.
+    pub fn take_source_materials(&mut self) -> Vec<(String, SourceMaterial)> {
+        std::mem::take(&mut self.source_materials)
+    }
+
+    pub fn get_mut(&mut self, mesh_id: &str) -> Option<&mut Mesh> {
+        self.meshes.get_mut(mesh_id)
+    }
+
+    /// Forget a generated mesh.
+    pub fn remove(&mut self, mesh_id: &str) {
+        self.generated.remove(mesh_id);
+        self.loaded.remove(mesh_id);
+        self.meshes.remove(mesh_id);
+    }
+
+    /// Whether the last load of the mesh failed.
+    pub fn has_failed(&self, mesh_id: &str) -> bool {
+        self.failed.contains(mesh_id)
+    }
+
+    /// Logical models with lods resolve to their first one, meshes that
+    /// failed to load to the fallback mesh.
     pub fn get(&self, mesh_id: &str) -> Option<&Mesh> {
-        self.meshes.get(mesh_id)
+        if self.failed.contains(mesh_id) {
+            return self.meshes.get(FALLBACK_MESH);
+        }
+        self.meshes.get(mesh_id).or_else(|| {
+            let lods = self.lods.get(mesh_id)?;
+            self.meshes.get(&lods.mesh_ids[0])
+        })
+    }
+
+    pub fn has_lods(&self, model_id: &str) -> bool {
+        self.lods.contains_key(model_id)
+    }
+
+    /// Mesh drawn for the model at the given camera distance.
+    pub fn lod(&self, model_id: &str, distance: f32) -> Option<&str> {
+        let lods = self.lods.get(model_id)?;
+        let index = lods
+            .distances
+            .iter()
+            .rposition(|from| distance >= *from)
+            .unwrap_or(0);
+        Some(&lods.mesh_ids[index])
+    }
+
+    pub fn load_lods(
+        &mut self,
+        model_id: &str,
+        mesh_ids: Vec<String>,
+        distances: Vec<f32>,
+    ) -> Result<()> {
+        if mesh_ids.is_empty() || mesh_ids.len() != distances.len() {
+            bail!("lods need one distance per mesh");
+        }
+        if distances.windows(2).any(|pair| pair[0] > pair[1]) {
+            bail!("lod distances must be increasing");
+        }
+        for mesh_id in &mesh_ids {
+            self.load(mesh_id);
+        }
+        self.lods.insert(
+            model_id.to_string(),
+            Lods {
+                distances,
+                mesh_ids,
+            },
+        );
+        Ok(())
+    }
+
+    /// Generated meshes the scripts draw without a file, shadowing the
+    /// files of the same name, and the fallback cube.
+    pub fn insert_primitives(&mut self, device: &wgpu::Device) {
+        let primitives = PRIMITIVES.map(|name| (name, name));
+        for (mesh_id, name) in
+            primitives.into_iter().chain([(FALLBACK_MESH, "cube")])
+        {
+            let (vertices, indices) = primitives::build(name).unwrap();
+            let aabb = Aabb::from_points(
+                vertices.iter().map(|v| Vec3::from(v.position)),
+            );
+            let mesh = Mesh::new(device, &vertices, &indices, aabb, mesh_id);
+            self.insert(mesh_id, mesh);
+        }
+    }
+
+    pub fn insert(&mut self, mesh_id: &str, mesh: Mesh) {
+        self.generated.insert(mesh_id.to_string());
+        self.loaded.insert(mesh_id.to_string());
+        self.meshes.insert(mesh_id.to_string(), mesh);
+    }
+
+    /// Mark the mesh as drawn this frame, loading it again if evicted. The
+    /// submeshes mark their source mesh.
+    pub fn touch(&mut self, mesh_id: &str) {
+        let parent = self.parents.get(mesh_id).cloned();
+        let mesh_id = parent.as_deref().unwrap_or(mesh_id);
+        if self.residency.touch(mesh_id) {
+            self.load_internal(mesh_id);
+        }
+    }
+
+    pub fn evict(&mut self, mesh_id: &str) {
+        if self.generated.contains(mesh_id) {
+            return;
+        }
+        info!("Mesh evicted: {}", mesh_id);
+        self.remove_submeshes(mesh_id);
+        self.meshes.remove(mesh_id);
+        self.residency.evict(mesh_id);
     }
 
     fn load_internal(&mut self, mesh_id: &str) {
-        let cache = self.cache.clone();
-        let mesh_id = mesh_id.to_string();
+        let (cache, local_id) = self.caches.resolve(mesh_id);
+        let dir = self.caches.dir(mesh_id);
+        let (mesh_id, local_id) = (mesh_id.to_string(), local_id.to_string());
         let load_tx = self.load_tx.clone();
 
         get_pool().execute(move || {
             let result = (|| {
-                let handle = cache.load::<ObjSource>(&mesh_id)?;
-                let data = handle.read();
-                let mut cursor = Cursor::new(data.0.deref());
-                let (obj_models, _) = tobj::load_obj_buf(
-                    &mut cursor,
-                    &tobj::GPU_LOAD_OPTIONS,
-                    |_| unreachable!(),
-                )?;
-
-                let mut vertices = Vec::<model::Vertex>::new();
-                let mut indices = Vec::<u32>::new();
-
-                for mut m in obj_models {
-                    for i in 0..m.mesh.positions.len() / 3 {
-                        vertices.push(model::Vertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coord: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [
-                                m.mesh.normals[i * 3],
-                                m.mesh.normals[i * 3 + 1],
-                                m.mesh.normals[i * 3 + 2],
-                            ],
-                        });
-                    }
-                    indices.append(&mut m.mesh.indices);
+                if let Some(path) =
+                    import::cached("meshes", &mesh_id, IMPORTED_EXTENSION)
+                {
+                    let bytes = fs::read(path)?;
+                    return Ok(Box::new(MeshData::from_bytes(&bytes)?));
                 }
-
-                Ok((Box::new(vertices), Box::new(indices)))
+                let handle = cache.load::<MeshSource>(&local_id)?;
+                let source = handle.read();
+                let data = MeshData::parse(&source.data, source.format, &dir)?;
+                Ok(Box::new(data))
             })();
             load_tx.send((mesh_id, result)).unwrap();
         });
     }
 
+    /// Whether the mesh was requested and is still loading.
+    pub fn is_pending(&self, mesh_id: &str) -> bool {
+        self.loaded.contains(mesh_id) && self.get(mesh_id).is_none()
+    }
+
     pub fn load(&mut self, mesh_id: &str) {
         if self.loaded.contains(mesh_id) {
             return;
@@ -185,3 +811,246 @@ impl MeshAssets {
         self.load_internal(mesh_id);
     }
 }
+
+/// Triangles of every object in the file, grouped by material when the
+/// file has `.mtl` materials. A missing material file is only logged.
+fn parse_obj(data: &[u8], dir: &Path) -> Result<MeshData> {
+    let mut cursor = Cursor::new(data);
+    let (mut obj_models, materials) =
+        tobj::load_obj_buf(&mut cursor, &tobj::GPU_LOAD_OPTIONS, |path| {
+            let file = fs::File::open(dir.join(path))
+                .map_err(|_| tobj::LoadError::OpenFileFailed)?;
+            tobj::load_mtl_buf(&mut BufReader::new(file))
+        })?;
+    let materials = materials.unwrap_or_else(|err| {
+        warn!("obj materials in {}: {}", dir.display(), err);
+        Vec::new()
+    });
+
+    let mut vertices = Vec::<model::Vertex>::new();
+    let mut indices = Vec::<u32>::new();
+    let mut extras = Vec::<model::VertexExtra>::new();
+    let mut groups = Vec::<Group>::new();
+    let mut has_colors = false;
+
+    // Objects of the same material are contiguous, the objects without one
+    // come first.
+    obj_models.sort_by_key(|m| m.mesh.material_id);
+    for m in obj_models {
+        let colors = m.mesh.vertex_color.len() == m.mesh.positions.len();
+        has_colors |= colors;
+        let base = vertices.len() as u32;
+        for i in 0..m.mesh.positions.len() / 3 {
+            let tex_coord =
+                [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]];
+            vertices.push(model::Vertex {
+                position: [
+                    m.mesh.positions[i * 3],
+                    m.mesh.positions[i * 3 + 1],
+                    m.mesh.positions[i * 3 + 2],
+                ],
+                tex_coord,
+                normal: [
+                    m.mesh.normals[i * 3],
+                    m.mesh.normals[i * 3 + 1],
+                    m.mesh.normals[i * 3 + 2],
+                ],
+            });
+            let color = match colors {
+                true => [
+                    m.mesh.vertex_color[i * 3],
+                    m.mesh.vertex_color[i * 3 + 1],
+                    m.mesh.vertex_color[i * 3 + 2],
+                    1.0,
+                ],
+                false => [1.0; 4],
+            };
+            extras.push(model::VertexExtra {
+                color,
+                tex_coord2: tex_coord,
+            });
+        }
+        let start = indices.len();
+        indices.extend(m.mesh.indices.iter().map(|i| base + i));
+        if materials.is_empty() {
+            continue;
+        }
+        let material = m.mesh.material_id.and_then(|id| materials.get(id));
+        let name = material.map_or("default", |m| m.name.as_str());
+        match groups.last_mut() {
+            Some(group) if group.material.name == name => {
+                group.indices.end = indices.len()
+            }
+            _ => groups.push(Group {
+                indices: start..indices.len(),
+                material: material
+                    .map_or_else(SourceMaterial::default, |material| {
+                        source_material(material, dir)
+                    }),
+            }),
+        }
+    }
+
+    Ok(MeshData {
+        extras: has_colors.then_some(extras),
+        groups,
+        indices,
+        morphs: Vec::new(),
+        vertices,
+    })
+}
+
+fn source_material(material: &tobj::Material, dir: &Path) -> SourceMaterial {
+    let texture_id = material
+        .diffuse_texture
+        .as_ref()
+        .and_then(|path| texture_id(path, dir));
+    SourceMaterial {
+        color: material.diffuse.unwrap_or([1.0; 3]),
+        name: material.name.clone(),
+        texture_id,
+    }
+}
+
+/// Id of a texture of the mtl, its path is relative to the obj file. One
+/// outside the textures directory is looked up there by its file name.
+fn texture_id(path: &str, dir: &Path) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let relative = fs::canonicalize(dir.join(&path)).ok().and_then(|file| {
+        let textures =
+            fs::canonicalize(assets::root().join("textures")).ok()?;
+        let relative = file.strip_prefix(textures).ok()?.with_extension("");
+        // Ids separate the directories with dots.
+        let components = relative
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?;
+        Some(components.join("."))
+    });
+    let stem = Path::new(&path).file_stem()?.to_str()?.to_string();
+    if relative.is_none() {
+        warn!(
+            "texture {} not in the textures directory, using {}",
+            path, stem
+        );
+    }
+    relative.or(Some(stem))
+}
+
+#[derive(Deserialize)]
+struct GltfMeshExtras {
+    #[serde(default, rename = "targetNames")]
+    target_names: Vec<String>,
+}
+
+/// Names of the morph targets, from the `targetNames` extras exporters
+/// write. Unnamed targets are named by index.
+fn target_names(mesh: &gltf::Mesh) -> Vec<String> {
+    mesh.extras()
+        .as_ref()
+        .and_then(|raw| serde_json::from_str::<GltfMeshExtras>(raw.get()).ok())
+        .map_or(Vec::new(), |extras| extras.target_names)
+}
+
+/// Target of the name, created with zero offsets up to the given vertex.
+fn morph_target<'a>(
+    targets: &'a mut Vec<MorphTarget>,
+    name: &str,
+    vertices: usize,
+) -> &'a mut MorphTarget {
+    let index = match targets.iter().position(|t| t.name == name) {
+        Some(index) => index,
+        None => {
+            targets.push(MorphTarget {
+                name: name.to_string(),
+                normals: vec![[0.0; 3]; vertices],
+                positions: vec![[0.0; 3]; vertices],
+            });
+            targets.len() - 1
+        }
+    };
+    &mut targets[index]
+}
+
+/// Triangles of every mesh in the file, in their local space. Buffers must
+/// be embedded. Morph targets of the same name are merged across meshes.
+fn parse_gltf(data: &[u8]) -> Result<MeshData> {
+    let (document, buffers, _) = gltf::import_slice(data)?;
+    let mut data = MeshData::default();
+    let mut extras = Vec::<model::VertexExtra>::new();
+    let mut has_extras = false;
+
+    for mesh in document.meshes() {
+        let names = target_names(&mesh);
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader =
+                primitive.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions = positions.collect::<Vec<_>>();
+            let count = positions.len();
+            let normals = reader
+                .read_normals()
+                .map(|normals| normals.collect())
+                .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; count]);
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .map(|tex_coords| tex_coords.into_f32().collect())
+                .unwrap_or_else(|| vec![[0.0; 2]; count]);
+            let tex_coords2 = reader
+                .read_tex_coords(1)
+                .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>());
+            let colors = reader
+                .read_colors(0)
+                .map(|colors| colors.into_rgba_f32().collect::<Vec<_>>());
+            has_extras |= tex_coords2.is_some() || colors.is_some();
+
+            let base = data.vertices.len() as u32;
+            for i in 0..count {
+                data.vertices.push(model::Vertex {
+                    position: positions[i],
+                    tex_coord: tex_coords[i],
+                    normal: normals[i],
+                });
+                extras.push(model::VertexExtra {
+                    color: colors.as_ref().map_or([1.0; 4], |c| c[i]),
+                    tex_coord2: tex_coords2
+                        .as_ref()
+                        .map_or(tex_coords[i], |t| t[i]),
+                });
+            }
+            match reader.read_indices() {
+                Some(indices) => {
+                    data.indices.extend(indices.into_u32().map(|i| base + i))
+                }
+                None => data.indices.extend(base..base + count as u32),
+            }
+
+            for (i, (positions, normals, _)) in
+                reader.read_morph_targets().enumerate()
+            {
+                let name = names.get(i).cloned().unwrap_or(i.to_string());
+                let target =
+                    morph_target(&mut data.morphs, &name, base as usize);
+                if let Some(positions) = positions {
+                    target.positions.extend(positions.take(count));
+                }
+                if let Some(normals) = normals {
+                    target.normals.extend(normals.take(count));
+                }
+            }
+            // Targets missing from the primitive leave its vertices as is.
+            for target in &mut data.morphs {
+                target.positions.resize(data.vertices.len(), [0.0; 3]);
+                target.normals.resize(data.vertices.len(), [0.0; 3]);
+            }
+        }
+    }
+
+    data.extras = has_extras.then_some(extras);
+    Ok(data)
+}
@@ -1,7 +1,24 @@
+pub mod adapter;
+pub mod buffers;
 pub mod bundle;
+pub mod bvh;
 pub mod camera;
+pub mod clusters;
+pub mod export;
+pub mod ktx;
 pub mod material;
 pub mod mesh;
+pub mod morph;
+pub mod point_cloud;
+pub mod primitives;
+pub mod quality;
+pub mod recording;
+pub mod residency;
+pub mod screenshot;
 pub mod shader;
 pub mod state;
+pub mod stats;
+pub mod terrain;
 pub mod texture;
+pub mod timer;
+pub mod view;
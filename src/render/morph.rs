@@ -0,0 +1,148 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use glam::Vec3;
+
+use crate::{
+    bounds::Aabb,
+    entity::{Entities, EntityId},
+};
+
+use super::{
+    bundle::model,
+    mesh::{Mesh, MeshAssets},
+};
+
+/// Blend shape of a mesh, offsets added to every vertex scaled by its
+/// weight.
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    pub name: String,
+    pub normals: Vec<[f32; 3]>,
+    pub positions: Vec<[f32; 3]>,
+}
+
+/// Vertices of a mesh with morph targets kept on the cpu, blended for the
+/// entities setting weights.
+pub struct Morphs {
+    pub base: Vec<model::Vertex>,
+    pub extras: Option<Vec<model::VertexExtra>>,
+    pub indices: Vec<u32>,
+    pub targets: Vec<MorphTarget>,
+}
+
+impl Morphs {
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.targets.iter().map(|target| target.name.as_str())
+    }
+
+    /// Unknown target names are ignored.
+    pub fn blend(&self, weights: &BTreeMap<String, f32>) -> Vec<model::Vertex> {
+        let mut vertices = self.base.clone();
+        for target in &self.targets {
+            let weight = weights.get(&target.name).copied().unwrap_or(0.0);
+            if weight == 0.0 {
+                continue;
+            }
+            for (i, vertex) in vertices.iter_mut().enumerate() {
+                let position = Vec3::from(vertex.position)
+                    + Vec3::from(target.positions[i]) * weight;
+                let normal = Vec3::from(vertex.normal)
+                    + Vec3::from(target.normals[i]) * weight;
+                vertex.position = position.to_array();
+                vertex.normal = normal.to_array();
+            }
+        }
+        for vertex in &mut vertices {
+            vertex.normal =
+                Vec3::from(vertex.normal).normalize_or(Vec3::Y).to_array();
+        }
+        vertices
+    }
+}
+
+/// Mesh drawn for an entity with morph weights.
+pub fn mesh_id(entity: EntityId) -> String {
+    format!("morph:{}", entity.0)
+}
+
+/// Blended meshes of the entities, their vertex buffer is written again
+/// when the weights change.
+#[derive(Default)]
+pub struct MorphedMeshes {
+    /// Morphs and weights of the last blend by entity, the morphs change
+    /// with the base mesh or when it is reloaded.
+    applied: HashMap<EntityId, (Arc<Morphs>, BTreeMap<String, f32>)>,
+}
+
+impl MorphedMeshes {
+    /// Returns the entities drawn with their blended mesh, the others wait
+    /// for their base mesh to load or it has no morph targets.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        meshes: &mut MeshAssets,
+        entities: &Entities,
+    ) -> HashSet<EntityId> {
+        let mut morphed = HashSet::new();
+        for (id, entity) in entities.iter() {
+            if entity.morph_weights.is_empty() {
+                continue;
+            }
+            // Not batched itself, the base mesh must stay loaded.
+            meshes.touch(&entity.mesh_id);
+            let Some(morphs) =
+                meshes.get(&entity.mesh_id).and_then(|m| m.morphs.clone())
+            else {
+                continue;
+            };
+            morphed.insert(id);
+            let mesh_id = mesh_id(id);
+            let same_morphs = self.applied.get(&id).is_some_and(|applied| {
+                Arc::ptr_eq(&applied.0, &morphs)
+                    && meshes.get(&mesh_id).is_some()
+            });
+            if same_morphs && self.applied[&id].1 == entity.morph_weights {
+                continue;
+            }
+            let vertices = morphs.blend(&entity.morph_weights);
+            let aabb =
+                Aabb::from_points(vertices.iter().map(|v| v.position.into()));
+            match meshes.get_mut(&mesh_id) {
+                Some(mesh) if same_morphs => {
+                    mesh.write_vertices(queue, &vertices, aabb)
+                }
+                _ => {
+                    let mut mesh = Mesh::new_dynamic(
+                        device,
+                        &vertices,
+                        &morphs.indices,
+                        aabb,
+                        &mesh_id,
+                    );
+                    if let Some(extras) = &morphs.extras {
+                        mesh = mesh.with_extras(device, extras, &mesh_id);
+                    }
+                    meshes.insert(&mesh_id, mesh);
+                }
+            }
+            self.applied
+                .insert(id, (morphs, entity.morph_weights.clone()));
+        }
+
+        let stale = self
+            .applied
+            .keys()
+            .filter(|id| !morphed.contains(id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in stale {
+            self.applied.remove(&id);
+            meshes.remove(&mesh_id(id));
+        }
+        morphed
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use assets_manager::{loader, Asset};
+use glam::Vec3;
+use log::{error, info};
+
+use crate::{
+    app::{get_pool, RELOAD_DEBOUNCE},
+    assets::AssetCaches,
+};
+
+use super::bundle::points::PointBuffer;
+
+/// Points per buffer, keeps each storage buffer well under the binding
+/// size limit.
+const CHUNK_SIZE: usize = 1 << 20;
+
+pub struct PointCloudSource(String);
+
+impl From<String> for PointCloudSource {
+    fn from(value: String) -> Self {
+        PointCloudSource(value)
+    }
+}
+
+impl Asset for PointCloudSource {
+    const EXTENSIONS: &'static [&'static str] = &["xyz", "pts"];
+    type Loader = loader::LoadFrom<String, loader::StringLoader>;
+}
+
+/// Points of a scan split in chunks, drawn with the points pipeline.
+pub struct PointCloud {
+    pub chunks: Vec<Arc<PointBuffer>>,
+}
+
+type LoadResult = (String, Result<PointCloud>);
+
+pub struct PointCloudAssets {
+    caches: AssetCaches,
+    clouds: HashMap<String, PointCloud>,
+    last_reload: Instant,
+    load_rx: Receiver<LoadResult>,
+    load_tx: Sender<LoadResult>,
+    loaded: HashSet<String>,
+}
+
+impl PointCloudAssets {
+    pub fn new() -> Self {
+        let (load_tx, load_rx) = channel();
+        Self {
+            caches: AssetCaches::new("point_clouds"),
+            clouds: HashMap::new(),
+            last_reload: Instant::now(),
+            load_rx,
+            load_tx,
+            loaded: HashSet::new(),
+        }
+    }
+
+    pub fn hot_reload(&mut self) {
+        self.caches.hot_reload();
+
+        let keys = self.clouds.keys().cloned().collect::<Vec<_>>();
+        for cloud_id in keys {
+            let (cache, local_id) = self.caches.resolve(&cloud_id);
+            let handle = cache.load_expect::<PointCloudSource>(local_id);
+            if self.last_reload.elapsed() >= RELOAD_DEBOUNCE
+                && handle.reloaded_global()
+            {
+                self.last_reload = Instant::now();
+                self.load_internal(&cloud_id);
+            }
+        }
+
+        if let Ok((cloud_id, result)) = self.load_rx.try_recv() {
+            match result {
+                Ok(cloud) => {
+                    info!("Point cloud loaded: {}", cloud_id);
+                    self.clouds.insert(cloud_id, cloud);
+                }
+                Err(err) => {
+                    error!("load\n{:?}", err);
+                    self.loaded.remove(&cloud_id);
+                }
+            };
+        }
+    }
+
+    pub fn get(&self, cloud_id: &str) -> Option<&PointCloud> {
+        self.clouds.get(cloud_id)
+    }
+
+    fn load_internal(&mut self, cloud_id: &str) {
+        let (cache, local_id) = self.caches.resolve(cloud_id);
+        let (cloud_id, local_id) = (cloud_id.to_string(), local_id.to_string());
+        let load_tx = self.load_tx.clone();
+
+        get_pool().execute(move || {
+            let result = (|| {
+                let handle = cache.load::<PointCloudSource>(&local_id)?;
+                let (points, colors) = parse(&handle.read().0)?;
+                let chunks = points
+                    .chunks(CHUNK_SIZE)
+                    .enumerate()
+                    .map(|(i, points)| {
+                        let colors = colors.as_ref().map(|colors| {
+                            colors[i * CHUNK_SIZE..][..points.len()].to_vec()
+                        });
+                        Arc::new(PointBuffer::with_colors(
+                            points.to_vec(),
+                            colors,
+                        ))
+                    })
+                    .collect();
+                Ok(PointCloud { chunks })
+            })();
+            load_tx.send((cloud_id, result)).unwrap();
+        });
+    }
+
+    pub fn load(&mut self, cloud_id: &str) {
+        if self.loaded.contains(cloud_id) {
+            return;
+        }
+        self.loaded.insert(cloud_id.to_string());
+        self.load_internal(cloud_id);
+    }
+}
+
+/// Lines of `x y z`, optionally followed by an intensity and `r g b`, the
+/// colors are only kept when every point has one. Lines with less than
+/// three values, like the point count of pts files, are skipped.
+fn parse(source: &str) -> Result<(Vec<Vec3>, Option<Vec<Vec3>>)> {
+    let mut points = Vec::new();
+    let mut colors = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let values = line
+            .split_whitespace()
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>();
+        let Ok(values) = values else {
+            bail!("invalid number at line {}", number + 1);
+        };
+        if values.len() < 3 {
+            continue;
+        }
+        points.push(Vec3::new(values[0], values[1], values[2]));
+        if values.len() >= 6 {
+            let rgb = &values[values.len() - 3..];
+            colors.push(Vec3::new(rgb[0], rgb[1], rgb[2]));
+        }
+    }
+
+    if colors.len() != points.len() {
+        return Ok((points, None));
+    }
+    let max = colors
+        .iter()
+        .fold(0.0f32, |max, c| max.max(c.max_element()));
+    if max > 1.0 {
+        colors.iter_mut().for_each(|color| *color /= 255.0);
+    }
+    Ok((points, Some(colors)))
+}
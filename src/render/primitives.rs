@@ -0,0 +1,145 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::Vec3;
+
+use super::bundle::model;
+
+/// Meshes registered by default, two units wide and centered like the
+/// usual modeling tool defaults.
+pub const PRIMITIVES: [&str; 6] =
+    ["cube", "sphere", "plane", "cylinder", "cone", "torus"];
+
+const SEGMENTS: u32 = 32;
+const RINGS: u32 = 16;
+const TORUS_RADIUS: f32 = 1.0;
+const TORUS_TUBE: f32 = 0.25;
+
+/// Vertices and triangles of a primitive, none for an unknown name.
+pub fn build(name: &str) -> Option<(Vec<model::Vertex>, Vec<u32>)> {
+    let mut builder = Builder::default();
+    match name {
+        "cube" => {
+            for (normal, right) in [
+                (Vec3::X, Vec3::NEG_Z),
+                (Vec3::NEG_X, Vec3::Z),
+                (Vec3::Y, Vec3::X),
+                (Vec3::NEG_Y, Vec3::X),
+                (Vec3::Z, Vec3::X),
+                (Vec3::NEG_Z, Vec3::NEG_X),
+            ] {
+                let down = right.cross(normal);
+                builder.grid(1, 1, |u, v| {
+                    let position = normal
+                        + right * (u * 2.0 - 1.0)
+                        + down * (v * 2.0 - 1.0);
+                    (position, normal)
+                });
+            }
+        }
+        "sphere" => builder.grid(SEGMENTS, RINGS, |u, v| {
+            let position =
+                around(u * TAU, (v * PI).sin()) + Vec3::Y * (v * PI).cos();
+            (position, position)
+        }),
+        "plane" => builder.grid(1, 1, |u, v| {
+            (Vec3::new(u * 2.0 - 1.0, 0.0, v * 2.0 - 1.0), Vec3::Y)
+        }),
+        "cylinder" => {
+            builder.grid(SEGMENTS, 1, |u, v| {
+                let normal = around(u * TAU, 1.0);
+                (normal + Vec3::Y * (1.0 - v * 2.0), normal)
+            });
+            builder.cap(1.0, Vec3::Y);
+            builder.cap(-1.0, Vec3::NEG_Y);
+        }
+        "cone" => {
+            // Apex on top, the slope normals have twice the height.
+            builder.grid(SEGMENTS, 1, |u, v| {
+                let position = around(u * TAU, v) + Vec3::Y * (1.0 - v * 2.0);
+                let normal = around(u * TAU, 2.0) + Vec3::Y;
+                (position, normal.normalize())
+            });
+            builder.cap(-1.0, Vec3::NEG_Y);
+        }
+        "torus" => builder.grid(SEGMENTS, RINGS, |u, v| {
+            let (sin, cos) = (v * TAU).sin_cos();
+            let normal = around(u * TAU, cos) - Vec3::Y * sin;
+            let position = around(u * TAU, TORUS_RADIUS) + normal * TORUS_TUBE;
+            (position, normal)
+        }),
+        _ => return None,
+    }
+    Some((builder.vertices, builder.indices))
+}
+
+/// Point of the circle around the y axis, counter clockwise seen from
+/// above.
+fn around(angle: f32, radius: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(cos * radius, 0.0, -sin * radius)
+}
+
+#[derive(Default)]
+struct Builder {
+    indices: Vec<u32>,
+    vertices: Vec<model::Vertex>,
+}
+
+impl Builder {
+    /// Surface of the position and normal at u and v in 0..1, its
+    /// triangles face the cross product of the v and u directions.
+    fn grid(
+        &mut self,
+        columns: u32,
+        rows: u32,
+        surface: impl Fn(f32, f32) -> (Vec3, Vec3),
+    ) {
+        let first = self.vertices.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (u, v) =
+                    (column as f32 / columns as f32, row as f32 / rows as f32);
+                let (position, normal) = surface(u, v);
+                self.vertices.push(model::Vertex {
+                    position: position.to_array(),
+                    tex_coord: [u, v],
+                    normal: normal.to_array(),
+                });
+            }
+        }
+        let stride = columns + 1;
+        for row in 0..rows {
+            for column in 0..columns {
+                let a = first + row * stride + column;
+                let b = a + stride;
+                self.indices
+                    .extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+            }
+        }
+    }
+
+    /// Unit disc at the height, facing up or down.
+    fn cap(&mut self, height: f32, normal: Vec3) {
+        let center = self.vertices.len() as u32;
+        self.vertices.push(model::Vertex {
+            position: [0.0, height, 0.0],
+            tex_coord: [0.5, 0.5],
+            normal: normal.to_array(),
+        });
+        for segment in 0..=SEGMENTS {
+            let edge = around(segment as f32 / SEGMENTS as f32 * TAU, 1.0);
+            self.vertices.push(model::Vertex {
+                position: (edge + Vec3::Y * height).to_array(),
+                tex_coord: [0.5 + edge.x * 0.5, 0.5 + edge.z * 0.5],
+                normal: normal.to_array(),
+            });
+        }
+        for segment in 0..SEGMENTS {
+            let (a, b) = (center + 1 + segment, center + 2 + segment);
+            match normal.y > 0.0 {
+                true => self.indices.extend_from_slice(&[center, a, b]),
+                false => self.indices.extend_from_slice(&[center, b, a]),
+            }
+        }
+    }
+}
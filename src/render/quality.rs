@@ -0,0 +1,32 @@
+/// Settings applied together, the scripts can still change each one after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+
+    /// Shades each visible fragment once, the extra vertex work pays off
+    /// when the fragments are the bottleneck.
+    pub fn depth_prepass(self) -> bool {
+        self != Self::Low
+    }
+}
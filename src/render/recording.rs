@@ -0,0 +1,304 @@
+use std::{
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use image::{imageops, Rgba, RgbaImage};
+use log::{error, info};
+
+use crate::app::get_pool;
+
+use super::{buffers::ReadbackQueue, screenshot::Readback};
+
+#[derive(Debug, Clone)]
+pub struct RecordingSettings {
+    /// Text stamped in the bottom left corner of every frame.
+    pub caption: Option<String>,
+    pub fps: f32,
+    /// Frames wider than this are downscaled.
+    pub width: u32,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            caption: None,
+            fps: 15.0,
+            width: 480,
+        }
+    }
+}
+
+struct Recording {
+    /// Read back frames by capture order.
+    frames: Vec<(u64, RgbaImage)>,
+    /// Seconds until the next frame is captured.
+    next_frame: f32,
+    path: PathBuf,
+    /// Seconds left to capture.
+    remaining: f32,
+    settings: RecordingSettings,
+}
+
+/// Final frames captured for some seconds and written as an animated png,
+/// shareable without a screen recorder.
+pub struct Recorder {
+    next_id: u64,
+    /// Frames by capture order.
+    readbacks: ReadbackQueue<(u64, Readback)>,
+    recording: Option<Recording>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            next_id: 0,
+            readbacks: ReadbackQueue::new(),
+            recording: None,
+        }
+    }
+
+    /// Record the next seconds into the file, replacing a recording in
+    /// progress.
+    pub fn start(
+        &mut self,
+        path: &Path,
+        seconds: f32,
+        settings: RecordingSettings,
+    ) {
+        info!("Recording {} seconds into {}", seconds, path.display());
+        self.recording = Some(Recording {
+            frames: Vec::new(),
+            next_frame: 0.0,
+            path: path.to_path_buf(),
+            remaining: seconds,
+            settings,
+        });
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Advance the recording clock, whether this frame is captured.
+    pub fn frame_due(&mut self, dt: f32) -> bool {
+        let Some(recording) = &mut self.recording else {
+            return false;
+        };
+        if recording.remaining <= 0.0 {
+            return false;
+        }
+        recording.remaining -= dt;
+        recording.next_frame -= dt;
+        if recording.next_frame > 0.0 {
+            return false;
+        }
+        let interval = 1.0 / recording.settings.fps.max(1.0);
+        recording.next_frame = (recording.next_frame + interval).max(0.0);
+        true
+    }
+
+    /// Copy a frame rendered into a texture of the surface format.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        match Readback::copy(device, encoder, texture) {
+            Ok(readback) => {
+                self.readbacks.push((self.next_id, readback));
+                self.next_id += 1;
+            }
+            Err(err) => {
+                error!("recording\n{:?}", err);
+                self.recording = None;
+            }
+        }
+    }
+
+    pub fn map_recorded(&mut self) {
+        self.readbacks.map_recorded();
+    }
+
+    /// Keep the frames mapped since the last call, then write the file on
+    /// the thread pool once every frame is in.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        for ((id, readback), result) in self.readbacks.take_mapped(device) {
+            let Some(recording) = &mut self.recording else {
+                continue;
+            };
+            match result {
+                Ok(()) => {
+                    let frame = prepare_frame(
+                        readback.read(),
+                        &recording.settings,
+                        recording.frames.first().map(|(_, f)| f.dimensions()),
+                    );
+                    recording.frames.push((id, frame));
+                }
+                Err(err) => error!("recording\n{:?}", err),
+            }
+        }
+
+        let done = self.recording.as_ref().is_some_and(|recording| {
+            recording.remaining <= 0.0 && self.readbacks.is_empty()
+        });
+        if !done {
+            return;
+        }
+        let mut recording = self.recording.take().unwrap();
+        recording.frames.sort_by_key(|(id, _)| *id);
+        get_pool().execute(move || {
+            let path = recording.path.clone();
+            match write_animation(recording) {
+                Ok(()) => info!("Recording written to {}", path.display()),
+                Err(err) => error!("recording {}\n{:?}", path.display(), err),
+            }
+        });
+    }
+}
+
+/// Downscaled to the settings width, then to the size of the first frame
+/// when the window was resized, with the caption.
+fn prepare_frame(
+    frame: RgbaImage,
+    settings: &RecordingSettings,
+    size: Option<(u32, u32)>,
+) -> RgbaImage {
+    let (width, height) = size.unwrap_or_else(|| {
+        let width = frame.width().min(settings.width.max(1));
+        let height = (frame.height() as u64 * width as u64
+            / frame.width().max(1) as u64)
+            .max(1) as u32;
+        (width, height)
+    });
+    let mut frame = match frame.dimensions() == (width, height) {
+        true => frame,
+        false => imageops::resize(
+            &frame,
+            width,
+            height,
+            imageops::FilterType::Triangle,
+        ),
+    };
+    if let Some(caption) = &settings.caption {
+        draw_caption(&mut frame, caption);
+    }
+    frame
+}
+
+fn write_animation(recording: Recording) -> Result<()> {
+    let Some((_, first)) = recording.frames.first() else {
+        anyhow::bail!("no frame captured");
+    };
+    let (width, height) = first.dimensions();
+    if let Some(parent) = recording.path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = BufWriter::new(fs::File::create(&recording.path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(recording.frames.len() as u32, 0)?;
+    let delay = (1000.0 / recording.settings.fps.max(1.0)).round() as u16;
+    encoder.set_frame_delay(delay, 1000)?;
+    let mut writer = encoder.write_header()?;
+    for (_, frame) in &recording.frames {
+        writer.write_image_data(frame.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// White text with a drop shadow, in a 5x7 pixel font scaled with the
+/// frame height. Letters are drawn uppercase.
+fn draw_caption(frame: &mut RgbaImage, text: &str) {
+    let scale = (frame.height() / 120).max(1);
+    let margin = 4 * scale;
+    let top = frame.height().saturating_sub(7 * scale + margin);
+    for (shadow, color) in [(scale, [0, 0, 0, 255]), (0, [255; 4])] {
+        let mut left = margin;
+        for c in text.chars() {
+            let rows = glyph(c.to_ascii_uppercase());
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..5 {
+                    if row & (0b10000 >> x) == 0 {
+                        continue;
+                    }
+                    let px = left + x * scale + shadow;
+                    let py = top + y as u32 * scale + shadow;
+                    fill(frame, px, py, scale, Rgba(color));
+                }
+            }
+            left += 6 * scale;
+        }
+    }
+}
+
+fn fill(frame: &mut RgbaImage, x: u32, y: u32, size: u32, color: Rgba<u8>) {
+    for py in y..(y + size).min(frame.height()) {
+        for px in x..(x + size).min(frame.width()) {
+            frame.put_pixel(px, py, color);
+        }
+    }
+}
+
+/// Rows of a 5x7 glyph, the leftmost pixel in the highest bit. Unknown
+/// characters are drawn as a question mark.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        ' ' => [0; 7],
+        'A' => [14, 17, 17, 31, 17, 17, 17],
+        'B' => [30, 17, 17, 30, 17, 17, 30],
+        'C' => [14, 17, 16, 16, 16, 17, 14],
+        'D' => [30, 17, 17, 17, 17, 17, 30],
+        'E' => [31, 16, 16, 30, 16, 16, 31],
+        'F' => [31, 16, 16, 30, 16, 16, 16],
+        'G' => [14, 17, 16, 23, 17, 17, 15],
+        'H' => [17, 17, 17, 31, 17, 17, 17],
+        'I' => [14, 4, 4, 4, 4, 4, 14],
+        'J' => [7, 2, 2, 2, 2, 18, 12],
+        'K' => [17, 18, 20, 24, 20, 18, 17],
+        'L' => [16, 16, 16, 16, 16, 16, 31],
+        'M' => [17, 27, 21, 21, 17, 17, 17],
+        'N' => [17, 17, 25, 21, 19, 17, 17],
+        'O' => [14, 17, 17, 17, 17, 17, 14],
+        'P' => [30, 17, 17, 30, 16, 16, 16],
+        'Q' => [14, 17, 17, 17, 21, 18, 13],
+        'R' => [30, 17, 17, 30, 20, 18, 17],
+        'S' => [15, 16, 16, 14, 1, 1, 30],
+        'T' => [31, 4, 4, 4, 4, 4, 4],
+        'U' => [17, 17, 17, 17, 17, 17, 14],
+        'V' => [17, 17, 17, 17, 17, 10, 4],
+        'W' => [17, 17, 17, 21, 21, 21, 10],
+        'X' => [17, 17, 10, 4, 10, 17, 17],
+        'Y' => [17, 17, 10, 4, 4, 4, 4],
+        'Z' => [31, 1, 2, 4, 8, 16, 31],
+        '0' => [14, 17, 19, 21, 25, 17, 14],
+        '1' => [4, 12, 4, 4, 4, 4, 14],
+        '2' => [14, 17, 1, 2, 4, 8, 31],
+        '3' => [31, 2, 4, 2, 1, 17, 14],
+        '4' => [2, 6, 10, 18, 31, 2, 2],
+        '5' => [31, 16, 30, 1, 1, 17, 14],
+        '6' => [6, 8, 16, 30, 17, 17, 14],
+        '7' => [31, 1, 2, 4, 8, 8, 8],
+        '8' => [14, 17, 17, 14, 17, 17, 14],
+        '9' => [14, 17, 17, 15, 1, 2, 12],
+        '.' => [0, 0, 0, 0, 0, 12, 12],
+        ',' => [0, 0, 0, 0, 12, 4, 8],
+        ':' => [0, 12, 12, 0, 12, 12, 0],
+        '-' => [0, 0, 0, 31, 0, 0, 0],
+        '+' => [0, 4, 4, 31, 4, 4, 0],
+        '=' => [0, 0, 31, 0, 31, 0, 0],
+        '_' => [0, 0, 0, 0, 0, 0, 31],
+        '!' => [4, 4, 4, 4, 4, 0, 4],
+        '/' => [1, 1, 2, 4, 8, 16, 16],
+        '(' => [2, 4, 8, 8, 8, 4, 2],
+        ')' => [8, 4, 2, 2, 2, 4, 8],
+        '\'' => [4, 4, 8, 0, 0, 0, 0],
+        _ => [14, 17, 1, 2, 4, 0, 4],
+    }
+}
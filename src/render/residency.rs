@@ -0,0 +1,60 @@
+use std::collections::{HashMap, HashSet};
+
+struct Entry {
+    bytes: u64,
+    last_used: u64,
+}
+
+/// Gpu memory of the assets loaded from files and the frame they were last
+/// drawn in, to evict the least recently used ones.
+#[derive(Default)]
+pub struct Residency {
+    entries: HashMap<String, Entry>,
+    /// Evicted assets, loaded again the next time they are used.
+    evicted: HashSet<String>,
+    frame: u64,
+}
+
+impl Residency {
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// Loaded or reloaded, counts as used.
+    pub fn insert(&mut self, id: &str, bytes: u64) {
+        self.evicted.remove(id);
+        self.entries.insert(
+            id.to_string(),
+            Entry {
+                bytes,
+                last_used: self.frame,
+            },
+        );
+    }
+
+    /// Returns true when the asset was evicted and must be loaded again.
+    pub fn touch(&mut self, id: &str) -> bool {
+        if let Some(entry) = self.entries.get_mut(id) {
+            entry.last_used = self.frame;
+        }
+        self.evicted.remove(id)
+    }
+
+    pub fn evict(&mut self, id: &str) {
+        if self.entries.remove(id).is_some() {
+            self.evicted.insert(id.to_string());
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.entries.values().map(|entry| entry.bytes).sum()
+    }
+
+    /// Assets not used this frame with their last used frame and size.
+    pub fn idle(&self) -> impl Iterator<Item = (&str, u64, u64)> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.last_used < self.frame)
+            .map(|(id, entry)| (id.as_str(), entry.last_used, entry.bytes))
+    }
+}
@@ -0,0 +1,261 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Result};
+use image::{Rgba, RgbaImage};
+
+use super::buffers::{ReadbackQueue, Staged};
+
+const SCREENSHOTS_DIR: &str = "screenshots";
+
+/// Frame copied to a staging buffer, rows padded to the copy alignment.
+pub struct Readback {
+    height: u32,
+    padded_row: u32,
+    staging: wgpu::Buffer,
+    /// Surface format is bgra, swapped to rgba when read.
+    swap_rb: bool,
+    width: u32,
+}
+
+impl Readback {
+    /// Copy an 8 bits rgba or bgra texture, an error for other formats.
+    pub fn copy(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Result<Self> {
+        let format = texture.format();
+        if !matches!(
+            format,
+            wgpu::TextureFormat::Rgba8Unorm
+                | wgpu::TextureFormat::Rgba8UnormSrgb
+                | wgpu::TextureFormat::Bgra8Unorm
+                | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            bail!("can not capture a {:?} surface", format);
+        }
+        let (width, height) = (texture.width(), texture.height());
+        let padded_row =
+            (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("screenshot_readback"),
+            size: padded_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        Ok(Self {
+            height,
+            padded_row,
+            staging,
+            swap_rb: matches!(
+                format,
+                wgpu::TextureFormat::Bgra8Unorm
+                    | wgpu::TextureFormat::Bgra8UnormSrgb
+            ),
+            width,
+        })
+    }
+
+    /// Opaque image of the mapped buffer, unmapped after.
+    pub fn read(&self) -> RgbaImage {
+        let data = self.staging.slice(..).get_mapped_range();
+        let mut image = RgbaImage::new(self.width, self.height);
+        for (y, row) in data.chunks(self.padded_row as usize).enumerate() {
+            for (x, pixel) in
+                row.chunks(4).take(self.width as usize).enumerate()
+            {
+                let [r, g, b, _] = pixel.try_into().unwrap();
+                let (r, b) = if self.swap_rb { (b, r) } else { (r, b) };
+                image.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+            }
+        }
+        drop(data);
+        self.staging.unmap();
+        image
+    }
+}
+
+impl Staged for Readback {
+    fn staging(&self) -> &wgpu::Buffer {
+        &self.staging
+    }
+}
+
+struct Capture {
+    golden: String,
+    id: u64,
+    readback: Readback,
+    threshold: f32,
+}
+
+impl Staged for Capture {
+    fn staging(&self) -> &wgpu::Buffer {
+        self.readback.staging()
+    }
+}
+
+/// Difference between a frame and a golden image.
+#[derive(Debug)]
+pub struct ScreenshotDiff {
+    /// The golden image did not exist and was written from the frame.
+    pub created: bool,
+    /// Pixels with a channel further apart than the threshold.
+    pub different_pixels: u32,
+    /// Heatmap of the differences, black where they match.
+    pub heatmap: RgbaImage,
+    pub max_difference: f32,
+    pub mean_difference: f32,
+}
+
+/// Final frames read back to be compared against images on disk.
+pub struct Screenshots {
+    /// Requests that could not be captured.
+    failed: Vec<(u64, String)>,
+    next_id: u64,
+    readbacks: ReadbackQueue<Capture>,
+    requested: Vec<(String, f32, u64)>,
+}
+
+impl Screenshots {
+    pub fn new() -> Self {
+        Self {
+            failed: Vec::new(),
+            next_id: 0,
+            readbacks: ReadbackQueue::new(),
+            requested: Vec::new(),
+        }
+    }
+
+    /// Compare the next frame against `screenshots/<golden>`, a channel
+    /// differs when further apart than the threshold, from 0 to 1.
+    pub fn request(&mut self, golden: &str, threshold: f32) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.requested.push((golden.to_string(), threshold, id));
+        id
+    }
+
+    pub fn is_requested(&self) -> bool {
+        !self.requested.is_empty()
+    }
+
+    /// Copy a frame rendered into a texture of the surface format, once for
+    /// all the requests of this frame.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        for (golden, threshold, id) in self.requested.drain(..) {
+            match Readback::copy(device, encoder, texture) {
+                Ok(readback) => self.readbacks.push(Capture {
+                    golden,
+                    id,
+                    readback,
+                    threshold,
+                }),
+                Err(err) => self.failed.push((id, err.to_string())),
+            }
+        }
+    }
+
+    pub fn map_recorded(&mut self) {
+        self.readbacks.map_recorded();
+    }
+
+    /// Comparisons of the frames mapped since the last call, by request id.
+    pub fn take_compared(
+        &mut self,
+        device: &wgpu::Device,
+    ) -> Vec<(u64, Result<ScreenshotDiff>)> {
+        let mut compared = self
+            .failed
+            .drain(..)
+            .map(|(id, error)| (id, Err(anyhow::anyhow!(error))))
+            .collect::<Vec<_>>();
+        for (capture, result) in self.readbacks.take_mapped(device) {
+            let diff = result
+                .map_err(anyhow::Error::from)
+                .and_then(|_| compare(&capture, capture.readback.read()));
+            compared.push((capture.id, diff));
+        }
+        compared
+    }
+}
+
+fn compare(capture: &Capture, frame: RgbaImage) -> Result<ScreenshotDiff> {
+    let path = Path::new(SCREENSHOTS_DIR).join(&capture.golden);
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        frame.save(&path)?;
+        return Ok(ScreenshotDiff {
+            created: true,
+            different_pixels: 0,
+            heatmap: RgbaImage::new(frame.width(), frame.height()),
+            max_difference: 0.0,
+            mean_difference: 0.0,
+        });
+    }
+
+    let golden = image::open(&path)?.to_rgba8();
+    if golden.dimensions() != frame.dimensions() {
+        bail!(
+            "golden is {:?} but the frame is {:?}",
+            golden.dimensions(),
+            frame.dimensions()
+        );
+    }
+    let mut heatmap = RgbaImage::new(frame.width(), frame.height());
+    let (mut different_pixels, mut max, mut sum) = (0, 0.0f32, 0.0);
+    for ((a, b), out) in golden
+        .pixels()
+        .zip(frame.pixels())
+        .zip(heatmap.pixels_mut())
+    {
+        let difference = (0..3)
+            .map(|c| (a[c] as f32 - b[c] as f32).abs() / 255.0)
+            .fold(0.0, f32::max);
+        if difference > capture.threshold {
+            different_pixels += 1;
+        }
+        max = max.max(difference);
+        sum += difference;
+        *out = heat(difference);
+    }
+
+    let diff_path = path.with_file_name(format!(
+        "{}-diff.png",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    ));
+    heatmap.save(diff_path)?;
+    Ok(ScreenshotDiff {
+        created: false,
+        different_pixels,
+        heatmap,
+        max_difference: max,
+        mean_difference: sum / (frame.width() * frame.height()) as f32,
+    })
+}
+
+/// Black to red to yellow, small differences are boosted to stay visible.
+fn heat(difference: f32) -> Rgba<u8> {
+    let t = difference.sqrt();
+    let r = (t * 2.0).min(1.0);
+    let g = (t * 2.0 - 1.0).clamp(0.0, 1.0);
+    Rgba([(r * 255.0) as u8, (g * 255.0) as u8, 0, 255])
+}
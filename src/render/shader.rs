@@ -8,10 +8,42 @@ use std::{
 };
 
 use anyhow::Result;
-use assets_manager::{loader, Asset, AssetCache};
-use log::{error, info};
+use assets_manager::{loader, Asset};
+use log::{error, info, warn};
+use wgpu::naga;
 
-use crate::app::{get_pool, RELOAD_DEBOUNCE};
+use crate::{
+    app::{get_pool, RELOAD_DEBOUNCE},
+    assets::AssetCaches,
+};
+
+/// Flat color drawn by the materials whose shader failed to load.
+pub const FALLBACK_SHADER: &str = "error";
+
+/// Parse and validate the source before the device sees it, its error
+/// handler panics. Not an error scope, pipelines built on the pool would
+/// share it.
+fn create_module(
+    device: &wgpu::Device,
+    shader_id: &str,
+    source: &str,
+) -> Result<wgpu::ShaderModule> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|err| {
+        anyhow::anyhow!("shader {}\n{}", shader_id, err.emit_to_string(source))
+    })?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|err| {
+        anyhow::anyhow!("shader {}\n{}", shader_id, err.emit_to_string(source))
+    })?;
+    Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(&format!("{}_module", shader_id)),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    }))
+}
 
 pub struct WgslSource(String);
 
@@ -29,36 +61,57 @@ impl Asset for WgslSource {
 type LoadResult = (String, Result<String>);
 
 pub struct ShaderAssets {
-    cache: Arc<AssetCache>,
+    caches: AssetCaches,
+    /// Shaders whose last load failed.
+    failed: HashSet<String>,
+    /// Shaders loaded for the first time since the last call to
+    /// `take_first_loads`.
+    first_loads: Vec<String>,
+    /// Shaders that failed to load before ever loading, since the last call
+    /// to `take_first_failures`.
+    first_failures: Vec<String>,
+    pub frame_failed: Option<String>,
     pub frame_reloaded: Option<String>,
     last_reload: Instant,
     load_rx: Receiver<LoadResult>,
     load_tx: Sender<LoadResult>,
     loaded: HashSet<String>,
-    modules: HashMap<String, wgpu::ShaderModule>,
+    /// Shared with the pipelines built on the thread pool.
+    modules: HashMap<String, Arc<wgpu::ShaderModule>>,
+    /// Shaders loaded again since the last call to `take_reloaded`.
+    reloaded_ids: Vec<String>,
+    sources: HashMap<String, String>,
 }
 
 impl ShaderAssets {
     pub fn new() -> Self {
         let (load_tx, load_rx) = channel();
         Self {
-            cache: Arc::new(AssetCache::new("assets/shaders").unwrap()),
+            caches: AssetCaches::new("shaders"),
+            failed: HashSet::new(),
+            first_loads: Vec::new(),
+            first_failures: Vec::new(),
+            frame_failed: None,
             frame_reloaded: None,
             last_reload: Instant::now(),
             load_rx,
             load_tx,
             loaded: HashSet::new(),
             modules: HashMap::new(),
+            reloaded_ids: Vec::new(),
+            sources: HashMap::new(),
         }
     }
 
     pub fn hot_reload(&mut self, device: &wgpu::Device) {
+        self.frame_failed = None;
         self.frame_reloaded = None;
-        self.cache.hot_reload();
+        self.caches.hot_reload();
 
         let keys = self.modules.keys().cloned().collect::<Vec<_>>();
         for shader_id in keys {
-            let handle = self.cache.load_expect::<WgslSource>(&shader_id);
+            let (cache, local_id) = self.caches.resolve(&shader_id);
+            let handle = cache.load_expect::<WgslSource>(local_id);
             if self.last_reload.elapsed() >= RELOAD_DEBOUNCE
                 && handle.reloaded_global()
             {
@@ -68,20 +121,30 @@ impl ShaderAssets {
         }
 
         if let Ok((shader_id, result)) = self.load_rx.try_recv() {
+            let result = result.and_then(|source| {
+                let module = create_module(device, &shader_id, &source)?;
+                Ok((source, module))
+            });
             match result {
-                Ok(source) => {
+                Ok((source, module)) => {
                     info!("Shader loaded: {}", shader_id);
-                    let module = device.create_shader_module(
-                        wgpu::ShaderModuleDescriptor {
-                            label: Some(&format!("{}_module", shader_id)),
-                            source: wgpu::ShaderSource::Wgsl(source.into()),
-                        },
-                    );
                     self.frame_reloaded = Some(shader_id.clone());
-                    self.modules.insert(shader_id, module);
+                    self.failed.remove(&shader_id);
+                    match self.modules.contains_key(&shader_id) {
+                        true => self.reloaded_ids.push(shader_id.clone()),
+                        false => self.first_loads.push(shader_id.clone()),
+                    }
+                    self.sources.insert(shader_id.clone(), source);
+                    self.modules.insert(shader_id, Arc::new(module));
                 }
                 Err(err) => {
                     error!("load\n{:?}", err);
+                    warn!("Shader {} drawn as {}", shader_id, FALLBACK_SHADER);
+                    self.frame_failed = Some(shader_id.clone());
+                    if !self.modules.contains_key(&shader_id) {
+                        self.first_failures.push(shader_id.clone());
+                    }
+                    self.failed.insert(shader_id.clone());
                     self.loaded.remove(&shader_id);
                 }
             };
@@ -89,7 +152,8 @@ impl ShaderAssets {
     }
 
     pub fn reloaded(&mut self, shader_id: &str) -> bool {
-        let handle = self.cache.load_expect::<WgslSource>(shader_id);
+        let (cache, local_id) = self.caches.resolve(shader_id);
+        let handle = cache.load_expect::<WgslSource>(local_id);
         if self.last_reload.elapsed() >= RELOAD_DEBOUNCE {
             self.last_reload = Instant::now();
             return handle.reloaded_global();
@@ -97,24 +161,89 @@ impl ShaderAssets {
         false
     }
 
+    pub fn take_reloaded(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.reloaded_ids)
+    }
+
+    pub fn take_first_loads(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.first_loads)
+    }
+
+    pub fn take_first_failures(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.first_failures)
+    }
+
+    /// Whether the last load of the shader failed.
+    pub fn has_failed(&self, shader_id: &str) -> bool {
+        self.failed.contains(shader_id)
+    }
+
+    /// The fallback module when the shader failed to load, for the model
+    /// pipelines whose layout it matches.
+    pub fn fallback(&self, shader_id: &str) -> Option<Arc<wgpu::ShaderModule>> {
+        self.failed
+            .contains(shader_id)
+            .then(|| self.get_shared(FALLBACK_SHADER))
+            .flatten()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &String> {
+        self.failed.iter()
+    }
+
     pub fn get(&self, shader_id: &str) -> Option<&wgpu::ShaderModule> {
-        self.modules.get(shader_id)
+        self.modules.get(shader_id).map(Arc::as_ref)
+    }
+
+    pub fn get_shared(
+        &self,
+        shader_id: &str,
+    ) -> Option<Arc<wgpu::ShaderModule>> {
+        self.modules.get(shader_id).cloned()
+    }
+
+    /// Whether the loaded source declares the function, to only build the
+    /// pipeline variants a shader provides.
+    pub fn has_entry_point(&self, shader_id: &str, entry_point: &str) -> bool {
+        self.sources.get(shader_id).is_some_and(|source| {
+            source.contains(&format!("fn {}(", entry_point))
+        })
+    }
+
+    /// Whether the loaded source declares the pipeline overridable
+    /// constant.
+    pub fn declares_override(&self, shader_id: &str, name: &str) -> bool {
+        let declaration = format!("override {}", name);
+        self.sources.get(shader_id).is_some_and(|source| {
+            source.match_indices(&declaration).any(|(index, _)| {
+                source[index + declaration.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| !c.is_alphanumeric() && c != '_')
+            })
+        })
     }
 
     fn load_internal(&mut self, shader_id: &str) {
-        let cache = self.cache.clone();
-        let module_id = shader_id.to_string();
+        let (cache, local_id) = self.caches.resolve(shader_id);
+        let (module_id, local_id) =
+            (shader_id.to_string(), local_id.to_string());
         let load_tx = self.load_tx.clone();
 
         get_pool().execute(move || {
             let result = (|| {
-                let source = cache.load::<WgslSource>(&module_id)?.read();
+                let source = cache.load::<WgslSource>(&local_id)?.read();
                 Ok(source.0.clone())
             })();
             load_tx.send((module_id, result)).unwrap();
         });
     }
 
+    /// Whether the shader was requested and is still loading.
+    pub fn is_pending(&self, shader_id: &str) -> bool {
+        self.loaded.contains(shader_id) && !self.modules.contains_key(shader_id)
+    }
+
     pub fn load(&mut self, shader_id: &str) {
         if self.loaded.contains(shader_id) {
             return;
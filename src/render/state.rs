@@ -1,31 +1,108 @@
-use std::{fmt, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+    time::Instant,
+};
 
-use winit::{dpi::PhysicalSize, window::Window};
+use glam::{Mat4, Quat, Vec2, Vec3};
+use image::DynamicImage;
+use log::{error, info, warn};
+use winit::{
+    dpi::PhysicalSize,
+    window::{Window, WindowId},
+};
 
-use crate::scene::Scene;
+#[cfg(feature = "ui")]
+use crate::ui::Ui;
+use crate::{bounds::Aabb, events::EngineEvent, scene::Scene};
 
 use super::{
-    bundle::{Bundles, Layouts},
-    material::MaterialManager,
-    mesh::MeshAssets,
-    shader::ShaderAssets,
-    texture::{Texture, TextureAssets},
+    adapter::AdapterOptions,
+    buffers::NamedBuffers,
+    bundle::{
+        globals::Environment,
+        impostor, model,
+        output::{self, DynamicResolution},
+        pathtrace::Renderer,
+        Bundles, Layouts,
+    },
+    camera::Camera,
+    export::FrameExporter,
+    material::{
+        custom::{CustomMaterial, UniformBlock},
+        file::{MaterialDescriptor, MaterialFiles},
+        simple::SimpleMaterial,
+        Material, MaterialManager,
+    },
+    mesh::{Mesh, MeshAssets},
+    morph::MorphedMeshes,
+    point_cloud::PointCloudAssets,
+    quality::Quality,
+    recording::Recorder,
+    screenshot::{ScreenshotDiff, Screenshots},
+    shader::{ShaderAssets, FALLBACK_SHADER},
+    stats::Stats,
+    terrain::{Terrain, TerrainAssets},
+    texture::{SamplerSettings, Texture, TextureAssets},
+    timer::GpuTimer,
+    view::{self, Viewport, WindowView},
 };
 
+/// Heatmap of the last screenshot comparison.
+pub const SCREENSHOT_DIFF_TEXTURE: &str = "screenshot_diff";
+
 pub struct RenderState {
-    _adapter: wgpu::Adapter,
-    _instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    pub buffers: NamedBuffers,
     pub bundles: Bundles,
     config: wgpu::SurfaceConfiguration,
     pub depth: Texture,
-    pub device: wgpu::Device,
+    /// Shared with the pipelines built on the thread pool.
+    pub device: Arc<wgpu::Device>,
+    /// Rendered frames, for the asset residency.
+    frame: u64,
+    /// Frames per second drawn at most, on top of the power settings.
+    pub frame_cap: Option<f32>,
+    /// Layers of the batches not drawn, by name.
+    pub hidden_layers: BTreeSet<String>,
+    /// Meshes, textures and shaders loaded for the first time during the
+    /// last frame, by kind and id.
+    pub last_loaded: Vec<(&'static str, String)>,
+    instance: wgpu::Instance,
+    last_frame: Instant,
     layouts: Layouts,
+    /// Assets queued by the scripts together, by kind and id.
+    preloads: Vec<(&'static str, String)>,
+    /// Rect of the window the scene camera is drawn in, the offset and
+    /// size as fractions.
+    pub main_viewport: [f32; 4],
+    material_files: MaterialFiles,
     pub materials: MaterialManager,
+    /// Bytes of meshes and textures kept loaded, the least recently used
+    /// are evicted above it.
+    pub memory_budget: Option<u64>,
     pub meshes: MeshAssets,
+    morphed: MorphedMeshes,
+    pub point_clouds: PointCloudAssets,
+    /// Last preset applied, none until a script picks one.
+    quality: Option<Quality>,
     queue: wgpu::Queue,
+    pub exporter: FrameExporter,
+    pub recorder: Recorder,
+    /// Meshes of the ropes drawn last frame, removed once their rope is no
+    /// longer attached.
+    rope_meshes: BTreeSet<String>,
+    pub screenshots: Screenshots,
     pub shaders: ShaderAssets,
+    pub stats: Stats,
     surface: wgpu::Surface<'static>,
+    pub terrains: TerrainAssets,
     pub textures: TextureAssets,
+    timer: Option<GpuTimer>,
+    /// Cameras of the scripts drawn over the scene camera, in order.
+    pub viewports: Vec<Viewport>,
+    pub views: Vec<WindowView>,
 }
 
 impl fmt::Debug for RenderState {
@@ -35,14 +112,29 @@ impl fmt::Debug for RenderState {
 }
 
 impl RenderState {
-    pub async fn new(window: Arc<Window>) -> Self {
-        let instance = wgpu::Instance::default();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions::default())
-            .await
-            .unwrap();
+    pub async fn new(
+        window: Arc<Window>,
+        options: &AdapterOptions,
+    ) -> anyhow::Result<Self> {
+        let instance = options.create_instance();
+        let adapter = options.request_adapter(&instance).await?;
+        let info = adapter.get_info();
+        info!("Adapter {} with {:?}", info.name, info.backend);
         let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    required_features: adapter.features()
+                        & (wgpu::Features::POLYGON_MODE_LINE
+                            | wgpu::Features::TIMESTAMP_QUERY
+                            | wgpu::Features::MULTI_DRAW_INDIRECT
+                            | wgpu::Features::INDIRECT_FIRST_INSTANCE
+                            | wgpu::Features::TEXTURE_COMPRESSION_BC
+                            | wgpu::Features::TEXTURE_COMPRESSION_ASTC
+                            | wgpu::Features::TEXTURE_COMPRESSION_ETC2),
+                    ..Default::default()
+                },
+                None,
+            )
             .await
             .unwrap();
 
@@ -55,40 +147,111 @@ impl RenderState {
         surface.configure(&device, &config);
 
         let mut shaders = ShaderAssets::new();
-        let mut textures = TextureAssets::new();
-        let meshes = MeshAssets::new();
+        let mut textures = TextureAssets::new(device.features());
+        let mut meshes = MeshAssets::new();
+        meshes.insert_primitives(&device);
+        textures.insert_fallback(&device, &queue);
+        shaders.load(FALLBACK_SHADER);
         let mut materials = MaterialManager::new();
         let layouts = Layouts::new(&device);
         let bundles = Bundles::new(
             &device,
+            &config,
             &layouts,
             &mut shaders,
             &mut textures,
             &mut materials,
         );
-        let depth = Texture::create_depth(&device, &config);
+        let depth = Texture::create_depth(&device, config.width, config.height);
+        let timer = GpuTimer::new(&device, &queue);
+        let stats = Stats::new(
+            config.format,
+            output::needs_srgb_conversion(config.format),
+        );
 
-        Self {
-            _adapter: adapter,
+        Ok(Self {
+            adapter,
+            buffers: NamedBuffers::new(),
             bundles,
             config,
             depth,
-            device,
-            _instance: instance,
+            device: Arc::new(device),
+            frame: 0,
+            frame_cap: None,
+            hidden_layers: BTreeSet::new(),
+            last_loaded: Vec::new(),
+            preloads: Vec::new(),
+            instance,
+            last_frame: Instant::now(),
             layouts,
+            main_viewport: [0.0, 0.0, 1.0, 1.0],
+            material_files: MaterialFiles::new(),
             materials,
+            memory_budget: None,
             meshes,
+            morphed: MorphedMeshes::default(),
+            point_clouds: PointCloudAssets::new(),
+            quality: None,
             queue,
+            exporter: FrameExporter::new(),
+            recorder: Recorder::new(),
+            rope_meshes: BTreeSet::new(),
+            screenshots: Screenshots::new(),
             shaders,
+            stats,
             surface,
+            terrains: TerrainAssets::new(),
             textures,
-        }
+            timer,
+            viewports: Vec::new(),
+            views: Vec::new(),
+        })
     }
 
     pub fn hot_reload(&mut self) {
         self.shaders.hot_reload(&self.device);
         self.meshes.hot_reload(&self.device);
+        for (key, source) in self.meshes.take_source_materials() {
+            let texture_id = source
+                .texture_id
+                .as_deref()
+                .unwrap_or(model::DEFAULT_TEXTURE);
+            let mut material = self.simple_material(
+                model::DEFAULT_SHADER,
+                texture_id,
+                &BTreeMap::new(),
+            );
+            material.uniform.color = Vec3::from(source.color);
+            self.materials.add(&key, material);
+        }
+        self.point_clouds.hot_reload();
+        self.terrains.hot_reload();
         self.textures.hot_reload(&self.device, &self.queue);
+        for (key, descriptor) in self.material_files.hot_reload() {
+            if descriptor.block.is_empty() {
+                let material = self.simple_material(
+                    &descriptor.shader_id,
+                    &descriptor.texture_id,
+                    &descriptor.constants,
+                );
+                self.add_described_material(&key, &descriptor, material);
+                continue;
+            }
+            let block = match UniformBlock::parse(&descriptor.block) {
+                Ok(block) => block,
+                Err(err) => {
+                    error!("material {}\n{:?}", key, err);
+                    continue;
+                }
+            };
+            let material = self.custom_material(
+                &descriptor.shader_id,
+                &descriptor.texture_id,
+                &descriptor.constants,
+                block,
+            );
+            self.add_described_material(&key, &descriptor, material);
+        }
         self.bundles.hot_reload(
             &self.device,
             &self.config,
@@ -97,14 +260,753 @@ impl RenderState {
         );
     }
 
+    fn add_described_material<M: Material>(
+        &mut self,
+        key: &str,
+        descriptor: &MaterialDescriptor,
+        mut material: M,
+    ) {
+        for (name, value) in descriptor.params() {
+            if !material.set_param(name, value) {
+                warn!("material {} has no {:?} param {}", key, value, name);
+            }
+        }
+        self.materials.add(key, material);
+    }
+
+    /// Load the shader and texture, returns the pipeline of the shader
+    /// built with the values of its `override` constants.
+    fn load_material_assets(
+        &mut self,
+        shader_id: &str,
+        texture_id: &str,
+        constants: &BTreeMap<String, f64>,
+    ) -> String {
+        self.shaders.load(shader_id);
+        self.textures.load(texture_id);
+        self.bundles.model.register_variant(shader_id, constants)
+    }
+
+    /// Material of the shader and texture, loading both.
+    pub fn simple_material(
+        &mut self,
+        shader_id: &str,
+        texture_id: &str,
+        constants: &BTreeMap<String, f64>,
+    ) -> SimpleMaterial {
+        let pipeline_id =
+            self.load_material_assets(shader_id, texture_id, constants);
+        SimpleMaterial::new(&pipeline_id, texture_id)
+    }
+
+    /// Material of a shader declaring the uniform block at group 2.
+    pub fn custom_material(
+        &mut self,
+        shader_id: &str,
+        texture_id: &str,
+        constants: &BTreeMap<String, f64>,
+        block: UniformBlock,
+    ) -> CustomMaterial {
+        let pipeline_id =
+            self.load_material_assets(shader_id, texture_id, constants);
+        CustomMaterial::new(&pipeline_id, texture_id, block)
+    }
+
+    pub fn surface_size(&self) -> PhysicalSize<u32> {
+        PhysicalSize::new(self.config.width, self.config.height)
+    }
+
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         self.config.width = size.width;
         self.config.height = size.height;
         self.surface.configure(&self.device, &self.config);
-        self.depth = Texture::create_depth(&self.device, &self.config);
+        self.resize_targets();
+    }
+
+    /// Recreate the textures the scene is rendered into.
+    fn resize_targets(&mut self) {
+        self.bundles
+            .output
+            .resize(&self.device, &self.config, &self.layouts);
+        let [width, height] = self.bundles.output.target_size(&self.config);
+        self.depth = Texture::create_depth(&self.device, width, height);
+        self.bundles.accumulation.resize(
+            &self.device,
+            &self.layouts,
+            &self.bundles.output,
+            [width, height],
+        );
+        self.bundles.pathtrace.resize(
+            &self.device,
+            &self.layouts,
+            &self.bundles.output,
+            [width, height],
+        );
+    }
+
+    /// Fragment shader drawn in place of the scene, none to go back to it.
+    pub fn set_fullscreen_shader(&mut self, shader_id: Option<String>) {
+        self.bundles
+            .fullscreen
+            .set_shader(&mut self.shaders, shader_id);
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.bundles.pathtrace.set_renderer(renderer);
+        self.resize_targets();
     }
 
-    pub fn render(&mut self, elapsed: f32, scene: &mut Scene) {
+    /// Average the frames while the camera and the scene are still.
+    pub fn set_accumulation(&mut self, enabled: bool) {
+        self.bundles.accumulation.set_enabled(enabled);
+        self.resize_targets();
+    }
+
+    pub fn set_dynamic_resolution(
+        &mut self,
+        dynamic: Option<DynamicResolution>,
+    ) {
+        self.bundles.output.set_dynamic(dynamic);
+        self.resize_targets();
+    }
+
+    pub fn quality(&self) -> Option<Quality> {
+        self.quality
+    }
+
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.quality = Some(quality);
+        self.bundles.model.depth_prepass = quality.depth_prepass();
+    }
+
+    /// Fails when the adapter can not execute indirect draws.
+    pub fn set_indirect_draws(&mut self, enabled: bool) -> anyhow::Result<()> {
+        let flags = self.adapter.get_downlevel_capabilities().flags;
+        if enabled && !flags.contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+        {
+            anyhow::bail!("indirect draws are not supported by the adapter");
+        }
+        self.bundles.model.indirect = enabled;
+        self.bundles.model.multi_draw = self.device.features().contains(
+            wgpu::Features::MULTI_DRAW_INDIRECT
+                | wgpu::Features::INDIRECT_FIRST_INSTANCE,
+        );
+        Ok(())
+    }
+
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    pub fn limits(&self) -> wgpu::Limits {
+        self.device.limits()
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Fails when the surface does not support the mode.
+    pub fn set_present_mode(
+        &mut self,
+        mode: wgpu::PresentMode,
+    ) -> anyhow::Result<()> {
+        let capabilities = self.surface.get_capabilities(&self.adapter);
+        if !capabilities.present_modes.contains(&mode) {
+            anyhow::bail!("present mode {:?} is not supported", mode);
+        }
+        self.config.present_mode = mode;
+        self.surface.configure(&self.device, &self.config);
+        Ok(())
+    }
+
+    pub fn set_exposure(&mut self, ev: f32) {
+        self.bundles.output.set_exposure(&self.queue, ev);
+    }
+
+    pub fn set_fade(&mut self, fade: f32) {
+        self.bundles.output.set_fade(&self.queue, fade);
+    }
+
+    pub fn create_buffer(&mut self, name: &str, size: u64) {
+        self.buffers.create(&self.device, name, size);
+    }
+
+    pub fn write_buffer(
+        &self,
+        name: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.buffers.write(&self.queue, name, offset, data)
+    }
+
+    /// Meshes, textures and shaders loaded for the first time since the
+    /// last call, kept until the next one, followed by the ones that failed
+    /// to load before ever loading.
+    pub fn take_loaded(&mut self) -> Vec<EngineEvent> {
+        let meshes = self.meshes.take_first_loads().into_iter();
+        let textures = self.textures.take_first_loads().into_iter();
+        let shaders = self.shaders.take_first_loads().into_iter();
+        self.last_loaded = meshes
+            .map(|id| ("mesh", id))
+            .chain(textures.map(|id| ("texture", id)))
+            .chain(shaders.map(|id| ("shader", id)))
+            .collect();
+        let meshes = self.meshes.take_first_failures().into_iter();
+        let textures = self.textures.take_first_failures().into_iter();
+        let shaders = self.shaders.take_first_failures().into_iter();
+        let failures = meshes
+            .map(|id| ("mesh", id))
+            .chain(textures.map(|id| ("texture", id)))
+            .chain(shaders.map(|id| ("shader", id)))
+            .map(|(kind, id)| EngineEvent::AssetFailed { kind, id });
+        self.last_loaded
+            .iter()
+            .map(|(kind, id)| EngineEvent::AssetLoaded {
+                kind,
+                id: id.clone(),
+            })
+            .chain(failures)
+            .collect()
+    }
+
+    /// Start loading the asset of the kind, counted by the loading progress.
+    /// The assets of a finished preload are forgotten by the next one.
+    pub fn preload(&mut self, kind: &'static str, id: &str) {
+        if self.loading_progress() >= 1.0 {
+            self.preloads.clear();
+        }
+        match kind {
+            "mesh" => self.meshes.load(id),
+            "texture" => self.textures.load(id),
+            _ => self.shaders.load(id),
+        }
+        self.preloads.push((kind, id.to_string()));
+    }
+
+    /// Fraction of the preloaded assets done loading, the failed ones
+    /// included, one when nothing is preloading.
+    pub fn loading_progress(&self) -> f32 {
+        if self.preloads.is_empty() {
+            return 1.0;
+        }
+        let pending = self
+            .preloads
+            .iter()
+            .filter(|(kind, id)| match *kind {
+                "mesh" => self.meshes.is_pending(id),
+                "texture" => self.textures.is_pending(id),
+                _ => self.shaders.is_pending(id),
+            })
+            .count();
+        1.0 - pending as f32 / self.preloads.len() as f32
+    }
+
+    /// Whether the asset of the kind is ready to be drawn with, not the
+    /// fallback drawn in place of a failed one.
+    pub fn is_loaded(&self, kind: &str, id: &str) -> Option<bool> {
+        match kind {
+            "mesh" => Some(
+                self.meshes.get(id).is_some() && !self.meshes.has_failed(id),
+            ),
+            "texture" => Some(
+                self.textures.get(id).is_some()
+                    && !self.textures.has_failed(id),
+            ),
+            "shader" => Some(self.shaders.get(id).is_some()),
+            _ => None,
+        }
+    }
+
+    /// Whether the last load of the asset of the kind failed.
+    pub fn has_failed(&self, kind: &str, id: &str) -> bool {
+        match kind {
+            "mesh" => self.meshes.has_failed(id),
+            "texture" => self.textures.has_failed(id),
+            _ => self.shaders.has_failed(id),
+        }
+    }
+
+    /// Meshes, textures and shaders loaded again since the last call.
+    pub fn take_reloaded(&mut self) -> Vec<EngineEvent> {
+        let meshes = self
+            .meshes
+            .take_reloaded()
+            .into_iter()
+            .map(|id| EngineEvent::AssetReloaded { kind: "mesh", id });
+        let textures = self.textures.take_reloaded().into_iter().map(|id| {
+            EngineEvent::AssetReloaded {
+                kind: "texture",
+                id,
+            }
+        });
+        let shaders = self
+            .shaders
+            .take_reloaded()
+            .into_iter()
+            .map(|id| EngineEvent::AssetReloaded { kind: "shader", id });
+        meshes.chain(textures).chain(shaders).collect()
+    }
+
+    /// Buffers read back since the last call, by request id.
+    pub fn take_readbacks(&mut self) -> Vec<(u64, anyhow::Result<Vec<u8>>)> {
+        self.buffers.take_mapped(&self.device)
+    }
+
+    /// Mark the assets drawn this frame, then evict the least recently used
+    /// ones while over the memory budget.
+    fn track_residency(&mut self, scene: &Scene) {
+        self.frame += 1;
+        self.meshes.residency.begin_frame(self.frame);
+        self.textures.residency.begin_frame(self.frame);
+        for mesh_id in scene.model_batches.mesh_ids() {
+            self.meshes.touch(mesh_id);
+        }
+        for material_id in scene.model_batches.material_ids() {
+            if let Some(texture_id) = self.materials.get_texture_id(material_id)
+            {
+                self.textures.touch(&texture_id);
+            }
+        }
+
+        self.stats.mesh_memory = self.meshes.residency.total();
+        self.stats.texture_memory = self.textures.residency.total();
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+        let mut used = self.stats.mesh_memory + self.stats.texture_memory;
+        if used <= budget {
+            return;
+        }
+        let mut idle =
+            self.meshes
+                .residency
+                .idle()
+                .map(|(id, last_used, bytes)| (last_used, bytes, id, true))
+                .chain(self.textures.residency.idle().map(
+                    |(id, last_used, bytes)| (last_used, bytes, id, false),
+                ))
+                .map(|(last_used, bytes, id, mesh)| {
+                    (last_used, bytes, id.to_string(), mesh)
+                })
+                .collect::<Vec<_>>();
+        idle.sort_by_key(|(last_used, ..)| *last_used);
+        for (_, bytes, id, mesh) in idle {
+            if used <= budget {
+                break;
+            }
+            match mesh {
+                true => self.meshes.evict(&id),
+                false => self.textures.evict(&id),
+            }
+            used -= bytes;
+        }
+    }
+
+    /// Tonemap the path traced or the accumulated frames when there are
+    /// some, the scene target otherwise.
+    fn tonemap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) {
+        if self.bundles.fullscreen.render(encoder, view) {
+            return;
+        }
+        let output = &self.bundles.output;
+        let bind_group = self
+            .bundles
+            .pathtrace
+            .output_bind_group()
+            .or(self.bundles.accumulation.output_bind_group());
+        let rect = Some(self.pixel_rect(self.main_viewport));
+        match bind_group {
+            Some(bind_group) => {
+                output.render_target(encoder, bind_group, view, rect)
+            }
+            None => output.render(encoder, view, rect),
+        }
+        for viewport in &self.viewports {
+            let rect = self.pixel_rect(viewport.rect);
+            output.render_over(
+                encoder,
+                &viewport.output_bind_group,
+                view,
+                rect,
+            );
+        }
+    }
+
+    /// Tonemap the scene again into a texture that can be copied, without
+    /// the ui.
+    fn capture_frame(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        record: bool,
+        export: bool,
+    ) {
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("screenshot_texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.tonemap(encoder, &view);
+        self.screenshots.record(&self.device, encoder, &texture);
+        if record {
+            self.recorder.record(&self.device, encoder, &texture);
+        }
+        if export {
+            self.exporter.record(&self.device, encoder, &texture);
+        }
+    }
+
+    /// Screenshot comparisons done since the last call, by request id. The
+    /// last heatmap is kept as the `screenshot_diff` texture.
+    pub fn take_screenshot_diffs(
+        &mut self,
+    ) -> Vec<(u64, anyhow::Result<ScreenshotDiff>)> {
+        let diffs = self.screenshots.take_compared(&self.device);
+        for (_, diff) in &diffs {
+            let Ok(diff) = diff else {
+                continue;
+            };
+            let texture = Texture::from_image(
+                &self.device,
+                &self.queue,
+                &DynamicImage::ImageRgba8(diff.heatmap.clone()),
+                SCREENSHOT_DIFF_TEXTURE,
+                &SamplerSettings::default(),
+            );
+            self.textures.insert(SCREENSHOT_DIFF_TEXTURE, texture);
+        }
+        diffs
+    }
+
+    pub fn has_view(&self, name: &str) -> bool {
+        self.views.iter().any(|view| view.name == name)
+    }
+
+    pub fn add_view(
+        &mut self,
+        name: &str,
+        window: Arc<Window>,
+    ) -> anyhow::Result<()> {
+        let mut view = WindowView::new(
+            &self.instance,
+            &self.adapter,
+            &self.device,
+            &self.layouts,
+            self.config.format,
+            name,
+            window,
+        )?;
+        view.bind_output(&self.device, &self.layouts, &self.bundles.output);
+        self.views.push(view);
+        Ok(())
+    }
+
+    /// Cameras with their rect by name, the viewports of the missing ones
+    /// are dropped.
+    pub fn set_viewports(&mut self, cameras: Vec<(String, Camera, [f32; 4])>) {
+        let window_size = [self.config.width, self.config.height];
+        self.viewports
+            .retain(|viewport| cameras.iter().any(|c| c.0 == viewport.name));
+        for (name, camera, rect) in cameras {
+            let index = self
+                .viewports
+                .iter()
+                .position(|viewport| viewport.name == name)
+                .unwrap_or_else(|| {
+                    self.viewports.push(Viewport::new(
+                        &self.device,
+                        &self.layouts,
+                        &self.bundles.output,
+                        &name,
+                        rect,
+                        window_size,
+                    ));
+                    self.viewports.len() - 1
+                });
+            let viewport = &mut self.viewports[index];
+            viewport.camera = camera;
+            viewport.rect = rect;
+            viewport.resize(
+                &self.device,
+                &self.layouts,
+                &self.bundles.output,
+                window_size,
+            );
+        }
+    }
+
+    /// Aspect ratio of the rect the scene camera is drawn in.
+    fn main_aspect_ratio(&self) -> f32 {
+        let [width, height] = view::pixel_size(
+            self.main_viewport,
+            [self.config.width, self.config.height],
+        );
+        width as f32 / height as f32
+    }
+
+    /// Rect in pixels of the surface, rounded and kept inside it with at
+    /// least a pixel, as wgpu requires of a viewport.
+    fn pixel_rect(&self, rect: [f32; 4]) -> [f32; 4] {
+        let size = [self.config.width as f32, self.config.height as f32];
+        let offset = [0, 1]
+            .map(|i| (rect[i] * size[i]).round().min(size[i] - 1.0).max(0.0));
+        let extent = [0, 1].map(|i| {
+            (rect[i + 2] * size[i])
+                .round()
+                .min(size[i] - offset[i])
+                .max(1.0)
+        });
+        [offset[0], offset[1], extent[0], extent[1]]
+    }
+
+    /// Returns the name of the removed view.
+    pub fn remove_view(&mut self, id: WindowId) -> Option<String> {
+        let index =
+            self.views.iter().position(|view| view.window.id() == id)?;
+        Some(self.views.remove(index).name)
+    }
+
+    pub fn resize_view(&mut self, id: WindowId, size: PhysicalSize<u32>) {
+        if let Some(view) =
+            self.views.iter_mut().find(|view| view.window.id() == id)
+        {
+            view.resize(&self.device, size);
+            view.bind_output(&self.device, &self.layouts, &self.bundles.output);
+        }
+    }
+
+    /// Stitch the tiles in a terrain mesh, false while they are loading.
+    pub fn build_terrain(
+        &mut self,
+        mesh_id: &str,
+        tile_ids: &[Vec<String>],
+        overlap: bool,
+        spacing: f32,
+        vertical_scale: f32,
+    ) -> anyhow::Result<bool> {
+        tile_ids
+            .iter()
+            .flatten()
+            .for_each(|id| self.terrains.load(id));
+        let mut tiles = Vec::new();
+        for row in tile_ids {
+            let mut row_tiles = Vec::new();
+            for tile_id in row {
+                match self.terrains.get_tile(tile_id) {
+                    Some(tile) => row_tiles.push(tile),
+                    None => return Ok(false),
+                }
+            }
+            tiles.push(row_tiles);
+        }
+        let terrain =
+            Terrain::stitch(&tiles, overlap, spacing, vertical_scale)?;
+        let (vertices, indices) = terrain.build_mesh();
+        let aabb =
+            Aabb::from_points(vertices.iter().map(|v| Vec3::from(v.position)));
+        self.meshes.insert(
+            mesh_id,
+            Mesh::new(&self.device, &vertices, &indices, aabb, mesh_id),
+        );
+        self.terrains.terrains.insert(mesh_id.to_string(), terrain);
+        Ok(true)
+    }
+
+    /// Draw every batched instance into the secondary windows, returns
+    /// their frames to present once submitted.
+    fn render_views(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        environment: &Environment,
+        scene: &Scene,
+        draw_calls: &mut u32,
+    ) -> Vec<wgpu::SurfaceTexture> {
+        let mut frames = Vec::new();
+        for window_view in &self.views {
+            let Some(frame) = window_view.current_texture() else {
+                continue;
+            };
+            let view = frame
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            window_view.prepare(&self.queue, environment);
+            {
+                let mut rpass = window_view.begin_pass(encoder);
+                let lights = &self.bundles.lights;
+                rpass.set_bind_group(1, &lights.unclustered_bind_group, &[]);
+                *draw_calls += scene.model_batches.render_filtered(
+                    &mut rpass,
+                    &self.bundles.model,
+                    &self.meshes,
+                    &self.materials,
+                    |_| true,
+                );
+            }
+            if let Some(bind_group) = &window_view.output_bind_group {
+                self.bundles
+                    .output
+                    .render_target(encoder, bind_group, &view, None);
+            }
+            frames.push(frame);
+        }
+        frames
+    }
+
+    /// Draw every instance from the cameras of the viewports, into their
+    /// own targets.
+    fn render_viewports(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        environment: &Environment,
+        scene: &Scene,
+    ) -> u32 {
+        let mut draw_calls = 0;
+        for viewport in &self.viewports {
+            viewport.prepare(&self.queue, environment);
+            let mut rpass = viewport.begin_pass(encoder);
+            let lights = &self.bundles.lights;
+            rpass.set_bind_group(1, &lights.unclustered_bind_group, &[]);
+            draw_calls += scene.model_batches.render_filtered(
+                &mut rpass,
+                &self.bundles.model,
+                &self.meshes,
+                &self.materials,
+                |_| true,
+            );
+        }
+        draw_calls
+    }
+
+    /// Insert the atlas, quad and material of the impostors baked this
+    /// frame.
+    fn bake_impostors(&mut self) {
+        let baked = self.bundles.impostor.bake(
+            &self.device,
+            &self.queue,
+            &self.layouts,
+            &self.meshes,
+            &self.materials,
+            &self.textures,
+        );
+        for baked in baked {
+            let impostor_id = &baked.impostor.impostor_id;
+            self.textures.insert(impostor_id, baked.texture);
+            self.meshes.insert(impostor_id, baked.mesh);
+            self.materials.add(
+                impostor_id,
+                SimpleMaterial::new(impostor::SHADER, impostor_id),
+            );
+            self.bundles
+                .impostor
+                .impostors
+                .insert(baked.mesh_id, baked.impostor);
+        }
+        // The impostors follow the color and variations of their source.
+        for impostor in self.bundles.impostor.impostors.values() {
+            let (impostor_id, material_id) =
+                (&impostor.impostor_id, &impostor.material_id);
+            if !self.materials.is_dirty(impostor_id)
+                && !self.materials.is_dirty(material_id)
+            {
+                continue;
+            }
+            let Some(uniform) = self
+                .materials
+                .get_any(material_id)
+                .and_then(|material| material.downcast_ref::<SimpleMaterial>())
+                .map(|material| material.uniform)
+            else {
+                continue;
+            };
+            if let Some(material) = self
+                .materials
+                .get_mut_any(impostor_id)
+                .and_then(|material| material.downcast_mut::<SimpleMaterial>())
+            {
+                material.uniform = uniform;
+            }
+        }
+    }
+
+    /// Blend the meshes of the entities with morph weights, before they
+    /// are batched.
+    pub fn prepare_morphs(&mut self, scene: &mut Scene) {
+        scene.morphed = self.morphed.prepare(
+            &self.device,
+            &self.queue,
+            &mut self.meshes,
+            &scene.entities,
+        );
+    }
+
+    /// Rebuild the rope meshes from their simulated points and batch them.
+    fn prepare_ropes(&mut self, scene: &mut Scene) {
+        let mut rope_meshes = BTreeSet::new();
+        for (key, rope) in scene.ropes.iter() {
+            let mesh_id = format!("rope:{}", key);
+            rope_meshes.insert(mesh_id.clone());
+            let (vertices, indices) = rope.build_mesh();
+            let aabb = Aabb::from_points(
+                vertices.iter().map(|v| Vec3::from(v.position)),
+            );
+            self.meshes.insert(
+                &mesh_id,
+                Mesh::new(&self.device, &vertices, &indices, aabb, &mesh_id),
+            );
+            scene.model_batches.add_model(
+                mesh_id,
+                rope.settings.material_id.clone(),
+                model::Instance::new(Mat4::IDENTITY, Quat::IDENTITY),
+            );
+        }
+        for mesh_id in self.rope_meshes.difference(&rope_meshes) {
+            self.meshes.remove(mesh_id);
+        }
+        self.rope_meshes = rope_meshes;
+    }
+
+    pub fn render(
+        &mut self,
+        environment: &Environment,
+        scene: &mut Scene,
+        #[cfg(feature = "ui")] ui: &mut Ui,
+    ) {
+        let dt = self.last_frame.elapsed().as_secs_f32();
+        self.last_frame = Instant::now();
+        let gpu_time = self.timer.as_ref().and_then(|timer| timer.last);
+        // The cpu frame time waits for vsync, only the gpu time tells the
+        // scene cost.
+        if let Some(gpu_time) = gpu_time {
+            if self.bundles.output.update(dt, gpu_time) {
+                self.resize_targets();
+            }
+        }
+        self.stats.push_frame_time(dt);
+        self.recorder.poll(&self.device);
+        self.exporter.poll(&self.device);
+        self.stats.gpu_time = gpu_time;
+        self.stats.render_scale = self.bundles.output.scale();
+        self.stats.entities = scene.entities.count() as u32;
+        self.stats.lights = scene.point_lights.len() as u32;
+        scene.model_batches.set_hidden_layers(&self.hidden_layers);
+        let mut draw_calls = 0;
+
         let frame = self
             .surface
             .get_current_texture()
@@ -116,17 +1018,86 @@ impl RenderState {
             &wgpu::CommandEncoderDescriptor { label: None },
         );
 
-        self.bundles.globals.prepare(
+        self.bundles.lights.prepare(
+            &self.device,
             &self.queue,
-            &self.config,
-            elapsed,
-            &scene.camera,
+            scene,
+            self.main_aspect_ratio(),
+            self.bundles.output.target_size(&self.config),
         );
-        self.bundles.lights.prepare(
+        self.bundles.probes.prepare(
             &self.device,
+            &self.queue,
             &self.layouts,
-            &scene.point_lights,
+            environment,
+            &mut scene.reflection_probes,
+            scene.camera.transform.pos,
+        );
+        let target_size = self.bundles.output.target_size(&self.config);
+        let jitter = self.bundles.accumulation.prepare(
+            &self.device,
+            &self.queue,
+            scene,
+            &self.meshes,
+            &self.materials,
+            target_size,
+        );
+        self.bundles.lights.bind(
+            &self.device,
+            &self.layouts,
+            &self.bundles.probes,
+            &self.bundles.accumulation,
+        );
+        let scene_format = self.bundles.output.scene_format();
+        let minimap_recreated = self.bundles.minimap.prepare(
+            &self.device,
+            &self.queue,
+            scene_format,
+            &mut self.textures,
+            environment,
+            &scene.camera,
+        );
+        self.prepare_ropes(scene);
+        self.bundles.globals.prepare(
+            &self.queue,
+            self.main_aspect_ratio(),
+            environment,
+            &scene.camera,
+            jitter.unwrap_or(Vec2::ZERO),
+        );
+        // Rebuilt, meshes no longer batched or evicted are dropped.
+        scene.mesh_bounds = scene
+            .model_batches
+            .mesh_ids()
+            .filter_map(|mesh_id| {
+                let mesh = self.meshes.get(mesh_id)?;
+                Some((mesh_id.clone(), mesh.aabb))
+            })
+            .collect();
+        self.bake_impostors();
+        scene.model_batches.select_impostors(
+            &self.bundles.impostor.impostors,
+            scene.camera.transform.pos,
         );
+        scene
+            .model_batches
+            .select_lods(&self.meshes, scene.camera.transform.pos);
+        scene.model_batches.select_submeshes(&self.meshes);
+        self.track_residency(scene);
+        let textures_changed = self.textures.take_changed();
+        let materials_replaced = self.materials.take_replaced();
+        if minimap_recreated || textures_changed || materials_replaced {
+            scene.model_batches.invalidate_materials();
+        }
+        let aspect_ratio = self.main_aspect_ratio();
+        scene
+            .model_batches
+            .cull(&self.meshes, &scene.camera.build_frustum(aspect_ratio));
+        self.stats.culled = scene.model_batches.culled;
+        self.stats.instances = scene.model_batches.instance_count() as u32;
+        self.stats.pipelines =
+            scene.model_batches.shader_instances(&self.materials);
+        scene.model_batches.sort(scene.camera.transform.pos);
         scene.model_batches.prepare(
             &self.device,
             &self.queue,
@@ -134,13 +1105,110 @@ impl RenderState {
             &self.textures,
             &self.materials,
         );
+        scene.model_batches.prepare_instances(
+            &self.device,
+            &self.layouts,
+            &self.meshes,
+            &self.materials,
+            &self.bundles.model,
+        );
+        scene.model_batches.prepare_indirect(
+            &self.device,
+            &self.queue,
+            &self.meshes,
+            &self.bundles.model,
+        );
+        self.stats.buffer_memory = scene.model_batches.buffer_memory();
+        self.materials.clear_dirty();
+        let [width, height] = self.bundles.output.target_size(&self.config);
+        self.bundles.points.prepare(
+            &self.device,
+            &self.layouts,
+            Vec2::new(width as f32, height as f32),
+            &scene.points,
+        );
+        self.bundles.fullscreen.prepare(
+            &self.queue,
+            &self.config,
+            environment.elapsed,
+            dt,
+        );
+        self.bundles.effect.prepare(
+            &self.device,
+            &self.queue,
+            &self.layouts,
+            &mut self.shaders,
+            scene.camera.transform.pos,
+            &scene.effect_planes,
+        );
+
+        let lights = &self.bundles.lights;
+        draw_calls += self.bundles.probes.render(&mut encoder, |rpass| {
+            rpass.set_bind_group(1, &lights.unclustered_bind_group, &[]);
+            scene.model_batches.render_filtered(
+                rpass,
+                &self.bundles.model,
+                &self.meshes,
+                &self.materials,
+                |_| true,
+            )
+        });
+
+        if let Some(mut rpass) = self
+            .bundles
+            .minimap
+            .begin_pass(&mut encoder, &self.textures)
+        {
+            let (minimap, materials) = (&self.bundles.minimap, &self.materials);
+            let lights = &self.bundles.lights;
+            rpass.set_bind_group(1, &lights.unclustered_bind_group, &[]);
+            draw_calls += scene.model_batches.render_filtered(
+                &mut rpass,
+                &self.bundles.model,
+                &self.meshes,
+                &self.materials,
+                |material_id| minimap.includes(materials, material_id),
+            );
+        }
+
+        let prepass = self.bundles.model.uses_prepass();
+        if prepass {
+            let mut rpass =
+                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("depth_prepass"),
+                    depth_stencil_attachment: Some(
+                        wgpu::RenderPassDepthStencilAttachment {
+                            view: &self.depth.view,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: wgpu::StoreOp::Store,
+                            }),
+                            stencil_ops: None,
+                        },
+                    ),
+                    ..Default::default()
+                });
+            rpass.set_bind_group(0, &self.bundles.globals.bind_group, &[]);
+            rpass.set_bind_group(1, &self.bundles.lights.bind_group, &[]);
+            draw_calls += scene.model_batches.render_depth(
+                &mut rpass,
+                &self.bundles.model,
+                &self.meshes,
+                &self.materials,
+            );
+        }
 
         {
             let mut rpass =
                 encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     color_attachments: &[Some(
                         wgpu::RenderPassColorAttachment {
-                            view: &view,
+                            view: self
+                                .bundles
+                                .output
+                                .target
+                                .as_ref()
+                                .map_or(&view, |target| &target.view),
                             resolve_target: None,
                             ops: wgpu::Operations {
                                 load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -157,26 +1225,129 @@ impl RenderState {
                         wgpu::RenderPassDepthStencilAttachment {
                             view: &self.depth.view,
                             depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
+                                load: match prepass {
+                                    true => wgpu::LoadOp::Load,
+                                    false => wgpu::LoadOp::Clear(1.0),
+                                },
                                 store: wgpu::StoreOp::Store,
                             }),
                             stencil_ops: None,
                         },
                     ),
+                    timestamp_writes: self
+                        .timer
+                        .as_ref()
+                        .map(GpuTimer::timestamp_writes),
                     ..Default::default()
                 });
 
             rpass.set_bind_group(0, &self.bundles.globals.bind_group, &[]);
-            rpass.set_bind_group(1, &self.bundles.lights.bind_group, &[]);
-            scene.model_batches.render(
-                &mut rpass,
-                &self.bundles.model,
-                &self.meshes,
-                &self.materials,
-            );
+            let lights = &self.bundles.lights;
+            match &self.bundles.model.isolated_material {
+                None => {
+                    rpass.set_bind_group(1, &lights.bind_group, &[]);
+                    draw_calls += scene.model_batches.render(
+                        &mut rpass,
+                        &self.bundles.model,
+                        &self.meshes,
+                        &self.materials,
+                    );
+                }
+                Some(isolated) => {
+                    rpass.set_bind_group(1, &lights.dimmed_bind_group, &[]);
+                    draw_calls += scene.model_batches.render_where(
+                        &mut rpass,
+                        &self.bundles.model,
+                        &self.meshes,
+                        &self.materials,
+                        |material_id| material_id != isolated,
+                    );
+                    rpass.set_bind_group(1, &lights.bind_group, &[]);
+                    draw_calls += scene.model_batches.render_where(
+                        &mut rpass,
+                        &self.bundles.model,
+                        &self.meshes,
+                        &self.materials,
+                        |material_id| material_id == isolated,
+                    );
+                }
+            }
+            self.bundles.points.render(&mut rpass);
+            draw_calls += self.bundles.effect.render(&mut rpass);
+        }
+
+        let batches = &scene.model_batches;
+        self.bundles.outline.prepare(
+            &self.device,
+            &self.layouts,
+            self.bundles.output.target_size(&self.config),
+            &scene.outlines,
+            |layer| batches.layer_hidden(layer),
+        );
+        draw_calls += self.bundles.outline.render(
+            &mut encoder,
+            self.bundles
+                .output
+                .target
+                .as_ref()
+                .map_or(&view, |target| &target.view),
+            &self.bundles.globals.bind_group,
+            &self.meshes,
+        );
+
+        draw_calls += self.render_viewports(&mut encoder, environment, scene);
+
+        if let Some(timer) = &mut self.timer {
+            timer.resolve(&mut encoder);
         }
+        self.bundles.accumulation.render(&mut encoder);
+        self.bundles.pathtrace.prepare(
+            &self.device,
+            &self.queue,
+            &self.layouts,
+            scene,
+            &self.meshes,
+            &self.materials,
+        );
+        self.bundles.pathtrace.render(&mut encoder);
+        self.tonemap(&mut encoder, &view);
+        let record = self.recorder.frame_due(dt);
+        let export = self.exporter.frame_due();
+        if self.screenshots.is_requested() || record || export {
+            self.capture_frame(&mut encoder, record, export);
+        }
+        #[cfg(feature = "ui")]
+        let ui_commands = self.bundles.ui.render(
+            &self.device,
+            &self.queue,
+            &self.config,
+            &mut encoder,
+            &view,
+            ui,
+        );
+        #[cfg(not(feature = "ui"))]
+        let ui_commands = Vec::new();
+        let view_frames = self.render_views(
+            &mut encoder,
+            environment,
+            scene,
+            &mut draw_calls,
+        );
+        self.stats.draw_calls = draw_calls;
+        self.buffers.record(&self.device, &mut encoder);
 
-        self.queue.submit(Some(encoder.finish()));
+        self.queue
+            .submit(ui_commands.into_iter().chain(Some(encoder.finish())));
+        self.buffers.map_recorded();
+        self.screenshots.map_recorded();
+        self.recorder.map_recorded();
+        self.exporter.map_recorded();
+        if let Some(timer) = &mut self.timer {
+            timer.read(&self.device);
+        }
         frame.present();
+        for view_frame in view_frames {
+            view_frame.present();
+        }
     }
 }
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+/// Frames kept in the frame time history.
+pub const HISTORY_LEN: usize = 120;
+
+/// Frame counters exposed to lua, filled during the last render.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// Bytes of the per frame instance, material and indirect buffers.
+    pub buffer_memory: u64,
+    pub culled: u32,
+    /// Draw calls of the models, in every pass and window.
+    pub draw_calls: u32,
+    pub entities: u32,
+    /// Cpu time between the last two frames in seconds.
+    pub frame_time: f32,
+    /// Frame times of the last frames, the oldest first.
+    pub frame_times: VecDeque<f32>,
+    /// Gpu time of the scene pass in seconds, when timestamps are supported.
+    pub gpu_time: Option<f32>,
+    /// Model instances batched, culled or not.
+    pub instances: u32,
+    pub lights: u32,
+    /// Bytes of the meshes and textures loaded from files.
+    pub mesh_memory: u64,
+    /// Instances batched this frame by shader.
+    pub pipelines: Vec<(String, u32)>,
+    pub render_scale: f32,
+    pub srgb_conversion: bool,
+    pub surface_format: wgpu::TextureFormat,
+    pub texture_memory: u64,
+}
+
+impl Stats {
+    pub fn new(
+        surface_format: wgpu::TextureFormat,
+        srgb_conversion: bool,
+    ) -> Self {
+        Self {
+            buffer_memory: 0,
+            culled: 0,
+            draw_calls: 0,
+            entities: 0,
+            frame_time: 0.0,
+            frame_times: VecDeque::with_capacity(HISTORY_LEN),
+            gpu_time: None,
+            instances: 0,
+            lights: 0,
+            mesh_memory: 0,
+            pipelines: Vec::new(),
+            render_scale: 1.0,
+            srgb_conversion,
+            surface_format,
+            texture_memory: 0,
+        }
+    }
+
+    pub fn push_frame_time(&mut self, dt: f32) {
+        if self.frame_times.len() == HISTORY_LEN {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+        self.frame_time = dt;
+    }
+
+    /// Color space of the values stored in the swapchain.
+    pub fn colorspace(&self) -> &'static str {
+        match self.surface_format {
+            wgpu::TextureFormat::Rgba16Float
+            | wgpu::TextureFormat::Rgba32Float => "linear",
+            _ => "srgb",
+        }
+    }
+
+    /// Gpu memory of the assets loaded from files, the render targets and
+    /// the per frame buffers are not counted.
+    pub fn vram_estimate(&self) -> u64 {
+        self.mesh_memory + self.texture_memory
+    }
+
+    /// One line summary, shown by the stats overlay.
+    pub fn hud_line(&self) -> String {
+        let fps = match self.frame_time > 0.0 {
+            true => 1.0 / self.frame_time,
+            false => 0.0,
+        };
+        format!(
+            "{:.0} fps  {:.2} ms  {} entities  {} instances  {} lights  \
+             {} draws  {:.1} MB",
+            fps,
+            self.frame_time * 1000.0,
+            self.entities,
+            self.instances,
+            self.lights,
+            self.draw_calls,
+            self.vram_estimate() as f64 / (1024.0 * 1024.0),
+        )
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use assets_manager::{loader::Loader, Asset, BoxedError};
+use glam::Vec3;
+use log::{error, info};
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+
+use crate::{
+    app::{get_pool, RELOAD_DEBOUNCE},
+    assets::AssetCaches,
+};
+
+use super::bundle::model;
+
+/// Grid of elevation samples, row major.
+pub struct Tile {
+    pub heights: Vec<f32>,
+    pub width: usize,
+    pub depth: usize,
+}
+
+pub struct TileLoader;
+impl Loader<Tile> for TileLoader {
+    fn load(content: Cow<[u8]>, ext: &str) -> Result<Tile, BoxedError> {
+        let tile = match ext {
+            "tif" | "tiff" => decode_tiff(&content)?,
+            _ => decode_raw16(&content)?,
+        };
+        Ok(tile)
+    }
+}
+
+impl Asset for Tile {
+    const EXTENSIONS: &'static [&'static str] = &["tif", "tiff", "r16", "raw"];
+    type Loader = TileLoader;
+}
+
+/// First band of the image, the samples are kept in their own unit, usually
+/// meters for elevation models.
+fn decode_tiff(content: &[u8]) -> Result<Tile> {
+    let mut decoder =
+        Decoder::new(Cursor::new(content))?.with_limits(Limits::unlimited());
+    let (width, depth) = decoder.dimensions()?;
+    let (width, depth) = (width as usize, depth as usize);
+    if width == 0 || depth == 0 {
+        bail!("tiff tile has no pixels");
+    }
+    let samples: Vec<f32> = match decoder.read_image()? {
+        DecodingResult::U8(v) => v.into_iter().map(|h| h as f32).collect(),
+        DecodingResult::U16(v) => v.into_iter().map(|h| h as f32).collect(),
+        DecodingResult::U32(v) => v.into_iter().map(|h| h as f32).collect(),
+        DecodingResult::I16(v) => v.into_iter().map(|h| h as f32).collect(),
+        DecodingResult::I32(v) => v.into_iter().map(|h| h as f32).collect(),
+        DecodingResult::F32(v) => v,
+        DecodingResult::F64(v) => v.into_iter().map(|h| h as f32).collect(),
+        _ => bail!("unsupported sample format"),
+    };
+    let bands = samples.len() / (width * depth);
+    if bands == 0 {
+        bail!("tiff tile has fewer samples than pixels");
+    }
+    let heights = samples.into_iter().step_by(bands).collect();
+    Ok(Tile {
+        heights,
+        width,
+        depth,
+    })
+}
+
+/// Square grid of little endian u16, normalized from 0 to 1.
+fn decode_raw16(content: &[u8]) -> Result<Tile> {
+    let count = content.len() / 2;
+    let size = (count as f64).sqrt() as usize;
+    if count == 0 || content.len() != count * 2 || size * size != count {
+        bail!("raw16 tile must be a square grid of u16");
+    }
+    let heights = content
+        .chunks_exact(2)
+        .map(|h| u16::from_le_bytes([h[0], h[1]]) as f32 / u16::MAX as f32)
+        .collect();
+    Ok(Tile {
+        heights,
+        width: size,
+        depth: size,
+    })
+}
+
+/// Tiles stitched in a single grid, centered on the origin.
+pub struct Terrain {
+    heights: Vec<f32>,
+    width: usize,
+    depth: usize,
+    /// Horizontal distance between two samples.
+    pub spacing: f32,
+    /// Multiplier applied to the samples.
+    pub vertical_scale: f32,
+}
+
+impl Terrain {
+    /// Rows of tiles from north to south, tiles in a row from west to east.
+    /// With `overlap`, neighbours share their edge samples and the
+    /// duplicates are dropped.
+    pub fn stitch(
+        tiles: &[Vec<&Tile>],
+        overlap: bool,
+        spacing: f32,
+        vertical_scale: f32,
+    ) -> Result<Self> {
+        let skip = overlap as usize;
+        let Some(first_row) = tiles.first() else {
+            bail!("terrain needs at least one tile");
+        };
+        let widths = first_row.iter().map(|t| t.width).collect::<Vec<_>>();
+        if widths.is_empty() {
+            bail!("terrain needs at least one tile");
+        }
+
+        let mut heights = Vec::new();
+        let mut depth = 0;
+        for (row, row_tiles) in tiles.iter().enumerate() {
+            if row_tiles.iter().map(|t| t.width).ne(widths.iter().copied()) {
+                bail!("tile widths differ in row {}", row + 1);
+            }
+            let row_depth = row_tiles[0].depth;
+            if row_tiles.iter().any(|t| t.depth != row_depth) {
+                bail!("tile depths differ in row {}", row + 1);
+            }
+            let first_z = if row == 0 { 0 } else { skip };
+            for z in first_z..row_depth {
+                for (column, tile) in row_tiles.iter().enumerate() {
+                    let first_x = if column == 0 { 0 } else { skip };
+                    let line = &tile.heights[z * tile.width..][..tile.width];
+                    heights.extend_from_slice(&line[first_x..]);
+                }
+                depth += 1;
+            }
+        }
+        let width = widths.iter().sum::<usize>() - skip * (widths.len() - 1);
+        if width < 2 || depth < 2 {
+            bail!("terrain needs at least two samples per side");
+        }
+
+        Ok(Self {
+            heights,
+            width,
+            depth,
+            spacing,
+            vertical_scale,
+        })
+    }
+
+    fn sample(&self, x: usize, z: usize) -> f32 {
+        let x = x.min(self.width - 1);
+        let z = z.min(self.depth - 1);
+        self.heights[z * self.width + x] * self.vertical_scale
+    }
+
+    fn origin(&self) -> (f32, f32) {
+        (
+            (self.width - 1) as f32 * self.spacing * 0.5,
+            (self.depth - 1) as f32 * self.spacing * 0.5,
+        )
+    }
+
+    /// Bilinear height at a local position, none outside of the terrain.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (origin_x, origin_z) = self.origin();
+        let gx = (x + origin_x) / self.spacing;
+        let gz = (z + origin_z) / self.spacing;
+        let max_x = (self.width - 1) as f32;
+        let max_z = (self.depth - 1) as f32;
+        if !(0.0..=max_x).contains(&gx) || !(0.0..=max_z).contains(&gz) {
+            return None;
+        }
+        let (x0, z0) = (gx.floor() as usize, gz.floor() as usize);
+        let (tx, tz) = (gx.fract(), gz.fract());
+        let top =
+            self.sample(x0, z0) * (1.0 - tx) + self.sample(x0 + 1, z0) * tx;
+        let bottom = self.sample(x0, z0 + 1) * (1.0 - tx)
+            + self.sample(x0 + 1, z0 + 1) * tx;
+        Some(top * (1.0 - tz) + bottom * tz)
+    }
+
+    pub fn build_mesh(&self) -> (Vec<model::Vertex>, Vec<u32>) {
+        let (origin_x, origin_z) = self.origin();
+        let mut vertices = Vec::with_capacity(self.width * self.depth);
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                let left = self.sample(x.saturating_sub(1), z);
+                let right = self.sample(x + 1, z);
+                let up = self.sample(x, z.saturating_sub(1));
+                let down = self.sample(x, z + 1);
+                let normal = Vec3::new(
+                    (left - right) / (2.0 * self.spacing),
+                    1.0,
+                    (up - down) / (2.0 * self.spacing),
+                )
+                .normalize();
+                vertices.push(model::Vertex {
+                    position: [
+                        x as f32 * self.spacing - origin_x,
+                        self.sample(x, z),
+                        z as f32 * self.spacing - origin_z,
+                    ],
+                    tex_coord: [
+                        x as f32 / (self.width - 1) as f32,
+                        z as f32 / (self.depth - 1) as f32,
+                    ],
+                    normal: normal.to_array(),
+                });
+            }
+        }
+
+        let mut indices =
+            Vec::with_capacity((self.width - 1) * (self.depth - 1) * 6);
+        for z in 0..self.depth - 1 {
+            for x in 0..self.width - 1 {
+                let i = (z * self.width + x) as u32;
+                let below = i + self.width as u32;
+                indices.extend_from_slice(&[
+                    i,
+                    below,
+                    i + 1,
+                    i + 1,
+                    below,
+                    below + 1,
+                ]);
+            }
+        }
+        (vertices, indices)
+    }
+}
+
+type LoadResult = (String, Result<Arc<Tile>>);
+
+pub struct TerrainAssets {
+    caches: AssetCaches,
+    last_reload: Instant,
+    load_rx: Receiver<LoadResult>,
+    load_tx: Sender<LoadResult>,
+    loaded: HashSet<String>,
+    pub terrains: HashMap<String, Terrain>,
+    tiles: HashMap<String, Arc<Tile>>,
+}
+
+impl TerrainAssets {
+    pub fn new() -> Self {
+        let (load_tx, load_rx) = channel();
+        Self {
+            caches: AssetCaches::new("heightfields"),
+            last_reload: Instant::now(),
+            load_rx,
+            load_tx,
+            loaded: HashSet::new(),
+            terrains: HashMap::new(),
+            tiles: HashMap::new(),
+        }
+    }
+
+    pub fn hot_reload(&mut self) {
+        self.caches.hot_reload();
+
+        let keys = self.tiles.keys().cloned().collect::<Vec<_>>();
+        for tile_id in keys {
+            let (cache, local_id) = self.caches.resolve(&tile_id);
+            let handle = cache.load_expect::<Tile>(local_id);
+            if self.last_reload.elapsed() >= RELOAD_DEBOUNCE
+                && handle.reloaded_global()
+            {
+                self.last_reload = Instant::now();
+                self.load_internal(&tile_id);
+            }
+        }
+
+        if let Ok((tile_id, result)) = self.load_rx.try_recv() {
+            match result {
+                Ok(tile) => {
+                    info!("Heightfield loaded: {}", tile_id);
+                    self.tiles.insert(tile_id, tile);
+                }
+                Err(err) => {
+                    error!("load\n{:?}", err);
+                    self.loaded.remove(&tile_id);
+                }
+            };
+        }
+    }
+
+    pub fn get_tile(&self, tile_id: &str) -> Option<&Tile> {
+        self.tiles.get(tile_id).map(Arc::as_ref)
+    }
+
+    fn load_internal(&mut self, tile_id: &str) {
+        let (cache, local_id) = self.caches.resolve(tile_id);
+        let (tile_id, local_id) = (tile_id.to_string(), local_id.to_string());
+        let load_tx = self.load_tx.clone();
+
+        get_pool().execute(move || {
+            let result = (|| {
+                let handle = cache.load::<Tile>(&local_id)?;
+                let tile = handle.read();
+                Ok(Arc::new(Tile {
+                    heights: tile.heights.clone(),
+                    width: tile.width,
+                    depth: tile.depth,
+                }))
+            })();
+            load_tx.send((tile_id, result)).unwrap();
+        });
+    }
+
+    pub fn load(&mut self, tile_id: &str) {
+        if self.loaded.contains(tile_id) {
+            return;
+        }
+        self.loaded.insert(tile_id.to_string());
+        self.load_internal(tile_id);
+    }
+}
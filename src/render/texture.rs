@@ -1,19 +1,108 @@
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
-    sync::{
-        mpsc::{channel, Receiver, Sender},
-        Arc,
-    },
+    fs,
+    sync::mpsc::{channel, Receiver, Sender},
     time::Instant,
 };
 
-use anyhow::Result;
-use assets_manager::{loader::Loader, Asset, AssetCache, BoxedError};
-use image::{DynamicImage, GenericImageView};
-use log::{error, info};
+use anyhow::{Context, Result};
+use assets_manager::{loader, loader::Loader, Asset, AssetCache, BoxedError};
+use glam::Vec4;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use log::{error, info, warn};
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
 
-use crate::app::{get_pool, RELOAD_DEBOUNCE};
+use crate::{
+    app::{get_pool, RELOAD_DEBOUNCE},
+    assets::{split_namespace, AssetCaches},
+    import,
+};
+
+use super::{
+    ktx::{self, CompressedImage, Ktx2Source},
+    residency::Residency,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    #[default]
+    Linear,
+    Nearest,
+}
+
+impl From<Filter> for wgpu::FilterMode {
+    fn from(value: Filter) -> Self {
+        match value {
+            Filter::Linear => wgpu::FilterMode::Linear,
+            Filter::Nearest => wgpu::FilterMode::Nearest,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl From<Wrap> for wgpu::AddressMode {
+    fn from(value: Wrap) -> Self {
+        match value {
+            Wrap::Repeat => wgpu::AddressMode::Repeat,
+            Wrap::Clamp => wgpu::AddressMode::ClampToEdge,
+            Wrap::Mirror => wgpu::AddressMode::MirrorRepeat,
+        }
+    }
+}
+
+/// Sampling of a texture, from the scripts or a `.ron` file next to the
+/// image, e.g. `(filter: nearest, wrap: clamp)`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SamplerSettings {
+    /// Only applies with every filter linear.
+    pub anisotropy: u16,
+    pub filter: Filter,
+    pub mipmap_filter: Filter,
+    pub wrap: Wrap,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            anisotropy: 1,
+            filter: Filter::Linear,
+            mipmap_filter: Filter::Linear,
+            wrap: Wrap::Repeat,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        let all_linear = self.filter == Filter::Linear
+            && self.mipmap_filter == Filter::Linear;
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: self.filter.into(),
+            min_filter: self.filter.into(),
+            mipmap_filter: self.mipmap_filter.into(),
+            address_mode_u: self.wrap.into(),
+            address_mode_v: self.wrap.into(),
+            address_mode_w: self.wrap.into(),
+            anisotropy_clamp: match all_linear {
+                true => self.anisotropy.clamp(1, 16),
+                false => 1,
+            },
+            ..Default::default()
+        })
+    }
+}
 
 pub struct Texture {
     pub sampler: wgpu::Sampler,
@@ -29,6 +118,7 @@ impl Texture {
         queue: &wgpu::Queue,
         image: &DynamicImage,
         label: &str,
+        sampler: &SamplerSettings,
     ) -> Self {
         let rgba = image.to_rgba8();
         let dimensions = image.dimensions();
@@ -52,15 +142,7 @@ impl Texture {
         });
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            ..Default::default()
-        });
+        let sampler = sampler.create_sampler(device);
 
         queue.write_texture(
             texture.as_image_copy(),
@@ -76,13 +158,104 @@ impl Texture {
         Self { sampler, view }
     }
 
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: &str,
+        sampler: &SamplerSettings,
+    ) -> Self {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(&format!("{}_texture", label)),
+                size: wgpu::Extent3d {
+                    width: image.width,
+                    height: image.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: image.levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: image.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::MipMajor,
+            &image.levels.concat(),
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = sampler.create_sampler(device);
+        Self { sampler, view }
+    }
+
+    pub fn create_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        Self::create_sampled(device, width, height, format, usage, label)
+    }
+
+    /// Written by a compute shader, sampled like a target.
+    pub fn create_storage(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let usage = wgpu::TextureUsages::STORAGE_BINDING;
+        Self::create_sampled(device, width, height, format, usage, label)
+    }
+
+    fn create_sampled(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("{}_texture", label)),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self { sampler, view }
+    }
+
     pub fn create_depth(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        width: u32,
+        height: u32,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
@@ -127,39 +300,161 @@ impl Asset for Image {
     type Loader = ImageLoader;
 }
 
-type LoadResult = (String, Result<Box<DynamicImage>>);
+/// Reads images on the calling thread, for the ones not used as textures.
+#[derive(Clone)]
+pub struct ImageReader(AssetCaches);
+
+impl ImageReader {
+    pub fn read(&self, texture_id: &str) -> Result<DynamicImage> {
+        let (cache, local_id) = self.0.resolve(texture_id);
+        let handle = cache.load::<Image>(local_id)?;
+        let image = handle.read().0.clone();
+        Ok(image)
+    }
+}
+
+pub struct SamplerSource(String);
+
+impl From<String> for SamplerSource {
+    fn from(value: String) -> Self {
+        SamplerSource(value)
+    }
+}
+
+impl Asset for SamplerSource {
+    const EXTENSION: &'static str = "ron";
+    type Loader = loader::LoadFrom<String, loader::StringLoader>;
+}
+
+enum Pixels {
+    Image(Box<DynamicImage>),
+    /// With the id of the ktx2 file it comes from, none when imported.
+    Compressed(CompressedImage, Option<String>),
+}
+
+type LoadResult = (String, Result<(Pixels, Option<SamplerSettings>)>);
+
+pub struct AtlasSource(String);
+
+impl From<String> for AtlasSource {
+    fn from(value: String) -> Self {
+        AtlasSource(value)
+    }
+}
+
+impl Asset for AtlasSource {
+    const EXTENSION: &'static str = "json";
+    type Loader = loader::LoadFrom<String, loader::StringLoader>;
+}
+
+/// Named regions of an image, `[x, y, width, height]` in pixels.
+#[derive(Deserialize)]
+struct AtlasDescriptor {
+    image: String,
+    regions: HashMap<String, [u32; 4]>,
+}
+
+/// Magenta and black checker drawn in place of the textures that failed to
+/// load.
+pub const FALLBACK_TEXTURE: &str = "error";
+/// Squares across the fallback checker.
+const FALLBACK_SIZE: u32 = 8;
+
+/// Regions as uv offset in xy and scale in zw.
+pub struct Atlas {
+    image: String,
+    regions: HashMap<String, Vec4>,
+}
+
+type AtlasLoadResult = (String, Result<Atlas>);
 
+/// Textures are referenced by image name, or by `atlas:region` for a region
+/// of an atlas described by a json file, both prefixed by `namespace:` for
+/// the textures of a mounted namespace.
 pub struct TextureAssets {
-    cache: Arc<AssetCache>,
+    atlas_rx: Receiver<AtlasLoadResult>,
+    atlas_tx: Sender<AtlasLoadResult>,
+    atlases: HashMap<String, Atlas>,
+    caches: AssetCaches,
+    /// Ktx2 file of the compressed textures, in their namespace.
+    compressed: HashMap<String, String>,
+    /// Textures and atlases whose last load failed, drawn with the fallback
+    /// texture.
+    failed: HashSet<String>,
+    features: wgpu::Features,
+    /// Textures loaded for the first time since the last call to
+    /// `take_first_loads`.
+    first_loads: Vec<String>,
+    /// Textures and atlases that failed to load before ever loading, since
+    /// the last call to `take_first_failures`.
+    first_failures: Vec<String>,
+    generated: HashSet<String>,
     last_reload: Instant,
     load_rx: Receiver<LoadResult>,
     load_tx: Sender<LoadResult>,
     loaded: HashSet<String>,
+    loaded_atlases: HashSet<String>,
+    pub residency: Residency,
+    samplers: HashMap<String, SamplerSettings>,
+    /// A texture got reloaded or its sampler changed since last taken.
+    changed: bool,
+    /// Textures loaded again since the last call to `take_reloaded`.
+    reloaded: Vec<String>,
     textures: HashMap<String, Texture>,
 }
 
 impl TextureAssets {
-    pub fn new() -> Self {
+    /// Compressed formats are used when the features allow them.
+    pub fn new(features: wgpu::Features) -> Self {
         let (load_tx, load_rx) = channel();
+        let (atlas_tx, atlas_rx) = channel();
         Self {
-            cache: Arc::new(AssetCache::new("assets/textures").unwrap()),
+            atlas_rx,
+            atlas_tx,
+            atlases: HashMap::new(),
+            caches: AssetCaches::new("textures"),
+            compressed: HashMap::new(),
+            failed: HashSet::new(),
+            features,
+            first_loads: Vec::new(),
+            first_failures: Vec::new(),
+            generated: HashSet::new(),
             last_reload: Instant::now(),
             load_rx,
             load_tx,
             loaded: HashSet::new(),
+            loaded_atlases: HashSet::new(),
+            residency: Residency::default(),
+            samplers: HashMap::new(),
+            changed: false,
+            reloaded: Vec::new(),
             textures: HashMap::new(),
         }
     }
 
     pub fn hot_reload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        self.cache.hot_reload();
+        self.caches.hot_reload();
 
-        let keys = self.textures.keys().cloned().collect::<Vec<_>>();
+        let keys = self
+            .textures
+            .keys()
+            .filter(|key| !self.generated.contains(*key))
+            .cloned()
+            .collect::<Vec<_>>();
         for texture_id in keys {
-            let handle = self.cache.load_expect::<Image>(&texture_id);
-            if self.last_reload.elapsed() >= RELOAD_DEBOUNCE
-                && handle.reloaded_global()
-            {
+            let (cache, local_id) = self.caches.resolve(&texture_id);
+            let reloaded = match self.compressed.get(&texture_id) {
+                Some(ktx2_id) => {
+                    cache.load_expect::<Ktx2Source>(ktx2_id).reloaded_global()
+                }
+                // Imported textures can be shipped without their image.
+                None => cache
+                    .load::<Image>(local_id)
+                    .is_ok_and(|handle| handle.reloaded_global()),
+            } || cache
+                .get_cached::<SamplerSource>(local_id)
+                .is_some_and(|source| source.reloaded_global());
+            if self.last_reload.elapsed() >= RELOAD_DEBOUNCE && reloaded {
                 self.last_reload = Instant::now();
                 self.load_internal(&texture_id);
             }
@@ -167,41 +462,331 @@ impl TextureAssets {
 
         if let Ok((texture_id, result)) = self.load_rx.try_recv() {
             match result {
-                Ok(image) => {
+                Ok((pixels, sampler)) => {
                     info!("Texture loaded: {}", texture_id);
-                    self.textures.insert(
-                        texture_id.clone(),
-                        Texture::from_image(device, queue, &image, &texture_id),
-                    );
+                    if let Some(sampler) = sampler {
+                        self.samplers.insert(texture_id.clone(), sampler);
+                    }
+                    let sampler = self
+                        .samplers
+                        .get(&texture_id)
+                        .copied()
+                        .unwrap_or_default();
+                    let texture = match pixels {
+                        Pixels::Image(image) => {
+                            let (width, height) = image.dimensions();
+                            self.residency.insert(
+                                &texture_id,
+                                width as u64 * height as u64 * 4,
+                            );
+                            self.compressed.remove(&texture_id);
+                            Texture::from_image(
+                                device,
+                                queue,
+                                &image,
+                                &texture_id,
+                                &sampler,
+                            )
+                        }
+                        Pixels::Compressed(image, ktx2_id) => {
+                            let bytes: usize =
+                                image.levels.iter().map(Vec::len).sum();
+                            self.residency.insert(&texture_id, bytes as u64);
+                            // Imported ones are reloaded with their image.
+                            match ktx2_id {
+                                Some(ktx2_id) => self
+                                    .compressed
+                                    .insert(texture_id.clone(), ktx2_id),
+                                None => self.compressed.remove(&texture_id),
+                            };
+                            Texture::from_compressed(
+                                device,
+                                queue,
+                                &image,
+                                &texture_id,
+                                &sampler,
+                            )
+                        }
+                    };
+                    match self.textures.contains_key(&texture_id) {
+                        true => {
+                            self.changed = true;
+                            self.reloaded.push(texture_id.clone());
+                        }
+                        false => self.first_loads.push(texture_id.clone()),
+                    }
+                    self.failed.remove(&texture_id);
+                    self.textures.insert(texture_id, texture);
                 }
                 Err(err) => {
                     error!("load\n{:?}", err);
+                    warn!(
+                        "Texture {} drawn as {}",
+                        texture_id, FALLBACK_TEXTURE
+                    );
+                    if !self.textures.contains_key(&texture_id) {
+                        self.first_failures.push(texture_id.clone());
+                    }
+                    self.failed.insert(texture_id.clone());
                     self.loaded.remove(&texture_id);
                 }
             };
         }
+
+        let keys = self.atlases.keys().cloned().collect::<Vec<_>>();
+        for atlas_id in keys {
+            let (cache, local_id) = self.caches.resolve(&atlas_id);
+            let handle = cache.load_expect::<AtlasSource>(local_id);
+            if self.last_reload.elapsed() >= RELOAD_DEBOUNCE
+                && handle.reloaded_global()
+            {
+                self.last_reload = Instant::now();
+                self.load_atlas(&atlas_id);
+            }
+        }
+
+        if let Ok((atlas_id, result)) = self.atlas_rx.try_recv() {
+            match result {
+                Ok(atlas) => {
+                    info!("Atlas loaded: {}", atlas_id);
+                    self.failed.remove(&atlas_id);
+                    self.load(&atlas.image);
+                    self.atlases.insert(atlas_id, atlas);
+                }
+                Err(err) => {
+                    error!("load\n{:?}", err);
+                    warn!("Atlas {} drawn as {}", atlas_id, FALLBACK_TEXTURE);
+                    if !self.atlases.contains_key(&atlas_id) {
+                        self.first_failures.push(atlas_id.clone());
+                    }
+                    self.failed.insert(atlas_id.clone());
+                    self.loaded_atlases.remove(&atlas_id);
+                }
+            };
+        }
     }
 
+    /// Whether the last load of the texture, or of the atlas of the region,
+    /// failed.
+    pub fn has_failed(&self, texture_id: &str) -> bool {
+        let id = split_region(texture_id).map_or(texture_id, |(id, _)| id);
+        self.failed.contains(id)
+    }
+
+    /// Textures and atlases that failed to load resolve to the fallback
+    /// texture.
     pub fn get(&self, texture_id: &str) -> Option<&Texture> {
-        self.textures.get(texture_id)
+        match split_region(texture_id) {
+            Some((atlas_id, _)) if self.failed.contains(atlas_id) => {
+                self.textures.get(FALLBACK_TEXTURE)
+            }
+            Some((atlas_id, region)) => {
+                let atlas = self.atlases.get(atlas_id)?;
+                atlas.regions.contains_key(region).then_some(())?;
+                self.get(&atlas.image)
+            }
+            None if self.failed.contains(texture_id) => {
+                self.textures.get(FALLBACK_TEXTURE)
+            }
+            None => self.textures.get(texture_id),
+        }
+    }
+
+    /// Replace the sampler of a texture, applied once loaded if it is not
+    /// yet.
+    pub fn set_sampler(
+        &mut self,
+        device: &wgpu::Device,
+        texture_id: &str,
+        settings: SamplerSettings,
+    ) {
+        self.samplers.insert(texture_id.to_string(), settings);
+        if let Some(texture) = self.textures.get_mut(texture_id) {
+            texture.sampler = settings.create_sampler(device);
+            self.changed = true;
+        }
+    }
+
+    /// Whether the bind groups using the textures must be recreated.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn take_reloaded(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.reloaded)
+    }
+
+    pub fn take_first_loads(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.first_loads)
+    }
+
+    pub fn take_first_failures(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.first_failures)
+    }
+
+    /// Uv offset in xy and scale in zw, the whole texture unless it is an
+    /// atlas region.
+    pub fn uv_rect(&self, texture_id: &str) -> Vec4 {
+        split_region(texture_id)
+            .and_then(|(atlas_id, region)| {
+                self.atlases.get(atlas_id)?.regions.get(region).copied()
+            })
+            .unwrap_or(Vec4::new(0.0, 0.0, 1.0, 1.0))
+    }
+
+    pub fn image_reader(&self) -> ImageReader {
+        ImageReader(self.caches.clone())
+    }
+
+    pub fn insert_fallback(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        let image = RgbaImage::from_fn(FALLBACK_SIZE, FALLBACK_SIZE, |x, y| {
+            match (x + y) % 2 {
+                0 => Rgba([255, 0, 255, 255]),
+                _ => Rgba([0, 0, 0, 255]),
+            }
+        });
+        let sampler = SamplerSettings {
+            filter: Filter::Nearest,
+            mipmap_filter: Filter::Nearest,
+            ..Default::default()
+        };
+        let texture = Texture::from_image(
+            device,
+            queue,
+            &DynamicImage::ImageRgba8(image),
+            FALLBACK_TEXTURE,
+            &sampler,
+        );
+        self.insert(FALLBACK_TEXTURE, texture);
+    }
+
+    /// Insert a texture created at runtime, it is not backed by a file and
+    /// will never be hot-reloaded.
+    pub fn insert(&mut self, texture_id: &str, texture: Texture) {
+        self.generated.insert(texture_id.to_string());
+        self.loaded.insert(texture_id.to_string());
+        self.textures.insert(texture_id.to_string(), texture);
+    }
+
+    /// Mark the texture as sampled this frame, loading it again if evicted.
+    pub fn touch(&mut self, texture_id: &str) {
+        let texture_id = match split_region(texture_id) {
+            Some((atlas_id, _)) => match self.atlases.get(atlas_id) {
+                Some(atlas) => atlas.image.clone(),
+                None => return,
+            },
+            None => texture_id.to_string(),
+        };
+        if self.residency.touch(&texture_id) {
+            self.load_internal(&texture_id);
+        }
+    }
+
+    /// The bind groups using it must be recreated.
+    pub fn evict(&mut self, texture_id: &str) {
+        if self.generated.contains(texture_id) {
+            return;
+        }
+        info!("Texture evicted: {}", texture_id);
+        self.textures.remove(texture_id);
+        self.residency.evict(texture_id);
+        self.changed = true;
     }
 
     fn load_internal(&mut self, texture_id: &str) {
-        let cache = self.cache.clone();
-        let texture_id = texture_id.to_string();
+        let (cache, local_id) = self.caches.resolve(texture_id);
+        let (texture_id, local_id) =
+            (texture_id.to_string(), local_id.to_string());
         let load_tx = self.load_tx.clone();
+        let features = self.features;
 
         get_pool().execute(move || {
             let result = (|| {
-                let handle = cache.load::<Image>(&texture_id)?;
-                let data = handle.read().0.clone();
-                Ok(Box::new(data))
+                let pixels = match load_compressed(&cache, &local_id, features)
+                    .or_else(|| load_imported(&texture_id, features))
+                {
+                    Some(pixels) => pixels,
+                    None => {
+                        let handle = cache.load::<Image>(&local_id)?;
+                        Pixels::Image(Box::new(handle.read().0.clone()))
+                    }
+                };
+                let sampler = match cache.load::<SamplerSource>(&local_id) {
+                    Ok(source) => Some(ron::from_str(&source.read().0)?),
+                    Err(_) => None,
+                };
+                Ok((pixels, sampler))
             })();
             load_tx.send((texture_id, result)).unwrap();
         });
     }
 
+    fn load_atlas(&mut self, atlas_id: &str) {
+        let (cache, local_id) = self.caches.resolve(atlas_id);
+        // The atlas image is in the namespace of the atlas.
+        let namespace = atlas_id[..atlas_id.len() - local_id.len()].to_string();
+        let (atlas_id, local_id) = (atlas_id.to_string(), local_id.to_string());
+        let atlas_tx = self.atlas_tx.clone();
+
+        get_pool().execute(move || {
+            let result = (|| {
+                let handle = cache.load::<AtlasSource>(&local_id)?;
+                let descriptor =
+                    serde_json::from_str::<AtlasDescriptor>(&handle.read().0)?;
+                let image = cache
+                    .load::<Image>(&descriptor.image)
+                    .context("atlas image")?;
+                let (width, height) = image.read().0.dimensions();
+                let size = Vec4::new(
+                    width as f32,
+                    height as f32,
+                    width as f32,
+                    height as f32,
+                );
+                let regions = descriptor
+                    .regions
+                    .into_iter()
+                    .map(|(name, rect)| {
+                        let rect = Vec4::from_array(rect.map(|v| v as f32));
+                        (name, rect / size)
+                    })
+                    .collect();
+                Ok(Atlas {
+                    image: namespace + &descriptor.image,
+                    regions,
+                })
+            })();
+            atlas_tx.send((atlas_id, result)).unwrap();
+        });
+    }
+
+    /// Whether the texture, or the atlas of the region, was requested and
+    /// is still loading.
+    pub fn is_pending(&self, texture_id: &str) -> bool {
+        let texture_id = match split_region(texture_id) {
+            Some((atlas_id, _)) => match self.atlases.get(atlas_id) {
+                Some(atlas) => atlas.image.as_str(),
+                None => return self.loaded_atlases.contains(atlas_id),
+            },
+            None => texture_id,
+        };
+        self.loaded.contains(texture_id)
+            && !self.textures.contains_key(texture_id)
+    }
+
+    /// Regions load their whole atlas.
     pub fn load(&mut self, texture_id: &str) {
+        if let Some((atlas_id, _)) = split_region(texture_id) {
+            if !self.loaded_atlases.contains(atlas_id) {
+                self.loaded_atlases.insert(atlas_id.to_string());
+                self.load_atlas(atlas_id);
+            }
+            return;
+        }
         if self.loaded.contains(texture_id) {
             return;
         }
@@ -209,3 +794,50 @@ impl TextureAssets {
         self.load_internal(texture_id);
     }
 }
+
+/// Atlas and region of a `[namespace:]atlas:region` id.
+fn split_region(texture_id: &str) -> Option<(&str, &str)> {
+    let local_id = split_namespace(texture_id).map_or(texture_id, |(_, id)| id);
+    let (atlas_id, region) = local_id.split_once(':')?;
+    let namespace_len = texture_id.len() - local_id.len();
+    Some((&texture_id[..namespace_len + atlas_id.len()], region))
+}
+
+/// First ktx2 variant the adapter supports, falls back to the image when
+/// none can be used.
+fn load_compressed(
+    cache: &AssetCache,
+    texture_id: &str,
+    features: wgpu::Features,
+) -> Option<Pixels> {
+    let variants = ktx::VARIANTS
+        .iter()
+        .filter(|(_, feature)| features.contains(*feature))
+        .map(|(suffix, _)| format!("{}-{}", texture_id, suffix))
+        .chain([texture_id.to_string()]);
+    for ktx2_id in variants {
+        let Ok(handle) = cache.load::<Ktx2Source>(&ktx2_id) else {
+            continue;
+        };
+        match CompressedImage::decode(&handle.read().0, features) {
+            Ok(image) => return Some(Pixels::Compressed(image, Some(ktx2_id))),
+            Err(err) => warn!("Skipped {}.ktx2: {:?}", ktx2_id, err),
+        }
+    }
+    None
+}
+
+/// Mipmapped texture written by `--import`, while newer than its image.
+fn load_imported(texture_id: &str, features: wgpu::Features) -> Option<Pixels> {
+    let path = import::cached("textures", texture_id, "ktx2")?;
+    let result = fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| CompressedImage::decode(&data, features));
+    match result {
+        Ok(image) => Some(Pixels::Compressed(image, None)),
+        Err(err) => {
+            warn!("Skipped {}: {:?}", path.display(), err);
+            None
+        }
+    }
+}
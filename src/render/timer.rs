@@ -0,0 +1,119 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Measures the gpu duration of a render pass with timestamp queries, the
+/// result is read back a few frames later without stalling.
+pub struct GpuTimer {
+    copied: bool,
+    /// Set when mapping the read buffer failed, the next copy retries.
+    failed: Arc<AtomicBool>,
+    mapping: bool,
+    period: f32,
+    query_set: wgpu::QuerySet,
+    read_buffer: wgpu::Buffer,
+    ready: Arc<AtomicBool>,
+    resolve_buffer: wgpu::Buffer,
+    /// Last measured duration in seconds.
+    pub last: Option<f32>,
+}
+
+impl GpuTimer {
+    const SIZE: u64 = 2 * wgpu::QUERY_SIZE as u64;
+
+    /// None when the device does not support timestamp queries.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("gpu_timer_query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_timer_resolve_buffer"),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_timer_read_buffer"),
+            size: Self::SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            copied: false,
+            failed: Arc::new(AtomicBool::new(false)),
+            mapping: false,
+            period: queue.get_timestamp_period(),
+            query_set,
+            read_buffer,
+            ready: Arc::new(AtomicBool::new(false)),
+            resolve_buffer,
+            last: None,
+        })
+    }
+
+    pub fn timestamp_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Copy the timestamps to the read buffer, unless still being read.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(
+            &self.query_set,
+            0..2,
+            &self.resolve_buffer,
+            0,
+        );
+        if !self.mapping {
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &self.read_buffer,
+                0,
+                Self::SIZE,
+            );
+            self.copied = true;
+        }
+    }
+
+    /// Collect a previous read if done, and request one after a copy.
+    pub fn read(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        if self.mapping && self.ready.swap(false, Ordering::Acquire) {
+            {
+                let data = self.read_buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                self.last = Some(ticks as f32 * self.period * 1e-9);
+            }
+            self.read_buffer.unmap();
+            self.mapping = false;
+        }
+        if self.mapping && self.failed.swap(false, Ordering::Acquire) {
+            self.mapping = false;
+        }
+        if self.copied {
+            self.copied = false;
+            self.mapping = true;
+            let failed = self.failed.clone();
+            let ready = self.ready.clone();
+            self.read_buffer.slice(..).map_async(
+                wgpu::MapMode::Read,
+                move |result| match result {
+                    Ok(()) => ready.store(true, Ordering::Release),
+                    Err(_) => failed.store(true, Ordering::Release),
+                },
+            );
+        }
+    }
+}
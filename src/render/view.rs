@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use glam::Vec2;
+use winit::{dpi::PhysicalSize, window::Window};
+
+use super::{
+    bundle::{
+        globals::{self, Environment},
+        output, Layouts,
+    },
+    camera::Camera,
+    texture::Texture,
+};
+
+/// Secondary window drawing the scene from its own camera.
+pub struct WindowView {
+    pub camera: Camera,
+    config: wgpu::SurfaceConfiguration,
+    depth: Texture,
+    pub globals: globals::Bundle,
+    pub name: String,
+    /// Tonemaps the target to the surface, set by `bind_output`.
+    pub output_bind_group: Option<wgpu::BindGroup>,
+    surface: wgpu::Surface<'static>,
+    target: Texture,
+    pub window: Arc<Window>,
+}
+
+impl WindowView {
+    /// The surface must support the format the output pass writes to.
+    pub fn new(
+        instance: &wgpu::Instance,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        format: wgpu::TextureFormat,
+        name: &str,
+        window: Arc<Window>,
+    ) -> Result<Self> {
+        let size = window.inner_size();
+        let surface = instance.create_surface(window.clone())?;
+        let Some(mut config) = surface.get_default_config(
+            adapter,
+            size.width.max(1),
+            size.height.max(1),
+        ) else {
+            bail!("Window surface is not supported by the adapter");
+        };
+        if !surface.get_capabilities(adapter).formats.contains(&format) {
+            bail!("Window surface does not support {:?}", format);
+        }
+        config.format = format;
+        surface.configure(device, &config);
+
+        Ok(Self {
+            camera: Camera::new(),
+            depth: Texture::create_depth(device, config.width, config.height),
+            globals: globals::Bundle::new(device, layouts),
+            name: name.to_string(),
+            output_bind_group: None,
+            surface,
+            target: create_target(device, &config),
+            config,
+            window,
+        })
+    }
+
+    pub fn bind_output(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        output: &output::Bundle,
+    ) {
+        self.output_bind_group =
+            Some(output.bind_target(device, layouts, &self.target));
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.config.width = size.width.max(1);
+        self.config.height = size.height.max(1);
+        self.surface.configure(device, &self.config);
+        self.depth = Texture::create_depth(
+            device,
+            self.config.width,
+            self.config.height,
+        );
+        self.target = create_target(device, &self.config);
+        self.output_bind_group = None;
+    }
+
+    pub fn prepare(&self, queue: &wgpu::Queue, environment: &Environment) {
+        self.globals.prepare(
+            queue,
+            self.config.width as f32 / self.config.height as f32,
+            environment,
+            &self.camera,
+            Vec2::ZERO,
+        );
+    }
+
+    /// None when the surface is not ready, the view then skips the frame.
+    pub fn current_texture(&self) -> Option<wgpu::SurfaceTexture> {
+        self.surface.get_current_texture().ok()
+    }
+
+    pub fn begin_pass<'encoder>(
+        &self,
+        encoder: &'encoder mut wgpu::CommandEncoder,
+    ) -> wgpu::RenderPass<'encoder> {
+        begin_pass(
+            encoder,
+            "window_view_pass",
+            &self.target,
+            &self.depth,
+            &self.globals,
+        )
+    }
+}
+
+/// Camera of the scene drawn in a rect of the main window, over the main
+/// camera.
+pub struct Viewport {
+    pub camera: Camera,
+    depth: Texture,
+    globals: globals::Bundle,
+    pub name: String,
+    /// Tonemaps the target to its rect.
+    pub output_bind_group: wgpu::BindGroup,
+    /// Offset and size as fractions of the window.
+    pub rect: [f32; 4],
+    /// Pixels covered by the rect.
+    size: [u32; 2],
+    target: Texture,
+}
+
+impl Viewport {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        output: &output::Bundle,
+        name: &str,
+        rect: [f32; 4],
+        window_size: [u32; 2],
+    ) -> Self {
+        let size = pixel_size(rect, window_size);
+        let target = create_viewport_target(device, size);
+        Self {
+            camera: Camera::new(),
+            depth: Texture::create_depth(device, size[0], size[1]),
+            globals: globals::Bundle::new(device, layouts),
+            name: name.to_string(),
+            output_bind_group: output.bind_target(device, layouts, &target),
+            rect,
+            size,
+            target,
+        }
+    }
+
+    /// Recreate the targets when the rect covers another number of pixels.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        output: &output::Bundle,
+        window_size: [u32; 2],
+    ) {
+        let size = pixel_size(self.rect, window_size);
+        if size == self.size {
+            return;
+        }
+        self.size = size;
+        self.depth = Texture::create_depth(device, size[0], size[1]);
+        self.target = create_viewport_target(device, size);
+        self.output_bind_group =
+            output.bind_target(device, layouts, &self.target);
+    }
+
+    pub fn prepare(&self, queue: &wgpu::Queue, environment: &Environment) {
+        self.globals.prepare(
+            queue,
+            self.size[0] as f32 / self.size[1] as f32,
+            environment,
+            &self.camera,
+            Vec2::ZERO,
+        );
+    }
+
+    pub fn begin_pass<'encoder>(
+        &self,
+        encoder: &'encoder mut wgpu::CommandEncoder,
+    ) -> wgpu::RenderPass<'encoder> {
+        begin_pass(
+            encoder,
+            "viewport_pass",
+            &self.target,
+            &self.depth,
+            &self.globals,
+        )
+    }
+}
+
+/// Rect as fractions of the window to pixels, at least one.
+pub fn pixel_size(rect: [f32; 4], window_size: [u32; 2]) -> [u32; 2] {
+    [
+        (rect[2] * window_size[0] as f32).round().max(1.0) as u32,
+        (rect[3] * window_size[1] as f32).round().max(1.0) as u32,
+    ]
+}
+
+fn begin_pass<'encoder>(
+    encoder: &'encoder mut wgpu::CommandEncoder,
+    label: &str,
+    target: &Texture,
+    depth: &Texture,
+    globals: &globals::Bundle,
+) -> wgpu::RenderPass<'encoder> {
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &target.view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.03,
+                    g: 0.03,
+                    b: 0.03,
+                    a: 1.0,
+                }),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: Some(
+            wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            },
+        ),
+        ..Default::default()
+    });
+    rpass.set_bind_group(0, &globals.bind_group, &[]);
+    rpass
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> Texture {
+    Texture::create_target(
+        device,
+        config.width,
+        config.height,
+        output::TARGET_FORMAT,
+        "window_view",
+    )
+}
+
+fn create_viewport_target(device: &wgpu::Device, size: [u32; 2]) -> Texture {
+    Texture::create_target(
+        device,
+        size[0],
+        size[1],
+        output::TARGET_FORMAT,
+        "viewport",
+    )
+}
@@ -0,0 +1,200 @@
+use std::{collections::HashMap, f32::consts::TAU};
+
+use glam::{Quat, Vec3};
+
+use crate::{render::bundle::model, wind::Wind};
+
+const CONSTRAINT_ITERATIONS: usize = 16;
+const DAMPING: f32 = 0.98;
+/// Acceleration per unit of wind velocity, light ropes follow the wind.
+const DRAG: f32 = 0.5;
+const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+const SIDES: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct RopeSettings {
+    /// Rest length of the rope, distance between the ends with some slack
+    /// when none.
+    pub length: Option<f32>,
+    pub material_id: String,
+    pub radius: f32,
+    pub segments: usize,
+}
+
+impl Default for RopeSettings {
+    fn default() -> Self {
+        Self {
+            length: None,
+            material_id: model::DEFAULT_MATERIAL.to_string(),
+            radius: 0.05,
+            segments: 20,
+        }
+    }
+}
+
+/// Verlet simulated chain of points pinned at both ends.
+#[derive(Debug)]
+pub struct Rope {
+    attached: bool,
+    length: f32,
+    points: Vec<Vec3>,
+    previous: Vec<Vec3>,
+    pub settings: RopeSettings,
+}
+
+impl Rope {
+    fn new(start: Vec3, end: Vec3, settings: RopeSettings) -> Self {
+        let segments = settings.segments.max(1);
+        let length = settings
+            .length
+            .unwrap_or(start.distance(end) * 1.2)
+            .max(f32::EPSILON);
+        let points = (0..=segments)
+            .map(|i| start.lerp(end, i as f32 / segments as f32))
+            .collect::<Vec<_>>();
+        Self {
+            attached: true,
+            length,
+            previous: points.clone(),
+            points,
+            settings,
+        }
+    }
+
+    fn simulate(&mut self, dt: f32, wind: &Wind) {
+        let last = self.points.len() - 1;
+        for i in 1..last {
+            let velocity = (self.points[i] - self.previous[i]) * DAMPING;
+            let acceleration = GRAVITY + wind.at(self.points[i]) * DRAG;
+            self.previous[i] = self.points[i];
+            self.points[i] += velocity + acceleration * dt * dt;
+        }
+
+        let rest = self.length / last as f32;
+        for _ in 0..CONSTRAINT_ITERATIONS {
+            for i in 0..last {
+                let delta = self.points[i + 1] - self.points[i];
+                let distance = delta.length();
+                if distance <= f32::EPSILON {
+                    continue;
+                }
+                let correction = delta * (distance - rest) / distance;
+                match (i == 0, i + 1 == last) {
+                    (true, true) => (),
+                    (true, false) => self.points[i + 1] -= correction,
+                    (false, true) => self.points[i] += correction,
+                    (false, false) => {
+                        self.points[i] += correction * 0.5;
+                        self.points[i + 1] -= correction * 0.5;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sweep a tube along the points, rotating each ring frame along the
+    /// curve to avoid twisting.
+    pub fn build_mesh(&self) -> (Vec<model::Vertex>, Vec<u32>) {
+        let count = self.points.len();
+        let mut vertices = Vec::with_capacity(count * (SIDES + 1));
+        let mut indices = Vec::with_capacity((count - 1) * SIDES * 6);
+
+        let tangent_at = |i: usize| {
+            let prev = self.points[i.saturating_sub(1)];
+            let next = self.points[(i + 1).min(count - 1)];
+            (next - prev).normalize_or(Vec3::Y)
+        };
+        let mut tangent = tangent_at(0);
+        let mut normal = tangent.any_orthonormal_vector();
+
+        for (i, point) in self.points.iter().enumerate() {
+            let next_tangent = tangent_at(i);
+            normal = Quat::from_rotation_arc(tangent, next_tangent) * normal;
+            tangent = next_tangent;
+            let binormal = tangent.cross(normal);
+
+            let v = i as f32 / (count - 1) as f32;
+            for side in 0..=SIDES {
+                let u = side as f32 / SIDES as f32;
+                let (sin, cos) = (u * TAU).sin_cos();
+                let direction = normal * cos + binormal * sin;
+                vertices.push(model::Vertex {
+                    position: (*point + direction * self.settings.radius)
+                        .to_array(),
+                    tex_coord: [u, v],
+                    normal: direction.to_array(),
+                });
+            }
+        }
+
+        let ring = (SIDES + 1) as u32;
+        for i in 0..(count - 1) as u32 {
+            for side in 0..SIDES as u32 {
+                let a = i * ring + side;
+                let b = a + ring;
+                indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+            }
+        }
+
+        (vertices, indices)
+    }
+}
+
+/// Ropes attached during the frame, their simulation state is kept across
+/// frames as long as they keep being attached.
+#[derive(Debug, Default)]
+pub struct Ropes {
+    frame_count: usize,
+    ropes: HashMap<String, Rope>,
+}
+
+impl Ropes {
+    pub fn begin_frame(&mut self) {
+        self.frame_count = 0;
+        self.ropes.retain(|_, rope| rope.attached);
+        for rope in self.ropes.values_mut() {
+            rope.attached = false;
+        }
+    }
+
+    /// Attach a rope between two points, ropes without key are identified by
+    /// their call order in the frame.
+    pub fn attach(
+        &mut self,
+        key: Option<String>,
+        start: Vec3,
+        end: Vec3,
+        settings: RopeSettings,
+    ) {
+        let key = key.unwrap_or_else(|| format!("#{}", self.frame_count));
+        self.frame_count += 1;
+
+        let rebuild = match self.ropes.get(&key) {
+            Some(rope) => {
+                rope.settings.segments != settings.segments
+                    || rope.settings.length != settings.length
+            }
+            None => true,
+        };
+        if rebuild {
+            self.ropes
+                .insert(key.clone(), Rope::new(start, end, settings.clone()));
+        }
+        let rope = self.ropes.get_mut(&key).unwrap();
+        rope.settings = settings;
+        let last = rope.points.len() - 1;
+        rope.points[0] = start;
+        rope.points[last] = end;
+        rope.attached = true;
+    }
+
+    pub fn simulate(&mut self, dt: f32, wind: &Wind) {
+        for rope in self.ropes.values_mut() {
+            rope.simulate(dt, wind);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Rope)> {
+        self.ropes.iter()
+    }
+}
@@ -1,17 +1,49 @@
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use glam::Vec2;
 
-use crate::render::{
-    bundle::{
-        lights,
-        model::{self, Batches},
+use crate::{
+    bounds::Aabb,
+    camera_path::PathState,
+    entity::{Entities, EntityId},
+    input::Inputs,
+    render::{
+        bundle::{
+            effect::EffectPlane,
+            globals::{Ambient, Fog},
+            lights,
+            model::{self, Batches, Pick},
+            outline::Outline,
+            points::PointBatch,
+            probes::ReflectionProbes,
+        },
+        camera::Camera,
     },
-    camera::Camera,
+    rope::Ropes,
 };
 
 pub struct Scene {
+    pub ambient: Ambient,
     pub camera: Camera,
+    pub effect_planes: Vec<EffectPlane>,
+    pub entities: Entities,
+    pub fog: Option<Fog>,
+    /// Bounds of the meshes batched last frame, rebuilt when rendering.
+    pub mesh_bounds: HashMap<String, Aabb>,
     pub model_batches: model::Batches,
+    /// Entities drawn with their blended mesh, set before batching.
+    pub morphed: HashSet<EntityId>,
+    /// Models outlined this frame, also batched as usual.
+    pub outlines: Vec<Outline>,
     pub point_lights: Vec<lights::PointLight>,
+    pub points: Vec<PointBatch>,
+    pub reflection_probes: ReflectionProbes,
+    pub ropes: Ropes,
+    /// Window size in physical pixels.
+    pub viewport: Vec2,
 }
 
 impl fmt::Debug for Scene {
@@ -23,14 +55,62 @@ impl fmt::Debug for Scene {
 impl Scene {
     pub fn new() -> Self {
         Self {
+            ambient: Ambient::default(),
             camera: Camera::new(),
+            effect_planes: Vec::new(),
+            entities: Entities::default(),
+            fog: None,
+            mesh_bounds: HashMap::new(),
             model_batches: Batches::default(),
+            morphed: HashSet::new(),
+            outlines: Vec::new(),
             point_lights: Vec::new(),
+            points: Vec::new(),
+            reflection_probes: ReflectionProbes::default(),
+            ropes: Ropes::default(),
+            viewport: Vec2::ONE,
+        }
+    }
+
+    /// Move the camera with its controller or the path it plays, if any.
+    pub fn update_camera(&mut self, inputs: &Inputs, dt: f32) {
+        let camera = &mut self.camera;
+        let playing = matches!(camera.path, Some(PathState::Playing { .. }));
+        if let (Some(controller), false) = (&mut camera.controller, playing) {
+            controller.update(
+                &mut camera.transform,
+                inputs,
+                &self.entities,
+                dt,
+            );
+        }
+        if let Some(path) = &mut camera.path {
+            if !path.update(&mut camera.transform, &mut camera.fovy, dt) {
+                camera.path = None;
+            }
         }
     }
 
     pub fn begin_frame(&mut self) {
+        self.effect_planes.clear();
         self.model_batches.clear();
+        self.outlines.clear();
         self.point_lights.clear();
+        self.points.clear();
+        self.ropes.begin_frame();
+    }
+
+    /// Add the retained entities to this frame batches.
+    pub fn batch_entities(&mut self) {
+        self.entities.batch(&mut self.model_batches, &self.morphed);
+    }
+
+    /// Instance under a window position, among the ones batched this frame.
+    pub fn pick(&self, cursor: Vec2) -> Option<Pick> {
+        let ndc = cursor / self.viewport * Vec2::new(2.0, -2.0)
+            + Vec2::new(-1.0, 1.0);
+        let aspect_ratio = self.viewport.x / self.viewport.y;
+        let (origin, dir) = self.camera.ray(ndc, aspect_ratio);
+        self.model_batches.pick(&self.mesh_bounds, origin, dir)
     }
 }
@@ -0,0 +1,113 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, TryRecvError},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    app::get_pool, assets, render::state::RenderState, snapshot::Snapshot,
+};
+
+const SCENES_DIR: &str = "scenes";
+
+pub enum Step {
+    Loading,
+    /// The snapshot replaces the scene this frame.
+    Swap(Snapshot),
+    FadingIn,
+    Done,
+}
+
+/// Scene file read on the thread pool and its meshes loaded while the
+/// current scene keeps rendering, swapped in once everything is ready.
+pub struct SceneSwitch {
+    /// Seconds of each half of the fade to black, none cuts directly.
+    fade: Option<f32>,
+    loaded: Option<Snapshot>,
+    pub name: String,
+    rx: Receiver<Result<Snapshot>>,
+    swapped: bool,
+    time: f32,
+}
+
+impl SceneSwitch {
+    /// Snapshots saved there, `assets/scenes/<name>.json`.
+    pub fn file(name: &str) -> PathBuf {
+        assets::root()
+            .join(SCENES_DIR)
+            .join(format!("{}.json", name))
+    }
+
+    pub fn start(name: &str, fade: Option<f32>) -> Self {
+        let (tx, rx) = channel();
+        let path = Self::file(name);
+        get_pool().execute(move || {
+            let _ = tx.send(Snapshot::load(&path));
+        });
+        Self {
+            fade: fade.filter(|duration| *duration > 0.0),
+            loaded: None,
+            name: name.to_string(),
+            rx,
+            swapped: false,
+            time: 0.0,
+        }
+    }
+
+    /// The fade stays at black until the scene is ready. Meshes failing to
+    /// load do not hold the swap.
+    pub fn update(
+        &mut self,
+        render_state: &mut RenderState,
+        dt: f32,
+    ) -> Result<Step> {
+        self.time += dt;
+        if self.swapped {
+            return Ok(match self.fade {
+                Some(duration) if self.time < duration => {
+                    render_state.set_fade(1.0 - self.time / duration);
+                    Step::FadingIn
+                }
+                _ => {
+                    render_state.set_fade(0.0);
+                    Step::Done
+                }
+            });
+        }
+
+        if self.loaded.is_none() {
+            match self.rx.try_recv() {
+                Ok(snapshot) => {
+                    let snapshot = snapshot?;
+                    for (_, entity) in snapshot.entities.iter() {
+                        render_state.meshes.load(&entity.mesh_id);
+                    }
+                    self.loaded = Some(snapshot);
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    bail!("scene {} was not loaded", self.name)
+                }
+            }
+        }
+        let faded = match self.fade {
+            Some(duration) => {
+                render_state.set_fade(self.time / duration);
+                self.time >= duration
+            }
+            None => true,
+        };
+        let ready = self.loaded.as_ref().is_some_and(|snapshot| {
+            snapshot.entities.iter().all(|(_, entity)| {
+                !render_state.meshes.is_pending(&entity.mesh_id)
+            })
+        });
+        if !faded || !ready {
+            return Ok(Step::Loading);
+        }
+        self.swapped = true;
+        self.time = 0.0;
+        Ok(Step::Swap(self.loaded.take().unwrap()))
+    }
+}
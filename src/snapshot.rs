@@ -0,0 +1,115 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entity::Entities,
+    render::bundle::lights::{Attenuation, PointLight},
+    scene::Scene,
+    transform::Transform,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraSnapshot {
+    pub fovy: f32,
+    pub transform: Transform,
+    pub zfar: f32,
+    pub znear: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSnapshot {
+    pub attenuation: Attenuation,
+    #[serde(default = "white")]
+    pub color: Vec3,
+    #[serde(default = "full")]
+    pub intensity: f32,
+    pub pos: Vec3,
+    pub radius: f32,
+}
+
+/// Defaults of the scenes saved before the lights had a color.
+fn white() -> Vec3 {
+    Vec3::ONE
+}
+
+fn full() -> f32 {
+    1.0
+}
+
+impl LightSnapshot {
+    pub fn light(&self) -> PointLight {
+        PointLight::new(self.pos, self.radius, self.attenuation)
+            .with_color(self.color, self.intensity)
+    }
+}
+
+/// Camera, lights submitted so far this frame and retained entities of a
+/// scene, with the data of the scripts, written as json.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub camera: CameraSnapshot,
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    pub entities: Entities,
+    pub lights: Vec<LightSnapshot>,
+}
+
+impl Snapshot {
+    pub fn capture(scene: &Scene, data: Option<serde_json::Value>) -> Self {
+        let camera = &scene.camera;
+        Self {
+            camera: CameraSnapshot {
+                fovy: camera.fovy,
+                transform: camera.transform,
+                zfar: camera.zfar,
+                znear: camera.znear,
+            },
+            data,
+            entities: scene.entities.clone(),
+            lights: scene
+                .point_lights
+                .iter()
+                .map(|light| LightSnapshot {
+                    attenuation: light.attenuation_mode(),
+                    color: light.color,
+                    intensity: light.intensity,
+                    pos: light.pos,
+                    radius: light.radius,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut snapshot: Self =
+            serde_json::from_str(&fs::read_to_string(path)?)?;
+        snapshot.entities.validate()?;
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Replace the camera and entities of the scene, the lights are only
+    /// added for this frame. The camera path stops, the controller stays.
+    pub fn apply(self, scene: &mut Scene) {
+        let camera = &mut scene.camera;
+        camera.fovy = self.camera.fovy;
+        camera.path = None;
+        camera.transform = self.camera.transform;
+        camera.zfar = self.camera.zfar;
+        camera.znear = self.camera.znear;
+        scene.entities = self.entities;
+        scene
+            .point_lights
+            .extend(self.lights.iter().map(LightSnapshot::light));
+    }
+}
@@ -0,0 +1,237 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use glam::Vec3;
+use log::warn;
+use serde::Deserialize;
+
+use crate::{
+    assets,
+    camera_path::CameraPath,
+    render::{
+        bundle::lights::{Attenuation, PointLight},
+        material::file::UniformValue,
+        state::RenderState,
+    },
+    scene::Scene,
+};
+
+const TIMELINES_DIR: &str = "timelines";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Key<T> {
+    time: f32,
+    value: T,
+}
+
+trait Lerp: Sized + Copy {
+    fn lerp(self, other: Self, t: f32) -> Option<Self>;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: Self, t: f32) -> Option<Self> {
+        Some(self + (other - self) * t)
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(self, other: Self, t: f32) -> Option<Self> {
+        Some(Vec3::lerp(self, other, t))
+    }
+}
+
+impl Lerp for UniformValue {
+    fn lerp(self, other: Self, t: f32) -> Option<Self> {
+        UniformValue::lerp(self, other, t)
+    }
+}
+
+/// Linear between the surrounding keys sorted by time, held before the
+/// first and after the last one.
+fn sample<T: Lerp>(keys: &[Key<T>], time: f32) -> Option<T> {
+    let next = keys.iter().position(|key| key.time > time);
+    match next {
+        None => keys.last().map(|key| key.value),
+        Some(0) => Some(keys[0].value),
+        Some(next) => {
+            let (a, b) = (&keys[next - 1], &keys[next]);
+            let t = (time - a.time) / (b.time - a.time);
+            a.value.lerp(b.value, t)
+        }
+    }
+}
+
+fn sort<T>(keys: &mut [Key<T>]) {
+    keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+}
+
+fn end<T>(keys: &[Key<T>]) -> f32 {
+    keys.last().map_or(0.0, |key| key.time)
+}
+
+/// Point light submitted every frame while the timeline plays, white at
+/// full intensity without their keys.
+#[derive(Debug, Deserialize)]
+struct LightTrack {
+    #[serde(default)]
+    attenuation: Attenuation,
+    #[serde(default)]
+    color: Vec<Key<Vec3>>,
+    #[serde(default)]
+    intensity: Vec<Key<f32>>,
+    pos: Vec<Key<Vec3>>,
+    radius: Vec<Key<f32>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MaterialTrack {
+    material: String,
+    param: String,
+    keys: Vec<Key<UniformValue>>,
+    /// Warned that the material is not loaded yet.
+    #[serde(skip)]
+    waiting: bool,
+}
+
+/// Keyframed camera, lights, material params and exposure, stored as
+/// `assets/timelines/<name>.json`. Times are in seconds from the start.
+#[derive(Debug, Deserialize)]
+pub struct Timeline {
+    camera: Option<CameraPath>,
+    #[serde(default)]
+    exposure: Vec<Key<f32>>,
+    #[serde(default)]
+    lights: Vec<LightTrack>,
+    #[serde(default)]
+    materials: Vec<MaterialTrack>,
+}
+
+impl Timeline {
+    fn file(name: &str) -> PathBuf {
+        assets::root()
+            .join(TIMELINES_DIR)
+            .join(format!("{}.json", name))
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let mut timeline: Self =
+            serde_json::from_str(&fs::read_to_string(Self::file(name))?)?;
+        if timeline.duration() <= 0.0 {
+            bail!("timeline {} has no keyframes past the start", name);
+        }
+        if let Some(camera) = &mut timeline.camera {
+            camera.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        }
+        sort(&mut timeline.exposure);
+        for light in &mut timeline.lights {
+            sort(&mut light.pos);
+            sort(&mut light.radius);
+            sort(&mut light.color);
+            sort(&mut light.intensity);
+        }
+        for track in &mut timeline.materials {
+            sort(&mut track.keys);
+        }
+        Ok(timeline)
+    }
+
+    /// Time of the last keyframe of every track.
+    pub fn duration(&self) -> f32 {
+        let camera = self.camera.as_ref().map_or(0.0, CameraPath::duration);
+        let lights = self.lights.iter().map(|light| {
+            end(&light.pos)
+                .max(end(&light.radius))
+                .max(end(&light.color))
+                .max(end(&light.intensity))
+        });
+        let materials = self.materials.iter().map(|track| end(&track.keys));
+        lights
+            .chain(materials)
+            .fold(camera.max(end(&self.exposure)), f32::max)
+    }
+}
+
+/// Timeline being played, applied after the scripts update so it wins
+/// over their changes.
+#[derive(Debug)]
+pub struct Playback {
+    pub looping: bool,
+    pub name: String,
+    pub time: f32,
+    timeline: Timeline,
+}
+
+impl Playback {
+    pub fn new(name: &str, timeline: Timeline, looping: bool) -> Self {
+        Self {
+            looping,
+            name: name.to_string(),
+            time: 0.0,
+            timeline,
+        }
+    }
+
+    /// Returns false once a timeline that does not loop is done playing,
+    /// its last values stay applied.
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        render_state: &mut RenderState,
+        dt: f32,
+    ) -> bool {
+        let duration = self.timeline.duration();
+        self.time += dt;
+        if self.looping {
+            self.time = self.time.rem_euclid(duration);
+        }
+        let time = self.time.min(duration);
+        let timeline = &mut self.timeline;
+
+        let camera = timeline.camera.as_ref();
+        if let Some(camera) = camera.filter(|c| !c.keyframes.is_empty()) {
+            let transform = &mut scene.camera.transform;
+            (transform.pos, transform.rot, scene.camera.fovy) =
+                camera.sample(time);
+        }
+        if let Some(ev) = sample(&timeline.exposure, time) {
+            render_state.set_exposure(ev);
+        }
+        for light in &timeline.lights {
+            if let (Some(pos), Some(radius)) =
+                (sample(&light.pos, time), sample(&light.radius, time))
+            {
+                scene.point_lights.push(
+                    PointLight::new(pos, radius, light.attenuation).with_color(
+                        sample(&light.color, time).unwrap_or(Vec3::ONE),
+                        sample(&light.intensity, time).unwrap_or(1.0),
+                    ),
+                );
+            }
+        }
+        // A track failing once is dropped, not to warn every frame. One whose
+        // material is still loading is retried on the next frames.
+        timeline.materials.retain_mut(|track| {
+            let Some(value) = sample(&track.keys, time) else {
+                warn!("timeline material {} mixes types", track.material);
+                return false;
+            };
+            if !render_state.materials.contains(&track.material) {
+                if !track.waiting {
+                    warn!(
+                        "timeline material {} not loaded yet",
+                        track.material
+                    );
+                    track.waiting = true;
+                }
+                return true;
+            }
+            render_state
+                .materials
+                .set_param(&track.material, &track.param, value.into())
+                .inspect_err(|err| warn!("timeline {}", err))
+                .is_ok()
+        });
+
+        self.looping || self.time < duration
+    }
+}
@@ -1,6 +1,7 @@
 use glam::{Mat3, Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Transform {
     pub pos: Vec3,
     pub rot: Quat,
@@ -22,6 +23,11 @@ impl Transform {
         Mat4::from_scale_rotation_translation(self.scale, self.rot, self.pos)
     }
 
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rot, pos) = matrix.to_scale_rotation_translation();
+        Self { pos, rot, scale }
+    }
+
     pub fn from_pos(pos: Vec3) -> Self {
         Self {
             pos,
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{
+    input::{key_name, Inputs},
+    render::stats::{Stats, HISTORY_LEN},
+};
+
+/// Keys handled by the engine itself, listed before the actions.
+const ENGINE_KEYS: &[(&str, &str)] = &[
+    ("help", "F1"),
+    ("stats", "F3"),
+    ("pause", "F5"),
+    ("step frame", "F6"),
+    ("reload scripts", "R"),
+    ("quit", "Escape"),
+];
+
+/// Immediate mode overlay, the scripts describe the widgets every frame.
+pub struct Ui {
+    pub ctx: egui::Context,
+    output: Option<egui::FullOutput>,
+    pub show_help: bool,
+    pub show_stats: bool,
+    state: egui_winit::State,
+}
+
+impl Ui {
+    pub fn new(window: &Window) -> Self {
+        let ctx = egui::Context::default();
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+        Self {
+            ctx,
+            output: None,
+            show_help: false,
+            show_stats: false,
+            state,
+        }
+    }
+
+    /// Returns whether the event was consumed by a widget.
+    pub fn on_window_event(
+        &mut self,
+        window: &Window,
+        event: &WindowEvent,
+    ) -> bool {
+        self.state.on_window_event(window, event).consumed
+    }
+
+    pub fn begin_frame(&mut self, window: &Window) {
+        let input = self.state.take_egui_input(window);
+        self.ctx.begin_pass(input);
+    }
+
+    pub fn end_frame(&mut self, window: &Window) {
+        let mut output = self.ctx.end_pass();
+        let platform_output = std::mem::take(&mut output.platform_output);
+        self.state.handle_platform_output(window, platform_output);
+        // Keep the texture changes of frames that were not drawn.
+        if let Some(previous) = self.output.take() {
+            let mut textures_delta = previous.textures_delta;
+            textures_delta.append(std::mem::take(&mut output.textures_delta));
+            output.textures_delta = textures_delta;
+        }
+        self.output = Some(output);
+    }
+
+    /// Controls overlay, generated from the registered actions.
+    pub fn help(&self, inputs: &Inputs) {
+        if !self.show_help {
+            return;
+        }
+        egui::Window::new("Controls")
+            .collapsible(false)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                egui::Grid::new("controls").striped(true).show(ui, |ui| {
+                    for (name, key) in ENGINE_KEYS {
+                        ui.label(*name);
+                        ui.label(*key);
+                        ui.end_row();
+                    }
+                    for (name, codes) in inputs.actions() {
+                        let keys = codes
+                            .iter()
+                            .map(|code| key_name(*code))
+                            .collect::<Vec<_>>();
+                        ui.label(name);
+                        ui.label(keys.join(", "));
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+
+    /// Frame statistics in the top left corner: the summary line, the
+    /// frame time graph, the instances by pipeline and the buffer memory.
+    pub fn stats(&self, stats: &Stats) {
+        if !self.show_stats {
+            return;
+        }
+        egui::Area::new(egui::Id::new("stats"))
+            .anchor(egui::Align2::LEFT_TOP, egui::vec2(8.0, 8.0))
+            .interactable(false)
+            .show(&self.ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(stats.hud_line());
+                    frame_graph(ui, stats);
+                    egui::Grid::new("pipelines").show(ui, |ui| {
+                        for (shader_id, instances) in &stats.pipelines {
+                            ui.monospace(shader_id);
+                            ui.monospace(format!("{} instances", instances));
+                            ui.end_row();
+                        }
+                    });
+                    ui.monospace(format!(
+                        "meshes {:.1} MB  textures {:.1} MB  buffers {:.1} MB",
+                        megabytes(stats.mesh_memory),
+                        megabytes(stats.texture_memory),
+                        megabytes(stats.buffer_memory),
+                    ));
+                });
+            });
+    }
+
+    /// Script components disabled by an error, until their script reloads.
+    pub fn script_errors(&self, errors: &[String]) {
+        if errors.is_empty() {
+            return;
+        }
+        egui::Window::new("Script errors")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+            .collapsible(true)
+            .resizable(false)
+            .show(&self.ctx, |ui| {
+                for error in errors {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+            });
+    }
+
+    pub fn take_output(&mut self) -> Option<egui::FullOutput> {
+        self.output.take()
+    }
+}
+
+/// Widgets added by a script callback, laid out in a child of the
+/// container it fills.
+pub struct Panel {
+    /// Widgets added for each label, salting the ids of the ones sharing it.
+    labels: HashMap<String, usize>,
+    pub ui: egui::Ui,
+}
+
+impl Panel {
+    pub fn new(container: &mut egui::Ui) -> Self {
+        Self {
+            labels: HashMap::new(),
+            ui: container.new_child(egui::UiBuilder::new()),
+        }
+    }
+
+    /// Make room for the added widgets in the container.
+    pub fn finish(self, container: &mut egui::Ui) {
+        container.advance_cursor_after_rect(self.ui.min_rect());
+    }
+
+    /// Id keeping the value of a widget across frames, unique in the panel.
+    fn widget_id(&mut self, label: &str) -> egui::Id {
+        let count = self.labels.entry(label.to_string()).or_default();
+        let id = self.ui.id().with(label).with(*count);
+        *count += 1;
+        id
+    }
+
+    pub fn checkbox(&mut self, label: &str, default: bool) -> bool {
+        let id = self.widget_id(label);
+        let mut checked =
+            self.ui.data_mut(|data| *data.get_temp_mut_or(id, default));
+        self.ui.push_id(id, |ui| ui.checkbox(&mut checked, label));
+        self.ui.data_mut(|data| data.insert_temp(id, checked));
+        checked
+    }
+
+    pub fn slider(
+        &mut self,
+        label: &str,
+        range: std::ops::RangeInclusive<f64>,
+        default: f64,
+    ) -> f64 {
+        let id = self.widget_id(label);
+        let mut value =
+            self.ui.data_mut(|data| *data.get_temp_mut_or(id, default));
+        self.ui.push_id(id, |ui| {
+            ui.add(egui::Slider::new(&mut value, range).text(label))
+        });
+        self.ui.data_mut(|data| data.insert_temp(id, value));
+        value
+    }
+}
+
+/// Bars of the frame times, the oldest on the left, with lines at 60 and 30
+/// fps. Frames slower than 30 fps are red.
+fn frame_graph(ui: &mut egui::Ui, stats: &Stats) {
+    let size = egui::vec2(HISTORY_LEN as f32 * 2.0, 60.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(120));
+    let max = stats.frame_times.iter().fold(1.0 / 30.0, |a, b| b.max(a));
+    let y = |time: f32| rect.bottom() - rect.height() * time / max;
+    for (fps, color) in
+        [(60.0, egui::Color32::GREEN), (30.0, egui::Color32::RED)]
+    {
+        let y = y(1.0 / fps);
+        painter.hline(rect.x_range(), y, egui::Stroke::new(1.0, color));
+    }
+    let bar = rect.width() / HISTORY_LEN as f32;
+    for (i, time) in stats.frame_times.iter().enumerate() {
+        let color = match *time > 1.0 / 30.0 {
+            true => egui::Color32::LIGHT_RED,
+            false => egui::Color32::LIGHT_GRAY,
+        };
+        let x = rect.left() + i as f32 * bar;
+        let bar_rect = egui::Rect::from_min_max(
+            egui::pos2(x, y(*time)),
+            egui::pos2(x + bar - 0.5, rect.bottom()),
+        );
+        painter.rect_filled(bar_rect, 0.0, color);
+    }
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
@@ -0,0 +1,32 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::assets;
+
+/// Namespace the directory of the viewed model is mounted as.
+const NAMESPACE: &str = "viewer";
+
+/// Script shown instead of `main`, gets the mesh id as first engine arg.
+pub const ENTRY_POINT: &str = "viewer";
+
+/// Mount the directory of the model, returns its mesh id.
+pub fn mount_model(path: &Path) -> Result<String> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if !matches!(extension, Some("obj" | "glb" | "gltf")) {
+        bail!("{} is not an obj or gltf file", path.display());
+    }
+    if !path.is_file() {
+        bail!("{} does not exist", path.display());
+    }
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .context("model name is not valid unicode")?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    assets::mount_dir(NAMESPACE, "meshes", dir)?;
+    Ok(format!("{}:{}", NAMESPACE, stem))
+}
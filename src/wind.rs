@@ -0,0 +1,58 @@
+use std::f32::consts::TAU;
+
+use glam::{Vec3, Vec4};
+
+/// Scale of the gust waves along the wind, in waves per meter.
+const GUST_SCALE: f32 = 0.15;
+
+/// Wind shared by the shaders, the ropes and the scripts, a constant
+/// direction with gusts travelling along it.
+#[derive(Debug, Clone, Copy)]
+pub struct Wind {
+    pub direction: Vec3,
+    pub strength: f32,
+    /// Relative strength change of the gusts, 0 for a steady wind.
+    pub gustiness: f32,
+    /// Gusts per second passing a point.
+    pub gust_frequency: f32,
+    /// Seconds since the start, the gusts move with it.
+    pub time: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::X,
+            strength: 0.0,
+            gustiness: 0.5,
+            gust_frequency: 0.3,
+            time: 0.0,
+        }
+    }
+}
+
+impl Wind {
+    pub fn set(&mut self, direction: Vec3, strength: f32) {
+        self.direction = direction.normalize_or(Vec3::X);
+        self.strength = strength.max(0.0);
+    }
+
+    /// Velocity of the air at a position, same formula as `wind_at` in the
+    /// shaders.
+    pub fn at(&self, position: Vec3) -> Vec3 {
+        let phase = (position.dot(self.direction) * GUST_SCALE
+            - self.time * self.gust_frequency)
+            * TAU;
+        let noise = (phase.sin() + (phase * 2.3 + 1.7).sin() * 0.5) / 1.5;
+        self.direction * self.strength * (1.0 + self.gustiness * noise)
+    }
+
+    /// Velocity in xyz and gustiness in w, then the gust frequency and
+    /// scale, for the globals uniform.
+    pub fn uniform(&self) -> (Vec4, Vec4) {
+        (
+            (self.direction * self.strength).extend(self.gustiness),
+            Vec4::new(self.gust_frequency, GUST_SCALE, self.time, 0.0),
+        )
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{bail, Context, Result};
+use winit::{
+    dpi::{LogicalPosition, LogicalSize},
+    window::{Fullscreen, Window, WindowAttributes, WindowLevel},
+};
+
+use crate::render::adapter::flag_value;
+
+/// Main window settings, from the command line flags.
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    pub decorations: bool,
+    pub fullscreen: bool,
+    pub on_top: bool,
+    /// Logical position, left to the platform when none.
+    pub position: Option<[i32; 2]>,
+    /// Logical inner size.
+    pub size: [u32; 2],
+    pub title: String,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        Self {
+            decorations: true,
+            fullscreen: false,
+            on_top: true,
+            position: Some([880, 0]),
+            size: [720, 550],
+            title: "bloup".to_string(),
+        }
+    }
+}
+
+impl WindowOptions {
+    /// Take `--title <title>`, `--size <w>x<h>`, `--position <x>,<y>`,
+    /// `--fullscreen`, `--no-decorations` and `--not-on-top` out of the
+    /// arguments.
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self> {
+        let mut options = Self::default();
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--title" => options.title = flag_value(args, i)?,
+                "--size" => {
+                    let [w, h] = parse_pair(&flag_value(args, i)?, 'x')?;
+                    options.size = [w, h];
+                }
+                "--position" => {
+                    options.position =
+                        Some(parse_pair(&flag_value(args, i)?, ',')?);
+                }
+                "--fullscreen" => {
+                    options.fullscreen = true;
+                    args.remove(i);
+                }
+                "--no-decorations" => {
+                    options.decorations = false;
+                    args.remove(i);
+                }
+                "--not-on-top" => {
+                    options.on_top = false;
+                    args.remove(i);
+                }
+                _ => i += 1,
+            }
+        }
+        if options.size.contains(&0) {
+            bail!("window size must not be empty");
+        }
+        Ok(options)
+    }
+
+    pub fn attributes(&self) -> WindowAttributes {
+        let [width, height] = self.size;
+        let mut attributes = Window::default_attributes()
+            .with_title(&self.title)
+            .with_inner_size(LogicalSize::new(width, height))
+            .with_decorations(self.decorations)
+            .with_fullscreen(fullscreen(self.fullscreen))
+            .with_window_level(window_level(self.on_top));
+        if let Some([x, y]) = self.position {
+            attributes = attributes.with_position(LogicalPosition::new(x, y));
+        }
+        attributes
+    }
+}
+
+/// Borderless on the current monitor, exclusive modes are not supported.
+pub fn fullscreen(enabled: bool) -> Option<Fullscreen> {
+    enabled.then_some(Fullscreen::Borderless(None))
+}
+
+pub fn window_level(on_top: bool) -> WindowLevel {
+    match on_top {
+        true => WindowLevel::AlwaysOnTop,
+        false => WindowLevel::Normal,
+    }
+}
+
+fn parse_pair<T: std::str::FromStr>(
+    value: &str,
+    separator: char,
+) -> Result<[T; 2]> {
+    let (a, b) = value
+        .split_once(separator)
+        .with_context(|| format!("expected a{}b, got {}", separator, value))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<T>()
+            .ok()
+            .with_context(|| format!("invalid number {}", v))
+    };
+    Ok([parse(a)?, parse(b)?])
+}